@@ -31,6 +31,11 @@ pub struct InitArgs {
     /// Max number of checkpoint files kept
     pub max_checkpoint_files: u32,
 
+    /// Number of delta checkpoints taken between two full checkpoints. 0 means every
+    /// checkpoint is a full one.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub delta_checkpoints: u32,
+
     /// Run the database garbage collection at given interval in blocks
     #[cfg_attr(feature = "serde", serde(default))]
     pub gc_interval: chain::BlockNumber,
@@ -40,6 +45,18 @@ pub struct InitArgs {
 
     /// The public rpc port with acl enabled
     pub public_port: Option<u16>,
+
+    /// The proxy that all HTTP requests made by contracts go through
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub contract_http_proxy: Option<String>,
+
+    /// Seal the sidevm filesystems to the storage path rather than keeping them in memory
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub seal_sidevm_fs: bool,
+
+    /// Keep the chain storage in a log under the storage path rather than in memory
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub disk_chain_storage: bool,
 }
 
 pub fn git_revision() -> String {
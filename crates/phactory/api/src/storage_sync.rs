@@ -10,7 +10,8 @@ use derive_more::Display;
 use parity_scale_codec::Encode;
 use serde::{Deserialize, Serialize};
 
-type Storage = phala_trie_storage::TrieStorage<RuntimeHasher>;
+type Storage =
+    phala_trie_storage::TrieStorage<RuntimeHasher, phala_trie_storage::NodeDB<RuntimeHasher>>;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -48,6 +49,9 @@ pub enum Error {
     },
     /// Solo/Para mode mismatch
     ChainModeMismatch,
+    /// Failed to write the storage changes to the trie node db
+    #[display(fmt = "StorageWriteFailed({_0})")]
+    StorageWriteFailed(String),
 }
 
 pub trait BlockValidator {
@@ -226,7 +230,9 @@ where
         }
 
         log::debug!("apply changes");
-        storage.apply_changes(state_root, transaction);
+        storage
+            .try_apply_changes(state_root, transaction)
+            .map_err(|err| Error::StorageWriteFailed(err.to_string()))?;
         log::debug!("applied");
 
         self.block_number_next += 1;
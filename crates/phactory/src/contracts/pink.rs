@@ -4,10 +4,10 @@ use crate::contracts;
 use crate::system::{TransactionError, TransactionResult};
 use anyhow::{anyhow, Result};
 use parity_scale_codec::{Decode, Encode};
-use phala_mq::{ContractClusterId, ContractId, MessageOrigin};
+use phala_mq::{ContractClusterId, ContractId, MessageOrigin, MessageSendQueue};
 use phala_types::contract::ConvertTo;
 use pink::predefined_accounts::pallet_account;
-use pink::runtime::{BoxedEventCallbacks, ExecSideEffects};
+use pink::runtime::{BoxedEventCallbacks, ExecSideEffects, PinkExtError};
 use runtime::{AccountId, BlockNumber, Hash};
 use sidevm::service::{Command as SidevmCommand, CommandSender, SystemMessage};
 use sp_runtime::{traits::ConstU32, BoundedVec};
@@ -96,10 +96,6 @@ impl Pink {
     pub fn address(&self) -> AccountId {
         self.instance.address.clone()
     }
-
-    pub fn set_on_block_end_selector(&mut self, selector: u32) {
-        self.instance.set_on_block_end_selector(selector)
-    }
 }
 
 impl Pink {
@@ -128,9 +124,11 @@ impl Pink {
                     true,
                     context.block_number,
                     context.now_ms,
-                    ContractEventCallback::from_log_sender(
+                    ContractEventCallback::for_query(
                         &context.log_handler,
                         context.block_number,
+                        self.cluster_id,
+                        context.cluster_sidevms.clone(),
                     ),
                 );
                 if ink_result.result.is_err() {
@@ -198,9 +196,10 @@ impl Pink {
                     false,
                     context.block.block_number,
                     context.block.now_ms,
-                    ContractEventCallback::from_log_sender(
+                    ContractEventCallback::for_command(
                         &context.log_handler,
                         context.block.block_number,
+                        context.block.send_mq,
                     ),
                 );
 
@@ -238,9 +237,10 @@ impl Pink {
                 storage,
                 context.block.block_number,
                 context.block.now_ms,
-                ContractEventCallback::from_log_sender(
+                ContractEventCallback::for_command(
                     &context.log_handler,
                     context.block.block_number,
+                    context.block.send_mq,
                 ),
             )
             .map_err(|err| {
@@ -250,6 +250,40 @@ impl Pink {
         Ok(effects)
     }
 
+    pub(crate) fn call_hook(
+        &mut self,
+        selector: u32,
+        gas_limit: u64,
+        rollback: bool,
+        context: &mut contracts::TransactionContext,
+    ) -> TransactionResult {
+        let storage = cluster_storage(context.contract_clusters, &self.cluster_id)
+            .expect("Pink cluster should always exists!");
+        let effects = self
+            .instance
+            .call_hook(
+                storage,
+                selector,
+                gas_limit,
+                rollback,
+                context.block.block_number,
+                context.block.now_ms,
+                ContractEventCallback::for_command(
+                    &context.log_handler,
+                    context.block.block_number,
+                    context.block.send_mq,
+                ),
+            )
+            .map_err(|err| {
+                log::error!("Pink [{:?}] hook exec error: {:?}", self.id(), err);
+                TransactionError::Other(format!("Call contract hook failed: {err:?}"))
+            })?;
+        if rollback {
+            return Ok(effects.into_query_only_effects());
+        }
+        Ok(effects)
+    }
+
     pub(crate) fn snapshot(&self) -> Self {
         self.clone()
     }
@@ -271,9 +305,9 @@ pub mod cluster {
     use phala_crypto::sr25519::{Persistence, Sr25519SecretKey, KDF};
     use phala_mq::{ContractClusterId, ContractId};
     use phala_serde_more as more;
-    use phala_types::contract::messaging::ResourceType;
+    use phala_types::contract::{messaging::ResourceType, ConvertTo};
     use pink::{
-        runtime::{BoxedEventCallbacks, ExecSideEffects},
+        runtime::{BoxedEventCallbacks, ExecSideEffects, HttpEgressConfig},
         types::{AccountId, Hash},
     };
     use runtime::BlockNumber;
@@ -388,10 +422,35 @@ pub mod cluster {
             self.contracts.insert(address)
         }
 
+        /// Replace the code of a contract in the cluster, keeping its storage.
+        pub fn upgrade_contract(
+            &mut self,
+            address: &ContractId,
+            code_hash: Hash,
+        ) -> Result<(), DispatchError> {
+            pink::Contract::from_address(address.convert_to())
+                .set_code(&mut self.storage, code_hash)
+        }
+
+        /// Remove a contract from the cluster and free its storage.
+        pub fn remove_contract(&mut self, address: &ContractId) -> Result<(), DispatchError> {
+            self.contracts.remove(address);
+            pink::Contract::from_address(address.convert_to()).terminate(&mut self.storage)
+        }
+
         pub fn key(&self) -> &sr25519::Pair {
             &self.key
         }
 
+        /// Replace the key of the cluster, used to sign its messages and to derive the keys of
+        /// the contracts deployed from now on.
+        ///
+        /// The deployed contracts keep the keys derived from the old one, which are registered
+        /// on chain, and so does the seed of the keys derived by the contracts themselves.
+        pub fn set_key(&mut self, key: sr25519::Pair) {
+            self.key = key;
+        }
+
         pub fn system_contract(&mut self) -> Option<AccountId32> {
             self.storage.system_contract()
         }
@@ -408,6 +467,12 @@ pub mod cluster {
             self.storage.set_key_seed(seed);
         }
 
+        /// Set the HTTP egress limits and domain filters, which are kept in the cluster storage
+        /// so that the pink runtime can apply them to every contract.
+        pub fn set_http_egress_config(&mut self, config: HttpEgressConfig) {
+            self.storage.set_http_egress_config(config);
+        }
+
         pub fn upload_resource(
             &mut self,
             origin: AccountId,
@@ -438,31 +503,59 @@ pub mod cluster {
 }
 
 pub(crate) struct ContractEventCallback {
-    log_handler: CommandSender,
+    log_handler: Option<CommandSender>,
     block_number: BlockNumber,
+    /// The cluster of the contract and the sidevm instances it can query.
+    sidevms: Option<(ContractClusterId, contracts::SidevmHandles)>,
+    /// The egress queue the messages of the contracts are pushed into.
+    egress: Option<MessageSendQueue>,
 }
 
 impl ContractEventCallback {
-    pub fn new(log_handler: CommandSender, block_number: BlockNumber) -> Self {
+    pub fn new(log_handler: Option<CommandSender>, block_number: BlockNumber) -> Self {
         ContractEventCallback {
             log_handler,
             block_number,
+            sidevms: None,
+            egress: None,
         }
     }
 
-    pub fn from_log_sender(
+    /// The callbacks for commands.
+    ///
+    /// They are always present, even without a log handler, since the contracts are told about
+    /// the room left in `egress` through them and that must be the same on all workers.
+    pub fn for_command(
         log_handler: &Option<CommandSender>,
         block_number: BlockNumber,
+        egress: &MessageSendQueue,
     ) -> Option<BoxedEventCallbacks> {
-        Some(Box::new(ContractEventCallback::new(
-            log_handler.as_ref().cloned()?,
-            block_number,
-        )))
+        Some(Box::new(ContractEventCallback {
+            egress: Some(egress.clone()),
+            ..ContractEventCallback::new(log_handler.clone(), block_number)
+        }))
+    }
+
+    /// The callbacks for queries, which can also query the sidevm instances in the given cluster.
+    pub fn for_query(
+        log_handler: &Option<CommandSender>,
+        block_number: BlockNumber,
+        cluster_id: ContractClusterId,
+        sidevms: contracts::SidevmHandles,
+    ) -> Option<BoxedEventCallbacks> {
+        Some(Box::new(ContractEventCallback {
+            sidevms: Some((cluster_id, sidevms)),
+            ..ContractEventCallback::new(log_handler.clone(), block_number)
+        }))
     }
 }
 
 impl pink::runtime::EventCallbacks for ContractEventCallback {
     fn emit_log(&self, contract: &AccountId, in_query: bool, level: u8, message: String) {
+        let log_handler = match &self.log_handler {
+            Some(log_handler) => log_handler,
+            None => return,
+        };
         let msg = SidevmCommand::PushSystemMessage(SystemMessage::PinkLog {
             block_number: self.block_number,
             timestamp_ms: std::time::SystemTime::now()
@@ -474,8 +567,189 @@ impl pink::runtime::EventCallbacks for ContractEventCallback {
             level,
             message,
         });
-        if self.log_handler.try_send(msg).is_err() {
+        if log_handler.try_send(msg).is_err() {
             error!("Pink emit_log failed");
         }
     }
+
+    fn query_sidevm(
+        &self,
+        origin: &AccountId,
+        contract: &AccountId,
+        payload: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, PinkExtError> {
+        use tokio::sync::mpsc::error::TrySendError;
+
+        let contract_id: ContractId = contract.convert_to();
+        let cmd_sender = self
+            .sidevms
+            .as_ref()
+            .and_then(|(cluster_id, sidevms)| sidevms.get(cluster_id, &contract_id))
+            .ok_or(PinkExtError::SidevmNotFound)?;
+        let (reply_tx, rx) = tokio::sync::oneshot::channel();
+        let query = SidevmCommand::PushQuery {
+            origin: Some(origin.clone().into()),
+            payload,
+            reply_tx,
+        };
+        match cmd_sender.try_send(query) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => return Err(PinkExtError::SidevmNoResponse),
+            Err(TrySendError::Closed(_)) => return Err(PinkExtError::SidevmNotFound),
+        }
+        // The contract is executed synchronously in a query future, which always runs on a
+        // multi-thread runtime. So block the worker thread in place to wait for the reply.
+        let handle =
+            tokio::runtime::Handle::try_current().or(Err(PinkExtError::SidevmNoResponse))?;
+        tokio::task::block_in_place(|| handle.block_on(tokio::time::timeout(timeout, rx)))
+            .or(Err(PinkExtError::Timeout))?
+            .or(Err(PinkExtError::SidevmNoResponse))
+    }
+
+    fn egress_room(&self, contract: &AccountId) -> Option<usize> {
+        let contract_id: ContractId = contract.convert_to();
+        self.egress
+            .as_ref()?
+            .remaining_quota(&MessageOrigin::Contract(contract_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pink::runtime::EventCallbacks as _;
+    use std::sync::{Arc, Mutex};
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn cluster_id() -> ContractClusterId {
+        ContractClusterId::repeat_byte(1)
+    }
+
+    fn contract() -> AccountId {
+        AccountId::new([2; 32])
+    }
+
+    /// Start a fake sidevm instance of `contract()` answering the queries with `answer`.
+    fn start_sidevm(
+        sidevms: &contracts::SidevmHandles,
+        answer: impl Fn(Vec<u8>, tokio::sync::oneshot::Sender<Vec<u8>>) + Send + 'static,
+    ) {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        sidevms.set(contract().convert_to(), Some((cluster_id(), tx)));
+        tokio::spawn(async move {
+            while let Some(cmd) = rx.recv().await {
+                if let SidevmCommand::PushQuery {
+                    payload, reply_tx, ..
+                } = cmd
+                {
+                    answer(payload, reply_tx);
+                }
+            }
+        });
+    }
+
+    fn query(callbacks: &ContractEventCallback) -> Result<Vec<u8>, PinkExtError> {
+        let origin = AccountId::new([3; 32]);
+        callbacks.query_sidevm(&origin, &contract(), b"ping".to_vec(), TIMEOUT)
+    }
+
+    fn callbacks(
+        cluster_id: ContractClusterId,
+        sidevms: &contracts::SidevmHandles,
+    ) -> ContractEventCallback {
+        ContractEventCallback {
+            sidevms: Some((cluster_id, sidevms.clone())),
+            ..ContractEventCallback::new(None, 1)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn query_sidevm_gets_the_reply() {
+        let sidevms = contracts::SidevmHandles::default();
+        start_sidevm(&sidevms, |payload, reply_tx| {
+            let _ = reply_tx.send([&payload[..], b" pong"].concat());
+        });
+        assert_eq!(
+            query(&callbacks(cluster_id(), &sidevms)),
+            Ok(b"ping pong".to_vec())
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn query_sidevm_without_reply() {
+        let sidevms = contracts::SidevmHandles::default();
+        let pending = Arc::new(Mutex::new(vec![]));
+        let pending_in_vm = pending.clone();
+        start_sidevm(&sidevms, move |_, reply_tx| {
+            pending_in_vm.lock().unwrap().push(reply_tx);
+        });
+        let callbacks = callbacks(cluster_id(), &sidevms);
+        assert_eq!(query(&callbacks), Err(PinkExtError::Timeout));
+        assert_eq!(pending.lock().unwrap().len(), 1);
+
+        start_sidevm(&sidevms, |_, reply_tx| drop(reply_tx));
+        assert_eq!(query(&callbacks), Err(PinkExtError::SidevmNoResponse));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn query_sidevm_not_found() {
+        let sidevms = contracts::SidevmHandles::default();
+        assert_eq!(
+            query(&callbacks(cluster_id(), &sidevms)),
+            Err(PinkExtError::SidevmNotFound)
+        );
+
+        start_sidevm(&sidevms, |_, _| ());
+        let other_cluster = ContractClusterId::repeat_byte(2);
+        assert_eq!(
+            query(&callbacks(other_cluster, &sidevms)),
+            Err(PinkExtError::SidevmNotFound)
+        );
+        assert_eq!(
+            query(&ContractEventCallback::new(None, 1)),
+            Err(PinkExtError::SidevmNotFound)
+        );
+
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        drop(rx);
+        sidevms.set(contract().convert_to(), Some((cluster_id(), tx)));
+        assert_eq!(
+            query(&callbacks(cluster_id(), &sidevms)),
+            Err(PinkExtError::SidevmNotFound)
+        );
+    }
+
+    #[test]
+    fn egress_room_follows_the_queue() {
+        use phala_mq::{
+            traits::MessageChannel as _, EgressPolicy, OriginKind, SignedMessageChannel,
+        };
+        use sp_core::Pair as _;
+
+        let queue = MessageSendQueue::new();
+        queue.set_policy(EgressPolicy {
+            quotas: [(OriginKind::Contract, 2)].into(),
+            ..Default::default()
+        });
+        let callbacks = ContractEventCallback::for_command(&None, 1, &queue).unwrap();
+        assert_eq!(callbacks.egress_room(&contract()), Some(2));
+
+        let key = sp_core::sr25519::Pair::from_seed(&[1; 32]);
+        let mq: SignedMessageChannel =
+            queue.channel(MessageOrigin::Contract(contract().convert_to()), key.into());
+        mq.push_data(b"0".to_vec(), b"topic".to_vec());
+        assert_eq!(callbacks.egress_room(&contract()), Some(1));
+        mq.push_data(b"1".to_vec(), b"topic".to_vec());
+        assert_eq!(callbacks.egress_room(&contract()), Some(0));
+
+        // Each contract has a quota of its own, and there is no limit without a queue.
+        let other = AccountId::new([3; 32]);
+        assert_eq!(callbacks.egress_room(&other), Some(2));
+        assert_eq!(
+            ContractEventCallback::new(None, 1).egress_room(&contract()),
+            None
+        );
+    }
 }
@@ -1,16 +1,22 @@
 use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
-use parity_scale_codec::Decode;
+use parity_scale_codec::{Decode, Encode};
 use phala_crypto::ecdh::EcdhPublicKey;
-use phala_mq::{traits::MessageChannel, SignedMessageChannel};
+use phala_mq::{traits::MessageChannel, MessageOrigin, SignedMessageChannel};
 use phala_scheduler::RequestScheduler;
 use runtime::BlockNumber;
 use sidevm::{
-    service::{Command as SidevmCommand, CommandSender, ExitReason},
+    service::{Command as SidevmCommand, CommandSender, ExitReason, OutgoingRequest},
     OcallAborted, VmId,
 };
+use tokio::sync::{
+    mpsc::{Receiver, Sender},
+    oneshot::Sender as OneshotSender,
+};
 
 use super::pink::cluster::ClusterKeeper;
 use crate::{
@@ -20,6 +26,7 @@ use crate::{
     types::BlockInfo,
     ContractId, H256,
 };
+use ::pink::runtime::HookPoint;
 use phactory_api::prpc as pb;
 
 use phala_serde_more as more;
@@ -44,6 +51,9 @@ pub struct QueryContext {
     pub now_ms: u64,
     pub storage: ::pink::Storage,
     pub sidevm_handle: Option<SidevmHandle>,
+    /// The running sidevm instances, reachable by sidevm queries from the contracts in the same
+    /// cluster.
+    pub cluster_sidevms: SidevmHandles,
     pub log_handler: Option<CommandSender>,
     pub query_scheduler: RequestScheduler<ContractId>,
     pub weight: u32,
@@ -110,6 +120,89 @@ struct SidevmInfo {
     start_time: String,
     auto_restart: bool,
     handle: Arc<Mutex<SidevmHandle>>,
+    /// Whether the OnSidevmExit hook has been fired for the current run of the instance.
+    #[serde(default)]
+    exit_reported: bool,
+    /// The latest snapshot saved by the instance, handed back to it when restarted.
+    #[serde(default)]
+    snapshot: sidevm::SnapshotSlot,
+    /// Requests from the current run of the instance waiting to be relayed to the contract.
+    #[serde(skip)]
+    requests: Option<Receiver<(Vec<u8>, SidevmAction)>>,
+}
+
+/// Max number of requests from a sidevm instance waiting to be handled.
+const SIDEVM_REQUEST_QUEUE_SIZE: usize = 64;
+
+/// A query from a sidevm instance to its contract.
+pub(crate) struct SidevmQuery {
+    pub contract: ContractId,
+    pub payload: Vec<u8>,
+    pub reply_tx: OneshotSender<Vec<u8>>,
+}
+
+/// Where the queries from the sidevm instances go. None to drop them.
+static SIDEVM_QUERIES: Mutex<Option<Sender<SidevmQuery>>> = Mutex::new(None);
+
+/// Route the queries from the sidevm instances to the returned receiver as they are made.
+///
+/// Only the latest receiver gets the queries.
+pub(crate) fn subscribe_sidevm_queries() -> Receiver<SidevmQuery> {
+    let (tx, rx) = tokio::sync::mpsc::channel(SIDEVM_REQUEST_QUEUE_SIZE);
+    *SIDEVM_QUERIES.lock().unwrap() = Some(tx);
+    rx
+}
+
+/// Number of blocks to remember the dedup key of a handled sidevm request for, about 2 days.
+const SIDEVM_REQUEST_KEY_TTL: BlockNumber = 14400;
+
+/// A request from a sidevm instance to its contract.
+///
+/// Such requests would not be deterministic if handled right away, so one elected worker of the
+/// cluster relays them to the contract through the chain, where they are handled by all the
+/// workers in the cluster in the same order. Only the first request landed for each dedup key
+/// takes effect, so a copy sent again by a newly elected worker is dropped.
+#[derive(Encode, Decode)]
+pub(crate) struct SidevmRequest {
+    dedup_key: H256,
+    action: SidevmAction,
+    /// Proves that the request comes from a worker holding the contract key.
+    mac: H256,
+}
+
+#[derive(Encode, Decode)]
+pub(crate) enum SidevmAction {
+    /// Call the contract with an ink message, the contract itself being the origin.
+    Command(Vec<u8>),
+    /// Emit an mq message under the origin of the contract.
+    Message { topic: Vec<u8>, payload: Vec<u8> },
+}
+
+impl SidevmRequest {
+    fn mac(key: &KeyPair, dedup_key: &H256, action: &SidevmAction) -> H256 {
+        sp_core::blake2_256(&(b"sidevm_request", key.secret(), dedup_key, action).encode()).into()
+    }
+}
+
+/// A contract method called by the runtime on some event.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct Hook {
+    selector: u32,
+    gas_limit: u64,
+}
+
+/// The hooks registered via `PinkEvent::SetHook`.
+///
+/// The OnBlockEnd selectors registered by earlier versions live in the pink instance and are
+/// still called, without gas limit, when no OnBlockEnd hook is registered here.
+#[derive(Serialize, Deserialize, Default)]
+struct Hooks {
+    on_block_end: Option<Hook>,
+    on_block_start: Option<Hook>,
+    /// The interval in blocks and the hook.
+    on_timer: Option<(BlockNumber, Hook)>,
+    on_cluster_key_rotated: Option<Hook>,
+    on_sidevm_exit: Option<Hook>,
 }
 
 pub(crate) enum SidevmCode {
@@ -130,6 +223,11 @@ pub struct FatContract {
     sidevm_info: Option<SidevmInfo>,
     weight: u32,
     code_hash: Option<H256>,
+    #[serde(default)]
+    hooks: Hooks,
+    /// The dedup keys of the handled sidevm requests and the blocks they expire at.
+    #[serde(default)]
+    sidevm_request_keys: BTreeMap<H256, BlockNumber>,
 }
 
 impl FatContract {
@@ -152,6 +250,8 @@ impl FatContract {
             sidevm_info: None,
             weight: 0,
             code_hash,
+            hooks: Default::default(),
+            sidevm_request_keys: Default::default(),
         }
     }
 
@@ -177,20 +277,23 @@ impl FatContract {
         &mut self,
         env: &mut ExecuteEnv,
     ) -> Option<TransactionResult> {
-        let secret_mq = SecretMessageChannel::new(&self.ecdh_key, &self.send_mq);
-        let mut context = TransactionContext {
-            block: env.block,
-            mq: &self.send_mq,
-            secret_mq,
-            contract_clusters: env.contract_clusters,
-            self_id: self.id(),
-            log_handler: env.log_handler.clone(),
-        };
-
         phala_mq::select! {
             next_cmd = self.cmd_rcv_mq => match next_cmd {
+                Ok((_, cmd, MessageOrigin::Worker(_))) => {
+                    info!("Contract {:?} handling sidevm request", self.id());
+                    self.handle_sidevm_request(cmd.0, env)
+                }
                 Ok((_, cmd, origin)) => {
                     info!("Contract {:?} handling command", self.id());
+                    let secret_mq = SecretMessageChannel::new(&self.ecdh_key, &self.send_mq);
+                    let mut context = TransactionContext {
+                        block: env.block,
+                        mq: &self.send_mq,
+                        secret_mq,
+                        contract_clusters: env.contract_clusters,
+                        self_id: self.id(),
+                        log_handler: env.log_handler.clone(),
+                    };
                     self.contract.handle_command(origin, cmd.0, &mut context)
                 }
                 Err(_e) => {
@@ -200,7 +303,141 @@ impl FatContract {
         }
     }
 
+    fn handle_sidevm_request(&mut self, data: Vec<u8>, env: &mut ExecuteEnv) -> TransactionResult {
+        let request = SidevmRequest::decode(&mut &data[..]).or(Err(TransactionError::BadInput))?;
+        if request.mac != SidevmRequest::mac(&self.ecdh_key, &request.dedup_key, &request.action) {
+            return Err(TransactionError::BadOrigin);
+        }
+        let block_number = env.block.block_number;
+        self.sidevm_request_keys
+            .retain(|_, expire_at| *expire_at > block_number);
+        if self
+            .sidevm_request_keys
+            .insert(request.dedup_key, block_number + SIDEVM_REQUEST_KEY_TTL)
+            .is_some()
+        {
+            // Already handled the copy from another worker
+            return Ok(Default::default());
+        }
+        match request.action {
+            SidevmAction::Command(message) => {
+                let cmd = super::pink::Command::InkMessage {
+                    nonce: Default::default(),
+                    message,
+                };
+                let secret_mq = SecretMessageChannel::new(&self.ecdh_key, &self.send_mq);
+                let mut context = TransactionContext {
+                    block: env.block,
+                    mq: &self.send_mq,
+                    secret_mq,
+                    contract_clusters: env.contract_clusters,
+                    self_id: self.id(),
+                    log_handler: env.log_handler.clone(),
+                };
+                self.contract.handle_command(
+                    MessageOrigin::AccountId(self.contract_id),
+                    cmd.encode(),
+                    &mut context,
+                )
+            }
+            SidevmAction::Message { topic, payload } => {
+                if let Err(err) = self.push_message(payload, topic) {
+                    error!("Dropped message from sidevm {:?}: {}", self.id(), err);
+                }
+                Ok(Default::default())
+            }
+        }
+    }
+
+    /// Take the requests from the sidevm instance waiting to be relayed, with their dedup keys.
+    pub(crate) fn take_sidevm_requests(&mut self) -> Vec<(Vec<u8>, SidevmAction)> {
+        let rx = match self
+            .sidevm_info
+            .as_mut()
+            .and_then(|info| info.requests.as_mut())
+        {
+            Some(rx) => rx,
+            None => return vec![],
+        };
+        let mut requests = vec![];
+        while let Ok(request) = rx.try_recv() {
+            requests.push(request);
+        }
+        requests
+    }
+
+    /// Encode a sidevm request to be relayed to the contract through the chain.
+    pub(crate) fn seal_sidevm_request(&self, dedup_key: &[u8], action: SidevmAction) -> Vec<u8> {
+        let dedup_key = sp_core::blake2_256(dedup_key).into();
+        let mac = SidevmRequest::mac(&self.ecdh_key, &dedup_key, &action);
+        SidevmRequest {
+            dedup_key,
+            action,
+            mac,
+        }
+        .encode()
+    }
+
+    pub(crate) fn ecdh_public_key(&self) -> EcdhPublicKey {
+        self.ecdh_key.public()
+    }
+
+    pub(crate) fn set_hook(
+        &mut self,
+        hook: HookPoint,
+        selector: u32,
+        gas_limit: u64,
+    ) -> Result<()> {
+        let new_hook = Some(Hook {
+            selector,
+            gas_limit,
+        });
+        match hook {
+            HookPoint::OnBlockEnd => self.hooks.on_block_end = new_hook,
+            HookPoint::OnBlockStart => self.hooks.on_block_start = new_hook,
+            HookPoint::OnTimer { interval_blocks } => {
+                if interval_blocks == 0 {
+                    bail!("The interval of the timer can not be 0");
+                }
+                self.hooks.on_timer = new_hook.map(|hook| (interval_blocks, hook));
+            }
+            HookPoint::OnClusterKeyRotated => self.hooks.on_cluster_key_rotated = new_hook,
+            HookPoint::OnSidevmExit => self.hooks.on_sidevm_exit = new_hook,
+        }
+        Ok(())
+    }
+
+    fn call_hook(
+        &mut self,
+        hook: Option<Hook>,
+        rollback: bool,
+        env: &mut ExecuteEnv,
+    ) -> TransactionResult {
+        let hook = match hook {
+            Some(hook) => hook,
+            None => return Ok(Default::default()),
+        };
+        let secret_mq = SecretMessageChannel::new(&self.ecdh_key, &self.send_mq);
+        let mut context = TransactionContext {
+            block: env.block,
+            mq: &self.send_mq,
+            secret_mq,
+            contract_clusters: env.contract_clusters,
+            self_id: self.id(),
+            log_handler: env.log_handler.clone(),
+        };
+        self.contract
+            .call_hook(hook.selector, hook.gas_limit, rollback, &mut context)
+    }
+
+    pub(crate) fn on_block_start(&mut self, env: &mut ExecuteEnv) -> TransactionResult {
+        self.call_hook(self.hooks.on_block_start, false, env)
+    }
+
     pub(crate) fn on_block_end(&mut self, env: &mut ExecuteEnv) -> TransactionResult {
+        if self.hooks.on_block_end.is_some() {
+            return self.call_hook(self.hooks.on_block_end, false, env);
+        }
         let secret_mq = SecretMessageChannel::new(&self.ecdh_key, &self.send_mq);
         let mut context = TransactionContext {
             block: env.block,
@@ -213,13 +450,55 @@ impl FatContract {
         self.contract.on_block_end(&mut context)
     }
 
-    pub(crate) fn set_on_block_end_selector(&mut self, selector: u32) {
-        let AnyContract::Pink(pink) = &mut self.contract;
-        pink.set_on_block_end_selector(selector)
+    pub(crate) fn on_timer(&mut self, env: &mut ExecuteEnv) -> TransactionResult {
+        let block_number = env.block.block_number;
+        let hook = self
+            .hooks
+            .on_timer
+            .filter(|(interval, _)| block_number % interval == 0)
+            .map(|(_, hook)| hook);
+        self.call_hook(hook, false, env)
+    }
+
+    pub(crate) fn on_cluster_key_rotated(&mut self, env: &mut ExecuteEnv) -> TransactionResult {
+        self.call_hook(self.hooks.on_cluster_key_rotated, false, env)
     }
 
-    pub(crate) fn push_message(&self, payload: Vec<u8>, topic: Vec<u8>) {
-        self.send_mq.push_data(payload, topic)
+    /// Fire the OnSidevmExit hook if the sidevm instance has exited since the last call.
+    ///
+    /// The hook runs in query mode because the instance exits at different moments on
+    /// different workers.
+    pub(crate) fn on_sidevm_exit(&mut self, env: &mut ExecuteEnv) -> TransactionResult {
+        if !self.take_sidevm_exit() {
+            return Ok(Default::default());
+        }
+        self.call_hook(self.hooks.on_sidevm_exit, true, env)
+    }
+
+    /// Returns true once for each run of the sidevm instance that has exited.
+    fn take_sidevm_exit(&mut self) -> bool {
+        let info = match &mut self.sidevm_info {
+            Some(info) => info,
+            None => return false,
+        };
+        if info.exit_reported {
+            return false;
+        }
+        let exited = match &*info.handle.lock().unwrap() {
+            SidevmHandle::Running(_) => false,
+            SidevmHandle::Stopped(ExitReason::Restore | ExitReason::WaitingForCode) => false,
+            SidevmHandle::Stopped(_) => true,
+        };
+        info.exit_reported = exited;
+        exited
+    }
+
+    pub(crate) fn push_message(
+        &self,
+        payload: Vec<u8>,
+        topic: Vec<u8>,
+    ) -> Result<(), phala_mq::QuotaExceeded> {
+        self.send_mq.try_push_data(payload, topic)
     }
 
     pub(crate) fn push_osp_message(
@@ -227,11 +506,11 @@ impl FatContract {
         payload: Vec<u8>,
         topic: Vec<u8>,
         remote_pubkey: Option<&EcdhPublicKey>,
-    ) {
+    ) -> Result<(), phala_mq::QuotaExceeded> {
         let secret_mq = SecretMessageChannel::new(&self.ecdh_key, &self.send_mq);
         secret_mq
             .bind_remote_key(remote_pubkey)
-            .push_data(payload, topic)
+            .try_push_data(payload, topic)
     }
 
     pub(crate) fn start_sidevm(
@@ -271,12 +550,22 @@ impl FatContract {
             }
         };
 
-        let handle = if code.is_empty() {
-            Arc::new(Mutex::new(SidevmHandle::Stopped(
+        // A new program never inherits the snapshot of the previous one.
+        let snapshot = sidevm::SnapshotSlot::default();
+        let (handle, requests) = if code.is_empty() {
+            let handle = Arc::new(Mutex::new(SidevmHandle::Stopped(
                 ExitReason::WaitingForCode,
-            )))
+            )));
+            (handle, None)
         } else {
-            do_start_sidevm(spawner, &code, self.contract_id.0, self.weight)?
+            let (handle, requests) = do_start_sidevm(
+                spawner,
+                &code,
+                self.contract_id.0,
+                self.weight,
+                snapshot.clone(),
+            )?;
+            (handle, Some(requests))
         };
 
         let start_time = chrono::Utc::now().to_rfc3339();
@@ -286,6 +575,9 @@ impl FatContract {
             start_time,
             handle,
             auto_restart: true,
+            exit_reported: false,
+            snapshot,
+            requests,
         });
         Ok(())
     }
@@ -296,7 +588,7 @@ impl FatContract {
     ) -> Result<()> {
         if let Some(sidevm_info) = &mut self.sidevm_info {
             let guard = sidevm_info.handle.lock().unwrap();
-            let handle = if let SidevmHandle::Stopped(reason) = &*guard {
+            let (handle, requests) = if let SidevmHandle::Stopped(reason) = &*guard {
                 let need_restart = match reason {
                     ExitReason::Exited(_) => false,
                     ExitReason::Stopped => false,
@@ -314,12 +606,22 @@ impl FatContract {
                     return Ok(());
                 }
                 sidevm_info.start_time = chrono::Utc::now().to_rfc3339();
-                do_start_sidevm(spawner, &sidevm_info.code, self.contract_id.0, self.weight)?
+                // The new run restores its state from the snapshot saved by the previous one,
+                // which is loaded from the checkpoint in the case of ExitReason::Restore.
+                do_start_sidevm(
+                    spawner,
+                    &sidevm_info.code,
+                    self.contract_id.0,
+                    self.weight,
+                    sidevm_info.snapshot.clone(),
+                )?
             } else {
                 return Ok(());
             };
             drop(guard);
             sidevm_info.handle = handle;
+            sidevm_info.requests = Some(requests);
+            sidevm_info.exit_reported = false;
         }
         Ok(())
     }
@@ -379,6 +681,19 @@ impl FatContract {
                 }
             }
         }
+        let id = self.contract_id.as_bytes();
+        ::pink::local_cache::GLOBAL_CACHE
+            .write()
+            .unwrap()
+            .remove_storage(id);
+        ::pink::local_store::GLOBAL_STORE
+            .write()
+            .unwrap()
+            .remove_storage(id);
+    }
+
+    pub(crate) fn set_code_hash(&mut self, code_hash: H256) {
+        self.code_hash = Some(code_hash);
     }
 
     pub fn set_weight(&mut self, weight: u32) {
@@ -427,15 +742,26 @@ fn do_start_sidevm(
     code: &[u8],
     id: VmId,
     weight: u32,
-) -> Result<Arc<Mutex<SidevmHandle>>> {
+    snapshot: sidevm::SnapshotSlot,
+) -> Result<(Arc<Mutex<SidevmHandle>>, Receiver<(Vec<u8>, SidevmAction)>)> {
     let max_memory_pages: u32 = 1024; // 64MB
     let gas_per_breath = 50_000_000_000_u64; // about 20 ms bench
+    let fs_config = sidevm::FsConfig {
+        quota: 64 * 1024 * 1024, // 64MB
+        sealed: SIDEVM_FS.lock().unwrap().clone(),
+    };
+    let (requests_tx, mut requests_rx) = tokio::sync::mpsc::channel(SIDEVM_REQUEST_QUEUE_SIZE);
+    let (relay_tx, relay_rx) = tokio::sync::mpsc::channel(SIDEVM_REQUEST_QUEUE_SIZE);
     let (sender, join_handle) = spawner.start(
         code,
         max_memory_pages,
         id,
         gas_per_breath,
         local_cache_ops(),
+        local_store_ops(),
+        fs_config,
+        snapshot,
+        Some(requests_tx),
         weight,
     )?;
     let handle = Arc::new(Mutex::new(SidevmHandle::Running(sender)));
@@ -449,7 +775,51 @@ fn do_start_sidevm(
         error!(target: "sidevm", "[{vmid}] Sidevm process terminated with reason: {:?}", reason);
         *cloned_handle.lock().unwrap() = SidevmHandle::Stopped(reason);
     });
-    Ok(handle)
+    // Queries are dispatched as they arrive, while the rest wait for the next block to be relayed.
+    spawner.spawn(async move {
+        use tokio::sync::mpsc::error::TrySendError;
+        let vmid = sidevm::ShortId(&id);
+        while let Some(request) = requests_rx.recv().await {
+            let relayed = match request {
+                OutgoingRequest::Query { payload, reply_tx } => {
+                    let queries = SIDEVM_QUERIES.lock().unwrap().clone();
+                    let queries = match queries {
+                        Some(queries) => queries,
+                        None => {
+                            warn!(target: "sidevm", "[{vmid}] No one serves the queries, dropped");
+                            continue;
+                        }
+                    };
+                    let query = SidevmQuery {
+                        contract: id.into(),
+                        payload,
+                        reply_tx,
+                    };
+                    if queries.send(query).await.is_err() {
+                        warn!(target: "sidevm", "[{vmid}] Query dispatcher stopped, dropped");
+                    }
+                    continue;
+                }
+                OutgoingRequest::Command { dedup_key, message } => {
+                    (dedup_key, SidevmAction::Command(message))
+                }
+                OutgoingRequest::Message {
+                    dedup_key,
+                    topic,
+                    payload,
+                } => (dedup_key, SidevmAction::Message { topic, payload }),
+            };
+            match relay_tx.try_send(relayed) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    error!(target: "sidevm", "[{vmid}] Too many requests to relay, dropped");
+                }
+                // The instance has been replaced or the contract destroyed.
+                Err(TrySendError::Closed(_)) => break,
+            }
+        }
+    });
+    Ok((handle, relay_rx))
 }
 
 fn local_cache_ops() -> sidevm::DynCacheOps {
@@ -480,9 +850,428 @@ fn local_cache_ops() -> sidevm::DynCacheOps {
         fn remove(&self, contract: &[u8], key: &[u8]) -> OpResult<Option<Vec<u8>>> {
             Ok(cache::local_cache_remove(contract, key))
         }
+
+        fn scan(
+            &self,
+            contract: &[u8],
+            prefix: &[u8],
+            start_after: Option<&[u8]>,
+            limit: u32,
+        ) -> OpResult<Vec<(Vec<u8>, Vec<u8>)>> {
+            Ok(cache::local_cache_scan(
+                contract,
+                prefix,
+                start_after,
+                limit as _,
+            ))
+        }
+
+        fn compare_and_swap(
+            &self,
+            contract: &[u8],
+            key: &[u8],
+            expected: Option<&[u8]>,
+            value: Option<&[u8]>,
+        ) -> OpResult<bool> {
+            cache::local_cache_compare_and_swap(contract, key, expected, value)
+                .map_err(|_| sidevm::OcallError::ResourceLimited)
+        }
+
+        fn increment(&self, contract: &[u8], key: &[u8], delta: i64) -> OpResult<i64> {
+            cache::local_cache_increment(contract, key, delta).map_err(|err| match err {
+                cache::PinkExtError::QuotaExceeded => sidevm::OcallError::ResourceLimited,
+                _ => sidevm::OcallError::InvalidParameter,
+            })
+        }
+
+        fn stats(&self, contract: &[u8]) -> OpResult<sidevm::LocalCacheStats> {
+            let stats = cache::local_cache_stats(contract);
+            Ok(sidevm::LocalCacheStats {
+                keys: stats.keys,
+                size: stats.size,
+                quota: stats.quota,
+            })
+        }
     }
     &CacheOps
 }
 
+fn local_store_ops() -> sidevm::DynStoreOps {
+    use ::pink::local_store as store;
+    type OpResult<T> = Result<T, sidevm::OcallError>;
+
+    struct StoreOps;
+    impl sidevm::StoreOps for StoreOps {
+        fn get(&self, contract: &[u8], key: &[u8]) -> OpResult<Option<Vec<u8>>> {
+            Ok(store::local_store_get(contract, key))
+        }
+
+        fn set(&self, contract: &[u8], key: &[u8], value: &[u8]) -> OpResult<()> {
+            store::local_store_set(contract, key, value)
+                .map_err(|_| sidevm::OcallError::ResourceLimited)
+        }
+
+        fn remove(&self, contract: &[u8], key: &[u8]) -> OpResult<Option<Vec<u8>>> {
+            Ok(store::local_store_remove(contract, key))
+        }
+    }
+    &StoreOps
+}
+
+/// Where the sidevm filesystems are sealed to. None to keep them in memory.
+static SIDEVM_FS: Mutex<Option<(PathBuf, sidevm::DynSealOps)>> = Mutex::new(None);
+
+/// Seal the filesystems of the sidevm instances started later to `dir` rather than keeping them
+/// in memory.
+pub fn seal_sidevm_fs_to(dir: PathBuf) {
+    use phala_crypto::aead::{self, IV, IV_BYTES};
+    use rand::Rng as _;
+
+    struct SealOps {
+        key: [u8; 32],
+    }
+    impl sidevm::SealOps for SealOps {
+        fn seal(&self, mut data: Vec<u8>) -> Option<Vec<u8>> {
+            let iv: IV = rand::thread_rng().gen();
+            aead::encrypt(&iv, &self.key, &mut data).ok()?;
+            Some([&iv[..], &data[..]].concat())
+        }
+
+        fn unseal(&self, mut data: Vec<u8>) -> Option<Vec<u8>> {
+            if data.len() < IV_BYTES {
+                return None;
+            }
+            let (iv, ciphertext) = data.split_at_mut(IV_BYTES);
+            aead::decrypt(iv, &self.key, ciphertext)
+                .ok()
+                .map(|plain| plain.to_vec())
+        }
+    }
+
+    // The filesystems are dropped together with the instances, so the key never needs to
+    // outlive the process, and whatever left by the previous run is garbage.
+    match std::fs::remove_dir_all(&dir) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            warn!("Failed to clean up the sidevm fs dir {dir:?}: {err}");
+        }
+        _ => (),
+    }
+    let ops: sidevm::DynSealOps = Box::leak(Box::new(SealOps {
+        key: rand::thread_rng().gen(),
+    }));
+    *SIDEVM_FS.lock().unwrap() = Some((dir, ops));
+}
+
 pub use keeper::*;
 mod keeper;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::pink::Pink;
+    use phala_crypto::sr25519::KDF;
+    use phala_mq::{MessageDispatcher, MessageSendQueue};
+    use sp_core::{sr25519, Pair};
+
+    const ON_BLOCK_END: u32 = 0x00000001;
+    const UNKNOWN_SELECTOR: u32 = 0xdeadbeef;
+
+    struct TestContract {
+        contract: FatContract,
+        clusters: ClusterKeeper,
+        /// The egress of the contract.
+        send_mq: MessageSendQueue,
+    }
+
+    impl TestContract {
+        /// Deploy the hooks_test contract, whose `on_block_end` message emits a pink message
+        /// when called by the runtime.
+        fn new() -> Self {
+            let cluster_id = H256::repeat_byte(1);
+            let cluster_key = sr25519::Pair::from_seed(&[1; 32]);
+            let origin = runtime::AccountId::new([1; 32]);
+            let mut clusters = ClusterKeeper::default();
+            let cluster = clusters.get_cluster_or_default_mut(&cluster_id, &cluster_key);
+            let code_hash = cluster
+                .storage
+                .upload_code(
+                    origin.clone(),
+                    include_bytes!("../../../pink/tests/fixtures/hooks_test/hooks_test.wasm")
+                        .to_vec(),
+                )
+                .unwrap();
+            let (pink, _) = Pink::instantiate(
+                cluster_id,
+                &mut cluster.storage,
+                origin,
+                code_hash,
+                vec![0xed, 0x4b, 0x9d, 0x1b],
+                vec![],
+                1,
+                0,
+                None,
+            )
+            .unwrap();
+            let contract_id = pink.id();
+            let contract_key = cluster_key
+                .derive_sr25519_pair(&[b"contract_key", contract_id.as_ref()])
+                .unwrap();
+            let ecdh_key = contract_key.derive_ecdh_key().unwrap();
+            let send_mq = MessageSendQueue::new();
+            let contract_mq =
+                send_mq.channel(MessageOrigin::Contract(contract_id), contract_key.into());
+            let cmd_rcv_mq = SecretReceiver::new_secret(
+                MessageDispatcher::new().subscribe(b"test".to_vec()).into(),
+                ecdh_key.clone(),
+            );
+            let contract = FatContract::new(
+                pink,
+                contract_mq,
+                cmd_rcv_mq,
+                ecdh_key,
+                cluster_id,
+                contract_id,
+                Some(code_hash),
+            );
+            Self {
+                contract,
+                clusters,
+                send_mq,
+            }
+        }
+
+        fn call(
+            &mut self,
+            block_number: BlockNumber,
+            hook: impl FnOnce(&mut FatContract, &mut ExecuteEnv) -> TransactionResult,
+        ) -> TransactionResult {
+            let storage = crate::Storage::default();
+            let send_mq = MessageSendQueue::new();
+            let mut recv_mq = MessageDispatcher::new();
+            let mut block = BlockInfo {
+                block_number,
+                now_ms: 0,
+                storage: &storage,
+                send_mq: &send_mq,
+                recv_mq: &mut recv_mq,
+            };
+            let mut env = ExecuteEnv {
+                block: &mut block,
+                contract_clusters: &mut self.clusters,
+                log_handler: None,
+            };
+            hook(&mut self.contract, &mut env)
+        }
+
+        /// Returns whether the hook called the contract.
+        fn fired(
+            &mut self,
+            block_number: BlockNumber,
+            hook: impl FnOnce(&mut FatContract, &mut ExecuteEnv) -> TransactionResult,
+        ) -> bool {
+            !self
+                .call(block_number, hook)
+                .unwrap()
+                .pink_events
+                .is_empty()
+        }
+
+        /// Handle a sidevm request landed in `block_number` and returns the number of messages
+        /// the contract has emitted so far.
+        fn relay(&mut self, block_number: BlockNumber, request: Vec<u8>) -> usize {
+            self.call(block_number, |contract, env| {
+                contract.handle_sidevm_request(request, env)
+            })
+            .unwrap();
+            self.send_mq.count_messages()
+        }
+
+        fn set_sidevm_handle(&mut self, handle: SidevmHandle) {
+            match &mut self.contract.sidevm_info {
+                Some(info) => *info.handle.lock().unwrap() = handle,
+                None => {
+                    self.contract.sidevm_info = Some(SidevmInfo {
+                        code: vec![],
+                        code_hash: Default::default(),
+                        start_time: Default::default(),
+                        auto_restart: true,
+                        handle: Arc::new(Mutex::new(handle)),
+                        exit_reported: false,
+                        snapshot: Default::default(),
+                        requests: None,
+                    })
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn on_block_start_fires_every_block() {
+        let mut test = TestContract::new();
+        assert!(!test.fired(2, FatContract::on_block_start));
+
+        test.contract
+            .set_hook(HookPoint::OnBlockStart, ON_BLOCK_END, 0)
+            .unwrap();
+        assert!(test.fired(2, FatContract::on_block_start));
+        assert!(test.fired(3, FatContract::on_block_start));
+    }
+
+    #[test]
+    fn on_timer_fires_at_the_interval() {
+        let mut test = TestContract::new();
+        let timer = |interval_blocks| HookPoint::OnTimer { interval_blocks };
+        assert!(test.contract.set_hook(timer(0), ON_BLOCK_END, 0).is_err());
+
+        test.contract.set_hook(timer(3), ON_BLOCK_END, 0).unwrap();
+        let fired: Vec<_> = (2..=9)
+            .filter(|&block| test.fired(block, FatContract::on_timer))
+            .collect();
+        assert_eq!(fired, vec![3, 6, 9]);
+    }
+
+    #[test]
+    fn on_cluster_key_rotated_fires_when_set() {
+        let mut test = TestContract::new();
+        assert!(!test.fired(2, FatContract::on_cluster_key_rotated));
+
+        test.contract
+            .set_hook(HookPoint::OnClusterKeyRotated, ON_BLOCK_END, 0)
+            .unwrap();
+        assert!(test.fired(2, FatContract::on_cluster_key_rotated));
+    }
+
+    #[test]
+    fn on_sidevm_exit_fires_once_per_exit() {
+        let mut test = TestContract::new();
+        // The hook runs in query mode, which drops the pink message of the fixture, so an
+        // unknown selector is used to tell whether the contract has been called.
+        test.contract
+            .set_hook(HookPoint::OnSidevmExit, UNKNOWN_SELECTOR, 0)
+            .unwrap();
+        let fired = |test: &mut TestContract| test.call(2, FatContract::on_sidevm_exit).is_err();
+
+        assert!(!fired(&mut test), "no sidevm deployed");
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        test.set_sidevm_handle(SidevmHandle::Running(tx.clone()));
+        assert!(!fired(&mut test), "still running");
+
+        for reason in [ExitReason::Restore, ExitReason::WaitingForCode] {
+            test.set_sidevm_handle(SidevmHandle::Stopped(reason));
+            assert!(!fired(&mut test), "not exited by itself");
+        }
+
+        test.set_sidevm_handle(SidevmHandle::Stopped(ExitReason::Panicked));
+        assert!(fired(&mut test));
+        assert!(!fired(&mut test), "fired twice for the same exit");
+
+        // Restarted by restart_sidevm_if_needed
+        test.set_sidevm_handle(SidevmHandle::Running(tx));
+        test.contract.sidevm_info.as_mut().unwrap().exit_reported = false;
+        test.set_sidevm_handle(SidevmHandle::Stopped(ExitReason::Exited(0)));
+        assert!(fired(&mut test));
+        assert!(!fired(&mut test), "fired twice for the same exit");
+    }
+
+    #[test]
+    fn sidevm_snapshot_survives_checkpoints_and_restarts() {
+        let (_service, spawner) = sidevm::service::service(1);
+        let mut test = TestContract::new();
+        let code = include_bytes!("../../../../e2e/res/check_system/sideprog.wasm").to_vec();
+        test.contract
+            .start_sidevm(&spawner, SidevmCode::Code(code), false)
+            .unwrap();
+        let info = test.contract.sidevm_info.as_ref().unwrap();
+        *info.snapshot.lock().unwrap() = Some(b"state".to_vec());
+
+        // Dumped into a checkpoint and restored.
+        let dumped = serde_cbor::to_vec(&test.contract.sidevm_info).unwrap();
+        test.contract.sidevm_info = serde_cbor::from_slice(&dumped).unwrap();
+        assert!(matches!(
+            test.contract.sidevm_handle(),
+            Some(SidevmHandle::Stopped(ExitReason::Restore))
+        ));
+        let snapshot = test.contract.sidevm_info.as_ref().unwrap().snapshot.clone();
+        assert_eq!(*snapshot.lock().unwrap(), Some(b"state".to_vec()));
+
+        // The new run is handed the restored snapshot.
+        test.contract.restart_sidevm_if_needed(&spawner).unwrap();
+        assert!(!matches!(
+            test.contract.sidevm_handle(),
+            Some(SidevmHandle::Stopped(ExitReason::Restore))
+        ));
+        let info = test.contract.sidevm_info.as_ref().unwrap();
+        assert!(Arc::ptr_eq(&info.snapshot, &snapshot));
+        assert_eq!(*info.snapshot.lock().unwrap(), Some(b"state".to_vec()));
+    }
+
+    fn sidevm_message(payload: &[u8]) -> SidevmAction {
+        SidevmAction::Message {
+            topic: b"sidevm/test".to_vec(),
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn sidevm_request_with_bad_mac_is_rejected() {
+        let mut test = TestContract::new();
+        let sealed = test
+            .contract
+            .seal_sidevm_request(b"key", sidevm_message(b"hello"));
+        let mut request = SidevmRequest::decode(&mut &sealed[..]).unwrap();
+        // Forged by someone without the contract key
+        request.action = sidevm_message(b"forged");
+        let result = test.call(1, |contract, env| {
+            contract.handle_sidevm_request(request.encode(), env)
+        });
+        assert!(matches!(result, Err(TransactionError::BadOrigin)));
+        assert!(test.contract.sidevm_request_keys.is_empty());
+        assert_eq!(test.send_mq.count_messages(), 0);
+
+        assert_eq!(test.relay(1, sealed), 1);
+    }
+
+    #[test]
+    fn sidevm_request_is_handled_once_per_dedup_key() {
+        let mut test = TestContract::new();
+        let first = test
+            .contract
+            .seal_sidevm_request(b"key", sidevm_message(b"first"));
+        let second = test
+            .contract
+            .seal_sidevm_request(b"key", sidevm_message(b"second"));
+        let other = test
+            .contract
+            .seal_sidevm_request(b"other", sidevm_message(b"first"));
+
+        assert_eq!(test.relay(1, first.clone()), 1);
+        assert_eq!(test.relay(2, first), 1, "a copy of a handled request");
+        assert_eq!(test.relay(2, second), 1, "the dedup key alone decides");
+        assert_eq!(test.relay(3, other), 2);
+    }
+
+    #[test]
+    fn sidevm_request_keys_expire() {
+        let mut test = TestContract::new();
+        let request = test
+            .contract
+            .seal_sidevm_request(b"key", sidevm_message(b"hello"));
+
+        let expiries = |test: &TestContract| -> Vec<BlockNumber> {
+            test.contract
+                .sidevm_request_keys
+                .values()
+                .cloned()
+                .collect()
+        };
+
+        assert_eq!(test.relay(1, request.clone()), 1);
+        let expire_at = 1 + SIDEVM_REQUEST_KEY_TTL;
+        assert_eq!(expiries(&test), vec![expire_at]);
+        assert_eq!(test.relay(expire_at - 1, request.clone()), 1);
+        // Forgotten and handled again
+        assert_eq!(test.relay(expire_at, request), 2);
+        assert_eq!(expiries(&test), vec![expire_at + SIDEVM_REQUEST_KEY_TTL]);
+    }
+}
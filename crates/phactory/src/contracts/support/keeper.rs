@@ -1,15 +1,16 @@
 use pink::runtime::ExecSideEffects;
 use serde::{Deserialize, Serialize};
-use sidevm::service::Spawner;
+use sidevm::service::{CommandSender, Spawner};
 use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 
 use crate::{
-    contracts::{pink::Pink, FatContract, TransactionContext},
+    contracts::{pink::Pink, FatContract, SidevmCode, SidevmHandle, TransactionContext},
     system::{TransactionError, TransactionResult},
     types::{deopaque_query, OpaqueError, OpaqueQuery, OpaqueReply},
 };
 use parity_scale_codec::{Decode, Encode};
-use phala_mq::{ContractId, MessageOrigin};
+use phala_mq::{ContractClusterId, ContractId, MessageOrigin};
 
 use super::QueryContext;
 
@@ -45,6 +46,20 @@ macro_rules! define_any_native_contract {
                 }
             }
 
+            pub(crate) fn call_hook(
+                &mut self,
+                selector: u32,
+                gas_limit: u64,
+                rollback: bool,
+                context: &mut TransactionContext,
+            ) -> TransactionResult {
+                match self {
+                    $(Self::$contract(me) => {
+                        me.call_hook(selector, gas_limit, rollback, context)
+                    })*
+                }
+            }
+
             pub(crate) fn snapshot(&self) -> Self {
                 match self {
                     $($name::$contract(me) => {
@@ -88,11 +103,46 @@ define_any_native_contract!(
     }
 );
 
+/// The running sidevm instances of the contracts, shared with the queries in flight.
+#[derive(Clone, Default)]
+pub struct SidevmHandles(Arc<Mutex<BTreeMap<ContractId, (ContractClusterId, CommandSender)>>>);
+
+impl SidevmHandles {
+    /// Get the sidevm instance of the given contract if it is running in the given cluster.
+    pub fn get(&self, cluster_id: &ContractClusterId, id: &ContractId) -> Option<CommandSender> {
+        match self.0.lock().unwrap().get(id) {
+            Some((cluster, tx)) if cluster == cluster_id => Some(tx.clone()),
+            _ => None,
+        }
+    }
+
+    /// Set or clear the running sidevm instance of a contract.
+    pub(crate) fn set(&self, id: ContractId, running: Option<(ContractClusterId, CommandSender)>) {
+        let mut handles = self.0.lock().unwrap();
+        match running {
+            Some(running) => handles.insert(id, running),
+            None => handles.remove(&id),
+        };
+    }
+
+    fn update(&self, contract: &FatContract) {
+        let running = match contract.sidevm_handle() {
+            Some(SidevmHandle::Running(tx)) => Some((contract.cluster_id(), tx)),
+            _ => None,
+        };
+        self.set(contract.id(), running);
+    }
+}
+
+/// The sidevm handles are not persisted, they are refilled when the instances are restarted
+/// after restoring.
 #[derive(Default, Serialize, Deserialize)]
-pub struct ContractsKeeper(ContractMap);
+#[serde(transparent)]
+pub struct ContractsKeeper(ContractMap, #[serde(skip)] SidevmHandles);
 
 impl ContractsKeeper {
     pub fn insert(&mut self, contract: FatContract) {
+        self.1.update(&contract);
         self.0.insert(contract.id(), contract);
     }
 
@@ -117,13 +167,35 @@ impl ContractsKeeper {
             if let Err(err) = contract.restart_sidevm_if_needed(spawner) {
                 error!("Failed to restart sidevm instance: {:?}", err);
             }
+            self.1.update(contract);
         }
     }
 
+    pub(crate) fn start_sidevm(
+        &mut self,
+        id: &ContractId,
+        spawner: &Spawner,
+        code: SidevmCode,
+        ensure_waiting_code: bool,
+    ) -> anyhow::Result<()> {
+        let contract = self
+            .0
+            .get_mut(id)
+            .ok_or_else(|| anyhow::anyhow!("Contract not found"))?;
+        let result = contract.start_sidevm(spawner, code, ensure_waiting_code);
+        self.1.update(contract);
+        result
+    }
+
     pub fn remove(&mut self, id: &ContractId) -> Option<FatContract> {
+        self.1.set(*id, None);
         self.0.remove(id)
     }
 
+    pub fn sidevm_handles(&self) -> SidevmHandles {
+        self.1.clone()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&ContractId, &FatContract)> {
         self.0.iter()
     }
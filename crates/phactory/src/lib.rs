@@ -44,10 +44,11 @@ use phala_crypto::{
     ecdh::EcdhKey,
     sr25519::{Persistence, Sr25519SecretKey, KDF, SEED_BYTES},
 };
-use phala_mq::{BindTopic, ContractId, MessageDispatcher, MessageSendQueue};
+use phala_mq::{BindTopic, ContractId, FileLogStorage, MessageDispatcher, MessageSendQueue};
 use phala_pallets::pallet_mq;
 use phala_scheduler::RequestScheduler;
 use phala_serde_more as more;
+use phala_trie_storage::{DiskDB, DEFAULT_CACHE_SIZE};
 use std::time::Instant;
 use types::Error;
 
@@ -78,6 +79,14 @@ type RuntimeHasher = <chain::Runtime as frame_system::Config>::Hashing;
 struct RuntimeState {
     send_mq: MessageSendQueue,
 
+    /// Egress of the messages which are not produced by the block processing.
+    ///
+    /// Replaying the blocks after restoring a checkpoint regenerates the messages in `send_mq`,
+    /// but not these, so they are kept in an append-only log under the storage path instead of
+    /// the checkpoint. Senders pushing into it must not push into `send_mq`.
+    #[serde(skip)]
+    durable_mq: MessageSendQueue,
+
     #[serde(skip)]
     recv_mq: MessageDispatcher,
 
@@ -85,6 +94,7 @@ struct RuntimeState {
     storage_synchronizer: Synchronizer<LightValidation<chain::Runtime>>,
 
     // TODO.kevin: use a better serialization approach
+    #[serde(with = "chain_storage_ser")]
     chain_storage: Storage,
 
     #[serde(with = "more::scale_bytes")]
@@ -93,7 +103,7 @@ struct RuntimeState {
 
 impl RuntimeState {
     fn purge_mq(&mut self) {
-        self.send_mq.purge(|sender| {
+        let next_sequence_for = |sender: &phala_mq::SenderId| {
             use pallet_mq::StorageMapTrait as _;
             type OffchainIngress = pallet_mq::OffchainIngress<chain::Runtime>;
 
@@ -103,8 +113,41 @@ impl RuntimeState {
             let sequence: u64 = self.chain_storage.get_decoded(key).unwrap_or(0);
             debug!("purging, sequence = {}", sequence);
             sequence
-        })
+        };
+        for mq in [&self.send_mq, &self.durable_mq] {
+            if let Err(err) = mq.purge(&next_sequence_for) {
+                error!("Failed to purge the egress: {}", err);
+            }
+        }
+    }
+
+    fn count_egress_messages(&self) -> usize {
+        self.send_mq.count_messages() + self.durable_mq.count_messages()
+    }
+}
+
+const EGRESS_LOG_FILE: &str = "egress.log";
+
+/// Open the egress queue which is kept in the log under `storage_path`.
+fn open_durable_mq(storage_path: &str) -> anyhow::Result<MessageSendQueue> {
+    let path = Path::new(storage_path).join(EGRESS_LOG_FILE);
+    let storage = FileLogStorage::open(&path)
+        .with_context(|| format!("Failed to open egress log {:?}", path))?;
+    Ok(MessageSendQueue::with_storage(storage))
+}
+
+const CHAIN_STORAGE_FILE: &str = "chain_storage.log";
+
+/// Create an empty chain storage, kept in the log under `storage_path` if `disk_chain_storage`
+/// is set.
+fn new_chain_storage(args: &InitArgs) -> anyhow::Result<Storage> {
+    if !args.disk_chain_storage {
+        return Ok(Default::default());
     }
+    let path = Path::new(&args.storage_path).join(CHAIN_STORAGE_FILE);
+    let db = DiskDB::open(&path, DEFAULT_CACHE_SIZE)
+        .with_context(|| format!("Failed to open chain storage {:?}", path))?;
+    Ok(Storage::on_disk(db))
 }
 
 const RUNTIME_SEALED_DATA_FILE: &str = "runtime-data.seal";
@@ -115,6 +158,14 @@ fn checkpoint_filename_for(block_number: chain::BlockNumber, basedir: &str) -> S
     format!("{basedir}/{CHECKPOINT_FILE}-{block_number:0>9}")
 }
 
+fn delta_checkpoint_filename_for(
+    block_number: chain::BlockNumber,
+    base: chain::BlockNumber,
+    basedir: &str,
+) -> String {
+    format!("{basedir}/{CHECKPOINT_FILE}-{block_number:0>9}.delta-{base:0>9}")
+}
+
 fn checkpoint_filename_pattern(basedir: &str) -> String {
     format!("{basedir}/{CHECKPOINT_FILE}-*")
 }
@@ -124,22 +175,34 @@ fn glob_checkpoint_files(basedir: &str) -> Result<impl Iterator<Item = PathBuf>,
     Ok(glob::glob(&pattern)?.filter_map(|path| path.ok()))
 }
 
-fn glob_checkpoint_files_sorted(
-    basedir: &str,
-) -> Result<Vec<(chain::BlockNumber, PathBuf)>, PatternError> {
-    fn parse_block(filename: &Path) -> Option<chain::BlockNumber> {
-        let filename = filename.to_str()?;
-        let block_number = filename.rsplit('-').next()?.parse().ok()?;
-        Some(block_number)
+/// A checkpoint file found in the storage directory.
+struct CheckpointFile {
+    block: chain::BlockNumber,
+    /// The block of the full checkpoint which this one is a delta against.
+    base: Option<chain::BlockNumber>,
+    path: PathBuf,
+}
+
+/// Returns the checkpoint files, the latest first.
+fn glob_checkpoint_files_sorted(basedir: &str) -> Result<Vec<CheckpointFile>, PatternError> {
+    type Parsed = (chain::BlockNumber, Option<chain::BlockNumber>);
+    fn parse_filename(filename: &Path) -> Option<Parsed> {
+        let name = filename.file_name()?.to_str()?;
+        let name = name.strip_prefix(CHECKPOINT_FILE)?.strip_prefix('-')?;
+        match name.split_once(".delta-") {
+            Some((block, base)) => Some((block.parse().ok()?, Some(base.parse().ok()?))),
+            None => Some((name.parse().ok()?, None)),
+        }
     }
     let mut files = Vec::new();
 
-    for filename in glob_checkpoint_files(basedir)? {
-        if let Some(block_number) = parse_block(&filename) {
-            files.push((block_number, filename));
+    for path in glob_checkpoint_files(basedir)? {
+        if let Some((block, base)) = parse_filename(&path) {
+            files.push(CheckpointFile { block, base, path });
         }
     }
-    files.sort_by_key(|(block_number, _)| std::cmp::Reverse(*block_number));
+    // A full checkpoint goes before a delta one of the same block.
+    files.sort_by_key(|file| std::cmp::Reverse((file.block, file.base.is_none())));
     Ok(files)
 }
 
@@ -162,23 +225,72 @@ fn remove_outdated_checkpoints(
     current_block: chain::BlockNumber,
 ) -> Result<()> {
     let mut kept = 0_u32;
-    for (block, filename) in glob_checkpoint_files_sorted(basedir)? {
-        if block > current_block {
+    // Full checkpoints which the kept delta checkpoints are based on.
+    let mut required_bases = std::collections::BTreeSet::new();
+    for file in glob_checkpoint_files_sorted(basedir)? {
+        if file.block > current_block {
             continue;
         }
         kept += 1;
-        if kept > max_kept {
-            match std::fs::remove_file(&filename) {
-                Err(e) => error!("Failed to remove {}: {}", filename.display(), e),
-                Ok(_) => {
-                    info!("Removed {}", filename.display());
-                }
+        if kept <= max_kept {
+            required_bases.extend(file.base);
+            continue;
+        }
+        if file.base.is_none() && required_bases.contains(&file.block) {
+            continue;
+        }
+        match std::fs::remove_file(&file.path) {
+            Err(e) => error!("Failed to remove {}: {}", file.path.display(), e),
+            Ok(_) => {
+                info!("Removed {}", file.path.display());
             }
         }
     }
     Ok(())
 }
 
+mod chain_storage_ser {
+    //! Serializes the chain storage of the runtime as a delta against the last full checkpoint
+    //! while taking a delta checkpoint, and applies such a delta to the given base storage
+    //! while restoring one. No delta checkpoint is taken of a chain storage on disk.
+
+    use super::Storage;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::cell::{Cell, RefCell};
+
+    thread_local! {
+        static WRITE_DELTA: Cell<bool> = Cell::new(false);
+        static DELTA_BASE: RefCell<Option<Storage>> = RefCell::new(None);
+    }
+
+    pub(crate) fn writing_delta<R>(f: impl FnOnce() -> R) -> R {
+        WRITE_DELTA.with(|v| v.set(true));
+        let _guard = scopeguard::guard((), |_| WRITE_DELTA.with(|v| v.set(false)));
+        f()
+    }
+
+    pub(crate) fn reading_delta<R>(base: Storage, f: impl FnOnce() -> R) -> R {
+        DELTA_BASE.with(|v| *v.borrow_mut() = Some(base));
+        let _guard = scopeguard::guard((), |_| DELTA_BASE.with(|v| *v.borrow_mut() = None));
+        f()
+    }
+
+    pub fn serialize<S: Serializer>(storage: &Storage, serializer: S) -> Result<S::Ok, S::Error> {
+        if WRITE_DELTA.with(|v| v.get()) {
+            storage.serialize_delta(serializer)
+        } else {
+            storage.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Storage, D::Error> {
+        match DELTA_BASE.with(|v| v.borrow_mut().take()) {
+            Some(base) => base.deserialize_delta(deserializer),
+            None => Storage::deserialize(deserializer),
+        }
+    }
+}
+
 #[derive(Encode, Decode, Clone, Debug)]
 struct PersistentRuntimeData {
     genesis_block_hash: H256,
@@ -245,6 +357,11 @@ pub struct Phactory<Platform> {
     #[serde(skip)]
     #[serde(default = "default_query_scheduler")]
     query_scheduler: RequestScheduler<ContractId>,
+    /// The block of the last full checkpoint, if the chain storage has been journaling since.
+    #[serde(skip)]
+    checkpoint_base: Option<chain::BlockNumber>,
+    #[serde(skip)]
+    deltas_since_full_checkpoint: u32,
 
     #[serde(default)]
     netconfig: Option<NetworkConfig>,
@@ -275,6 +392,8 @@ impl<Platform: pal::Platform> Phactory<Platform> {
             last_checkpoint: Instant::now(),
             last_storage_purge_at: 0,
             query_scheduler: default_query_scheduler(),
+            checkpoint_base: None,
+            deltas_since_full_checkpoint: 0,
             netconfig: Default::default(),
         }
     }
@@ -292,6 +411,12 @@ impl<Platform: pal::Platform> Phactory<Platform> {
             benchmark::resume();
         }
 
+        pink::runtime::set_http_proxy(args.contract_http_proxy.clone());
+
+        if args.seal_sidevm_fs {
+            contracts::seal_sidevm_fs_to(Path::new(&args.storage_path).join("sidevm_fs"));
+        }
+
         self.args = args;
     }
 
@@ -423,18 +548,38 @@ impl<P: pal::Platform> Phactory<P> {
 
 impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform> {
     pub fn take_checkpoint(&mut self, current_block: chain::BlockNumber) -> anyhow::Result<()> {
-        let key = self
+        let system = self
             .system
             .as_ref()
-            .context("Take checkpoint failed, runtime is not ready")?
-            .identity_key
-            .dump_secret_key();
+            .context("Take checkpoint failed, runtime is not ready")?;
+        let key = system.identity_key.dump_secret_key();
+        // The local stores are not part of the checkpoint, so seal them alongside it.
+        if let Err(err) = system.persist_local_stores() {
+            error!("Failed to persist the local stores: {err:?}");
+        }
+        let delta_base = self
+            .checkpoint_base
+            .filter(|_| self.deltas_since_full_checkpoint < self.args.delta_checkpoints);
         info!("Taking checkpoint...");
-        let checkpoint_file = checkpoint_filename_for(current_block, &self.args.storage_path);
+        let checkpoint_file = match delta_base {
+            Some(base) => {
+                delta_checkpoint_filename_for(current_block, base, &self.args.storage_path)
+            }
+            None => checkpoint_filename_for(current_block, &self.args.storage_path),
+        };
         let file = File::create(&checkpoint_file).context("Failed to create checkpoint file")?;
-        self.take_checkpoint_to_writer(&key, file)
-            .context("Take checkpoint to writer failed")?;
+        match delta_base {
+            Some(_) => {
+                chain_storage_ser::writing_delta(|| self.take_checkpoint_to_writer(&key, file))
+            }
+            None => self.take_checkpoint_to_writer(&key, file),
+        }
+        .context("Take checkpoint to writer failed")?;
         info!("Checkpoint saved to {}", checkpoint_file);
+        match delta_base {
+            Some(_) => self.deltas_since_full_checkpoint += 1,
+            None => self.on_full_checkpoint_taken(current_block),
+        }
         self.last_checkpoint = Instant::now();
         remove_outdated_checkpoints(
             &self.args.storage_path,
@@ -444,6 +589,22 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         Ok(())
     }
 
+    fn on_full_checkpoint_taken(&mut self, current_block: chain::BlockNumber) {
+        self.deltas_since_full_checkpoint = 0;
+        self.checkpoint_base = None;
+        let chain_storage = match &mut self.runtime_state {
+            Some(state) => &mut state.chain_storage,
+            None => return,
+        };
+        // A checkpoint of the storage on disk only refers to the log, so no delta is needed.
+        if self.args.delta_checkpoints > 0 && !chain_storage.is_on_disk() {
+            chain_storage.reset_journal();
+            self.checkpoint_base = Some(current_block);
+        } else {
+            chain_storage.stop_journal();
+        }
+    }
+
     pub fn take_checkpoint_to_writer<W: std::io::Write>(
         &mut self,
         key: &[u8],
@@ -460,6 +621,12 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         Ok(())
     }
 
+    /// Restore from the latest checkpoint in `storage_path`.
+    ///
+    /// A delta checkpoint is restored on top of the chain storage of the full checkpoint it is
+    /// based on. Deltas whose base is missing or can not be loaded are skipped, falling back to
+    /// the newest checkpoint which can be restored. The first checkpoint taken after restoring
+    /// is always a full one.
     pub fn restore_from_checkpoint(
         platform: &Platform,
         sealing_path: &str,
@@ -476,10 +643,113 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         if files.is_empty() {
             return Ok(None);
         }
-        let (_block, ckpt_filename) = &files[0];
+        let remove_corrupted = |filename: &Path| -> anyhow::Result<()> {
+            if remove_corrupted_checkpoint {
+                error!("Removing {:?}", filename);
+                std::fs::remove_file(filename)
+                    .context("Failed to remove corrupted checkpoint file")?;
+            }
+            Ok(())
+        };
 
-        let file = match File::open(ckpt_filename) {
+        let mut bad_bases = Vec::new();
+        for ckpt in files.iter() {
+            let base_storage = match ckpt.base {
+                None => None,
+                Some(base) => {
+                    let storage = if bad_bases.contains(&base) {
+                        None
+                    } else {
+                        Self::load_base_chain_storage(
+                            &runtime_data.sk,
+                            &files,
+                            base,
+                            &remove_corrupted,
+                        )?
+                    };
+                    match storage {
+                        Some(storage) => Some(storage),
+                        None => {
+                            error!("Skipping {:?}, its base can not be loaded", ckpt.path);
+                            bad_bases.push(base);
+                            remove_corrupted(&ckpt.path)?;
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            let file = Self::open_checkpoint_file(&ckpt.path, &remove_corrupted)?;
+            info!("Loading checkpoint from file {:?}", ckpt.path);
+            let restored = match base_storage {
+                Some(base) => chain_storage_ser::reading_delta(base, || {
+                    Self::restore_from_checkpoint_reader(&runtime_data.sk, file, n_workers)
+                }),
+                None => Self::restore_from_checkpoint_reader(&runtime_data.sk, file, n_workers),
+            };
+            return match restored {
+                Ok(mut state) => {
+                    info!("Succeeded to load checkpoint file {:?}", ckpt.path);
+                    if let Some(runtime_state) = &mut state.runtime_state {
+                        runtime_state.durable_mq = open_durable_mq(storage_path)?;
+                    }
+                    Ok(Some(state))
+                }
+                Err(_err /*Don't leak it into the log*/) => {
+                    error!("Failed to load checkpoint file {:?}", ckpt.path);
+                    remove_corrupted(&ckpt.path)?;
+                    anyhow::bail!("Failed to load checkpoint file {:?}", ckpt.path);
+                }
+            };
+        }
+        anyhow::bail!("No checkpoint in {:?} can be restored", storage_path);
+    }
+
+    /// Load the chain storage of the full checkpoint at block `base`, or None if it is missing
+    /// or corrupted.
+    fn load_base_chain_storage(
+        key: &[u8],
+        files: &[CheckpointFile],
+        base: chain::BlockNumber,
+        remove_corrupted: &impl Fn(&Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Option<Storage>> {
+        let base_file = files
+            .iter()
+            .find(|file| file.base.is_none() && file.block == base);
+        let base_file = match base_file {
+            Some(file) => file,
+            None => {
+                error!("Base checkpoint at block {} is missing", base);
+                return Ok(None);
+            }
+        };
+        let file = match File::open(&base_file.path) {
             Ok(file) => file,
+            Err(err) => {
+                error!(
+                    "Failed to open checkpoint file {:?}: {:?}",
+                    base_file.path, err
+                );
+                return Ok(None);
+            }
+        };
+        info!("Loading base chain storage from file {:?}", base_file.path);
+        match Self::load_chain_storage_from_checkpoint_reader(key, file) {
+            Ok(storage) => Ok(Some(storage)),
+            Err(_err /*Don't leak it into the log*/) => {
+                error!("Failed to load checkpoint file {:?}", base_file.path);
+                remove_corrupted(&base_file.path)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn open_checkpoint_file(
+        ckpt_filename: &Path,
+        remove_corrupted: impl Fn(&Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<File> {
+        match File::open(ckpt_filename) {
+            Ok(file) => Ok(file),
             Err(err) if matches!(err.kind(), ErrorKind::NotFound) => {
                 // This should never happen unless it was removed just after the glob.
                 anyhow::bail!("Checkpoint file {:?} is not found", ckpt_filename);
@@ -489,34 +759,13 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
                     "Failed to open checkpoint file {:?}: {:?}",
                     ckpt_filename, err
                 );
-                if remove_corrupted_checkpoint {
-                    error!("Removing {:?}", ckpt_filename);
-                    std::fs::remove_file(ckpt_filename)
-                        .context("Failed to remove corrupted checkpoint file")?;
-                }
+                remove_corrupted(ckpt_filename)?;
                 anyhow::bail!(
                     "Failed to open checkpoint file {:?}: {:?}",
                     ckpt_filename,
                     err
                 );
             }
-        };
-
-        info!("Loading checkpoint from file {:?}", ckpt_filename);
-        match Self::restore_from_checkpoint_reader(&runtime_data.sk, file, n_workers) {
-            Ok(state) => {
-                info!("Succeeded to load checkpoint file {:?}", ckpt_filename);
-                Ok(Some(state))
-            }
-            Err(_err /*Don't leak it into the log*/) => {
-                error!("Failed to load checkpoint file {:?}", ckpt_filename);
-                if remove_corrupted_checkpoint {
-                    error!("Removing {:?}", ckpt_filename);
-                    std::fs::remove_file(ckpt_filename)
-                        .context("Failed to remove corrupted checkpoint file")?;
-                }
-                anyhow::bail!("Failed to load checkpoint file {:?}", ckpt_filename);
-            }
         }
     }
 
@@ -532,6 +781,18 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             serde_cbor::de::from_reader(dec_reader).context("Failed to decode state")?;
         Ok(loader.0)
     }
+
+    /// Load only the chain storage from a full checkpoint, skipping the rest of the state.
+    fn load_chain_storage_from_checkpoint_reader<R: std::io::Read>(
+        key: &[u8],
+        reader: R,
+    ) -> anyhow::Result<Storage> {
+        let key128 = derive_key_for_checkpoint(key);
+        let dec_reader = aead::stream::new_aes128gcm_reader(key128, reader);
+        let loader: ChainStorageLoader =
+            serde_cbor::de::from_reader(dec_reader).context("Failed to decode state")?;
+        Ok(loader.0)
+    }
 }
 
 impl<Platform: Serialize + DeserializeOwned> Phactory<Platform> {
@@ -598,6 +859,60 @@ impl<Platform: Serialize + DeserializeOwned> Phactory<Platform> {
     }
 }
 
+/// Loads the chain storage in a checkpoint without restoring the System.
+struct ChainStorageLoader(Storage);
+impl<'de> Deserialize<'de> for ChainStorageLoader {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        /// The part of a serialized `Phactory` holding the chain storage, other fields are
+        /// skipped without being decoded into values.
+        #[derive(Deserialize)]
+        struct PhactoryStorage {
+            runtime_state: Option<RuntimeStateStorage>,
+        }
+
+        #[derive(Deserialize)]
+        struct RuntimeStateStorage {
+            #[serde(with = "chain_storage_ser")]
+            chain_storage: Storage,
+        }
+
+        struct StorageVisitor;
+
+        impl<'de> Visitor<'de> for StorageVisitor {
+            type Value = Storage;
+
+            fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+                formatter.write_str("Phactory")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let version: u32 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::custom("Checkpoint version missing"))?;
+                if version > CHECKPOINT_VERSION {
+                    return Err(de::Error::custom(format!(
+                        "Checkpoint version {} is not supported",
+                        version
+                    )));
+                }
+                let _benchmark: de::IgnoredAny = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::custom("Missing benchmark::State"))?;
+                let factory: PhactoryStorage = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::custom("Missing Phactory"))?;
+                while seq.next_element::<de::IgnoredAny>()?.is_some() {}
+                let runtime_state = factory
+                    .runtime_state
+                    .ok_or_else(|| de::Error::custom("Missing runtime_state"))?;
+                Ok(runtime_state.chain_storage)
+            }
+        }
+
+        deserializer.deserialize_seq(StorageVisitor).map(Self)
+    }
+}
+
 struct PhactoryDumper<'a, Platform>(&'a Phactory<Platform>);
 impl<Platform: Serialize + DeserializeOwned> Serialize for PhactoryDumper<'_, Platform> {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
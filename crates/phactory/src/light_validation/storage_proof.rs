@@ -68,46 +68,80 @@ where
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     use primitives::{Blake2Hasher, H256};
-//     use state_machine::{
-//         backend::{Backend, InMemory},
-//         prove_read,
-//     };
-
-//     #[test]
-//     fn storage_proof_check() {
-//         // construct storage proof
-//         let backend = <InMemory<Blake2Hasher>>::from(vec![
-//             (None, b"key1".to_vec(), Some(b"value1".to_vec())),
-//             (None, b"key2".to_vec(), Some(b"value2".to_vec())),
-//             (None, b"key3".to_vec(), Some(b"value3".to_vec())),
-//             // Value is too big to fit in a branch node
-//             (None, b"key11".to_vec(), Some(vec![0u8; 32])),
-//         ]);
-//         let root = backend.storage_root(std::iter::empty()).0;
-//         let proof: StorageProof = prove_read(backend, &[&b"key1"[..], &b"key2"[..], &b"key22"[..]])
-//             .unwrap()
-//             .iter_nodes()
-//             .collect();
-
-//         // check proof in runtime
-//         let checker = <StorageProofChecker<Blake2Hasher>>::new(root, proof.clone()).unwrap();
-//         assert_eq!(checker.read_value(b"key1"), Ok(Some(b"value1".to_vec())));
-//         assert_eq!(checker.read_value(b"key2"), Ok(Some(b"value2".to_vec())));
-//         assert_eq!(
-//             checker.read_value(b"key11111"),
-//             Err(Error::StorageValueUnavailable)
-//         );
-//         assert_eq!(checker.read_value(b"key22"), Ok(None));
-
-//         // checking proof against invalid commitment fails
-//         assert_eq!(
-//             <StorageProofChecker<Blake2Hasher>>::new(H256::random(), proof).err(),
-//             Some(Error::StorageRootMismatch)
-//         );
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::RuntimeHasher;
+    use phala_trie_storage::TrieStorage;
+    use sp_core::{storage::ChildInfo, H256};
+
+    fn make_storage() -> TrieStorage<RuntimeHasher> {
+        let mut storage = TrieStorage::default();
+        storage.load(
+            [
+                (b"key1".to_vec(), b"value1".to_vec()),
+                (b"key2".to_vec(), b"value2".to_vec()),
+                (b"key3".to_vec(), b"value3".to_vec()),
+                // Value is too big to fit in a branch node
+                (b"key11".to_vec(), vec![0u8; 32]),
+            ]
+            .into_iter(),
+        );
+        let child_changes = vec![(
+            b"child".to_vec(),
+            vec![(b"ckey".to_vec(), Some(b"cvalue".to_vec()))],
+        )];
+        let (root, transaction) = storage.calc_root_if_changes(&Vec::new(), &child_changes);
+        storage.apply_changes(root, transaction);
+        storage
+    }
+
+    #[test]
+    fn storage_proof_check() {
+        let storage = make_storage();
+        let proof = storage
+            .read_proof([&b"key1"[..], &b"key2"[..], &b"key22"[..]])
+            .unwrap();
+
+        let checker =
+            StorageProofChecker::<RuntimeHasher>::new(*storage.root(), proof.clone()).unwrap();
+        assert_eq!(
+            checker.read_value(b"key1").unwrap(),
+            Some(b"value1".to_vec())
+        );
+        assert_eq!(
+            checker.read_value(b"key2").unwrap(),
+            Some(b"value2".to_vec())
+        );
+        assert_eq!(checker.read_value(b"key22").unwrap(), None);
+        assert!(checker.read_value(b"key11111").is_err());
+
+        // Checking the proof against another root fails
+        let checker =
+            StorageProofChecker::<RuntimeHasher>::new(H256::repeat_byte(1), proof).unwrap();
+        assert!(checker.read_value(b"key1").is_err());
+    }
+
+    #[test]
+    fn child_storage_proof_check() {
+        let storage = make_storage();
+        let proof = storage.read_child_proof(b"child", [b"ckey"]).unwrap();
+
+        // The proof leads from the state root to the child trie root, and on to the value
+        let checker =
+            StorageProofChecker::<RuntimeHasher>::new(*storage.root(), proof.clone()).unwrap();
+        let child_key = ChildInfo::new_default(b"child")
+            .prefixed_storage_key()
+            .into_inner();
+        let child_root = checker.read_value(&child_key).unwrap().unwrap();
+        let checker =
+            StorageProofChecker::<RuntimeHasher>::new(H256::from_slice(&child_root), proof)
+                .unwrap();
+        assert_eq!(
+            checker.read_value(b"ckey").unwrap(),
+            Some(b"cvalue".to_vec())
+        );
+        assert_eq!(checker.read_value(b"missing").unwrap(), None);
+    }
+}
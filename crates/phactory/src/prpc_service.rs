@@ -71,7 +71,7 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         let (state_root, pending_messages, counters) = match state.as_ref() {
             Some(state) => {
                 let state_root = hex::encode(state.chain_storage.root());
-                let pending_messages = state.send_mq.count_messages();
+                let pending_messages = state.count_egress_messages();
                 let counters = state.storage_synchronizer.counters();
                 (state_root, pending_messages, counters)
             }
@@ -81,6 +81,9 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         let system_info = self.system.as_ref().map(|s| s.get_info());
         let score = benchmark::score();
         let m_usage = self.platform.memory_usage();
+        let storage_gc = state
+            .map(|state| state.chain_storage.gc_stats())
+            .unwrap_or_default();
 
         // Deprecated fields
         let registered;
@@ -126,6 +129,8 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
                 rust_used: m_usage.rust_used as _,
                 rust_peak_used: m_usage.rust_peak_used as _,
                 total_peak_used: m_usage.total_peak_used as _,
+                storage_gc_reclaimed_nodes: storage_gc.reclaimed_nodes,
+                storage_gc_reclaimed_bytes: storage_gc.reclaimed_bytes,
             }),
             system: system_info,
         }
@@ -245,7 +250,11 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             {
                 self.last_storage_purge_at = block.block_header.number;
                 info!("Purging database");
-                self.runtime_state()?.chain_storage.purge();
+                let stats = self.runtime_state()?.chain_storage.purge();
+                info!(
+                    "Database purged, reclaimed {} nodes, {} bytes",
+                    stats.reclaimed_nodes, stats.reclaimed_bytes
+                );
             }
             last_block = block.block_header.number;
 
@@ -362,20 +371,25 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         };
 
         let send_mq = MessageSendQueue::default();
+        let durable_mq = open_durable_mq(&self.args.storage_path).map_err(from_debug)?;
         let recv_mq = MessageDispatcher::default();
 
         let contracts = contracts::ContractsKeeper::default();
 
         let mut runtime_state = RuntimeState {
             send_mq,
+            durable_mq,
             recv_mq,
             storage_synchronizer,
-            chain_storage: Default::default(),
+            chain_storage: new_chain_storage(&self.args).map_err(from_debug)?,
             genesis_block_hash,
         };
 
         // Initialize other states
-        runtime_state.chain_storage.load(genesis_state.into_iter());
+        runtime_state
+            .chain_storage
+            .try_load(genesis_state.into_iter())
+            .map_err(from_debug)?;
 
         info!(
             "Genesis state loaded: {:?}",
@@ -408,6 +422,7 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             contracts,
             self.args.cores as _,
         );
+        system.restore_local_stores();
 
         let resp = pb::InitRuntimeResponse::new(
             runtime_info,
@@ -490,7 +505,9 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         let messages: Vec<_> = self
             .runtime_state
             .as_ref()
-            .map(|state| state.send_mq.all_messages_grouped().into_iter().collect())
+            .map(|state| {
+                MessageSendQueue::prioritized_messages_of(&[&state.send_mq, &state.durable_mq])
+            })
             .unwrap_or_default();
         Ok(messages)
     }
@@ -588,6 +605,21 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
         })
     }
 
+    /// Query a contract on behalf of its sidevm instance, the contract itself being the origin.
+    fn sidevm_query(
+        &mut self,
+        contract_id: ContractId,
+        payload: Vec<u8>,
+    ) -> RpcResult<impl Future<Output = Result<types::OpaqueReply, types::OpaqueError>>> {
+        let origin = chain::AccountId::from(contract_id.0);
+        let query = contracts::pink::Query::InkMessage(payload).encode();
+        let query_scheduler = self.query_scheduler.clone();
+        let query_future =
+            self.system()?
+                .make_query(&contract_id, Some(&origin), query, query_scheduler)?;
+        Ok(async move { query_future.await.map(|(reply, _, _)| reply) })
+    }
+
     fn handle_inbound_messages(&mut self, block_number: chain::BlockNumber) -> RpcResult<()> {
         let state = self
             .runtime_state
@@ -654,6 +686,7 @@ impl<Platform: pal::Platform + Serialize + DeserializeOwned> Phactory<Platform>
             system.process_messages(&mut block);
         }
         system.did_process_block(&mut block);
+        system.process_sidevm_requests(block.storage, &state.durable_mq);
 
         let n_unhandled = block.recv_mq.clear();
         if n_unhandled > 0 {
@@ -828,6 +861,39 @@ impl<Platform> RpcService<Platform>
 where
     Platform: pal::Platform + Serialize + DeserializeOwned,
 {
+    /// Serve the queries made by the sidevm instances to their contracts as they arrive.
+    ///
+    /// The queries run on the latest state, with their side effects dropped.
+    pub async fn serve_sidevm_queries(&self) {
+        let mut queries = contracts::subscribe_sidevm_queries();
+        while let Some(query) = queries.recv().await {
+            let contract = query.contract;
+            let vmid = sidevm::ShortId(contract.as_bytes());
+            let result = self
+                .lock_phactory()
+                .sidevm_query(query.contract, query.payload);
+            let query_future = match result {
+                Ok(query_future) => query_future,
+                Err(err) => {
+                    error!(target: "sidevm", "[{vmid}] Query to the contract failed: {err:?}");
+                    continue;
+                }
+            };
+            let reply_tx = query.reply_tx;
+            tokio::spawn(async move {
+                let vmid = sidevm::ShortId(contract.as_bytes());
+                match query_future.await {
+                    Ok(reply) => {
+                        let _ = reply_tx.send(reply);
+                    }
+                    Err(err) => {
+                        error!(target: "sidevm", "[{vmid}] Query to the contract failed: {err:?}");
+                    }
+                }
+            });
+        }
+    }
+
     pub fn dispatch_request(
         &self,
         path: String,
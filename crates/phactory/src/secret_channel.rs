@@ -14,7 +14,7 @@ mod sender {
     use crate::contracts::Data as OpaqueData;
     use phactory_api::crypto::{ecdh, EncryptedData};
     use phala_mq::traits::{MessageChannel, MessagePrepareChannel};
-    use phala_mq::Path;
+    use phala_mq::{Path, QuotaExceeded};
 
     pub type KeyPair = ecdh::EcdhKey;
 
@@ -67,6 +67,11 @@ mod sender {
             let payload = self.encrypt_payload(data);
             self.inner.mq.push_message_to(&payload, to)
         }
+
+        fn try_push_data(&self, data: Vec<u8>, to: impl Into<Path>) -> Result<(), QuotaExceeded> {
+            let payload = self.encrypt_payload(data);
+            self.inner.mq.try_push_message_to(&payload, to)
+        }
     }
 
     impl<'a, MsgChan: MessagePrepareChannel> phala_mq::traits::MessagePrepareChannel
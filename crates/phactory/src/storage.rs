@@ -2,7 +2,7 @@ use crate::light_validation::{storage_proof::StorageProof, LightValidation};
 use phactory_api::storage_sync::{BlockValidator, Error as SyncError, Result};
 use std::string::ToString;
 
-pub use storage_ext::{Storage, StorageExt};
+pub use storage_ext::{MapHasher, Storage, StorageExt};
 
 impl BlockValidator for LightValidation<chain::Runtime> {
     fn submit_finalized_headers(
@@ -36,17 +36,97 @@ impl BlockValidator for LightValidation<chain::Runtime> {
 
 mod storage_ext {
     use crate::chain;
-    use crate::light_validation::utils::storage_prefix;
+    use crate::light_validation::utils::{storage_map_prefix_twox_64_concat, storage_prefix};
     use phactory_api::blocks::ParaId;
     use log::error;
     use parity_scale_codec::{Decode, Error};
-    use phala_mq::Message;
-    use phala_trie_storage::TrieStorage;
+    use phala_mq::{ContractClusterId, Message};
+    use phala_trie_storage::{NodeDB, TrieStorage};
+    use phala_types::WorkerPublicKey;
 
-    pub type Storage = TrieStorage<crate::RuntimeHasher>;
+    pub type Storage = TrieStorage<crate::RuntimeHasher, NodeDB<crate::RuntimeHasher>>;
+
+    /// The hasher of a storage map, which decides where the map key is in the storage key.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum MapHasher {
+        Twox64Concat,
+        Blake2_128Concat,
+        Identity,
+    }
+
+    impl MapHasher {
+        fn hash_len(&self) -> usize {
+            match self {
+                MapHasher::Twox64Concat => 8,
+                MapHasher::Blake2_128Concat => 16,
+                MapHasher::Identity => 0,
+            }
+        }
+    }
 
     pub trait StorageExt {
         fn get_raw(&self, key: impl AsRef<[u8]>) -> Option<Vec<u8>>;
+        /// Lazily iterate over the pairs whose keys start with `prefix`, in order, starting
+        /// right after `start_key` if given.
+        ///
+        /// A failure to read the storage is yielded as the last item.
+        fn iter_prefix<'a>(
+            &'a self,
+            prefix: &[u8],
+            start_key: Option<&[u8]>,
+        ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), Error>> + 'a>;
+        /// Lazily iterate over the entries of the storage map `pallet::item`, decoding the
+        /// keys and values.
+        ///
+        /// `start_key` is a raw storage key, usually the last one returned by `map_page`.
+        fn iter_map<'a, K: Decode + 'a, V: Decode + 'a>(
+            &'a self,
+            pallet: &str,
+            item: &str,
+            hasher: MapHasher,
+            start_key: Option<&[u8]>,
+        ) -> Box<dyn Iterator<Item = Result<(K, V), Error>> + 'a> {
+            let prefix = storage_prefix(pallet, item);
+            let key_offset = prefix.len() + hasher.hash_len();
+            Box::new(self.iter_prefix(&prefix, start_key).map(move |pair| {
+                let (key, value) = pair?;
+                let k = match key.get(key_offset..) {
+                    Some(mut encoded) => K::decode(&mut encoded)?,
+                    None => return Err("Storage key too short for the map".into()),
+                };
+                let v = V::decode(&mut &value[..])?;
+                Ok((k, v))
+            }))
+        }
+        /// Read up to `limit` entries of the storage map `pallet::item` after `start_key`.
+        ///
+        /// Returns the entries and the raw key to continue from, if there might be more.
+        #[allow(clippy::type_complexity)]
+        fn map_page<K: Decode, V: Decode>(
+            &self,
+            pallet: &str,
+            item: &str,
+            hasher: MapHasher,
+            start_key: Option<&[u8]>,
+            limit: usize,
+        ) -> Result<(Vec<(K, V)>, Option<Vec<u8>>), Error> {
+            let prefix = storage_prefix(pallet, item);
+            let key_offset = prefix.len() + hasher.hash_len();
+            let mut entries = Vec::new();
+            let mut last_key = None;
+            for pair in self.iter_prefix(&prefix, start_key).take(limit) {
+                let (key, value) = pair?;
+                let mut encoded = key
+                    .get(key_offset..)
+                    .ok_or("Storage key too short for the map")?;
+                entries.push((K::decode(&mut encoded)?, V::decode(&mut &value[..])?));
+                last_key = Some(key);
+            }
+            if entries.len() < limit {
+                last_key = None;
+            }
+            Ok((entries, last_key))
+        }
         fn get_decoded_result<T: Decode>(&self, key: impl AsRef<[u8]>) -> Result<Option<T>, Error> {
             self.get_raw(key)
                 .map(|v| match Decode::decode(&mut &v[..]) {
@@ -87,11 +167,115 @@ mod storage_ext {
         fn pink_system_code(&self) -> Option<(u16, Vec<u8>)> {
             self.get_decoded(storage_prefix("PhalaFatContracts", "PinkSystemCode"))
         }
+        fn cluster_workers(&self, cluster: &ContractClusterId) -> Vec<WorkerPublicKey> {
+            let key =
+                storage_map_prefix_twox_64_concat(b"PhalaFatContracts", b"ClusterWorkers", cluster);
+            self.get_decoded(key).unwrap_or_default()
+        }
     }
 
     impl StorageExt for Storage {
         fn get_raw(&self, key: impl AsRef<[u8]>) -> Option<Vec<u8>> {
             self.get(key)
         }
+        fn iter_prefix<'a>(
+            &'a self,
+            prefix: &[u8],
+            start_key: Option<&[u8]>,
+        ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), Error>> + 'a> {
+            Box::new(self.pairs_iter(prefix, start_key).map(|pair| {
+                pair.map_err(|err| {
+                    error!("Failed to read the chain storage: {:?}", err);
+                    Error::from("Failed to read the chain storage")
+                })
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light_validation::utils::{storage_map_prefix_twox_64_concat, storage_prefix};
+    use parity_scale_codec::Encode;
+
+    fn storage_with(pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Storage {
+        let mut storage = Storage::default();
+        storage.try_load(pairs.into_iter()).unwrap();
+        storage
+    }
+
+    fn map_storage() -> (Storage, Vec<(u32, u64)>) {
+        let entries: Vec<(u32, u64)> = (0..5).map(|i| (i, i as u64 * 100)).collect();
+        let mut pairs: Vec<_> = entries
+            .iter()
+            .map(|(k, v)| {
+                let key = storage_map_prefix_twox_64_concat(b"Test", b"Map", k);
+                (key, v.encode())
+            })
+            .collect();
+        pairs.push((storage_prefix("Test", "Value"), 1u32.encode()));
+        (storage_with(pairs), entries)
+    }
+
+    fn sorted<T: Ord>(mut items: Vec<T>) -> Vec<T> {
+        items.sort();
+        items
+    }
+
+    #[test]
+    fn iter_map_decodes_all_entries() {
+        let (storage, entries) = map_storage();
+        let decoded: Vec<(u32, u64)> = storage
+            .iter_map("Test", "Map", MapHasher::Twox64Concat, None)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(sorted(decoded), entries);
+    }
+
+    #[test]
+    fn map_page_continues_from_last_key() {
+        let (storage, entries) = map_storage();
+        let mut all = vec![];
+        let mut start_key = None;
+        let mut pages = 0;
+        loop {
+            let (page, next): (Vec<(u32, u64)>, _) = storage
+                .map_page(
+                    "Test",
+                    "Map",
+                    MapHasher::Twox64Concat,
+                    start_key.as_deref(),
+                    2,
+                )
+                .unwrap();
+            pages += 1;
+            all.extend(page);
+            match next {
+                Some(key) => start_key = Some(key),
+                None => break,
+            }
+        }
+        assert_eq!(pages, 3);
+        assert_eq!(sorted(all), entries);
+    }
+
+    #[test]
+    fn map_errors_are_reported() {
+        let prefix = storage_prefix("Test", "Map");
+        let mut short_key = prefix.clone();
+        short_key.extend([1, 2]);
+        let storage = storage_with(vec![(short_key, 1u64.encode())]);
+        assert!(storage
+            .map_page::<u32, u64>("Test", "Map", MapHasher::Twox64Concat, None, 10)
+            .is_err());
+
+        let key = storage_map_prefix_twox_64_concat(b"Test", b"Map", &1u32);
+        let storage = storage_with(vec![(key, vec![1])]);
+        let items: Vec<Result<(u32, u64), _>> = storage
+            .iter_map("Test", "Map", MapHasher::Twox64Concat, None)
+            .collect();
+        assert_eq!(items.len(), 1);
+        assert!(items[0].is_err());
     }
 }
@@ -0,0 +1,168 @@
+//! Seals the contract local stores to disk.
+//!
+//! Each store is kept in its own file named by the hex encoded contract id, encrypted with a key
+//! derived from the worker identity key. The stores are written when a checkpoint is taken, so
+//! a restart brings them back as of the latest checkpoint.
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context as _, Result};
+use log::{info, warn};
+use parity_scale_codec::Encode;
+use phala_crypto::aead::{self, IV, IV_BYTES};
+use pink::local_store::GLOBAL_STORE;
+use rand::Rng as _;
+
+/// The directory under the storage path that holds the sealed stores.
+const LOCAL_STORE_DIR: &str = "local_store";
+
+fn local_store_dir(storage_path: &str) -> PathBuf {
+    PathBuf::from(storage_path).join(LOCAL_STORE_DIR)
+}
+
+fn derive_key_for_local_store(identity_key: &[u8]) -> [u8; 32] {
+    sp_core::blake2_256(&(identity_key, b"/local_store").encode())
+}
+
+/// Write the stores changed since the last call to disk.
+///
+/// If a store fails to be written, it and the stores after it stay marked as changed, so the next
+/// call writes them again.
+pub(crate) fn persist(storage_path: &str, identity_key: &[u8]) -> Result<()> {
+    let mut changes = GLOBAL_STORE.write().unwrap().take_changes();
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let dir = local_store_dir(storage_path);
+    let key = derive_key_for_local_store(identity_key);
+    let mut n_written = 0;
+    let mut result = std::fs::create_dir_all(&dir).context("Failed to create the local store dir");
+    if result.is_ok() {
+        for (id, encoded) in changes.iter_mut() {
+            result = write_store(&dir.join(hex::encode(id)), &key, encoded.take());
+            if result.is_err() {
+                break;
+            }
+            n_written += 1;
+        }
+    }
+    if result.is_err() {
+        let unwritten = changes.into_iter().skip(n_written).map(|(id, _)| id);
+        GLOBAL_STORE.write().unwrap().mark_changed(unwritten);
+    }
+    result
+}
+
+/// Seal a store dumped by `take_changes` to `path`, or remove the file if `encoded` is `None`.
+fn write_store(path: &Path, key: &[u8; 32], encoded: Option<Vec<u8>>) -> Result<()> {
+    match encoded {
+        Some(mut data) => {
+            let iv: IV = rand::thread_rng().gen();
+            aead::encrypt(&iv, key, &mut data)
+                .map_err(|err| anyhow!("Failed to seal local store: {err:?}"))?;
+            // Write to a temporary file first so that a crash never leaves a broken store.
+            let tmp_path = path.with_extension("tmp");
+            std::fs::write(&tmp_path, [&iv[..], &data[..]].concat())
+                .context("Failed to write local store")?;
+            std::fs::rename(&tmp_path, path).context("Failed to write local store")?;
+        }
+        None => match std::fs::remove_file(path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                return Err(err).context("Failed to remove local store");
+            }
+            _ => (),
+        },
+    }
+    Ok(())
+}
+
+/// Load the stores sealed by `persist`.
+pub(crate) fn restore(storage_path: &str, identity_key: &[u8]) -> Result<()> {
+    let dir = local_store_dir(storage_path);
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).context("Failed to read the local store dir"),
+    };
+    let key = derive_key_for_local_store(identity_key);
+    let mut store = GLOBAL_STORE.write().unwrap();
+    let mut n_loaded = 0;
+    for entry in entries {
+        let path = entry.context("Failed to read the local store dir")?.path();
+        let id = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => match hex::decode(name) {
+                Ok(id) => id,
+                // Leftover temporary files
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+        let mut data = std::fs::read(&path).context("Failed to read local store")?;
+        if data.len() < IV_BYTES {
+            warn!("Ignored broken local store {path:?}");
+            continue;
+        }
+        let (iv, ciphertext) = data.split_at_mut(IV_BYTES);
+        let plain = match aead::decrypt(iv, &key, ciphertext) {
+            Ok(plain) => plain,
+            Err(_) => {
+                warn!("Ignored local store {path:?} that can not be unsealed");
+                continue;
+            }
+        };
+        store
+            .load(id, plain)
+            .context("Failed to decode local store")?;
+        n_loaded += 1;
+    }
+    info!("Loaded {n_loaded} local stores");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn stored(id: &[u8]) -> Option<Vec<u8>> {
+        GLOBAL_STORE.read().unwrap().get(id, b"key")
+    }
+
+    #[test]
+    fn failed_stores_are_persisted_later() {
+        let id = b"failed_stores_are_persisted_later".to_vec();
+        let identity_key = [1u8; 32];
+        let root =
+            std::env::temp_dir().join(format!("phactory-local-store-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        // A file where the local store dir should be makes every write fail.
+        let storage_path = root.to_str().unwrap();
+        std::fs::write(local_store_dir(storage_path), b"").unwrap();
+
+        GLOBAL_STORE
+            .write()
+            .unwrap()
+            .set(
+                Cow::Borrowed(&id[..]),
+                Cow::Borrowed(b"key"),
+                Cow::Borrowed(b"value"),
+            )
+            .unwrap();
+        assert!(persist(storage_path, &identity_key).is_err());
+
+        std::fs::remove_file(local_store_dir(storage_path)).unwrap();
+        persist(storage_path, &identity_key).unwrap();
+        assert!(local_store_dir(storage_path)
+            .join(hex::encode(&id))
+            .exists());
+
+        GLOBAL_STORE.write().unwrap().remove_storage(&id);
+        assert_eq!(stored(&id), None);
+        restore(storage_path, &identity_key).unwrap();
+        assert_eq!(stored(&id), Some(b"value".to_vec()));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
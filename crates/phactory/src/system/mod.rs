@@ -1,11 +1,14 @@
 pub mod gk;
+mod local_store;
 mod master_key;
 
 use crate::{
     benchmark,
-    contracts::{pink::cluster::Cluster, AnyContract, ContractsKeeper, ExecuteEnv, SidevmCode},
+    contracts::{
+        pink::cluster::Cluster, AnyContract, ContractsKeeper, ExecuteEnv, FatContract, SidevmCode,
+    },
     pink::{cluster::ClusterKeeper, ContractEventCallback, Pink},
-    secret_channel::{ecdh_serde, SecretReceiver},
+    secret_channel::{ecdh_serde, SecretMessageChannel, SecretReceiver},
     types::{BlockInfo, OpaqueError, OpaqueQuery, OpaqueReply},
     StorageExt,
 };
@@ -54,8 +57,9 @@ use serde::{Deserialize, Serialize};
 use sidevm::service::{Command as SidevmCommand, CommandSender, Report, Spawner, SystemMessage};
 use sp_core::{hashing::blake2_256, sr25519, Pair, U256};
 
-use pink::runtime::{HookPoint, PinkEvent};
+use pink::runtime::PinkEvent;
 use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::future::Future;
 
@@ -631,11 +635,13 @@ impl<Platform: pal::Platform> System<Platform> {
         let sidevm_handle = contract.sidevm_handle();
         let weight = contract.weight();
         let contract = contract.snapshot_for_query();
+        let cluster_sidevms = self.contracts.sidevm_handles();
         let mut context = contracts::QueryContext {
             block_number: self.block_number,
             now_ms: self.now_ms,
             storage,
             sidevm_handle,
+            cluster_sidevms,
             log_handler: self.get_system_message_handler(&cluster_id),
             query_scheduler,
             weight,
@@ -689,6 +695,8 @@ impl<Platform: pal::Platform> System<Platform> {
         if let Some(gatekeeper) = &mut self.gatekeeper {
             gatekeeper.will_process_block(block);
         }
+
+        self.call_contract_hooks(block, FatContract::on_block_start);
     }
 
     pub fn process_messages(&mut self, block: &mut BlockInfo) {
@@ -763,11 +771,93 @@ impl<Platform: pal::Platform> System<Platform> {
                 n_contracts: self.contracts.len() as _,
             },
         );
+        self.call_contract_hooks(block, FatContract::on_block_end);
+        self.call_contract_hooks(block, FatContract::on_timer);
+        // Must go before the restarting, otherwise the exits of the restarted instances are missed.
+        self.call_contract_hooks(block, FatContract::on_sidevm_exit);
+        self.contracts.try_restart_sidevms(&self.sidevm_spawner);
+
+        let contract_running = !self.contract_clusters.is_empty();
+        benchmark::set_flag(benchmark::Flags::CONTRACT_RUNNING, contract_running);
+    }
+
+    /// Relay the requests made by the sidevm instances to their contracts since the last block.
+    ///
+    /// They are not regenerated when the blocks are replayed, so they go to the `durable_mq`
+    /// under an origin of their own rather than the worker egress. Only the worker with the
+    /// lowest pubkey in the cluster relays them, the others drop theirs. See
+    /// `contracts::SidevmRequest` for how they are kept deterministic.
+    pub fn process_sidevm_requests(
+        &mut self,
+        storage: &crate::Storage,
+        durable_mq: &MessageSendQueue,
+    ) {
+        let pubkey = self.identity_key.public();
+        let relay_key = self
+            .identity_key
+            .derive_sr25519_pair(&[b"sidevm_relay"])
+            .expect("should not fail with valid info");
+        let relay_mq =
+            durable_mq.channel(MessageOrigin::Worker(relay_key.public()), relay_key.into());
+        let mut elected = BTreeMap::new();
         let contract_ids: Vec<_> = self.contracts.keys().cloned().collect();
-        'outer: for key in contract_ids {
+        for id in contract_ids {
+            let contract = match self.contracts.get_mut(&id) {
+                Some(contract) => contract,
+                None => continue,
+            };
+            let requests = contract.take_sidevm_requests();
+            if requests.is_empty() {
+                continue;
+            }
+            let cluster_id = contract.cluster_id();
+            let is_relayer = *elected.entry(cluster_id).or_insert_with(|| {
+                storage.cluster_workers(&cluster_id).into_iter().min() == Some(pubkey)
+            });
+            if !is_relayer {
+                continue;
+            }
+            let vmid = sidevm::ShortId(id.as_bytes());
+            let contract_pubkey = contract.ecdh_public_key();
+            for (dedup_key, action) in requests {
+                let sealed = contract.seal_sidevm_request(&dedup_key, action);
+                let result = SecretMessageChannel::new(&self.ecdh_key, &relay_mq)
+                    .bind_remote_key(Some(&contract_pubkey))
+                    .try_push_data(sealed, contract::command_topic(id));
+                if let Err(err) = result {
+                    error!(target: "sidevm", "[{vmid}] Dropped request to the contract: {err}");
+                }
+            }
+        }
+    }
+
+    /// Fire the OnClusterKeyRotated hooks of the contracts in given cluster.
+    fn on_cluster_key_rotated(
+        &mut self,
+        block: &mut BlockInfo,
+        cluster_id: phala_mq::ContractClusterId,
+    ) {
+        self.call_contract_hooks(block, |contract, env| {
+            if contract.cluster_id() != cluster_id {
+                return Ok(Default::default());
+            }
+            contract.on_cluster_key_rotated(env)
+        });
+    }
+
+    /// Call a hook of each contract and apply the side effects.
+    ///
+    /// The hooks may instantiate new contracts, so the contracts are looked up by id one by one.
+    fn call_contract_hooks(
+        &mut self,
+        block: &mut BlockInfo,
+        hook: impl Fn(&mut FatContract, &mut ExecuteEnv) -> TransactionResult,
+    ) {
+        let contract_ids: Vec<_> = self.contracts.keys().cloned().collect();
+        for key in contract_ids {
             let log_handler = self.get_system_message_handler_for_contract_id(&key);
             let contract = match self.contracts.get_mut(&key) {
-                None => continue 'outer,
+                None => continue,
                 Some(v) => v,
             };
             let mut env = ExecuteEnv {
@@ -775,7 +865,7 @@ impl<Platform: pal::Platform> System<Platform> {
                 contract_clusters: &mut self.contract_clusters,
                 log_handler: log_handler.clone(),
             };
-            let result = contract.on_block_end(&mut env);
+            let result = hook(contract, &mut env);
             let cluster_id = contract.cluster_id();
             handle_contract_command_result(
                 result,
@@ -788,10 +878,6 @@ impl<Platform: pal::Platform> System<Platform> {
                 log_handler,
             );
         }
-        self.contracts.try_restart_sidevms(&self.sidevm_spawner);
-
-        let contract_running = !self.contract_clusters.is_empty();
-        benchmark::set_flag(benchmark::Flags::CONTRACT_RUNNING, contract_running);
     }
 
     fn process_system_event(&mut self, block: &BlockInfo, event: &SystemEvent) {
@@ -1243,9 +1329,10 @@ impl<Platform: pal::Platform> System<Platform> {
                                 contract_info.salt,
                                 block.block_number,
                                 block.now_ms,
-                                ContractEventCallback::from_log_sender(
+                                ContractEventCallback::for_command(
                                     &log_handler,
                                     block.block_number,
+                                    block.send_mq,
                                 ),
                             )
                             .with_context(|| format!("Contract deployer: {deployer:?}"))?;
@@ -1267,6 +1354,46 @@ impl<Platform: pal::Platform> System<Platform> {
                     }
                 }
             }
+            ContractOperation::UpgradeCode {
+                contract_id,
+                code_hash,
+            } => {
+                let contract = match self.contracts.get_mut(&contract_id) {
+                    // The contract is not deployed on this worker, just ignore it.
+                    None => return Ok(()),
+                    Some(contract) => contract,
+                };
+                let cluster = self
+                    .contract_clusters
+                    .get_cluster_mut(&contract.cluster_id())
+                    .context("Cluster not deployed")?;
+                cluster
+                    .upgrade_contract(&contract_id, code_hash)
+                    .map_err(|err| anyhow!("Failed to upgrade contract: {:?}", err))?;
+                contract.set_code_hash(code_hash);
+                info!(
+                    "Upgraded contract {}, code_hash={:?}",
+                    hex_fmt::HexFmt(&contract_id),
+                    code_hash
+                );
+            }
+            ContractOperation::Destroy { contract_id } => {
+                let contract = match self.contracts.remove(&contract_id) {
+                    // The contract is not deployed on this worker, just ignore it.
+                    None => return Ok(()),
+                    Some(contract) => contract,
+                };
+                let cluster_id = contract.cluster_id();
+                contract.destroy(&self.sidevm_spawner);
+                let cluster = self
+                    .contract_clusters
+                    .get_cluster_mut(&cluster_id)
+                    .context("Cluster not deployed")?;
+                cluster
+                    .remove_contract(&contract_id)
+                    .map_err(|err| anyhow!("Failed to destroy contract: {:?}", err))?;
+                info!("Destroyed contract {}", hex_fmt::HexFmt(&contract_id));
+            }
         }
         Ok(())
     }
@@ -1454,10 +1581,16 @@ impl<Platform: pal::Platform> System<Platform> {
             info!("Worker: successfully decrypt received cluster key");
 
             // TODO(shelven): forget cluster key after expiration time
-            let cluster = self.contract_clusters.get_cluster_mut(&event.cluster);
-            if cluster.is_some() {
-                error!("Cluster {:?} is already deployed", &event.cluster);
-                return Err(TransactionError::DuplicatedClusterDeploy.into());
+            if let Some(cluster) = self.contract_clusters.get_cluster_mut(&event.cluster) {
+                if cluster.key().public() == cluster_key.public() {
+                    error!("Cluster {:?} is already deployed", &event.cluster);
+                    return Err(TransactionError::DuplicatedClusterDeploy.into());
+                }
+                // A new key for a deployed cluster rotates its key.
+                info!("Worker: rotating the key of cluster {:?}", event.cluster);
+                cluster.set_key(cluster_key);
+                self.on_cluster_key_rotated(block, event.cluster);
+                return Ok(());
             }
             let system_code = block
                 .storage
@@ -1581,11 +1714,26 @@ impl<Platform: pal::Platform> System<Platform> {
 
 impl<P: pal::Platform> System<P> {
     pub fn on_restored(&mut self) -> Result<()> {
+        self.restore_local_stores();
         self.contracts.try_restart_sidevms(&self.sidevm_spawner);
         self.check_retirement();
         Ok(())
     }
 
+    /// Load the contract local stores sealed in the storage path.
+    pub(crate) fn restore_local_stores(&self) {
+        let key = self.identity_key.dump_secret_key();
+        if let Err(err) = local_store::restore(&self.storage_path, &key) {
+            error!("Failed to restore the local stores: {err:?}");
+        }
+    }
+
+    /// Seal the contract local stores changed since last time to the storage path.
+    pub(crate) fn persist_local_stores(&self) -> Result<()> {
+        let key = self.identity_key.dump_secret_key();
+        local_store::persist(&self.storage_path, &key)
+    }
+
     pub(crate) fn apply_side_effects(
         &mut self,
         cluster_id: phala_mq::ContractClusterId,
@@ -1615,11 +1763,12 @@ impl<P: pal::Platform> System<P> {
         contract_id: ContractId,
         code: Vec<u8>,
     ) -> Result<()> {
-        let contract = self
-            .contracts
-            .get_mut(&contract_id)
-            .ok_or_else(|| anyhow!("Contract not found"))?;
-        contract.start_sidevm(&self.sidevm_spawner, SidevmCode::Code(code), true)
+        self.contracts.start_sidevm(
+            &contract_id,
+            &self.sidevm_spawner,
+            SidevmCode::Code(code),
+            true,
+        )
     }
 }
 
@@ -1775,15 +1924,19 @@ pub(crate) fn apply_pink_events(
         match event {
             PinkEvent::Message(message) => {
                 let contract = get_contract!(&origin);
-                contract.push_message(message.payload, message.topic);
+                if let Err(err) = contract.push_message(message.payload, message.topic) {
+                    error!("Dropped message from contract {:?}: {}", origin, err);
+                }
             }
             PinkEvent::OspMessage(message) => {
                 let contract = get_contract!(&origin);
-                contract.push_osp_message(
+                if let Err(err) = contract.push_osp_message(
                     message.message.payload,
                     message.message.topic,
                     message.remote_pubkey.as_ref(),
-                );
+                ) {
+                    error!("Dropped osp message from contract {:?}: {}", origin, err);
+                }
             }
             PinkEvent::SetHook {
                 hook,
@@ -1792,10 +1945,20 @@ pub(crate) fn apply_pink_events(
             } => {
                 ensure_system!();
                 let contract = get_contract!(&target_contract);
-                match hook {
-                    HookPoint::OnBlockEnd => {
-                        contract.set_on_block_end_selector(selector);
-                    }
+                if let Err(err) = contract.set_hook(hook, selector, 0) {
+                    error!("Failed to set hook {hook:?} for {target_contract:?}: {err:?}");
+                }
+            }
+            PinkEvent::SetHookWithGasLimit {
+                hook,
+                contract: target_contract,
+                selector,
+                gas_limit,
+            } => {
+                ensure_system!();
+                let contract = get_contract!(&target_contract);
+                if let Err(err) = contract.set_hook(hook, selector, gas_limit) {
+                    error!("Failed to set hook {hook:?} for {target_contract:?}: {err:?}");
                 }
             }
             PinkEvent::DeploySidevmTo {
@@ -1804,13 +1967,13 @@ pub(crate) fn apply_pink_events(
             } => {
                 ensure_system!();
                 let vmid = sidevm::ShortId(target_contract.as_ref());
-                let target_contract = get_contract!(&target_contract);
+                let target_contract: ContractId = target_contract.convert_to();
                 let code_hash = code_hash.into();
                 let code = match cluster.get_resource(ResourceType::SidevmCode, &code_hash) {
                     Some(code) => SidevmCode::Code(code),
                     None => SidevmCode::Hash(code_hash),
                 };
-                if let Err(err) = target_contract.start_sidevm(spawner, code, false) {
+                if let Err(err) = contracts.start_sidevm(&target_contract, spawner, code, false) {
                     error!(target: "sidevm", "[{vmid}] Start sidevm failed: {:?}", err);
                 }
             }
@@ -1853,6 +2016,14 @@ pub(crate) fn apply_pink_events(
                 let contract = get_contract!(&contract);
                 contract.set_weight(weight);
             }
+            PinkEvent::SetHttpEgressConfig(config) => {
+                ensure_system!();
+                info!(
+                    "Set http egress config for {:?} to {:?}",
+                    cluster_id, config
+                );
+                cluster.set_http_egress_config(config);
+            }
         }
     }
 }
@@ -22,7 +22,7 @@ mod send_mq {
 }
 
 mod dispatcher {
-    use crate::{Message, MessageDispatcher, Path, dispatcher::Receiver};
+    use crate::{Message, MessageDispatcher, dispatcher::{Receiver, Subscription}};
 
     environmental::environmental!(global_dispatcher: MessageDispatcher);
 
@@ -37,8 +37,8 @@ mod dispatcher {
         global_dispatcher::with(f)
     }
 
-    pub fn subscribe_default(path: impl Into<Path>) -> Receiver<Message> {
-        with(move |dispatcher| dispatcher.subscribe(path))
+    pub fn subscribe_default(subscription: Subscription) -> Receiver<Message> {
+        with(move |dispatcher| dispatcher.subscribe_with(subscription))
             .expect("subscribe_default called without using a global dispatcher")
     }
 }
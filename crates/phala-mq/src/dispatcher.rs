@@ -1,11 +1,12 @@
 use core::marker::PhantomData;
 
-use alloc::{collections::BTreeMap, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
 use serde::{Deserialize, Serialize};
 
 use crate::simple_mpsc::{channel, ReceiveError, Receiver as RawReceiver, Sender, Seq};
-use crate::types::{Message, Path};
-use crate::{BindTopic, MessageOrigin};
+use crate::signer::MessageVerifier;
+use crate::types::{Message, Path, SignedMessage};
+use crate::{BindTopic, MessageOrigin, SenderId};
 use derive_more::Display;
 use parity_scale_codec::{Decode, Error as CodecError};
 
@@ -15,16 +16,109 @@ impl Seq for (u64, Message) {
     }
 }
 
+/// Describes which topics a subscriber wants to receive messages from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Subscription {
+    /// Messages sent to exactly the given path.
+    Exact(Path),
+    /// Messages sent to any path starting with the given prefix.
+    Prefix(Path),
+    /// Messages sent to any path matching the given glob pattern, where `*` matches any
+    /// (possibly empty) sequence of bytes.
+    Pattern(Path),
+}
+
+impl Subscription {
+    // Topics can never start with a reserved byte (see `Topic`), so we use them to tag the
+    // non-exact subscriptions when persisting a receiver.
+    const PREFIX_INDICATOR: u8 = b'~';
+    const PATTERN_INDICATOR: u8 = b'*';
+
+    /// Returns if a message sent to `path` should be delivered to this subscription.
+    pub fn matches(&self, path: &[u8]) -> bool {
+        match self {
+            Self::Exact(topic) => topic[..] == *path,
+            Self::Prefix(prefix) => path.starts_with(prefix),
+            Self::Pattern(pattern) => glob_match(pattern, path),
+        }
+    }
+
+    fn to_key(&self) -> Vec<u8> {
+        let (indicator, path) = match self {
+            Self::Exact(path) => return path.clone(),
+            Self::Prefix(path) => (Self::PREFIX_INDICATOR, path),
+            Self::Pattern(path) => (Self::PATTERN_INDICATOR, path),
+        };
+        let mut key = Vec::with_capacity(path.len() + 1);
+        key.push(indicator);
+        key.extend_from_slice(path);
+        key
+    }
+
+    fn from_key(mut key: Vec<u8>) -> Self {
+        match key.first() {
+            Some(&Self::PREFIX_INDICATOR) => {
+                key.remove(0);
+                Self::Prefix(key)
+            }
+            Some(&Self::PATTERN_INDICATOR) => {
+                key.remove(0);
+                Self::Pattern(key)
+            }
+            _ => Self::Exact(key),
+        }
+    }
+}
+
+/// Matches `path` against a glob `pattern` in which `*` matches any sequence of bytes.
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Position of the last `*` seen in the pattern and the path position it is matched up to.
+    let mut backtrack = None;
+    while s < path.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, s));
+                p += 1;
+            }
+            Some(&c) if c == path[s] => {
+                p += 1;
+                s += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    s = matched + 1;
+                    backtrack = Some((star, s));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+type MessageSender = Sender<(u64, Message)>;
+
 #[derive(Default)]
 pub struct MessageDispatcher {
-    subscribers: BTreeMap<Path, Vec<Sender<(u64, Message)>>>,
+    subscribers: BTreeMap<Path, Vec<MessageSender>>,
     local_index: u64,
-    //match_subscribers: Vec<Matcher, Vec<Sender<Message>>>,
+    match_subscribers: Vec<(Subscription, Vec<MessageSender>)>,
+    verifier: Option<Box<dyn MessageVerifier + Send>>,
+    next_sequences: BTreeMap<SenderId, u64>,
 }
 
 pub struct Receiver<T> {
     inner: RawReceiver<(u64, T)>,
-    topic: Vec<u8>,
+    subscription: Subscription,
+}
+
+impl<T> Receiver<T> {
+    /// The subscription this receiver was created with.
+    pub fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
 }
 
 impl core::ops::Deref for Receiver<Message> {
@@ -46,6 +140,20 @@ impl MessageDispatcher {
         MessageDispatcher {
             subscribers: Default::default(),
             local_index: 0,
+            match_subscribers: Default::default(),
+            verifier: None,
+            next_sequences: Default::default(),
+        }
+    }
+
+    /// Create a dispatcher in verifying mode.
+    ///
+    /// In verifying mode, messages fed through `dispatch_signed` must carry a valid signature
+    /// according to `verifier`.
+    pub fn new_verifying(verifier: impl MessageVerifier + Send + 'static) -> Self {
+        MessageDispatcher {
+            verifier: Some(Box::new(verifier)),
+            ..Self::new()
         }
     }
 
@@ -58,7 +166,49 @@ impl MessageDispatcher {
         entry.push(tx);
         Receiver {
             inner: rx,
-            topic: path,
+            subscription: Subscription::Exact(path),
+        }
+    }
+
+    /// Subscribe messages which are sent to any path starting with `prefix`.
+    /// Returns a Receiver channel end.
+    pub fn subscribe_prefix(&mut self, prefix: impl Into<Path>) -> Receiver<Message> {
+        self.subscribe_matching(Subscription::Prefix(prefix.into()))
+    }
+
+    /// Subscribe messages which are sent to any path matching the glob `pattern`.
+    ///
+    /// A `*` in the pattern matches any (possibly empty) sequence of bytes, e.g.
+    /// `phala/cluster/*` matches every topic under `phala/cluster/`.
+    /// Returns a Receiver channel end.
+    pub fn subscribe_pattern(&mut self, pattern: impl Into<Path>) -> Receiver<Message> {
+        self.subscribe_matching(Subscription::Pattern(pattern.into()))
+    }
+
+    /// Subscribe messages according to the given `subscription`.
+    /// Returns a Receiver channel end.
+    pub fn subscribe_with(&mut self, subscription: Subscription) -> Receiver<Message> {
+        match subscription {
+            Subscription::Exact(path) => self.subscribe(path),
+            subscription => self.subscribe_matching(subscription),
+        }
+    }
+
+    fn subscribe_matching(&mut self, subscription: Subscription) -> Receiver<Message> {
+        let (rx, tx) = channel();
+        match self
+            .match_subscribers
+            .iter_mut()
+            .find(|(sub, _)| *sub == subscription)
+        {
+            Some((_, senders)) => senders.push(tx),
+            None => self
+                .match_subscribers
+                .push((subscription.clone(), alloc::vec![tx])),
+        }
+        Receiver {
+            inner: rx,
+            subscription,
         }
     }
 
@@ -74,7 +224,7 @@ impl MessageDispatcher {
         let mut count = 0;
         let sn = self.local_index;
         self.local_index += 1;
-        if let Some(receivers) = self.subscribers.get_mut(message.destination.path()) {
+        let mut send_to = |receivers: &mut Vec<MessageSender>| {
             receivers.retain(|receiver| {
                 if let Err(error) = receiver.send((sn, message.clone())) {
                     use crate::simple_mpsc::SendError::*;
@@ -86,10 +236,46 @@ impl MessageDispatcher {
                     true
                 }
             });
+        };
+        let path = message.destination.path();
+        if let Some(receivers) = self.subscribers.get_mut(path) {
+            send_to(receivers);
         }
+        for (subscription, receivers) in self.match_subscribers.iter_mut() {
+            if subscription.matches(path) {
+                send_to(receivers);
+            }
+        }
+        self.match_subscribers
+            .retain(|(_, receivers)| !receivers.is_empty());
         count
     }
 
+    /// Verify and dispatch a signed message.
+    ///
+    /// The signature is checked if the dispatcher is in verifying mode. The sequence of the
+    /// message must be the next one expected from its sender, starting from 0, the same rule
+    /// the chain applies to offchain ingress messages.
+    /// Returns number of receivers dispatched to.
+    pub fn dispatch_signed(&mut self, message: SignedMessage) -> Result<usize, VerifyError> {
+        let sender = &message.message.sender;
+        if let Some(verifier) = &self.verifier {
+            if !verifier.verify(&message) {
+                return Err(VerifyError::BadSignature(sender.clone()));
+            }
+        }
+        let expected = self.next_sequences.get(sender).cloned().unwrap_or(0);
+        if message.sequence != expected {
+            return Err(VerifyError::BadSequence {
+                sender: sender.clone(),
+                expected,
+                actual: message.sequence,
+            });
+        }
+        self.next_sequences.insert(sender.clone(), expected + 1);
+        Ok(self.dispatch(message.message))
+    }
+
     pub fn reset_local_index(&mut self) {
         self.local_index = 0;
     }
@@ -97,7 +283,8 @@ impl MessageDispatcher {
     /// Drop all unhandled messages.
     pub fn clear(&mut self) -> usize {
         let mut count = 0;
-        for subscriber in self.subscribers.values_mut().flatten() {
+        let matchers = self.match_subscribers.iter_mut().map(|(_, v)| v);
+        for subscriber in self.subscribers.values_mut().chain(matchers).flatten() {
             count += subscriber.clear();
         }
         count
@@ -112,6 +299,18 @@ pub enum TypedReceiveError {
     CodecError(CodecError),
 }
 
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    #[display(fmt = "Bad signature of message from {_0}")]
+    BadSignature(SenderId),
+    #[display(fmt = "Bad sequence of message from {sender}, expected {expected}, got {actual}")]
+    BadSequence {
+        sender: SenderId,
+        expected: u64,
+        actual: u64,
+    },
+}
+
 impl From<CodecError> for TypedReceiveError {
     fn from(e: CodecError) -> Self {
         Self::CodecError(e)
@@ -173,7 +372,7 @@ const _: () = {
         where
             S: Serializer,
         {
-            self.topic.serialize(serializer)
+            self.subscription.to_key().serialize(serializer)
         }
     }
 
@@ -182,8 +381,8 @@ const _: () = {
         where
             D: serde::Deserializer<'de>,
         {
-            let topic: Vec<u8> = Deserialize::deserialize(de)?;
-            Ok(subscribe_default(topic))
+            let key: Vec<u8> = Deserialize::deserialize(de)?;
+            Ok(subscribe_default(Subscription::from_key(key)))
         }
     }
 };
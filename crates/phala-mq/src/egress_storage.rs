@@ -0,0 +1,390 @@
+use crate::{SenderId, SignedMessage};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// A change to the queue could not be written to the storage backend.
+///
+/// The change is not applied when this is returned, so the queue stays as it was.
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+#[display(fmt = "Egress storage error: {}", _0)]
+pub struct StorageError(pub String);
+
+/// The egress queue of a single sender.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Channel {
+    sequence: u64,
+    messages: Vec<SignedMessage>,
+    dummy: bool,
+}
+
+impl Channel {
+    /// The sequence which will be assigned to the next message of the channel.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Messages pushed but not yet purged.
+    pub fn messages(&self) -> &[SignedMessage] {
+        &self.messages
+    }
+
+    /// Whether the channel is in dummy mode, in which messages are dropped but the sequence
+    /// still increases.
+    pub fn is_dummy(&self) -> bool {
+        self.dummy
+    }
+}
+
+/// The storage backend of a `MessageSendQueue`.
+pub trait EgressStorage: Send {
+    /// All channels held by the storage.
+    fn channels(&self) -> &BTreeMap<SenderId, Channel>;
+
+    /// Push `message` into the channel of `sender` and increase the channel's sequence by one.
+    ///
+    /// A `None` message only increases the sequence, which is used by channels in dummy mode.
+    fn push(
+        &mut self,
+        sender: &SenderId,
+        message: Option<SignedMessage>,
+    ) -> Result<(), StorageError>;
+
+    /// Set the dummy mode of the channel of `sender`.
+    fn set_dummy(&mut self, sender: &SenderId, dummy: bool) -> Result<(), StorageError>;
+
+    /// Remove messages of `sender` whose sequence is less than `next_sequence`.
+    fn purge(&mut self, sender: &SenderId, next_sequence: u64) -> Result<(), StorageError>;
+}
+
+/// Keeps all channels in memory. They only survive a restart through the runtime checkpoint.
+#[derive(Default)]
+pub struct MemoryStorage {
+    channels: BTreeMap<SenderId, Channel>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn push_message(&mut self, sender: &SenderId, message: Option<SignedMessage>) {
+        let entry = self.channels.entry(sender.clone()).or_default();
+        if let Some(message) = message {
+            entry.messages.push(message);
+        }
+        entry.sequence += 1;
+    }
+
+    fn set_dummy_mode(&mut self, sender: &SenderId, dummy: bool) {
+        self.channels.entry(sender.clone()).or_default().dummy = dummy;
+    }
+
+    fn purge_messages(&mut self, sender: &SenderId, next_sequence: u64) {
+        if let Some(channel) = self.channels.get_mut(sender) {
+            channel.messages.retain(|msg| msg.sequence >= next_sequence);
+        }
+    }
+}
+
+impl From<BTreeMap<SenderId, Channel>> for MemoryStorage {
+    fn from(channels: BTreeMap<SenderId, Channel>) -> Self {
+        Self { channels }
+    }
+}
+
+impl EgressStorage for MemoryStorage {
+    fn channels(&self) -> &BTreeMap<SenderId, Channel> {
+        &self.channels
+    }
+
+    fn push(
+        &mut self,
+        sender: &SenderId,
+        message: Option<SignedMessage>,
+    ) -> Result<(), StorageError> {
+        self.push_message(sender, message);
+        Ok(())
+    }
+
+    fn set_dummy(&mut self, sender: &SenderId, dummy: bool) -> Result<(), StorageError> {
+        self.set_dummy_mode(sender, dummy);
+        Ok(())
+    }
+
+    fn purge(&mut self, sender: &SenderId, next_sequence: u64) -> Result<(), StorageError> {
+        self.purge_messages(sender, next_sequence);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+pub use file_log::FileLogStorage;
+
+#[cfg(feature = "std")]
+mod file_log {
+    use super::*;
+    use parity_scale_codec::{Decode, Encode};
+    use std::{
+        fs::{File, OpenOptions},
+        io::{self, Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+    };
+
+    /// Compact the log once it holds this many more records than live messages.
+    const COMPACT_THRESHOLD: usize = 1024;
+
+    #[derive(Encode, Decode)]
+    enum Record {
+        /// The header of a channel, written when the log is compacted.
+        Channel {
+            sender: SenderId,
+            sequence: u64,
+            dummy: bool,
+        },
+        Push {
+            sender: SenderId,
+            message: Option<SignedMessage>,
+        },
+        SetDummy {
+            sender: SenderId,
+            dummy: bool,
+        },
+        Purge {
+            sender: SenderId,
+            next_sequence: u64,
+        },
+    }
+
+    impl Record {
+        fn apply(self, memory: &mut MemoryStorage) {
+            match self {
+                Record::Channel {
+                    sender,
+                    sequence,
+                    dummy,
+                } => {
+                    let channel = memory.channels.entry(sender).or_default();
+                    channel.sequence = sequence;
+                    channel.dummy = dummy;
+                }
+                Record::Push { sender, message } => memory.push_message(&sender, message),
+                Record::SetDummy { sender, dummy } => memory.set_dummy_mode(&sender, dummy),
+                Record::Purge {
+                    sender,
+                    next_sequence,
+                } => memory.purge_messages(&sender, next_sequence),
+            }
+        }
+    }
+
+    /// An append-only log file backed storage.
+    ///
+    /// Every change to the queue is appended to the log and synced to the disk as soon as it
+    /// happens, so the egress messages survive a crash of the process, or of the machine,
+    /// without taking a checkpoint. The channels are also kept in memory to serve reads, and
+    /// the log gets rewritten from them when most of its records have been purged.
+    pub struct FileLogStorage {
+        memory: MemoryStorage,
+        path: PathBuf,
+        file: File,
+        /// Length of the valid records in the log.
+        len: u64,
+        n_records: usize,
+        /// Set when a failed append could not be rolled back, the log is then rewritten from
+        /// memory before the next append.
+        torn: bool,
+    }
+
+    impl FileLogStorage {
+        /// Open the log at `path`, replaying the records in it if it exists.
+        ///
+        /// A partially written record at the end of the log, which is left when the process
+        /// crashes in the middle of a write, is discarded.
+        pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+            let path = path.as_ref().to_path_buf();
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?;
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf)?;
+
+            let mut memory = MemoryStorage::new();
+            let mut n_records = 0;
+            let mut valid_len = 0;
+            let mut input = &buf[..];
+            while !input.is_empty() {
+                let record: Record = match Decode::decode(&mut input) {
+                    Ok(record) => record,
+                    Err(_) => {
+                        log::warn!(target: "phala_mq",
+                            "Discarding corrupted tail of egress log {}",
+                            path.display(),
+                        );
+                        break;
+                    }
+                };
+                record.apply(&mut memory);
+                n_records += 1;
+                valid_len = buf.len() - input.len();
+            }
+            if valid_len < buf.len() {
+                file.set_len(valid_len as u64)?;
+            }
+            file.seek(SeekFrom::Start(valid_len as u64))?;
+            Ok(Self {
+                memory,
+                path,
+                file,
+                len: valid_len as u64,
+                n_records,
+                torn: false,
+            })
+        }
+
+        fn append(&mut self, record: Record) -> Result<(), StorageError> {
+            self.try_append(record).map_err(|err| {
+                StorageError(format!(
+                    "failed to append to egress log {}: {}",
+                    self.path.display(),
+                    err
+                ))
+            })
+        }
+
+        fn try_append(&mut self, record: Record) -> io::Result<()> {
+            if self.torn {
+                self.compact()?;
+            }
+            let encoded = record.encode();
+            // The record must be on the disk before the change is applied, or a crash could
+            // lose a message whose sequence has been consumed.
+            let written = self
+                .file
+                .write_all(&encoded)
+                .and_then(|_| self.file.sync_data());
+            if let Err(err) = written {
+                // Cut the partially written record, or the records appended after it would be
+                // discarded together with it when the log is replayed.
+                let rolled_back = self
+                    .file
+                    .set_len(self.len)
+                    .and_then(|_| self.file.seek(SeekFrom::Start(self.len)));
+                if rolled_back.is_err() {
+                    self.torn = true;
+                }
+                return Err(err);
+            }
+            self.len += encoded.len() as u64;
+            self.n_records += 1;
+            Ok(())
+        }
+
+        fn maybe_compact(&mut self) {
+            let n_live = self
+                .memory
+                .channels
+                .values()
+                .map(|ch| ch.messages.len() + 1)
+                .sum::<usize>();
+            if self.n_records < n_live + COMPACT_THRESHOLD {
+                return;
+            }
+            // Compacting only saves space, the current log is still complete if it fails.
+            if let Err(err) = self.compact() {
+                log::error!(target: "phala_mq",
+                    "Failed to compact egress log {}: {}",
+                    self.path.display(),
+                    err,
+                );
+            }
+        }
+
+        /// Rewrite the log with only the records needed to rebuild the current channels.
+        fn compact(&mut self) -> io::Result<()> {
+            let tmp_path = self.path.with_extension("compacting");
+            let mut tmp_file = File::create(&tmp_path)?;
+            let mut n_records = 0;
+            let mut len = 0;
+            for (sender, channel) in self.memory.channels.iter() {
+                let header = Record::Channel {
+                    sender: sender.clone(),
+                    sequence: channel.sequence - channel.messages.len() as u64,
+                    dummy: channel.dummy,
+                };
+                let encoded = header.encode();
+                tmp_file.write_all(&encoded)?;
+                len += encoded.len();
+                for message in channel.messages.iter() {
+                    let record = Record::Push {
+                        sender: sender.clone(),
+                        message: Some(message.clone()),
+                    };
+                    let encoded = record.encode();
+                    tmp_file.write_all(&encoded)?;
+                    len += encoded.len();
+                }
+                n_records += channel.messages.len() + 1;
+            }
+            tmp_file.sync_all()?;
+            std::fs::rename(&tmp_path, &self.path)?;
+            self.file = tmp_file;
+            self.len = len as u64;
+            self.n_records = n_records;
+            self.torn = false;
+            Ok(())
+        }
+    }
+
+    impl EgressStorage for FileLogStorage {
+        fn channels(&self) -> &BTreeMap<SenderId, Channel> {
+            self.memory.channels()
+        }
+
+        fn push(
+            &mut self,
+            sender: &SenderId,
+            message: Option<SignedMessage>,
+        ) -> Result<(), StorageError> {
+            self.append(Record::Push {
+                sender: sender.clone(),
+                message: message.clone(),
+            })?;
+            self.memory.push_message(sender, message);
+            Ok(())
+        }
+
+        fn set_dummy(&mut self, sender: &SenderId, dummy: bool) -> Result<(), StorageError> {
+            self.append(Record::SetDummy {
+                sender: sender.clone(),
+                dummy,
+            })?;
+            self.memory.set_dummy_mode(sender, dummy);
+            Ok(())
+        }
+
+        fn purge(&mut self, sender: &SenderId, next_sequence: u64) -> Result<(), StorageError> {
+            let purged = match self.memory.channels.get(sender) {
+                Some(channel) => channel
+                    .messages
+                    .first()
+                    .map(|msg| msg.sequence < next_sequence)
+                    .unwrap_or(false),
+                None => false,
+            };
+            if !purged {
+                return Ok(());
+            }
+            self.append(Record::Purge {
+                sender: sender.clone(),
+                next_sequence,
+            })?;
+            self.memory.purge_messages(sender, next_sequence);
+            self.maybe_compact();
+            Ok(())
+        }
+    }
+}
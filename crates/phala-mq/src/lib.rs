@@ -11,6 +11,8 @@ pub mod types;
 #[cfg(feature = "dispatcher")]
 mod dispatcher;
 #[cfg(feature = "queue")]
+mod egress_storage;
+#[cfg(feature = "queue")]
 mod send_queue;
 #[cfg(any(feature = "queue", feature = "dispatcher"))]
 mod simple_mpsc;
@@ -19,13 +21,21 @@ mod simple_mpsc;
 pub mod checkpoint_helper;
 
 #[cfg(feature = "dispatcher")]
-pub use dispatcher::{MessageDispatcher, TypedReceiveError, TypedReceiver};
+pub use dispatcher::{
+    MessageDispatcher, Subscription, TypedReceiveError, TypedReceiver, VerifyError,
+};
+#[cfg(feature = "queue")]
+pub use egress_storage::{Channel, EgressStorage, MemoryStorage, StorageError};
+#[cfg(all(feature = "queue", feature = "std"))]
+pub use egress_storage::FileLogStorage;
 #[cfg(feature = "queue")]
-pub use send_queue::{MessageChannel, MessageSendQueue};
+pub use send_queue::{EgressPolicy, EnqueueError, MessageChannel, MessageSendQueue};
 #[cfg(any(feature = "queue", feature = "dispatcher"))]
 pub use simple_mpsc::{ReceiveError, Receiver};
 
-pub use signer::MessageSigner;
+pub use signer::{MessageSigner, MessageVerifier};
+#[cfg(feature = "signers")]
+pub use signer::signers::Sr25519Verifier;
 
 pub use types::*;
 
@@ -48,13 +58,16 @@ mod alias {
 pub mod traits {
     use parity_scale_codec::Encode;
 
-    use crate::{BindTopic, Path, SigningMessage};
+    use crate::{BindTopic, Path, QuotaExceeded, SigningMessage};
 
     /// A MessageChannel is used to push messages into the egress queue, then the messages
     /// are ready to be synchronized to the chain by pherry or prb.
     pub trait MessageChannel {
         type Signer;
         /// Push given binary data as message payload into the egress queue.
+        ///
+        /// The message is dropped if the sender has reached its egress quota, use try_push_data
+        /// to be told about it.
         fn push_data(&self, data: alloc::vec::Vec<u8>, topic: impl Into<Path>);
         /// Same as push_data, except that it a SCALE encodable typed message which will be encoded into binary data.
         fn push_message_to(&self, message: &impl Encode, topic: impl Into<Path>) {
@@ -64,6 +77,26 @@ pub mod traits {
         fn push_message<M: Encode + BindTopic>(&self, message: &M) {
             self.push_message_to(message, M::topic())
         }
+        /// Like push_data, but reports the message refused by the egress quota of the sender,
+        /// so that the caller can back off.
+        ///
+        /// Channels without quotas always accept the message.
+        fn try_push_data(
+            &self,
+            data: alloc::vec::Vec<u8>,
+            topic: impl Into<Path>,
+        ) -> Result<(), QuotaExceeded> {
+            self.push_data(data, topic);
+            Ok(())
+        }
+        /// Same as try_push_data, except that it a SCALE encodable typed message which will be encoded into binary data.
+        fn try_push_message_to(
+            &self,
+            message: &impl Encode,
+            topic: impl Into<Path>,
+        ) -> Result<(), QuotaExceeded> {
+            self.try_push_data(message.encode(), topic)
+        }
         fn set_dummy(&self, _dummy: bool) {}
         /// Set signer for the channel.
         fn set_signer(&mut self, _signer: Self::Signer) {}
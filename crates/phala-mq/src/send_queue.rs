@@ -1,19 +1,79 @@
 use crate::{
-    Message, MessageOrigin, MessageSigner, Mutex, SenderId, SignedMessage, SigningMessage,
+    egress_storage::{Channel, EgressStorage, MemoryStorage, StorageError},
+    Message, MessageOrigin, MessageSigner, Mutex, OriginKind, QuotaExceeded, SenderId,
+    SignedMessage, SigningMessage,
 };
-use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use derive_more::Display;
 use serde::{Deserialize, Serialize};
 
-#[derive(Default, Serialize, Deserialize)]
-struct Channel {
-    sequence: u64,
-    messages: Vec<SignedMessage>,
-    dummy: bool,
+/// A message could not be pushed into the egress queue.
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub enum EnqueueError {
+    #[display(fmt = "{}", _0)]
+    QuotaExceeded(QuotaExceeded),
+    #[display(fmt = "{}", _0)]
+    Storage(StorageError),
 }
 
-#[derive(Clone, Default)]
+/// Egress rules applied by a `MessageSendQueue`.
+#[derive(Clone, Debug)]
+pub struct EgressPolicy {
+    /// Origin kinds from the highest priority to the lowest. Unlisted kinds come last.
+    pub priority: Vec<OriginKind>,
+    /// Max number of pending messages of a single sender, by origin kind. Unlisted kinds are
+    /// unbounded.
+    pub quotas: BTreeMap<OriginKind, usize>,
+    /// Max number of messages returned by `prioritized_messages`.
+    pub batch_size: usize,
+}
+
+impl Default for EgressPolicy {
+    fn default() -> Self {
+        const DEFAULT_CONTRACT_QUOTA: usize = 512;
+        const DEFAULT_BATCH_SIZE: usize = 1024;
+
+        Self {
+            priority: vec![
+                OriginKind::Gatekeeper,
+                OriginKind::Worker,
+                OriginKind::Cluster,
+                OriginKind::Contract,
+            ],
+            quotas: [(OriginKind::Contract, DEFAULT_CONTRACT_QUOTA)].into(),
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+impl EgressPolicy {
+    fn rank(&self, sender: &SenderId) -> usize {
+        let kind = sender.kind();
+        self.priority
+            .iter()
+            .position(|k| *k == kind)
+            .unwrap_or(self.priority.len())
+    }
+
+    fn quota(&self, sender: &SenderId) -> Option<usize> {
+        self.quotas.get(&sender.kind()).cloned()
+    }
+}
+
+struct Inner {
+    storage: Box<dyn EgressStorage>,
+    policy: EgressPolicy,
+}
+
+#[derive(Clone)]
 pub struct MessageSendQueue {
-    inner: Arc<Mutex<BTreeMap<SenderId, Channel>>>,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for MessageSendQueue {
+    fn default() -> Self {
+        Self::with_storage(MemoryStorage::new())
+    }
 }
 
 impl Serialize for MessageSendQueue {
@@ -22,50 +82,87 @@ impl Serialize for MessageSendQueue {
         S: serde::Serializer,
     {
         let inner = self.inner.lock();
-        let inner = &*inner;
-        inner.serialize(serializer)
+        inner.storage.channels().serialize(serializer)
     }
 }
 
+/// Always restores the channels into a `MemoryStorage`. Queues backed by a log are reopened
+/// from the log instead of being serialized.
 impl<'de> Deserialize<'de> for MessageSendQueue {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
         let inner = BTreeMap::<SenderId, Channel>::deserialize(deserializer)?;
-        Ok(MessageSendQueue {
-            inner: Arc::new(Mutex::new(inner)),
-        })
+        Ok(Self::with_storage(MemoryStorage::from(inner)))
     }
 }
 
 impl MessageSendQueue {
     pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create a queue which keeps its messages in the given storage.
+    pub fn with_storage(storage: impl EgressStorage + 'static) -> Self {
         MessageSendQueue {
-            inner: Default::default(),
+            inner: Arc::new(Mutex::new(Inner {
+                storage: Box::new(storage),
+                policy: Default::default(),
+            })),
         }
     }
 
+    pub fn set_policy(&self, policy: EgressPolicy) {
+        self.inner.lock().policy = policy;
+    }
+
     pub fn channel<Si: MessageSigner>(&self, sender: SenderId, signer: Si) -> MessageChannel<Si> {
         MessageChannel::new(self.clone(), sender, signer)
     }
 
+    /// Push a message into the channel of `sender`.
+    ///
+    /// Fails without consuming a sequence if the sender has reached its quota in the egress
+    /// policy, or if the storage can not keep the message.
+    ///
+    /// The queue is purged against the chain state on every block, so the number of pending
+    /// messages, and hence the result, is the same on all workers processing the same blocks.
     pub fn enqueue_message(
         &self,
         sender: SenderId,
         constructor: impl FnOnce(u64) -> SignedMessage,
-    ) {
+    ) -> Result<(), EnqueueError> {
         let mut inner = self.inner.lock();
-        let entry = inner.entry(sender).or_default();
-        if !entry.dummy {
-            let message = constructor(entry.sequence);
+        let (sequence, dummy, pending) = inner
+            .storage
+            .channels()
+            .get(&sender)
+            .map(|ch| (ch.sequence(), ch.is_dummy(), ch.messages().len()))
+            .unwrap_or_default();
+        if !dummy {
+            if let Some(quota) = inner.policy.quota(&sender) {
+                if pending >= quota {
+                    log::warn!(target: "phala_mq",
+                        "Egress quota exceeded, from={}, pending={}",
+                        sender,
+                        pending,
+                    );
+                    return Err(EnqueueError::QuotaExceeded(QuotaExceeded(sender)));
+                }
+            }
+        }
+        let message = if dummy {
+            None
+        } else {
+            let message = constructor(sequence);
 
             if log::log_enabled!(target: "phala_mq", log::Level::Debug) {
                 log::debug!(target: "phala_mq",
                     "Sending message, from={}, to={:?}, seq={}, payload_hash={}",
                     message.message.sender,
                     message.message.destination,
-                    entry.sequence,
+                    sequence,
                     hex::encode(sp_core::blake2_256(&message.message.payload)),
                 );
             } else {
@@ -73,66 +170,155 @@ impl MessageSendQueue {
                     "Sending message, from={}, to={:?}, seq={}",
                     message.message.sender,
                     message.message.destination,
-                    entry.sequence,
+                    sequence,
                 );
             }
-            entry.messages.push(message);
-        }
-        entry.sequence += 1;
+            Some(message)
+        };
+        inner
+            .storage
+            .push(&sender, message)
+            .map_err(EnqueueError::Storage)
     }
 
-    pub fn set_dummy_mode(&self, sender: SenderId, dummy: bool) {
-        let mut inner = self.inner.lock();
-        let entry = inner.entry(sender).or_default();
-        entry.dummy = dummy;
+    /// Number of messages `sender` can still push before reaching its quota, `None` if it has
+    /// no quota.
+    pub fn remaining_quota(&self, sender: &SenderId) -> Option<usize> {
+        let inner = self.inner.lock();
+        let quota = inner.policy.quota(sender)?;
+        let pending = match inner.storage.channels().get(sender) {
+            Some(ch) if ch.is_dummy() => return None,
+            Some(ch) => ch.messages().len(),
+            None => 0,
+        };
+        Some(quota.saturating_sub(pending))
+    }
+
+    pub fn set_dummy_mode(&self, sender: SenderId, dummy: bool) -> Result<(), StorageError> {
+        self.inner.lock().storage.set_dummy(&sender, dummy)
     }
 
     pub fn all_messages(&self) -> Vec<SignedMessage> {
         let inner = self.inner.lock();
         inner
-            .iter()
-            .flat_map(|(_k, v)| v.messages.iter().cloned())
+            .storage
+            .channels()
+            .values()
+            .flat_map(|v| v.messages().iter().cloned())
             .collect()
     }
 
     pub fn all_messages_grouped(&self) -> BTreeMap<MessageOrigin, Vec<SignedMessage>> {
         let inner = self.inner.lock();
         inner
+            .storage
+            .channels()
             .iter()
-            .map(|(k, v)| (k.clone(), v.messages.clone()))
+            .map(|(k, v)| (k.clone(), v.messages().to_vec()))
             .collect()
     }
 
     pub fn messages(&self, sender: &SenderId) -> Vec<SignedMessage> {
         let inner = self.inner.lock();
         inner
+            .storage
+            .channels()
             .get(sender)
-            .map(|x| x.messages.clone())
+            .map(|x| x.messages().to_vec())
             .unwrap_or_default()
     }
 
     pub fn count_messages(&self) -> usize {
         self.inner
             .lock()
-            .iter()
-            .map(|(_k, v)| v.messages.len())
+            .storage
+            .channels()
+            .values()
+            .map(|v| v.messages().len())
             .sum()
     }
 
     /// Purge the messages which are aready accepted on chain.
-    pub fn purge(&self, next_sequence_for: impl Fn(&SenderId) -> u64) {
+    ///
+    /// All the channels are purged even if some of them fail, the first error is returned.
+    pub fn purge(&self, next_sequence_for: impl Fn(&SenderId) -> u64) -> Result<(), StorageError> {
         let mut inner = self.inner.lock();
-        for (k, v) in inner.iter_mut() {
-            let seq = next_sequence_for(k);
-            v.messages.retain(|msg| msg.sequence >= seq);
+        let senders: Vec<SenderId> = inner.storage.channels().keys().cloned().collect();
+        let mut result = Ok(());
+        for sender in senders {
+            let seq = next_sequence_for(&sender);
+            if let Err(err) = inner.storage.purge(&sender, seq) {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
         }
+        result
     }
+
+    /// Returns a batch of the pending messages, grouped by sender.
+    ///
+    /// The senders are ordered by the priority of their origin kinds in the egress policy, and
+    /// the batch holds at most `batch_size` messages. Messages of a sender are always taken
+    /// from its lowest sequence so that they can be submitted in order.
+    pub fn prioritized_messages(&self) -> Vec<(MessageOrigin, Vec<SignedMessage>)> {
+        let inner = self.inner.lock();
+        let channels = inner
+            .storage
+            .channels()
+            .iter()
+            .map(|(sender, ch)| (sender, ch.messages()))
+            .collect();
+        take_batch(channels, &inner.policy)
+    }
+
+    /// Like `prioritized_messages`, but over the pending messages of all `queues` together,
+    /// ranked by and limited to the egress policy of the first one.
+    pub fn prioritized_messages_of(
+        queues: &[&MessageSendQueue],
+    ) -> Vec<(MessageOrigin, Vec<SignedMessage>)> {
+        let policy = match queues.first() {
+            Some(queue) => queue.inner.lock().policy.clone(),
+            None => return Vec::new(),
+        };
+        // The batch of each queue holds its highest ranked messages, which are all the merged
+        // batch can take from it.
+        let batches: Vec<_> = queues
+            .iter()
+            .flat_map(|queue| queue.prioritized_messages())
+            .collect();
+        let channels = batches
+            .iter()
+            .map(|(sender, messages)| (sender, &messages[..]))
+            .collect();
+        take_batch(channels, &policy)
+    }
+}
+
+fn take_batch(
+    mut channels: Vec<(&SenderId, &[SignedMessage])>,
+    policy: &EgressPolicy,
+) -> Vec<(MessageOrigin, Vec<SignedMessage>)> {
+    channels.retain(|(_, messages)| !messages.is_empty());
+    // Stable sort, senders of the same rank stay in their original order.
+    channels.sort_by_key(|(sender, _)| policy.rank(sender));
+    let mut budget = policy.batch_size;
+    let mut batch = Vec::new();
+    for (sender, messages) in channels {
+        if budget == 0 {
+            break;
+        }
+        let n = messages.len().min(budget);
+        budget -= n;
+        batch.push((sender.clone(), messages[..n].to_vec()));
+    }
+    batch
 }
 
 pub use msg_channel::*;
 mod msg_channel {
     use super::*;
-    use crate::{types::Path, MessageSigner, SenderId};
+    use crate::{types::Path, EnqueueError, MessageSigner, QuotaExceeded, SenderId};
 
     #[derive(Clone, Serialize, Deserialize)]
     pub struct MessageChannel<Si> {
@@ -170,18 +356,41 @@ mod msg_channel {
         }
     }
 
+    /// The channels are driven by the block processing, which has no way to recover from a
+    /// failing storage. Dropping the message instead would make this worker diverge from the
+    /// others processing the same blocks, so the channels panic on storage errors. Push into
+    /// the queue directly to handle them.
     impl<T: MessageSigner + Clone> crate::traits::MessageChannel for MessageChannel<T> {
         type Signer = T;
 
         fn push_data(&self, payload: Vec<u8>, to: impl Into<Path>) {
+            // The queue has logged the refusal already.
+            let _ = self.try_push_data(payload, to);
+        }
+
+        fn try_push_data(
+            &self,
+            payload: Vec<u8>,
+            to: impl Into<Path>,
+        ) -> Result<(), QuotaExceeded> {
             let signing = self.prepare_with_data(payload, to);
-            self.queue
-                .enqueue_message(self.sender.clone(), move |sequence| signing.sign(sequence))
+            let result = self
+                .queue
+                .enqueue_message(self.sender.clone(), move |sequence| signing.sign(sequence));
+            match result {
+                Ok(()) => Ok(()),
+                Err(EnqueueError::QuotaExceeded(err)) => Err(err),
+                Err(EnqueueError::Storage(err)) => {
+                    panic!("Failed to keep message from {}: {}", self.sender, err);
+                }
+            }
         }
 
         /// Set the channel to dummy mode which increasing the sequence but dropping the message.
         fn set_dummy(&self, dummy: bool) {
-            self.queue.set_dummy_mode(self.sender.clone(), dummy);
+            if let Err(err) = self.queue.set_dummy_mode(self.sender.clone(), dummy) {
+                panic!("Failed to set dummy mode of {}: {}", self.sender, err);
+            }
         }
 
         fn set_signer(&mut self, signer: Self::Signer) {
@@ -6,12 +6,14 @@ pub trait MessageSigner {
     fn sign(&self, data: &[u8]) -> Vec<u8>;
 }
 pub trait MessageVerifier {
+    /// Returns true if the signature of `message` is valid for its sender.
     fn verify(&self, message: &SignedMessage) -> bool;
 }
 
 #[cfg(feature = "signers")]
 pub mod signers {
-    use super::MessageSigner;
+    use super::{MessageSigner, MessageVerifier};
+    use crate::{MessageOrigin, SignedMessage};
     use alloc::vec::Vec;
     use sp_core::{crypto::Pair as PairTrait, sr25519};
     use serde::{Serialize, Deserialize};
@@ -34,4 +36,25 @@ pub mod signers {
             Self { key }
         }
     }
+
+    /// Verifies messages sent by pRuntime workers against the sr25519 public key in their
+    /// `MessageOrigin::Worker` origin.
+    ///
+    /// Messages from any other origin can not be verified and are always rejected.
+    #[derive(Default, Clone, Copy)]
+    pub struct Sr25519Verifier;
+
+    impl MessageVerifier for Sr25519Verifier {
+        fn verify(&self, message: &SignedMessage) -> bool {
+            let pubkey = match &message.message.sender {
+                MessageOrigin::Worker(pubkey) => pubkey,
+                _ => return false,
+            };
+            let signature = match sr25519::Signature::from_slice(&message.signature) {
+                Some(signature) => signature,
+                None => return false,
+            };
+            sr25519::Pair::verify(&signature, message.data_be_signed(), pubkey)
+        }
+    }
 }
@@ -99,6 +99,20 @@ impl MessageOrigin {
         matches!(self, Self::Gatekeeper)
     }
 
+    /// Returns the kind of the origin
+    pub fn kind(&self) -> OriginKind {
+        match self {
+            Self::Pallet(_) => OriginKind::Pallet,
+            Self::Contract(_) => OriginKind::Contract,
+            Self::Worker(_) => OriginKind::Worker,
+            Self::AccountId(_) => OriginKind::AccountId,
+            Self::MultiLocation(_) => OriginKind::MultiLocation,
+            Self::Gatekeeper => OriginKind::Gatekeeper,
+            Self::Cluster(_) => OriginKind::Cluster,
+            Self::Reserved => OriginKind::Reserved,
+        }
+    }
+
     /// Returns the account id if the origin is from a user, or `Err(BadOrigin)` otherwise
     pub fn account(&self) -> Result<AccountId32, BadOrigin> {
         match self {
@@ -110,6 +124,24 @@ impl MessageOrigin {
 
 pub struct BadOrigin;
 
+/// The variant of a `MessageOrigin` without its payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OriginKind {
+    Pallet,
+    Contract,
+    Worker,
+    AccountId,
+    MultiLocation,
+    Gatekeeper,
+    Cluster,
+    Reserved,
+}
+
+/// The sender has reached its quota of pending messages in the egress queue.
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+#[display(fmt = "Egress quota of {} exceeded", _0)]
+pub struct QuotaExceeded(pub SenderId);
+
 /// The topic in the message queue, indicating a group of destination message receivers.
 ///
 /// A topic can be any non-empty binary string except there are some reserved value for the first byte.
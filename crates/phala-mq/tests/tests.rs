@@ -61,10 +61,12 @@ fn test_send_message() {
     }
 
     {
-        queue.purge(|sender| match &sender {
-            MessageOrigin::Pallet(_) => 1,
-            _ => 0,
-        });
+        queue
+            .purge(|sender| match &sender {
+                MessageOrigin::Pallet(_) => 1,
+                _ => 0,
+            })
+            .unwrap();
 
         let runtime_msgs = queue.messages(&runtime);
         let contract1_msgs = queue.messages(&worker0);
@@ -174,3 +176,312 @@ fn test_select_order() {
     }
     assert_eq!(payloads, [0, 1, 2, 3, 4]);
 }
+
+#[cfg(feature = "dispatcher")]
+#[test]
+fn test_dispatcher_match_subscriptions() {
+    use phala_mq::{Message, MessageDispatcher, TypedReceiver};
+    let sender = MessageOrigin::Pallet(b"sender".to_vec());
+
+    let mut dispatcher = MessageDispatcher::new();
+
+    let mut exact = dispatcher.subscribe(*b"phala/cluster/a");
+    let mut prefix = dispatcher.subscribe_prefix(*b"phala/");
+    let mut pattern = dispatcher.subscribe_pattern(*b"phala/cluster/*/event");
+    let mut typed: TypedReceiver<u32> = dispatcher.subscribe_pattern(*b"*/number").into();
+
+    let n = dispatcher.dispatch(Message::new(
+        sender.clone(),
+        *b"phala/cluster/a",
+        b"payload0".to_vec(),
+    ));
+    assert_eq!(n, 2);
+    let n = dispatcher.dispatch(Message::new(
+        sender.clone(),
+        *b"phala/cluster/b/event",
+        b"payload1".to_vec(),
+    ));
+    assert_eq!(n, 2);
+    let n = dispatcher.dispatch(Message::new(
+        sender.clone(),
+        *b"phala/cluster/event",
+        b"payload2".to_vec(),
+    ));
+    assert_eq!(n, 1);
+    let n = dispatcher.dispatch(Message::new(
+        sender.clone(),
+        *b"other/number",
+        42u32.to_le_bytes().to_vec(),
+    ));
+    assert_eq!(n, 1);
+
+    let payloads = |rx: &mut phala_mq::Receiver<(u64, Message)>| -> Vec<Vec<u8>> {
+        rx.drain().map(|x| x.1.payload).collect()
+    };
+    assert_eq!(payloads(&mut exact), [b"payload0".to_vec()]);
+    assert_eq!(
+        payloads(&mut prefix),
+        [
+            b"payload0".to_vec(),
+            b"payload1".to_vec(),
+            b"payload2".to_vec()
+        ]
+    );
+    assert_eq!(payloads(&mut pattern), [b"payload1".to_vec()]);
+    let (sn, number, origin) = typed.try_next().unwrap().unwrap();
+    assert_eq!((sn, number, origin), (3, 42, sender.clone()));
+
+    let _ = dispatcher.dispatch(Message::new(sender.clone(), *b"phala/x", vec![]));
+    assert_eq!(dispatcher.clear(), 1);
+
+    drop(prefix);
+    let n = dispatcher.dispatch(Message::new(sender, *b"phala/x", vec![]));
+    assert_eq!(n, 0);
+}
+
+#[cfg(all(feature = "queue", feature = "std"))]
+#[test]
+fn test_file_log_storage() {
+    use phala_mq::{FileLogStorage, MessageSendQueue, MessageSigner};
+
+    #[derive(Clone)]
+    struct TestSigner;
+
+    impl MessageSigner for TestSigner {
+        fn sign(&self, _data: &[u8]) -> Vec<u8> {
+            b"sig".to_vec()
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("phala-mq-egress-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sender0 = MessageOrigin::Pallet(b"p0".to_vec());
+    let sender1 = MessageOrigin::Gatekeeper;
+
+    {
+        let queue = MessageSendQueue::with_storage(FileLogStorage::open(&path).unwrap());
+        let ch0 = queue.channel(sender0.clone(), TestSigner);
+        let ch1 = queue.channel(sender1.clone(), TestSigner);
+        for _ in 0..3 {
+            ch0.push_data(b"payload".to_vec(), b"topic0".to_vec());
+        }
+        ch1.set_dummy(true);
+        ch1.push_data(b"dropped".to_vec(), b"topic1".to_vec());
+        ch1.set_dummy(false);
+        ch1.push_data(b"kept".to_vec(), b"topic1".to_vec());
+        queue
+            .purge(|sender| if sender == &sender0 { 2 } else { 0 })
+            .unwrap();
+    }
+
+    // Reopening the log restores the queue, including the sequences.
+    let queue = MessageSendQueue::with_storage(FileLogStorage::open(&path).unwrap());
+    let msgs0 = queue.messages(&sender0);
+    assert_eq!(msgs0.len(), 1);
+    assert_eq!(msgs0[0].sequence, 2);
+    let msgs1 = queue.messages(&sender1);
+    assert_eq!(msgs1.len(), 1);
+    assert_eq!(msgs1[0].sequence, 1);
+    assert_eq!(msgs1[0].message.payload, b"kept");
+
+    let ch0 = queue.channel(sender0.clone(), TestSigner);
+    ch0.push_data(b"payload".to_vec(), b"topic0".to_vec());
+    assert_eq!(queue.messages(&sender0)[1].sequence, 3);
+
+    // A torn write at the end of the log is discarded.
+    drop(queue);
+    {
+        use std::io::Write;
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(&[1, 2]).unwrap();
+    }
+    let queue = MessageSendQueue::with_storage(FileLogStorage::open(&path).unwrap());
+    assert_eq!(queue.count_messages(), 3);
+
+    // Purging keeps the log compact without losing live messages.
+    let ch0 = queue.channel(sender0.clone(), TestSigner);
+    for seq in 4..3000 {
+        ch0.push_data(b"payload".to_vec(), b"topic0".to_vec());
+        queue
+            .purge(|sender| if sender == &sender0 { seq } else { 0 })
+            .unwrap();
+    }
+    let log_len = std::fs::metadata(&path).unwrap().len();
+    drop(queue);
+    let queue = MessageSendQueue::with_storage(FileLogStorage::open(&path).unwrap());
+    let msgs0 = queue.messages(&sender0);
+    assert_eq!(msgs0.len(), 1);
+    assert_eq!(msgs0[0].sequence, 2999);
+    assert!(log_len < 1024 * 200);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(all(feature = "dispatcher", feature = "signers"))]
+#[test]
+fn test_verifying_dispatcher() {
+    use phala_mq::{
+        Message, MessageDispatcher, Sr25519Signer, Sr25519Verifier, SigningMessage,
+        VerifyError,
+    };
+    use sp_core::Pair;
+
+    let key = sp_core::sr25519::Pair::from_seed(&[1u8; 32]);
+    let worker = MessageOrigin::Worker(key.public());
+    let signed = |sender: &MessageOrigin, payload: &[u8], sequence: u64| {
+        SigningMessage {
+            message: Message::new(sender.clone(), *b"path", payload.to_vec()),
+            signer: Sr25519Signer::from(key.clone()),
+        }
+        .sign(sequence)
+    };
+
+    let mut dispatcher = MessageDispatcher::new_verifying(Sr25519Verifier);
+    let mut sub = dispatcher.subscribe(*b"path");
+
+    assert_eq!(dispatcher.dispatch_signed(signed(&worker, b"0", 0)), Ok(1));
+
+    let mut tampered = signed(&worker, b"1", 1);
+    tampered.message.payload = b"evil".to_vec();
+    assert_eq!(
+        dispatcher.dispatch_signed(tampered),
+        Err(VerifyError::BadSignature(worker.clone()))
+    );
+
+    let other_worker = MessageOrigin::Worker(sp_core::sr25519::Public::from_raw([0u8; 32]));
+    assert_eq!(
+        dispatcher.dispatch_signed(signed(&other_worker, b"1", 0)),
+        Err(VerifyError::BadSignature(other_worker))
+    );
+    assert_eq!(
+        dispatcher.dispatch_signed(signed(&MessageOrigin::Gatekeeper, b"1", 0)),
+        Err(VerifyError::BadSignature(MessageOrigin::Gatekeeper))
+    );
+
+    assert_eq!(
+        dispatcher.dispatch_signed(signed(&worker, b"0", 0)),
+        Err(VerifyError::BadSequence {
+            sender: worker.clone(),
+            expected: 1,
+            actual: 0,
+        })
+    );
+    assert_eq!(dispatcher.dispatch_signed(signed(&worker, b"1", 1)), Ok(1));
+
+    let payloads: Vec<Vec<u8>> = sub.drain().map(|x| x.1.payload).collect();
+    assert_eq!(payloads, [b"0".to_vec(), b"1".to_vec()]);
+}
+
+#[cfg(feature = "queue")]
+#[test]
+fn test_egress_policy() {
+    use phala_mq::{EgressPolicy, MessageSendQueue, MessageSigner, OriginKind, QuotaExceeded};
+
+    #[derive(Clone)]
+    struct TestSigner;
+
+    impl MessageSigner for TestSigner {
+        fn sign(&self, _data: &[u8]) -> Vec<u8> {
+            vec![]
+        }
+    }
+
+    let queue = MessageSendQueue::new();
+    queue.set_policy(EgressPolicy {
+        quotas: [(OriginKind::Contract, 2)].into(),
+        batch_size: 5,
+        ..Default::default()
+    });
+    let contract = MessageOrigin::Contract([1u8; 32].into());
+    let cluster = MessageOrigin::Cluster([2u8; 32].into());
+    let pallet = MessageOrigin::Pallet(b"p".to_vec());
+
+    let ch = queue.channel(contract.clone(), TestSigner);
+    assert_eq!(queue.remaining_quota(&contract), Some(2));
+    assert_eq!(ch.try_push_data(b"0".to_vec(), b"t".to_vec()), Ok(()));
+    assert_eq!(queue.remaining_quota(&contract), Some(1));
+    assert_eq!(ch.try_push_data(b"1".to_vec(), b"t".to_vec()), Ok(()));
+    assert_eq!(queue.remaining_quota(&contract), Some(0));
+    assert_eq!(
+        ch.try_push_data(b"2".to_vec(), b"t".to_vec()),
+        Err(QuotaExceeded(contract.clone()))
+    );
+    // Messages pushed without backpressure are dropped as well.
+    ch.push_data(b"2".to_vec(), b"t".to_vec());
+    assert_eq!(queue.messages(&contract).len(), 2);
+    assert_eq!(queue.remaining_quota(&cluster), None);
+
+    let ch = queue.channel(pallet, TestSigner);
+    ch.push_data(b"0".to_vec(), b"t".to_vec());
+    let ch = queue.channel(cluster.clone(), TestSigner);
+    ch.push_data(b"0".to_vec(), b"t".to_vec());
+    let ch = queue.channel(MessageOrigin::Gatekeeper, TestSigner);
+    ch.push_data(b"0".to_vec(), b"t".to_vec());
+    ch.push_data(b"1".to_vec(), b"t".to_vec());
+
+    // The pallet is ranked last and doesn't fit in the batch.
+    let batch: Vec<_> = queue
+        .prioritized_messages()
+        .into_iter()
+        .map(|(sender, msgs)| (sender, msgs.len()))
+        .collect();
+    assert_eq!(
+        batch,
+        [
+            (MessageOrigin::Gatekeeper, 2),
+            (cluster, 1),
+            (contract.clone(), 2),
+        ]
+    );
+
+    queue
+        .purge(|sender| if sender == &contract { 2 } else { 0 })
+        .unwrap();
+    assert_eq!(queue.remaining_quota(&contract), Some(2));
+    let ch = queue.channel(contract.clone(), TestSigner);
+    assert_eq!(ch.try_push_data(b"2".to_vec(), b"t".to_vec()), Ok(()));
+    assert_eq!(queue.messages(&contract).len(), 1);
+    assert_eq!(queue.messages(&contract)[0].sequence, 2);
+}
+
+#[test]
+fn test_prioritized_messages_of() {
+    use phala_mq::{EgressPolicy, MessageSendQueue, MessageSigner};
+
+    #[derive(Clone)]
+    struct TestSigner;
+
+    impl MessageSigner for TestSigner {
+        fn sign(&self, _data: &[u8]) -> Vec<u8> {
+            vec![]
+        }
+    }
+
+    let policy = EgressPolicy {
+        batch_size: 3,
+        ..Default::default()
+    };
+    let queue = MessageSendQueue::new();
+    queue.set_policy(policy.clone());
+    let durable = MessageSendQueue::new();
+    durable.set_policy(policy);
+
+    let contract = MessageOrigin::Contract([1u8; 32].into());
+    let ch = queue.channel(contract.clone(), TestSigner);
+    for i in 0..3u8 {
+        ch.push_data(vec![i], b"t".to_vec());
+    }
+    let ch = durable.channel(MessageOrigin::Gatekeeper, TestSigner);
+    ch.push_data(b"0".to_vec(), b"t".to_vec());
+    ch.push_data(b"1".to_vec(), b"t".to_vec());
+
+    // The higher ranked messages of the second queue go first, and the batch size is kept.
+    let batch: Vec<_> = MessageSendQueue::prioritized_messages_of(&[&queue, &durable])
+        .into_iter()
+        .map(|(sender, msgs)| (sender, msgs.len()))
+        .collect();
+    assert_eq!(batch, [(MessageOrigin::Gatekeeper, 2), (contract, 1)]);
+}
@@ -0,0 +1,474 @@
+//! A trie node database kept in a log file on disk.
+//!
+//! Nodes are appended to the log together with their reference count, and an in-memory index
+//! maps each live node to its position in the file. Only the index and a bounded LRU cache of
+//! recently read nodes are held in memory, so the size of the chain state is no longer bounded
+//! by the enclave memory.
+//!
+//! The chain state is public, but the log may be placed where it is not protected from
+//! tampering, such as the storage path of pRuntime, which Gramine does not encrypt. So every
+//! node read from the log is checked against its hash.
+//!
+//! pRuntime keeps its chain storage in this backend, through `NodeDB`, when started with
+//! `--disk-chain-storage`. A checkpoint then only refers to the log, see `DiskCheckpoint`.
+//!
+//! The log is compacted by rewriting it as a new generation, which invalidates every
+//! `DiskCheckpoint` taken before. See `DiskDB::checkpoint` for what this means for restoring.
+
+use crate::MemoryDB;
+use hash_db::{Hasher, Prefix};
+use lru::LruCache;
+use parity_scale_codec::{Codec, Decode, Encode, Input};
+use sp_state_machine::{DefaultError, TrieBackendStorage};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use trie_db::DBValue;
+
+/// The number of nodes cached in memory by default.
+pub const DEFAULT_CACHE_SIZE: usize = 1 << 16;
+
+/// Compact the log once its garbage exceeds both the size of the live nodes and this many bytes.
+const COMPACT_THRESHOLD: u64 = 64 << 20;
+
+/// The length of the file header, which holds the generation of the log.
+const HEADER_LEN: u64 = 8;
+
+#[derive(Encode, Decode)]
+struct Record<K> {
+    key: K,
+    /// The reference count of the node after this record. Zero or less removes the node.
+    rc: i32,
+    /// The length of the node data following the record, if the record adds the node.
+    value_len: Option<u32>,
+}
+
+struct Node {
+    offset: u64,
+    len: u32,
+    rc: i32,
+}
+
+struct Inner<K> {
+    path: PathBuf,
+    file: File,
+    generation: u64,
+    len: u64,
+    live_bytes: u64,
+    index: HashMap<K, Node>,
+    cache: LruCache<K, DBValue>,
+}
+
+/// A reference-counted trie node database stored in an append-only log file.
+///
+/// The handle is cheap to clone, all clones share the same underlying file.
+pub struct DiskDB<H: Hasher> {
+    inner: Arc<Mutex<Inner<H::Out>>>,
+    hashed_null_node: H::Out,
+}
+
+impl<H: Hasher> Clone for DiskDB<H> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            hashed_null_node: self.hashed_null_node,
+        }
+    }
+}
+
+/// What a checkpoint of a disk backed `TrieStorage` refers to.
+///
+/// It can only be reopened while the log is still at the same generation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiskCheckpoint {
+    pub path: String,
+    pub generation: u64,
+    pub len: u64,
+    pub cache_size: u32,
+}
+
+struct LogReader<R> {
+    inner: R,
+    pos: u64,
+}
+
+impl<R: Read> Input for LogReader<R> {
+    fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+        Ok(None)
+    }
+
+    fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+        self.inner
+            .read_exact(into)
+            .map_err(|_| "Unexpected end of the log")?;
+        self.pos += into.len() as u64;
+        Ok(())
+    }
+}
+
+impl<H: Hasher> DiskDB<H>
+where
+    H::Out: Codec,
+{
+    /// Open the database at `path`, creating it if it does not exist.
+    ///
+    /// A partially written record at the end of the log is discarded.
+    pub fn open(path: impl AsRef<Path>, cache_size: usize) -> io::Result<Self> {
+        Self::open_inner(path.as_ref(), cache_size, None)
+    }
+
+    /// Reopen the database as it was when `checkpoint` was taken.
+    ///
+    /// Any changes written after the checkpoint are discarded. Fails if the log has been
+    /// compacted since, which happens only when a newer checkpoint is taken.
+    pub fn open_checkpoint(checkpoint: &DiskCheckpoint) -> io::Result<Self> {
+        Self::open_inner(
+            checkpoint.path.as_ref(),
+            checkpoint.cache_size as usize,
+            Some((checkpoint.generation, checkpoint.len)),
+        )
+    }
+
+    fn open_inner(path: &Path, cache_size: usize, at: Option<(u64, u64)>) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let file_len = file.metadata()?.len();
+        let generation = if file_len < HEADER_LEN {
+            file.set_len(0)?;
+            file.write_all(&0_u64.to_le_bytes())?;
+            0
+        } else {
+            let mut header = [0u8; HEADER_LEN as usize];
+            file.read_exact(&mut header)?;
+            u64::from_le_bytes(header)
+        };
+        let limit = match at {
+            Some((expected, len)) => {
+                if expected != generation || len > file_len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "The trie node log has changed since the checkpoint",
+                    ));
+                }
+                len
+            }
+            None => file_len,
+        };
+
+        file.seek(SeekFrom::Start(HEADER_LEN))?;
+        let mut reader = LogReader {
+            inner: BufReader::new((&mut file).take(limit.saturating_sub(HEADER_LEN))),
+            pos: HEADER_LEN,
+        };
+        let mut index = HashMap::new();
+        let mut live_bytes = 0;
+        let mut valid_len = HEADER_LEN;
+        while reader.pos < limit {
+            let record: Record<H::Out> = match Decode::decode(&mut reader) {
+                Ok(record) => record,
+                Err(_) => break,
+            };
+            if let Some(len) = record.value_len {
+                let offset = reader.pos;
+                let skipped = io::copy(&mut (&mut reader.inner).take(len as u64), &mut io::sink())?;
+                if skipped != len as u64 {
+                    break;
+                }
+                reader.pos += skipped;
+                if let Some(old) = index.insert(
+                    record.key,
+                    Node {
+                        offset,
+                        len,
+                        rc: record.rc,
+                    },
+                ) {
+                    live_bytes -= old.len as u64;
+                }
+                live_bytes += len as u64;
+            } else if record.rc <= 0 {
+                if let Some(old) = index.remove(&record.key) {
+                    live_bytes -= old.len as u64;
+                }
+            } else if let Some(node) = index.get_mut(&record.key) {
+                node.rc = record.rc;
+            }
+            valid_len = reader.pos;
+        }
+        drop(reader);
+        if valid_len < file_len {
+            file.set_len(valid_len)?;
+        }
+        let cache_size = NonZeroUsize::new(cache_size).unwrap_or(NonZeroUsize::new(1).unwrap());
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Inner {
+                path: path.to_path_buf(),
+                file,
+                generation,
+                len: valid_len,
+                live_bytes,
+                index,
+                cache: LruCache::new(cache_size),
+            })),
+            hashed_null_node: H::hash(&[0u8]),
+        })
+    }
+
+    /// Whether the node with the given hash is in the database.
+    pub fn contains(&self, key: &H::Out) -> bool {
+        key == &self.hashed_null_node || self.lock().index.contains_key(key)
+    }
+
+    /// The number of nodes in the database.
+    pub fn len(&self) -> usize {
+        self.lock().index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all nodes from the database.
+    ///
+    /// This starts a new generation of the log, like compacting it does.
+    pub fn clear(&self) -> io::Result<()> {
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        let generation = inner.generation + 1;
+        inner.file.set_len(0)?;
+        // The nodes are gone from here on, even if writing the header fails below.
+        inner.len = 0;
+        inner.live_bytes = 0;
+        inner.index.clear();
+        inner.cache.clear();
+        inner.file.seek(SeekFrom::Start(0))?;
+        inner.file.write_all(&generation.to_le_bytes())?;
+        inner.generation = generation;
+        inner.len = HEADER_LEN;
+        Ok(())
+    }
+
+    /// Apply the changes of a transaction calculated by the trie.
+    ///
+    /// The records are written to the log in one go. If the write fails, the database is left
+    /// unchanged and the error is returned.
+    pub fn commit(&self, mut transaction: MemoryDB<H>) -> io::Result<()> {
+        enum Change {
+            Remove,
+            SetRc(i32),
+            Insert(Node),
+        }
+
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        if inner.len < HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "The trie node log was not cleared completely",
+            ));
+        }
+        let mut buffer = Vec::new();
+        let mut changes = Vec::new();
+        let base = inner.len;
+        for (key, (value, delta)) in transaction.drain() {
+            if delta == 0 || key == self.hashed_null_node {
+                continue;
+            }
+            let current = inner.index.get(&key).map(|node| node.rc);
+            let rc = current.unwrap_or(0) + delta;
+            if rc <= 0 {
+                // Nodes are only removed as a whole. A reference dropped before the node is
+                // inserted is not kept, the trie never produces one for existing state.
+                if current.is_some() {
+                    Record {
+                        key,
+                        rc: 0,
+                        value_len: None,
+                    }
+                    .encode_to(&mut buffer);
+                    changes.push((key, Change::Remove));
+                }
+                continue;
+            }
+            if current.is_some() {
+                Record {
+                    key,
+                    rc,
+                    value_len: None,
+                }
+                .encode_to(&mut buffer);
+                changes.push((key, Change::SetRc(rc)));
+            } else {
+                let len = value.len() as u32;
+                Record {
+                    key,
+                    rc,
+                    value_len: Some(len),
+                }
+                .encode_to(&mut buffer);
+                let node = Node {
+                    offset: base + buffer.len() as u64,
+                    len,
+                    rc,
+                };
+                buffer.extend_from_slice(&value);
+                changes.push((key, Change::Insert(node)));
+            }
+        }
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let result = inner
+            .file
+            .seek(SeekFrom::Start(base))
+            .and_then(|_| inner.file.write_all(&buffer));
+        if let Err(err) = result {
+            // Cut what has been written, or reopening the log would replay it.
+            let _ = inner.file.set_len(base);
+            return Err(err);
+        }
+        inner.len += buffer.len() as u64;
+        for (key, change) in changes {
+            match change {
+                Change::Remove => {
+                    if let Some(node) = inner.index.remove(&key) {
+                        inner.live_bytes -= node.len as u64;
+                        inner.cache.pop(&key);
+                    }
+                }
+                Change::SetRc(rc) => {
+                    if let Some(node) = inner.index.get_mut(&key) {
+                        node.rc = rc;
+                    }
+                }
+                Change::Insert(node) => {
+                    inner.live_bytes += node.len as u64;
+                    inner.index.insert(key, node);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sync the log to disk, compacting it first if it holds too much garbage, and return the
+    /// point which the database can be reopened at with `open_checkpoint`.
+    ///
+    /// Compacting starts a new generation of the log, so once this returns, only the returned
+    /// checkpoint and later ones can be reopened. A restore which falls back to an older
+    /// checkpoint of the state holding this one fails with the trie node db.
+    pub fn checkpoint(&self) -> io::Result<DiskCheckpoint> {
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        let garbage = inner.len - inner.live_bytes;
+        if garbage > inner.live_bytes && garbage > COMPACT_THRESHOLD {
+            inner.compact()?;
+        }
+        inner.file.sync_data()?;
+        Ok(DiskCheckpoint {
+            path: inner.path.to_string_lossy().into(),
+            generation: inner.generation,
+            len: inner.len,
+            cache_size: inner.cache.cap().get() as u32,
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner<H::Out>> {
+        self.inner.lock().expect("Trie node db poisoned")
+    }
+}
+
+impl<K: Codec + Copy + Eq + std::hash::Hash> Inner<K> {
+    fn read_value(&mut self, node_offset: u64, len: u32) -> io::Result<DBValue> {
+        let mut value = vec![0u8; len as usize];
+        self.file.seek(SeekFrom::Start(node_offset))?;
+        self.file.read_exact(&mut value)?;
+        Ok(value)
+    }
+
+    /// Rewrite the log with only the live nodes, as a new generation.
+    fn compact(&mut self) -> io::Result<()> {
+        let generation = self.generation + 1;
+        let tmp_path = self.path.with_extension("compacting");
+        let mut tmp_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        let mut writer = io::BufWriter::new(&mut tmp_file);
+        writer.write_all(&generation.to_le_bytes())?;
+        let mut len = HEADER_LEN;
+        let keys: Vec<K> = self.index.keys().copied().collect();
+        let mut new_offsets = Vec::with_capacity(keys.len());
+        for key in keys.iter() {
+            let (offset, node_len, rc) = {
+                let node = &self.index[key];
+                (node.offset, node.len, node.rc)
+            };
+            let value = self.read_value(offset, node_len)?;
+            let record = Record {
+                key: *key,
+                rc,
+                value_len: Some(node_len),
+            }
+            .encode();
+            writer.write_all(&record)?;
+            writer.write_all(&value)?;
+            new_offsets.push(len + record.len() as u64);
+            len += (record.len() + value.len()) as u64;
+        }
+        writer.flush()?;
+        drop(writer);
+        tmp_file.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        for (key, offset) in keys.iter().zip(new_offsets) {
+            if let Some(node) = self.index.get_mut(key) {
+                node.offset = offset;
+            }
+        }
+        self.file = tmp_file;
+        self.generation = generation;
+        self.len = len;
+        Ok(())
+    }
+}
+
+impl<H: Hasher> TrieBackendStorage<H> for DiskDB<H>
+where
+    H::Out: Codec,
+{
+    type Overlay = MemoryDB<H>;
+
+    fn get(&self, key: &H::Out, _prefix: Prefix) -> Result<Option<DBValue>, DefaultError> {
+        if key == &self.hashed_null_node {
+            return Ok(Some(vec![0u8]));
+        }
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        if let Some(value) = inner.cache.get(key) {
+            return Ok(Some(value.clone()));
+        }
+        let (offset, len) = match inner.index.get(key) {
+            Some(node) => (node.offset, node.len),
+            None => return Ok(None),
+        };
+        let value = inner
+            .read_value(offset, len)
+            .map_err(|err| format!("Failed to read trie node: {}", err))?;
+        if H::hash(&value) != *key {
+            return Err(format!("Trie node {:?} corrupted on disk", key));
+        }
+        inner.cache.put(*key, value.clone());
+        Ok(Some(value))
+    }
+}
@@ -3,7 +3,13 @@ extern crate alloc;
 #[cfg(feature = "serde")]
 pub mod ser;
 
+#[cfg(feature = "std")]
+mod disk;
 mod memdb;
+#[cfg(feature = "std")]
+mod node_db;
+#[cfg(feature = "serde")]
+mod stream;
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -15,7 +21,9 @@ use alloc::vec::Vec;
 use parity_scale_codec::Codec;
 use sp_core::storage::ChildInfo;
 use sp_core::Hasher;
-use sp_state_machine::{Backend, TrieBackend, TrieBackendBuilder};
+use sp_state_machine::{
+    Backend, DefaultError, TrieBackend, TrieBackendBuilder, TrieBackendStorage,
+};
 use sp_trie::{
     TrieMut,
     trie_types::{
@@ -23,7 +31,11 @@ use sp_trie::{
     },
 };
 
-pub use memdb::GenericMemoryDB as MemoryDB;
+#[cfg(feature = "std")]
+pub use disk::{DiskCheckpoint, DiskDB, DEFAULT_CACHE_SIZE};
+pub use memdb::{GcStats, GenericMemoryDB as MemoryDB};
+#[cfg(feature = "std")]
+pub use node_db::NodeDB;
 
 /// Storage key.
 pub type StorageKey = Vec<u8>;
@@ -37,15 +49,134 @@ pub type StorageCollection = Vec<(StorageKey, Option<StorageValue>)>;
 /// In memory arrays of storage values for multiple child tries.
 pub type ChildStorageCollection = Vec<(StorageKey, StorageCollection)>;
 
+/// The trie nodes proving some storage values against a state root.
+pub type StorageProof = Vec<Vec<u8>>;
+
 pub type InMemoryBackend<H> = TrieBackend<MemoryDB<H>, H>;
-pub struct TrieStorage<H: Hasher>(InMemoryBackend<H>);
+#[cfg(feature = "std")]
+pub type DiskBackend<H> = TrieBackend<DiskDB<H>, H>;
+
+/// A database of trie nodes which a `TrieStorage` can be built upon.
+pub trait TrieNodeDB<H: Hasher>: TrieBackendStorage<H, Overlay = MemoryDB<H>> + Sized {
+    /// The error of writing nodes to the database.
+    type Error;
+
+    /// Replace everything in `backend` with the nodes of `trie`.
+    ///
+    /// On failure `backend` is left empty.
+    fn reset(
+        backend: &mut TrieBackend<Self, H>,
+        trie: InMemoryBackend<H>,
+    ) -> Result<(), Self::Error>;
+
+    /// Apply a `transaction` calculated by the trie to `backend` and move it to `root`.
+    ///
+    /// On failure `backend` is left unchanged.
+    fn commit(
+        backend: &mut TrieBackend<Self, H>,
+        root: H::Out,
+        transaction: MemoryDB<H>,
+    ) -> Result<(), Self::Error>;
+
+    /// Reclaim the nodes no longer referenced by the trie.
+    fn gc(backend: &mut TrieBackend<Self, H>) -> GcStats;
+}
+
+impl<H: Hasher> TrieNodeDB<H> for MemoryDB<H>
+where
+    H::Out: Codec,
+{
+    type Error = core::convert::Infallible;
+
+    fn reset(
+        backend: &mut TrieBackend<Self, H>,
+        trie: InMemoryBackend<H>,
+    ) -> Result<(), Self::Error> {
+        *backend = trie;
+        Ok(())
+    }
+
+    fn commit(
+        backend: &mut TrieBackend<Self, H>,
+        root: H::Out,
+        transaction: MemoryDB<H>,
+    ) -> Result<(), Self::Error> {
+        let taken = core::mem::replace(
+            backend,
+            TrieBackendBuilder::new(Default::default(), Default::default()).build(),
+        );
+        let mut storage = taken.into_storage();
+        storage.consolidate(transaction);
+        *backend = TrieBackendBuilder::new(storage, root).build();
+        Ok(())
+    }
+
+    fn gc(backend: &mut TrieBackend<Self, H>) -> GcStats {
+        let root = *backend.root();
+        let taken = core::mem::replace(
+            backend,
+            TrieBackendBuilder::new(Default::default(), Default::default()).build(),
+        );
+        let mut storage = taken.into_storage();
+        let stats = storage.gc();
+        *backend = TrieBackendBuilder::new(storage, root).build();
+        stats
+    }
+}
+
+#[cfg(feature = "std")]
+impl<H: Hasher> TrieNodeDB<H> for DiskDB<H>
+where
+    H::Out: Codec,
+{
+    type Error = std::io::Error;
+
+    fn reset(
+        backend: &mut TrieBackend<Self, H>,
+        trie: InMemoryBackend<H>,
+    ) -> Result<(), Self::Error> {
+        let root = *trie.root();
+        let db = backend.backend_storage().clone();
+        *backend = TrieBackendBuilder::new(db.clone(), Default::default()).build();
+        db.clear()?;
+        db.commit(trie.into_storage())?;
+        *backend = TrieBackendBuilder::new(db, root).build();
+        Ok(())
+    }
+
+    fn commit(
+        backend: &mut TrieBackend<Self, H>,
+        root: H::Out,
+        transaction: MemoryDB<H>,
+    ) -> Result<(), Self::Error> {
+        let db = backend.backend_storage().clone();
+        db.commit(transaction)?;
+        *backend = TrieBackendBuilder::new(db, root).build();
+        Ok(())
+    }
+
+    fn gc(_backend: &mut TrieBackend<Self, H>) -> GcStats {
+        // Nodes are dropped from the disk as soon as they are no longer referenced.
+        Default::default()
+    }
+}
+
+/// The trie of the chain state, keeping its nodes in an in-memory `MemoryDB` by default, or
+/// on disk with a `DiskDB`.
+pub struct TrieStorage<H: Hasher, DB = MemoryDB<H>> {
+    backend: TrieBackend<DB, H>,
+    /// Net changes applied to the trie DB since the journal was last reset, if journaling.
+    journal: Option<MemoryDB<H>>,
+    /// What the garbage collection has reclaimed since the storage was created or restored.
+    gc_stats: GcStats,
+}
 
 impl<H: Hasher> Default for TrieStorage<H>
 where
     H::Out: Codec,
 {
     fn default() -> Self {
-        Self(TrieBackendBuilder::new(Default::default(), Default::default()).build())
+        Self::from_backend(TrieBackendBuilder::new(Default::default(), Default::default()).build())
     }
 }
 
@@ -68,6 +199,7 @@ where
     TrieBackendBuilder::new(mdb, root).build()
 }
 
+/// Serialize the trie as its root and nodes, writing the nodes one by one.
 #[cfg(feature = "serde")]
 pub fn serialize_trie_backend<H: Hasher, S>(
     trie: &TrieBackend<MemoryDB<H>, H>,
@@ -77,11 +209,10 @@ where
     H::Out: Codec + Serialize,
     S: Serializer,
 {
-    let root = trie.root();
-    let kvs: im::HashMap<_, _> = trie.backend_storage().clone().drain();
-    (root, kvs).serialize(serializer)
+    stream::serialize_trie(trie.root(), trie.backend_storage(), serializer)
 }
 
+/// Deserialize a trie written by `serialize_trie_backend`, inserting the nodes one by one.
 #[cfg(feature = "serde")]
 pub fn deserialize_trie_backend<'de, H: Hasher, De>(
     deserializer: De,
@@ -90,13 +221,19 @@ where
     H::Out: Codec + Deserialize<'de>,
     De: Deserializer<'de>,
 {
-    let (root, kvs): (H::Out, im::HashMap<_, (Vec<u8>, i32)>) =
-        Deserialize::deserialize(deserializer)?;
+    let mut kvs = im::HashMap::new();
+    let root = stream::deserialize_trie(deserializer, |key, value, rc| {
+        kvs.insert(key, (value, rc));
+    })?;
     let mdb = MemoryDB::from_inner(kvs);
     let backend = TrieBackendBuilder::new(mdb, root).build();
     Ok(backend)
 }
 
+/// Take a snapshot of `trie`.
+///
+/// This is O(1): the node map of `MemoryDB` is a persistent `im::HashMap`, so the snapshot shares
+/// every node with `trie`, and later commits on either side only copy the paths they touch.
 pub fn clone_trie_backend<H: Hasher>(
     trie: &TrieBackend<MemoryDB<H>, H>,
 ) -> TrieBackend<MemoryDB<H>, H>
@@ -110,14 +247,89 @@ where
     TrieBackendBuilder::new(mdb, *root).build()
 }
 
+impl<H: Hasher, DB> TrieStorage<H, DB> {
+    fn from_backend(backend: TrieBackend<DB, H>) -> Self {
+        Self {
+            backend,
+            journal: None,
+            gc_stats: Default::default(),
+        }
+    }
+}
+
 impl<H: Hasher> TrieStorage<H>
 where
     H::Out: Codec + Ord,
 {
     /// Overwrite all data in the trie DB with given key/value pairs.
     pub fn load(&mut self, pairs: impl Iterator<Item = (impl AsRef<[u8]>, impl AsRef<[u8]>)>) {
-        let trie = load_trie_backend(pairs);
-        let _ = core::mem::replace(&mut self.0, trie);
+        match self.try_load(pairs) {
+            Ok(()) => (),
+            Err(never) => match never {},
+        }
+    }
+
+    /// Apply storage changes calculated from `calc_root_if_changes`.
+    pub fn apply_changes(&mut self, root: H::Out, transaction: MemoryDB<H>) {
+        match self.try_apply_changes(root, transaction) {
+            Ok(()) => (),
+            Err(never) => match never {},
+        }
+    }
+
+    /// A read-only view of the current state that later changes to `self` do not affect.
+    ///
+    /// See `clone_trie_backend` for the cost. The snapshot does not carry the journal.
+    pub fn snapshot(&self) -> Self {
+        Self::from_backend(clone_trie_backend(&self.backend))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<H: Hasher> TrieStorage<H, DiskDB<H>>
+where
+    H::Out: Codec,
+{
+    /// Create an empty trie storing its nodes in `db`.
+    ///
+    /// Nodes already in `db` are kept but not reachable until the storage is moved to their
+    /// root, usually by restoring a checkpoint.
+    pub fn with_disk_db(db: DiskDB<H>) -> Self {
+        Self::from_backend(TrieBackendBuilder::new(db, Default::default()).build())
+    }
+}
+
+impl<H: Hasher, DB: TrieNodeDB<H>> TrieStorage<H, DB>
+where
+    H::Out: Codec + Ord,
+{
+    /// Overwrite all data in the trie DB with given key/value pairs.
+    ///
+    /// The trie is left empty if the nodes can not be written to the DB.
+    pub fn try_load(
+        &mut self,
+        pairs: impl Iterator<Item = (impl AsRef<[u8]>, impl AsRef<[u8]>)>,
+    ) -> Result<(), DB::Error> {
+        self.journal = None;
+        DB::reset(&mut self.backend, load_trie_backend(pairs))
+    }
+
+    /// Start recording the changes applied to the trie DB, discarding any recorded ones.
+    ///
+    /// The recorded changes can be written out with `serialize_delta` and applied to a copy of
+    /// the trie taken at the time of the reset to bring it up to date.
+    pub fn reset_journal(&mut self) {
+        self.journal = Some(Default::default());
+    }
+
+    /// Stop recording the changes applied to the trie DB.
+    pub fn stop_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// Whether the changes applied to the trie DB are being recorded.
+    pub fn is_journaling(&self) -> bool {
+        self.journal.is_some()
     }
 
     /// Calculate the new state root given storage changes. Returns the new root and a transaction to apply.
@@ -134,7 +346,7 @@ where
                 (chinfo, v)
             })
             .collect();
-        self.0.full_storage_root(
+        self.backend.full_storage_root(
             delta
                 .iter()
                 .map(|(k, v)| (k.as_ref(), v.as_ref().map(|v| v.as_ref()))),
@@ -150,25 +362,142 @@ where
     }
 
     /// Apply storage changes calculated from `calc_root_if_changes`.
-    pub fn apply_changes(&mut self, root: H::Out, transaction: MemoryDB<H>) {
-        let mut storage = core::mem::take(self).0.into_storage();
-        storage.consolidate(transaction);
-        let _ = core::mem::replace(
-            &mut self.0,
-            TrieBackendBuilder::new(storage, root).build()
-        );
+    ///
+    /// The trie is left unchanged if the nodes can not be written to the DB.
+    pub fn try_apply_changes(
+        &mut self,
+        root: H::Out,
+        transaction: MemoryDB<H>,
+    ) -> Result<(), DB::Error> {
+        let journaled = self.journal.as_ref().map(|_| transaction.clone());
+        DB::commit(&mut self.backend, root, transaction)?;
+        if let (Some(journal), Some(transaction)) = (&mut self.journal, journaled) {
+            journal.consolidate(transaction);
+        }
+        Ok(())
     }
 
-    pub fn purge(&mut self) {}
+    /// Reclaim the trie nodes no longer referenced, returning what this pass has reclaimed.
+    pub fn purge(&mut self) -> GcStats {
+        let stats = DB::gc(&mut self.backend);
+        self.gc_stats.accumulate(stats);
+        stats
+    }
+
+    /// What the garbage collection has reclaimed in total.
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc_stats
+    }
 
     /// Return the state root hash
     pub fn root(&self) -> &H::Out {
-        self.0.root()
+        self.backend.root()
     }
 
     /// Given storage key return storage value
     pub fn get(&self, key: impl AsRef<[u8]>) -> Option<Vec<u8>> {
-        self.0.storage(key.as_ref()).ok().flatten()
+        self.backend.storage(key.as_ref()).ok().flatten()
+    }
+
+    /// Generate a proof of the values of `keys` against the current root.
+    ///
+    /// The proof consists of the trie nodes visited while reading the keys, so a missing key
+    /// is proven absent as well.
+    #[cfg(feature = "std")]
+    pub fn read_proof<I>(&self, keys: I) -> Result<StorageProof, String>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        sp_state_machine::prove_read_on_trie_backend(&self.backend, keys)
+            .map(|proof| proof.into_nodes().into_iter().collect())
+            .map_err(|err| err.to_string())
+    }
+
+    /// Generate a proof of the values of `keys` in the default child trie `storage_key`.
+    ///
+    /// The proof includes the nodes of the main trie leading to the child trie root.
+    #[cfg(feature = "std")]
+    pub fn read_child_proof<I>(
+        &self,
+        storage_key: impl AsRef<[u8]>,
+        keys: I,
+    ) -> Result<StorageProof, String>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let child_info = ChildInfo::new_default(storage_key.as_ref());
+        sp_state_machine::prove_child_read_on_trie_backend(&self.backend, &child_info, keys)
+            .map(|proof| proof.into_nodes().into_iter().collect())
+            .map_err(|err| err.to_string())
+    }
+
+    /// Lazily iterate over the keys which start with `prefix`, in order.
+    ///
+    /// If `start_key` is given, the iteration starts right after it, so the last key of a page
+    /// can be passed to get the next page. A failure to read the trie DB is yielded as the last
+    /// item, so a truncated iteration can be told from a complete one.
+    pub fn keys_iter<'a>(
+        &'a self,
+        prefix: &[u8],
+        start_key: Option<&[u8]>,
+    ) -> impl Iterator<Item = Result<Vec<u8>, DefaultError>> + 'a {
+        enum Cursor {
+            At(Vec<u8>),
+            After(Vec<u8>),
+            Done,
+        }
+        let prefix = prefix.to_vec();
+        let mut cursor = match start_key {
+            Some(key) if key >= &prefix[..] => Cursor::After(key.to_vec()),
+            _ => Cursor::At(prefix.clone()),
+        };
+        core::iter::from_fn(move || {
+            let next = match core::mem::replace(&mut cursor, Cursor::Done) {
+                Cursor::At(key) => match self.backend.exists_storage(&key) {
+                    Ok(true) => Ok(Some(key)),
+                    Ok(false) => self.backend.next_storage_key(&key),
+                    Err(err) => Err(err),
+                },
+                Cursor::After(key) => self.backend.next_storage_key(&key),
+                Cursor::Done => Ok(None),
+            };
+            let key = match next {
+                Ok(key) => key?,
+                Err(err) => return Some(Err(err)),
+            };
+            if !key.starts_with(&prefix) {
+                return None;
+            }
+            cursor = Cursor::After(key.clone());
+            Some(Ok(key))
+        })
+    }
+
+    /// Lazily iterate over the pairs whose keys start with `prefix`, in order.
+    ///
+    /// See `keys_iter` for the meaning of `start_key` and how errors are yielded.
+    pub fn pairs_iter<'a>(
+        &'a self,
+        prefix: &[u8],
+        start_key: Option<&[u8]>,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), DefaultError>> + 'a {
+        let mut keys = Some(self.keys_iter(prefix, start_key));
+        core::iter::from_fn(move || loop {
+            let next = keys.as_mut()?.next()?.and_then(|key| {
+                let value = self.backend.storage(&key)?;
+                Ok(value.map(|value| (key, value)))
+            });
+            match next {
+                Ok(Some(pair)) => return Some(Ok(pair)),
+                Ok(None) => continue,
+                Err(err) => {
+                    keys = None;
+                    return Some(Err(err));
+                }
+            }
+        })
     }
 
     /// Return storage pairs which start with given storage key prefix
@@ -177,7 +506,7 @@ where
     }
 
     fn pairs_into<R: FromIterator<(Vec<u8>, Vec<u8>)>>(&self, prefix: impl AsRef<[u8]>) -> R {
-        self.0
+        self.backend
             .keys(prefix.as_ref())
             .into_iter()
             .map(|key| {
@@ -198,7 +527,7 @@ const _: () = {
         where
             S: Serializer,
         {
-            serialize_trie_backend(&self.0, serializer)
+            serialize_trie_backend(&self.backend, serializer)
         }
     }
 
@@ -210,7 +539,93 @@ const _: () = {
         where
             D: Deserializer<'de>,
         {
-            Ok(Self(deserialize_trie_backend(deserializer)?))
+            Ok(Self::from_backend(deserialize_trie_backend(deserializer)?))
+        }
+    }
+
+    impl<H: Hasher> TrieStorage<H>
+    where
+        H::Out: Codec + Ord,
+    {
+        /// Serialize the changes recorded since the last `reset_journal` along with the
+        /// current root.
+        ///
+        /// Fails if the trie is not journaling.
+        pub fn serialize_delta<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+            H::Out: Serialize,
+        {
+            let journal = self
+                .journal
+                .as_ref()
+                .ok_or_else(|| serde::ser::Error::custom("Trie storage is not journaling"))?;
+            stream::serialize_trie(self.root(), journal, serializer)
+        }
+
+        /// Deserialize a delta written by `serialize_delta` and apply it to `self`, which must
+        /// be the trie as it was when the journal of the delta was reset.
+        pub fn deserialize_delta<'de, D>(self, deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+            H::Out: Deserialize<'de>,
+        {
+            let mut storage = self.backend.into_storage();
+            let root = stream::deserialize_trie(deserializer, |key, value, rc| {
+                storage.consolidate_entry(key, value, rc);
+            })?;
+            if !hash_db::HashDB::contains(&storage, &root, hash_db::EMPTY_PREFIX) {
+                return Err(serde::de::Error::custom(
+                    "State root missing after applying the delta, wrong base?",
+                ));
+            }
+            Ok(Self::from_backend(
+                TrieBackendBuilder::new(storage, root).build(),
+            ))
+        }
+    }
+
+    /// A disk backed trie is serialized as its root and a reference to the `DiskDB`, which
+    /// gets reopened as it was at the time of serialization when deserializing.
+    #[cfg(feature = "std")]
+    impl<H: Hasher> Serialize for TrieStorage<H, DiskDB<H>>
+    where
+        H::Out: Codec + Serialize + Ord,
+    {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            let checkpoint = self
+                .backend
+                .backend_storage()
+                .checkpoint()
+                .map_err(|err| serde::ser::Error::custom(format!("{}", err)))?;
+            (self.root(), checkpoint).serialize(serializer)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl<'de, H: Hasher> Deserialize<'de> for TrieStorage<H, DiskDB<H>>
+    where
+        H::Out: Codec + Deserialize<'de> + Ord,
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let (root, checkpoint): (H::Out, DiskCheckpoint) =
+                Deserialize::deserialize(deserializer)?;
+            let db = DiskDB::open_checkpoint(&checkpoint)
+                .map_err(|err| serde::de::Error::custom(format!("{}", err)))?;
+            if !db.contains(&root) {
+                return Err(serde::de::Error::custom(
+                    "State root missing in the trie node db",
+                ));
+            }
+            Ok(Self::from_backend(
+                TrieBackendBuilder::new(db, root).build(),
+            ))
         }
     }
 };
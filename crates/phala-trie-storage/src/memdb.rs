@@ -247,32 +247,42 @@ where
     /// Consolidate all the entries of `other` into `self`.
     pub fn consolidate(&mut self, mut other: Self) {
         for (key, (value, rc)) in other.drain() {
-            if rc == 0 {
-                continue;
-            }
-            match self.data.entry(key) {
-                Entry::Occupied(mut entry) => {
-                    if entry.get().1 < 0 {
-                        self.malloc_tracker.on_insert(&value);
-                        self.malloc_tracker.on_remove(&entry.get().0);
-                        entry.get_mut().0 = value;
-                    }
-
-                    entry.get_mut().1 += rc;
-
-                    if entry.get().1 == 0 {
-                        let (value, _) = entry.remove();
-                        self.malloc_tracker.on_remove(&value);
-                    }
-                }
-                Entry::Vacant(entry) => {
+            self.consolidate_entry(key, value, rc);
+        }
+    }
+
+    /// Consolidate a single raw entry, as returned by `iter` or `drain`, into `self`.
+    pub fn consolidate_entry(&mut self, key: KF::Key, value: T, rc: i32) {
+        if rc == 0 {
+            return;
+        }
+        match self.data.entry(key) {
+            Entry::Occupied(mut entry) => {
+                if entry.get().1 < 0 {
                     self.malloc_tracker.on_insert(&value);
-                    entry.insert((value, rc));
+                    self.malloc_tracker.on_remove(&entry.get().0);
+                    entry.get_mut().0 = value;
                 }
+
+                entry.get_mut().1 += rc;
+
+                if entry.get().1 == 0 {
+                    let (value, _) = entry.remove();
+                    self.malloc_tracker.on_remove(&value);
+                }
+            }
+            Entry::Vacant(entry) => {
+                self.malloc_tracker.on_insert(&value);
+                entry.insert((value, rc));
             }
         }
     }
 
+    /// Iterate over the raw entries, including the ones with a non-positive reference count.
+    pub fn iter(&self) -> impl Iterator<Item = (&KF::Key, &(T, i32))> {
+        self.data.iter()
+    }
+
     /// Get the keys in the database together with number of underlying references.
     pub fn keys(&self) -> HashMap<KF::Key, i32> {
         self.data
@@ -288,6 +298,49 @@ where
     }
 }
 
+/// What a garbage collection pass over a `MemoryDB` has reclaimed.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcStats {
+    pub reclaimed_nodes: u64,
+    pub reclaimed_bytes: u64,
+}
+
+impl GcStats {
+    /// Add the stats of another pass to `self`.
+    pub fn accumulate(&mut self, other: GcStats) {
+        self.reclaimed_nodes += other.reclaimed_nodes;
+        self.reclaimed_bytes += other.reclaimed_bytes;
+    }
+}
+
+impl<H, KF, T, M> MemoryDB<H, KF, T, M>
+where
+    H: KeyHasher,
+    T: AsRef<[u8]> + Clone,
+    KF: KeyFunction<H>,
+    M: MemTracker<T>,
+{
+    /// Remove all entries with a zero or negative reference count.
+    ///
+    /// Unlike `purge`, this also drops the pending removals of nodes which are not in the
+    /// database. Such an entry never refers to a live node, but it would swallow the node if
+    /// the same node got inserted later.
+    pub fn gc(&mut self) -> GcStats {
+        let mut stats = GcStats::default();
+        let malloc_tracker = &mut self.malloc_tracker;
+        self.data.retain(|_, (v, rc)| {
+            let keep = *rc > 0;
+            if !keep {
+                stats.reclaimed_nodes += 1;
+                stats.reclaimed_bytes += v.as_ref().len() as u64;
+                malloc_tracker.on_remove(v);
+            }
+            keep
+        });
+        stats
+    }
+}
+
 impl<H, KF, T, M> MallocSizeOf for MemoryDB<H, KF, T, M>
 where
     H: KeyHasher,
@@ -678,6 +731,42 @@ mod tests {
         assert!(m.remove_and_purge(&hello_key, EMPTY_PREFIX).is_none());
     }
 
+    #[test]
+    fn gc_reclaims_dead_entries() {
+        let hello_bytes = b"Hello world!";
+        let hello_key = KeccakHasher::hash(hello_bytes);
+
+        let mut m = MemoryDB::<KeccakHasher, HashKey<_>, Vec<u8>>::default();
+        let live_key = m.insert(EMPTY_PREFIX, b"live");
+        m.remove(&hello_key, EMPTY_PREFIX);
+        assert_eq!(m.raw(&hello_key, EMPTY_PREFIX).unwrap().1, -1);
+        let stats = m.gc();
+        assert_eq!(stats.reclaimed_nodes, 1);
+        assert_eq!(m.raw(&hello_key, EMPTY_PREFIX), None);
+        assert_eq!(m.raw(&live_key, EMPTY_PREFIX).unwrap().1, 1);
+
+        // The node is no longer swallowed by the dropped removal
+        m.insert(EMPTY_PREFIX, hello_bytes);
+        assert_eq!(m.raw(&hello_key, EMPTY_PREFIX).unwrap().1, 1);
+
+        let mut other = MemoryDB::<KeccakHasher, HashKey<_>, Vec<u8>>::default();
+        other.insert(EMPTY_PREFIX, hello_bytes);
+        other.remove(&hello_key, EMPTY_PREFIX);
+        other.remove(&hello_key, EMPTY_PREFIX);
+        other.remove(&hello_key, EMPTY_PREFIX);
+        m.consolidate(other);
+        assert_eq!(m.raw(&hello_key, EMPTY_PREFIX).unwrap().1, -1);
+        let stats = m.gc();
+        assert_eq!(
+            stats,
+            super::GcStats {
+                reclaimed_nodes: 1,
+                reclaimed_bytes: hello_bytes.len() as u64,
+            }
+        );
+        assert_eq!(m.gc(), Default::default());
+    }
+
     #[test]
     fn consolidate() {
         let mut main = MemoryDB::<KeccakHasher, HashKey<_>, Vec<u8>>::default();
@@ -0,0 +1,292 @@
+//! A trie node database kept either in memory or on disk, chosen at runtime.
+//!
+//! This lets a single `TrieStorage` type serve as the chain storage whichever backend the
+//! runtime has been configured with.
+
+use crate::{DiskDB, GcStats, InMemoryBackend, MemoryDB, TrieNodeDB, TrieStorage};
+use hash_db::{Hasher, Prefix};
+use parity_scale_codec::Codec;
+use sp_state_machine::{DefaultError, TrieBackend, TrieBackendBuilder, TrieBackendStorage};
+use trie_db::DBValue;
+
+/// The trie nodes of a `TrieStorage`, either in a `MemoryDB` or in a `DiskDB`.
+pub enum NodeDB<H: Hasher> {
+    Memory(MemoryDB<H>),
+    Disk(DiskDB<H>),
+}
+
+impl<H: Hasher> Default for NodeDB<H> {
+    fn default() -> Self {
+        Self::Memory(Default::default())
+    }
+}
+
+impl<H: Hasher> TrieBackendStorage<H> for NodeDB<H>
+where
+    H::Out: Codec,
+{
+    type Overlay = MemoryDB<H>;
+
+    fn get(&self, key: &H::Out, prefix: Prefix) -> Result<Option<DBValue>, DefaultError> {
+        match self {
+            Self::Memory(db) => TrieBackendStorage::get(db, key, prefix),
+            Self::Disk(db) => TrieBackendStorage::get(db, key, prefix),
+        }
+    }
+}
+
+/// A backend over `NodeDB` taken apart into a backend over the concrete database.
+enum Split<H: Hasher> {
+    Memory(TrieBackend<MemoryDB<H>, H>),
+    Disk(TrieBackend<DiskDB<H>, H>),
+}
+
+fn split<H: Hasher>(backend: &mut TrieBackend<NodeDB<H>, H>) -> Split<H>
+where
+    H::Out: Codec,
+{
+    let root = *backend.root();
+    let taken = core::mem::replace(
+        backend,
+        TrieBackendBuilder::new(Default::default(), Default::default()).build(),
+    );
+    match taken.into_storage() {
+        NodeDB::Memory(db) => Split::Memory(TrieBackendBuilder::new(db, root).build()),
+        NodeDB::Disk(db) => Split::Disk(TrieBackendBuilder::new(db, root).build()),
+    }
+}
+
+fn join<H: Hasher>(backend: &mut TrieBackend<NodeDB<H>, H>, split: Split<H>)
+where
+    H::Out: Codec,
+{
+    *backend = match split {
+        Split::Memory(inner) => {
+            let root = *inner.root();
+            TrieBackendBuilder::new(NodeDB::Memory(inner.into_storage()), root).build()
+        }
+        Split::Disk(inner) => {
+            let root = *inner.root();
+            TrieBackendBuilder::new(NodeDB::Disk(inner.into_storage()), root).build()
+        }
+    };
+}
+
+fn infallible<T>(result: Result<T, core::convert::Infallible>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(never) => match never {},
+    }
+}
+
+impl<H: Hasher> TrieNodeDB<H> for NodeDB<H>
+where
+    H::Out: Codec,
+{
+    type Error = std::io::Error;
+
+    fn reset(
+        backend: &mut TrieBackend<Self, H>,
+        trie: InMemoryBackend<H>,
+    ) -> Result<(), Self::Error> {
+        let mut inner = split(backend);
+        let result = match &mut inner {
+            Split::Memory(inner) => Ok(infallible(MemoryDB::reset(inner, trie))),
+            Split::Disk(inner) => DiskDB::reset(inner, trie),
+        };
+        join(backend, inner);
+        result
+    }
+
+    fn commit(
+        backend: &mut TrieBackend<Self, H>,
+        root: H::Out,
+        transaction: MemoryDB<H>,
+    ) -> Result<(), Self::Error> {
+        let mut inner = split(backend);
+        let result = match &mut inner {
+            Split::Memory(inner) => Ok(infallible(MemoryDB::commit(inner, root, transaction))),
+            Split::Disk(inner) => DiskDB::commit(inner, root, transaction),
+        };
+        join(backend, inner);
+        result
+    }
+
+    fn gc(backend: &mut TrieBackend<Self, H>) -> GcStats {
+        let mut inner = split(backend);
+        let stats = match &mut inner {
+            Split::Memory(inner) => MemoryDB::gc(inner),
+            Split::Disk(inner) => DiskDB::gc(inner),
+        };
+        join(backend, inner);
+        stats
+    }
+}
+
+impl<H: Hasher> Default for TrieStorage<H, NodeDB<H>>
+where
+    H::Out: Codec,
+{
+    fn default() -> Self {
+        Self::from_backend(TrieBackendBuilder::new(Default::default(), Default::default()).build())
+    }
+}
+
+impl<H: Hasher> TrieStorage<H, NodeDB<H>>
+where
+    H::Out: Codec,
+{
+    /// Create an empty trie storing its nodes in `db`.
+    ///
+    /// See `TrieStorage::with_disk_db`.
+    pub fn on_disk(db: DiskDB<H>) -> Self {
+        Self::from_backend(TrieBackendBuilder::new(NodeDB::Disk(db), Default::default()).build())
+    }
+
+    /// Whether the nodes are kept on disk.
+    pub fn is_on_disk(&self) -> bool {
+        matches!(self.backend.backend_storage(), NodeDB::Disk(_))
+    }
+}
+
+#[cfg(feature = "serde")]
+const _: () = {
+    use crate::{stream, DiskCheckpoint};
+    use core::{fmt, marker::PhantomData};
+    use serde::{
+        de::{self, MapAccess, SeqAccess, Visitor},
+        ser::SerializeMap,
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+
+    /// The key which a reference to the on-disk nodes is serialized under.
+    const DISK: &str = "disk";
+
+    /// An in-memory trie is serialized in the same format as `TrieStorage<H>`, so checkpoints
+    /// written before the disk backend existed can still be restored. An on-disk one is
+    /// serialized as a map holding its root and a `DiskCheckpoint` under the key `"disk"`.
+    ///
+    /// Telling the two apart requires a self-describing format.
+    impl<H: Hasher> Serialize for TrieStorage<H, NodeDB<H>>
+    where
+        H::Out: Codec + Serialize + Ord,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self.backend.backend_storage() {
+                NodeDB::Memory(db) => stream::serialize_trie(self.root(), db, serializer),
+                NodeDB::Disk(db) => {
+                    let checkpoint = db
+                        .checkpoint()
+                        .map_err(|err| serde::ser::Error::custom(format!("{}", err)))?;
+                    let mut map = serializer.serialize_map(Some(1))?;
+                    map.serialize_entry(DISK, &(self.root(), checkpoint))?;
+                    map.end()
+                }
+            }
+        }
+    }
+
+    struct NodeDBVisitor<H>(PhantomData<H>);
+
+    impl<'de, H: Hasher> Visitor<'de> for NodeDBVisitor<H>
+    where
+        H::Out: Codec + Deserialize<'de> + Ord,
+    {
+        type Value = TrieStorage<H, NodeDB<H>>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a trie in memory or a reference to a trie on disk")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+            let mut kvs = im::HashMap::new();
+            let root = stream::visit_trie(seq, |key, value, rc| {
+                kvs.insert(key, (value, rc));
+            })?;
+            let db = NodeDB::Memory(MemoryDB::from_inner(kvs));
+            Ok(TrieStorage::from_backend(
+                TrieBackendBuilder::new(db, root).build(),
+            ))
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let key: alloc::string::String = map
+                .next_key()?
+                .ok_or_else(|| de::Error::custom("Missing trie node db"))?;
+            if key != DISK {
+                return Err(de::Error::unknown_field(&key, &[DISK]));
+            }
+            let (root, checkpoint): (H::Out, DiskCheckpoint) = map.next_value()?;
+            let db = DiskDB::open_checkpoint(&checkpoint)
+                .map_err(|err| de::Error::custom(format!("{}", err)))?;
+            if !db.contains(&root) {
+                return Err(de::Error::custom("State root missing in the trie node db"));
+            }
+            Ok(TrieStorage::from_backend(
+                TrieBackendBuilder::new(NodeDB::Disk(db), root).build(),
+            ))
+        }
+    }
+
+    impl<'de, H: Hasher> Deserialize<'de> for TrieStorage<H, NodeDB<H>>
+    where
+        H::Out: Codec + Deserialize<'de> + Ord,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(NodeDBVisitor(PhantomData))
+        }
+    }
+
+    impl<H: Hasher> TrieStorage<H, NodeDB<H>>
+    where
+        H::Out: Codec + Ord,
+    {
+        /// Like `TrieStorage::serialize_delta`.
+        ///
+        /// Fails if the nodes are on disk, a checkpoint of those refers to the log on disk
+        /// instead, which is already written incrementally.
+        pub fn serialize_delta<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+            H::Out: Serialize,
+        {
+            if self.is_on_disk() {
+                return Err(serde::ser::Error::custom(
+                    "Delta of a trie on disk is not supported",
+                ));
+            }
+            let journal = self
+                .journal
+                .as_ref()
+                .ok_or_else(|| serde::ser::Error::custom("Trie storage is not journaling"))?;
+            stream::serialize_trie(self.root(), journal, serializer)
+        }
+
+        /// Like `TrieStorage::deserialize_delta`. Fails if the nodes are on disk.
+        pub fn deserialize_delta<'de, D>(self, deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+            H::Out: Deserialize<'de>,
+        {
+            let mut storage = match self.backend.into_storage() {
+                NodeDB::Memory(storage) => storage,
+                NodeDB::Disk(_) => {
+                    return Err(de::Error::custom(
+                        "Delta of a trie on disk is not supported",
+                    ))
+                }
+            };
+            let root = stream::deserialize_trie(deserializer, |key, value, rc| {
+                storage.consolidate_entry(key, value, rc);
+            })?;
+            if !hash_db::HashDB::contains(&storage, &root, hash_db::EMPTY_PREFIX) {
+                return Err(de::Error::custom(
+                    "State root missing after applying the delta, wrong base?",
+                ));
+            }
+            Ok(Self::from_backend(
+                TrieBackendBuilder::new(NodeDB::Memory(storage), root).build(),
+            ))
+        }
+    }
+};
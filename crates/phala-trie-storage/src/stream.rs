@@ -0,0 +1,117 @@
+//! Serializes a trie as its root followed by its nodes, visiting the nodes one by one in
+//! both directions, so no intermediate copy of the whole node database is ever built.
+//!
+//! The format is the same as serializing `(root, im::HashMap<key, (value, rc)>)`.
+
+use crate::MemoryDB;
+use core::{fmt, marker::PhantomData};
+use serde::{
+    de::{DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use sp_core::Hasher;
+use trie_db::DBValue;
+
+struct Nodes<'a, H: Hasher>(&'a MemoryDB<H>);
+
+impl<H: Hasher> Serialize for Nodes<'_, H>
+where
+    H::Out: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter())
+    }
+}
+
+/// Serialize `root` followed by the nodes of `db`.
+pub(crate) fn serialize_trie<H: Hasher, S: Serializer>(
+    root: &H::Out,
+    db: &MemoryDB<H>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    H::Out: Serialize,
+{
+    let mut tuple = serializer.serialize_tuple(2)?;
+    tuple.serialize_element(root)?;
+    tuple.serialize_element(&Nodes(db))?;
+    tuple.end()
+}
+
+struct NodesSeed<K, F>(F, PhantomData<K>);
+
+impl<'de, K, F> DeserializeSeed<'de> for NodesSeed<K, F>
+where
+    K: Deserialize<'de>,
+    F: FnMut(K, DBValue, i32),
+{
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, K, F> Visitor<'de> for NodesSeed<K, F>
+where
+    K: Deserialize<'de>,
+    F: FnMut(K, DBValue, i32),
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("trie nodes")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        while let Some((key, (value, rc))) = map.next_entry()? {
+            (self.0)(key, value, rc);
+        }
+        Ok(())
+    }
+}
+
+struct TrieVisitor<K, F>(F, PhantomData<K>);
+
+impl<'de, K, F> Visitor<'de> for TrieVisitor<K, F>
+where
+    K: Deserialize<'de>,
+    F: FnMut(K, DBValue, i32),
+{
+    type Value = K;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a trie root followed by the trie nodes")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<K, A::Error> {
+        let root = seq
+            .next_element()?
+            .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+        seq.next_element_seed(NodesSeed(self.0, PhantomData))?
+            .ok_or_else(|| serde::de::Error::custom("Missing trie nodes"))?;
+        Ok(root)
+    }
+}
+
+/// Like `deserialize_trie`, for a caller which has already found the trie to be a sequence.
+pub(crate) fn visit_trie<'de, K, A, F>(seq: A, on_node: F) -> Result<K, A::Error>
+where
+    K: Deserialize<'de>,
+    A: SeqAccess<'de>,
+    F: FnMut(K, DBValue, i32),
+{
+    TrieVisitor(on_node, PhantomData).visit_seq(seq)
+}
+
+/// Deserialize a trie written by `serialize_trie`, feeding each node to `on_node` as soon as
+/// it is read. Returns the root.
+pub(crate) fn deserialize_trie<'de, K, D, F>(deserializer: D, on_node: F) -> Result<K, D::Error>
+where
+    K: Deserialize<'de>,
+    D: Deserializer<'de>,
+    F: FnMut(K, DBValue, i32),
+{
+    deserializer.deserialize_tuple(2, TrieVisitor(on_node, PhantomData))
+}
@@ -125,3 +125,291 @@ fn test_apply_main_changes() {
         assert_eq!(format!("{:?}", trie.root()), roots[number + 1]);
     }
 }
+
+#[test]
+fn test_apply_delta() {
+    let mut trie = load_genesis_trie();
+    let changes = load_changes();
+    let roots = load_roots();
+
+    let base = serde_json::to_vec(&trie).unwrap();
+    trie.reset_journal();
+    for change in changes.into_iter().skip(1).take(10) {
+        let main_storage_changes = map_storage_collection(change.main_storage_changes);
+        let child_storage_changes: Vec<_> = change
+            .child_storage_changes
+            .into_iter()
+            .map(|(k, v)| (k.0, map_storage_collection(v)))
+            .collect();
+        let (root, trans) =
+            trie.calc_root_if_changes(&main_storage_changes, &child_storage_changes);
+        trie.apply_changes(root, trans);
+    }
+    assert_eq!(format!("{:?}", trie.root()), roots[10]);
+
+    let mut delta = Vec::new();
+    trie.serialize_delta(&mut serde_json::Serializer::new(&mut delta))
+        .unwrap();
+    assert!(delta.len() < base.len());
+
+    let base: TrieStorage<NativeBlakeTwo256> = serde_json::from_slice(&base).unwrap();
+    let restored = base
+        .deserialize_delta(&mut serde_json::Deserializer::from_slice(&delta))
+        .unwrap();
+    assert_eq!(restored.root(), trie.root());
+    assert_eq!(restored.pairs(b""), trie.pairs(b""));
+
+    // Applying the delta to a wrong base must fail
+    let wrong_base = TrieStorage::<NativeBlakeTwo256>::default();
+    assert!(wrong_base
+        .deserialize_delta(&mut serde_json::Deserializer::from_slice(&delta))
+        .is_err());
+}
+
+#[test]
+fn test_disk_backend() {
+    let path = std::env::temp_dir().join(format!("phala-trie-nodes-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let changes = load_changes();
+    let roots = load_roots();
+
+    let db = DiskDB::<NativeBlakeTwo256>::open(&path, 1024).unwrap();
+    let mut trie = TrieStorage::with_disk_db(db);
+    trie.try_load(load_genesis_trie().pairs(b"").into_iter())
+        .unwrap();
+    assert_eq!(format!("{:?}", trie.root()), roots[0]);
+
+    let mut checkpoint = None;
+    for (number, change) in changes.into_iter().skip(1).take(30).enumerate() {
+        let main_storage_changes = map_storage_collection(change.main_storage_changes);
+        let child_storage_changes: Vec<_> = change
+            .child_storage_changes
+            .into_iter()
+            .map(|(k, v)| (k.0, map_storage_collection(v)))
+            .collect();
+
+        let (root, trans) =
+            trie.calc_root_if_changes(&main_storage_changes, &child_storage_changes);
+        trie.try_apply_changes(root, trans).unwrap();
+        assert_eq!(format!("{:?}", trie.root()), roots[number + 1]);
+        if number == 19 {
+            checkpoint = Some((serde_json::to_vec(&trie).unwrap(), trie.pairs(b"")));
+        }
+    }
+    drop(trie);
+
+    // Changes written after the checkpoint are discarded when restoring it
+    let (checkpoint, pairs) = checkpoint.unwrap();
+    let mut restored: TrieStorage<NativeBlakeTwo256, DiskDB<_>> =
+        serde_json::from_slice(&checkpoint).unwrap();
+    assert_eq!(format!("{:?}", restored.root()), roots[20]);
+    assert_eq!(restored.pairs(b""), pairs);
+
+    // Reloading starts a new generation of the log, which older checkpoints can not be reopened at
+    restored
+        .try_load(core::iter::empty::<(Vec<u8>, Vec<u8>)>())
+        .unwrap();
+    assert!(
+        serde_json::from_slice::<TrieStorage<NativeBlakeTwo256, DiskDB<_>>>(&checkpoint).is_err()
+    );
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_node_db() {
+    type Storage = TrieStorage<NativeBlakeTwo256, NodeDB<NativeBlakeTwo256>>;
+
+    let genesis = load_genesis_trie();
+    let pairs = genesis.pairs(b"");
+
+    // In memory it is compatible with the plain trie in both directions
+    let mut trie = Storage::default();
+    trie.try_load(pairs.clone().into_iter()).unwrap();
+    assert!(!trie.is_on_disk());
+    assert_eq!(trie.root(), genesis.root());
+    let serialized = serde_json::to_vec(&trie).unwrap();
+    let plain: TrieStorage<NativeBlakeTwo256> = serde_json::from_slice(&serialized).unwrap();
+    assert_eq!(plain.root(), genesis.root());
+    let restored: Storage = serde_json::from_slice(&serde_json::to_vec(&genesis).unwrap()).unwrap();
+    assert_eq!(restored.pairs(b""), pairs);
+
+    // On disk it is serialized as a reference to the log
+    let path = std::env::temp_dir().join(format!("phala-node-db-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let db = DiskDB::<NativeBlakeTwo256>::open(&path, 1024).unwrap();
+    let mut trie = Storage::on_disk(db);
+    trie.try_load(pairs.clone().into_iter()).unwrap();
+    assert!(trie.is_on_disk());
+    assert_eq!(trie.root(), genesis.root());
+    let serialized = serde_json::to_vec(&trie).unwrap();
+    assert!(serialized.len() < 1024);
+    let mut restored: Storage = serde_json::from_slice(&serialized).unwrap();
+    assert!(restored.is_on_disk());
+    assert_eq!(restored.pairs(b""), pairs);
+    restored.reset_journal();
+    assert!(restored
+        .serialize_delta(&mut serde_json::Serializer::new(Vec::new()))
+        .is_err());
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_read_proof() {
+    use hash_db::{HashDB, EMPTY_PREFIX};
+    use sp_trie::{trie_types::TrieDBBuilder, Trie};
+
+    let trie = load_genesis_trie();
+    let pairs = trie.pairs(b"");
+    let (key, value) = &pairs[pairs.len() / 2];
+    let missing_key = b"not a key".to_vec();
+
+    let proof = trie.read_proof([key, &missing_key]).unwrap();
+    assert!(!proof.is_empty());
+    assert!(proof.len() < pairs.len());
+
+    let mut db = sp_trie::MemoryDB::<NativeBlakeTwo256>::default();
+    for node in proof.iter() {
+        db.insert(EMPTY_PREFIX, node);
+    }
+    let checker = TrieDBBuilder::<NativeBlakeTwo256>::new(&db, trie.root()).build();
+    assert_eq!(checker.get(key).unwrap().as_ref(), Some(value));
+    assert_eq!(checker.get(&missing_key).unwrap(), None);
+}
+
+#[test]
+fn test_read_child_proof() {
+    use hash_db::{HashDB, EMPTY_PREFIX};
+    use sp_core::storage::ChildInfo;
+    use sp_trie::{trie_types::TrieDBBuilder, Trie};
+
+    let mut trie = load_genesis_trie();
+    let child_changes = vec![(
+        b"child".to_vec(),
+        vec![(b"ckey".to_vec(), Some(b"cvalue".to_vec()))],
+    )];
+    let (root, trans) = trie.calc_root_if_changes(&StorageCollection::new(), &child_changes);
+    trie.apply_changes(root, trans);
+
+    let proof = trie.read_child_proof(b"child", [b"ckey"]).unwrap();
+    let mut db = sp_trie::MemoryDB::<NativeBlakeTwo256>::default();
+    for node in proof.iter() {
+        db.insert(EMPTY_PREFIX, node);
+    }
+
+    // The proof leads from the state root to the child trie root, and on to the value
+    let child_key = ChildInfo::new_default(b"child")
+        .prefixed_storage_key()
+        .into_inner();
+    let main = TrieDBBuilder::<NativeBlakeTwo256>::new(&db, trie.root()).build();
+    let child_root = main.get(&child_key).unwrap().unwrap();
+    let child_root = sp_core::H256::from_slice(&child_root);
+    let child = TrieDBBuilder::<NativeBlakeTwo256>::new(&db, &child_root).build();
+    assert_eq!(child.get(b"ckey").unwrap(), Some(b"cvalue".to_vec()));
+}
+
+#[test]
+fn test_purge_keeps_memory_bounded() {
+    let changes = load_changes();
+    let roots = load_roots();
+    let mut trie = load_genesis_trie();
+    let mut trie_without_gc = load_genesis_trie();
+    let genesis_size = serde_json::to_vec(&trie).unwrap().len();
+
+    let mut max_size = 0;
+    for (number, change) in changes.into_iter().skip(1).take(30).enumerate() {
+        let main_storage_changes = map_storage_collection(change.main_storage_changes);
+        let child_storage_changes: Vec<_> = change
+            .child_storage_changes
+            .into_iter()
+            .map(|(k, v)| (k.0, map_storage_collection(v)))
+            .collect();
+
+        let (root, trans) =
+            trie.calc_root_if_changes(&main_storage_changes, &child_storage_changes);
+        trie_without_gc.apply_changes(root, trans.clone());
+        trie.apply_changes(root, trans);
+        trie.purge();
+        assert_eq!(format!("{:?}", trie.root()), roots[number + 1]);
+        // Everything reclaimable has been reclaimed by the previous pass
+        assert_eq!(trie.purge(), GcStats::default());
+        let size = serde_json::to_vec(&trie).unwrap().len();
+        assert!(size <= serde_json::to_vec(&trie_without_gc).unwrap().len());
+        max_size = max_size.max(size);
+    }
+    assert_eq!(trie.pairs(b""), trie_without_gc.pairs(b""));
+    assert!(max_size < genesis_size * 2);
+}
+
+#[test]
+fn test_serde_format_compat() {
+    type Nodes = im::HashMap<sp_core::H256, (Vec<u8>, i32)>;
+
+    let trie = load_genesis_trie();
+    let streamed = serde_json::to_vec(&trie).unwrap();
+
+    // The streamed form is the same as serializing the root and the whole node map
+    let (root, nodes): (sp_core::H256, Nodes) = serde_json::from_slice(&streamed).unwrap();
+    assert_eq!(&root, trie.root());
+    let legacy = serde_json::to_vec(&(root, nodes)).unwrap();
+
+    let restored: TrieStorage<NativeBlakeTwo256> = serde_json::from_slice(&legacy).unwrap();
+    assert_eq!(restored.root(), trie.root());
+    assert_eq!(restored.pairs(b""), trie.pairs(b""));
+}
+
+#[test]
+fn test_pairs_iter_paged() {
+    let trie = load_genesis_trie();
+    let all = trie.pairs(b"");
+    let pairs: Result<Vec<_>, _> = trie.pairs_iter(b"", None).collect();
+    assert_eq!(pairs.unwrap(), all);
+
+    // Page through the keys under the prefix of the first key
+    let prefix = &all[0].0[..16];
+    let expected: Vec<_> = all
+        .iter()
+        .filter(|(k, _)| k.starts_with(prefix))
+        .cloned()
+        .collect();
+    let mut paged = vec![];
+    let mut start_key: Option<Vec<u8>> = None;
+    loop {
+        let page: Vec<(Vec<u8>, Vec<u8>)> = trie
+            .pairs_iter(prefix, start_key.as_deref())
+            .take(3)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        if page.is_empty() {
+            break;
+        }
+        start_key = page.last().map(|(k, _)| k.clone());
+        paged.extend(page);
+    }
+    assert_eq!(paged, expected);
+
+    assert_eq!(trie.keys_iter(b"not a prefix", None).count(), 0);
+}
+
+#[test]
+fn test_snapshot_isolation() {
+    let mut trie = load_genesis_trie();
+    let changes = load_changes();
+    let roots = load_roots();
+
+    let snapshot = trie.snapshot();
+    let genesis_pairs = snapshot.pairs(b"");
+
+    for change in changes.into_iter().skip(1).take(10) {
+        let main_storage_changes = map_storage_collection(change.main_storage_changes);
+        let (root, trans) = trie.calc_root_if_changes(&main_storage_changes, &vec![]);
+        trie.apply_changes(root, trans);
+        trie.purge();
+    }
+    assert_ne!(trie.root(), snapshot.root());
+
+    // The snapshot still sees the genesis state
+    assert_eq!(format!("{:?}", snapshot.root()), roots[0]);
+    assert_eq!(snapshot.pairs(b""), genesis_pairs);
+}
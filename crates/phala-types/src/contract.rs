@@ -44,7 +44,7 @@ pub mod messaging {
     use core::fmt::Debug;
     use scale_info::TypeInfo;
 
-    use super::{ContractClusterId, ContractInfo};
+    use super::{ContractClusterId, ContractId, ContractInfo};
     use crate::messaging::EncryptedKey;
     use crate::{ClusterPublicKey, WorkerIdentity, WorkerPublicKey};
     use phala_mq::bind_topic;
@@ -67,12 +67,30 @@ pub mod messaging {
         InstantiateCode {
             contract_info: ContractInfo<CodeHash, AccountId>,
         },
+        /// Replace the code of a contract, keeping its address and storage.
+        UpgradeCode {
+            contract_id: ContractId,
+            code_hash: CodeHash,
+        },
+        /// Remove a contract from its cluster and free its storage.
+        Destroy { contract_id: ContractId },
     }
 
     impl<CodeHash, AccountId> ContractOperation<CodeHash, AccountId> {
         pub fn instantiate_code(contract_info: ContractInfo<CodeHash, AccountId>) -> Self {
             ContractOperation::InstantiateCode { contract_info }
         }
+
+        pub fn upgrade_code(contract_id: ContractId, code_hash: CodeHash) -> Self {
+            ContractOperation::UpgradeCode {
+                contract_id,
+                code_hash,
+            }
+        }
+
+        pub fn destroy(contract_id: ContractId) -> Self {
+            ContractOperation::Destroy { contract_id }
+        }
     }
 
     // Pink messages
@@ -11,6 +11,7 @@ mod system {
     use super::pink;
     use alloc::string::String;
     use ink_storage::{traits::SpreadAllocate, Mapping};
+    use pink::chain_extension::HttpEgressConfig;
     use pink::system::{ContractDeposit, ContractDepositRef, Error, Result};
     use pink::{HookPoint, PinkEnvironment};
 
@@ -116,12 +117,32 @@ mod system {
             Ok(())
         }
 
+        #[ink(message)]
+        fn set_hook_with_gas_limit(
+            &mut self,
+            hook: HookPoint,
+            contract: AccountId,
+            selector: u32,
+            gas_limit: u64,
+        ) -> Result<()> {
+            self.ensure_admin()?;
+            pink::set_hook_with_gas_limit(hook, contract, selector, gas_limit);
+            Ok(())
+        }
+
         #[ink(message)]
         fn set_contract_weight(&self, contract_id: AccountId, weight: u32) -> Result<()> {
             self.ensure_admin()?;
             pink::set_contract_weight(contract_id, weight);
             Ok(())
         }
+
+        #[ink(message)]
+        fn set_http_egress_config(&mut self, config: HttpEgressConfig) -> Result<()> {
+            self.ensure_owner()?;
+            pink::set_http_egress_config(config);
+            Ok(())
+        }
     }
 
     impl ContractDeposit for System {
@@ -0,0 +1,39 @@
+//! Crypto primitives behind the pink extensions, built on `phala-crypto`.
+
+use phala_crypto::{
+    aead::{self, IV, IV_BYTES},
+    ecdh::{self, EcdhKey, EcdhSecretKey},
+    CryptoError,
+};
+use pink_extension::chain_extension::PinkExtError;
+
+/// Agree on a shared secret between a sr25519 secret key and a sr25519 public key.
+pub fn ecdh_agree(secret_key: &[u8], public_key: &[u8]) -> Result<Vec<u8>, PinkExtError> {
+    let secret_key: &EcdhSecretKey = secret_key.try_into().or(Err(PinkExtError::InvalidKey))?;
+    let key = EcdhKey::from_secret(secret_key).or(Err(PinkExtError::InvalidKey))?;
+    ecdh::agree(&key, public_key).or(Err(PinkExtError::InvalidPublicKey))
+}
+
+/// Encrypt `plaintext` with AES-256-GCM, appending the auth tag to the returned ciphertext.
+pub fn aead_encrypt(key: &[u8], iv: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, PinkExtError> {
+    let iv: &IV = iv.try_into().or(Err(PinkExtError::InvalidIv))?;
+    let mut data = plaintext.to_vec();
+    aead::encrypt(iv, key, &mut data).map_err(|err| match err {
+        CryptoError::AeadInvalidKey => PinkExtError::InvalidKey,
+        _ => PinkExtError::EncryptionFailed,
+    })?;
+    Ok(data)
+}
+
+/// Decrypt a ciphertext produced by `aead_encrypt`.
+pub fn aead_decrypt(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, PinkExtError> {
+    if iv.len() != IV_BYTES {
+        return Err(PinkExtError::InvalidIv);
+    }
+    let mut data = ciphertext.to_vec();
+    let plaintext = aead::decrypt(iv, key, &mut data).map_err(|err| match err {
+        CryptoError::AeadInvalidKey => PinkExtError::InvalidKey,
+        _ => PinkExtError::DecryptionFailed,
+    })?;
+    Ok(plaintext.to_vec())
+}
@@ -1,26 +1,41 @@
 use std::borrow::Cow;
+use std::sync::RwLock;
 use std::{fmt::Display, str::FromStr, time::Duration};
 
 use pink_extension::{
     chain_extension::{
-        self as ext, HttpRequest, HttpResponse, PinkExtBackend, SigType, StorageQuotaExceeded,
+        self as ext, BatchHttpResult, CacheStats, HashAlgorithm, HttpEgressConfig, HttpRequest,
+        HttpRequestError, HttpResponse, PinkExtBackend, PinkExtError, SigType,
+        StorageQuotaExceeded,
     },
-    EcdsaPublicKey, EcdsaSignature, Hash,
+    EcdhPublicKey, EcdsaPublicKey, EcdsaSignature, Hash,
 };
 use reqwest::{
     header::{HeaderMap, HeaderName, HeaderValue},
-    Method,
+    Method, Proxy,
 };
 use reqwest_env_proxy::EnvProxyBuilder;
 use sp_core::{ByteArray as _, Pair};
 
+pub mod crypto;
 pub mod mock_ext;
 
+static HTTP_PROXY: RwLock<Option<String>> = RwLock::new(None);
+
+/// Route all HTTP requests made by contracts through the given proxy, overriding the proxies
+/// configured by environment variables. `None` restores the environment proxies.
+pub fn set_http_proxy(uri: Option<String>) {
+    *HTTP_PROXY.write().unwrap() = uri;
+}
+
 pub trait PinkRuntimeEnv {
     type AccountId: AsRef<[u8]> + Display;
 
     fn address(&self) -> &Self::AccountId;
     fn call_elapsed(&self) -> Option<Duration>;
+    fn http_egress_config(&self) -> HttpEgressConfig {
+        Default::default()
+    }
 }
 
 pub struct DefaultPinkExtension<'a, T, Error> {
@@ -37,84 +52,51 @@ impl<'a, T, E> DefaultPinkExtension<'a, T, E> {
     }
 }
 
+impl<T: PinkRuntimeEnv, E: From<&'static str>> DefaultPinkExtension<'_, T, E> {
+    fn http_timeout(&self, config: &HttpEgressConfig) -> Result<Duration, E> {
+        let elapsed = self.env.call_elapsed().ok_or("Invalid exec env")?;
+        Ok(Duration::from_millis(config.timeout_ms).saturating_sub(elapsed))
+    }
+}
+
 impl<T: PinkRuntimeEnv, E: From<&'static str>> PinkExtBackend for DefaultPinkExtension<'_, T, E> {
     type Error = E;
     fn http_request(&self, request: HttpRequest) -> Result<HttpResponse, Self::Error> {
-        // Hardcoded limitations for now
-        const MAX_QUERY_TIME: u64 = 10; // seconds
-        const MAX_BODY_SIZE: usize = 1024 * 256; // 256KB
+        let config = self.env.http_egress_config();
+        let timeout = self.http_timeout(&config)?;
+        http_request(request, timeout, &config).map_err(|err| err.display().into())
+    }
 
-        let elapsed = self.env.call_elapsed().ok_or("Invalid exec env")?;
-        let timeout = Duration::from_secs(MAX_QUERY_TIME) - elapsed;
-
-        let url: reqwest::Url = request.url.parse().or(Err("Invalid url"))?;
-
-        let client = reqwest::blocking::Client::builder()
-            .timeout(timeout)
-            .env_proxy(url.host_str().unwrap_or_default())
-            .build()
-            .or(Err("Failed to create client"))?;
-
-        let method: Method =
-            FromStr::from_str(request.method.as_str()).or(Err("Invalid HTTP method"))?;
-        let mut headers = HeaderMap::new();
-        for (key, value) in &request.headers {
-            let key = HeaderName::from_str(key.as_str()).or(Err("Invalid HTTP header key"))?;
-            let value = HeaderValue::from_str(value).or(Err("Invalid HTTP header value"))?;
-            headers.insert(key, value);
+    fn batch_http_request(
+        &self,
+        requests: Vec<HttpRequest>,
+        timeout_ms: u64,
+    ) -> Result<BatchHttpResult, Self::Error> {
+        const MAX_CONCURRENT_REQUESTS: usize = 5;
+        if requests.len() > MAX_CONCURRENT_REQUESTS {
+            return Ok(Err(HttpRequestError::TooManyRequests));
         }
-
-        let result = client
-            .request(method, url)
-            .headers(headers)
-            .body(request.body)
-            .send();
-
-        let mut response = match result {
-            Ok(response) => response,
-            Err(err) => {
-                // If there is somthing wrong with the network, we can not inspect the reason too
-                // much here. Let it return a non-standard 523 here.
-                log::info!("HTTP request error: {}", err);
-                return Ok(HttpResponse {
-                    status_code: 523,
-                    reason_phrase: "Unreachable".into(),
-                    body: format!("{:?}", err).into_bytes(),
-                    headers: vec![],
-                });
-            }
-        };
-
-        let headers: Vec<_> = response
-            .headers()
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().into()))
-            .collect();
-
-        let mut body = Vec::new();
-        let mut writer = LimitedWriter::new(&mut body, MAX_BODY_SIZE);
-
-        if let Err(err) = response.copy_to(&mut writer) {
-            log::info!("Failed to read HTTP body: {}", err);
-            return Ok(HttpResponse {
-                status_code: 524,
-                reason_phrase: "IO Error".into(),
-                body: format!("{:?}", err).into_bytes(),
-                headers: vec![],
-            });
-        };
-
-        let response = HttpResponse {
-            status_code: response.status().as_u16(),
-            reason_phrase: response
-                .status()
-                .canonical_reason()
-                .unwrap_or_default()
-                .into(),
-            body,
-            headers,
-        };
-        Ok(response)
+        let config = self.env.http_egress_config();
+        let timeout = self
+            .http_timeout(&config)?
+            .min(Duration::from_millis(timeout_ms));
+        let config = &config;
+        let results = std::thread::scope(|s| {
+            let handles: Vec<_> = requests
+                .into_iter()
+                .map(|request| s.spawn(move || http_request(request, timeout, config)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle.join().unwrap_or_else(|_| {
+                        log::error!("HTTP request thread panicked");
+                        Err(HttpRequestError::Internal)
+                    })
+                })
+                .collect()
+        });
+        Ok(Ok(results))
     }
 
     fn sign(
@@ -123,20 +105,8 @@ impl<T: PinkRuntimeEnv, E: From<&'static str>> PinkExtBackend for DefaultPinkExt
         key: Cow<[u8]>,
         message: Cow<[u8]>,
     ) -> Result<Vec<u8>, Self::Error> {
-        macro_rules! sign_with {
-            ($sigtype:ident) => {{
-                let pair = sp_core::$sigtype::Pair::from_seed_slice(&key).or(Err("Invalid key"))?;
-                let signature = pair.sign(&message);
-                let signature: &[u8] = signature.as_ref();
-                signature.to_vec()
-            }};
-        }
-
-        Ok(match sigtype {
-            SigType::Sr25519 => sign_with!(sr25519),
-            SigType::Ed25519 => sign_with!(ed25519),
-            SigType::Ecdsa => sign_with!(ecdsa),
-        })
+        self.sign_v1(sigtype, key, message)?
+            .map_err(|err| err.display().into())
     }
 
     fn verify(
@@ -169,20 +139,8 @@ impl<T: PinkRuntimeEnv, E: From<&'static str>> PinkExtBackend for DefaultPinkExt
     }
 
     fn get_public_key(&self, sigtype: SigType, key: Cow<[u8]>) -> Result<Vec<u8>, Self::Error> {
-        macro_rules! public_key_with {
-            ($sigtype:ident) => {{
-                sp_core::$sigtype::Pair::from_seed_slice(&key)
-                    .or(Err("Invalid key"))?
-                    .public()
-                    .to_raw_vec()
-            }};
-        }
-        let pubkey = match sigtype {
-            SigType::Ed25519 => public_key_with!(ed25519),
-            SigType::Sr25519 => public_key_with!(sr25519),
-            SigType::Ecdsa => public_key_with!(ecdsa),
-        };
-        Ok(pubkey)
+        self.get_public_key_v1(sigtype, key)?
+            .map_err(|err| err.display().into())
     }
 
     fn cache_set(
@@ -234,9 +192,8 @@ impl<T: PinkRuntimeEnv, E: From<&'static str>> PinkExtBackend for DefaultPinkExt
         key: Cow<[u8]>,
         message_hash: Hash,
     ) -> Result<EcdsaSignature, Self::Error> {
-        let pair = sp_core::ecdsa::Pair::from_seed_slice(&key).or(Err("Invalid key"))?;
-        let signature = pair.sign_prehashed(&message_hash);
-        Ok(signature.0)
+        self.ecdsa_sign_prehashed_v1(key, message_hash)?
+            .map_err(|err| err.display().into())
     }
 
     fn ecdsa_verify_prehashed(
@@ -257,6 +214,403 @@ impl<T: PinkRuntimeEnv, E: From<&'static str>> PinkExtBackend for DefaultPinkExt
     fn system_contract_id(&self) -> Result<ext::AccountId, Self::Error> {
         Err("No default system contract id".into())
     }
+
+    fn hash(
+        &self,
+        algorithm: HashAlgorithm,
+        message: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        use sp_core::hashing::{blake2_128, blake2_256, keccak_256, sha2_256};
+        Ok(Ok(match algorithm {
+            HashAlgorithm::Keccak256 => keccak_256(&message).to_vec(),
+            HashAlgorithm::Blake2b128 => blake2_128(&message).to_vec(),
+            HashAlgorithm::Blake2b256 => blake2_256(&message).to_vec(),
+            HashAlgorithm::Sha256 => sha2_256(&message).to_vec(),
+        }))
+    }
+
+    fn ecdh_agree(
+        &self,
+        salt: Cow<[u8]>,
+        public_key: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        let secret_key = self.derive_sr25519_key(salt)?;
+        Ok(crypto::ecdh_agree(&secret_key, &public_key))
+    }
+
+    fn aead_encrypt(
+        &self,
+        key: Cow<[u8]>,
+        iv: Cow<[u8]>,
+        plaintext: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        Ok(crypto::aead_encrypt(&key, &iv, &plaintext))
+    }
+
+    fn aead_decrypt(
+        &self,
+        key: Cow<[u8]>,
+        iv: Cow<[u8]>,
+        ciphertext: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        Ok(crypto::aead_decrypt(&key, &iv, &ciphertext))
+    }
+
+    fn recover_prehashed(
+        &self,
+        sigtype: SigType,
+        signature: Cow<[u8]>,
+        message_hash: Hash,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        if !matches!(sigtype, SigType::Ecdsa) {
+            return Ok(Err(PinkExtError::UnsupportedSigType));
+        }
+        let signature = match sp_core::ecdsa::Signature::try_from(signature.as_ref()) {
+            Ok(signature) => signature,
+            Err(_) => return Ok(Err(PinkExtError::InvalidSignature)),
+        };
+        Ok(signature
+            .recover_prehashed(&message_hash)
+            .map(|public| public.0.to_vec())
+            .ok_or(PinkExtError::InvalidSignature))
+    }
+
+    fn local_store_get(
+        &self,
+        _key: Cow<[u8]>,
+    ) -> Result<Result<Option<Vec<u8>>, PinkExtError>, Self::Error> {
+        Ok(Ok(None))
+    }
+
+    fn local_store_set(
+        &self,
+        _key: Cow<[u8]>,
+        _value: Cow<[u8]>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        Ok(Ok(()))
+    }
+
+    fn local_store_remove(
+        &self,
+        _key: Cow<[u8]>,
+    ) -> Result<Result<Option<Vec<u8>>, PinkExtError>, Self::Error> {
+        Ok(Ok(None))
+    }
+
+    fn cache_scan(
+        &self,
+        _prefix: Cow<[u8]>,
+        _start_after: Option<Vec<u8>>,
+        _limit: u32,
+    ) -> Result<Result<Vec<(Vec<u8>, Vec<u8>)>, PinkExtError>, Self::Error> {
+        Ok(Ok(vec![]))
+    }
+
+    fn cache_compare_and_swap(
+        &self,
+        _key: Cow<[u8]>,
+        expected: Option<Vec<u8>>,
+        _value: Option<Vec<u8>>,
+    ) -> Result<Result<bool, PinkExtError>, Self::Error> {
+        // Nothing is cached, so only a swap of an absent key succeeds.
+        Ok(Ok(expected.is_none()))
+    }
+
+    fn cache_increment(
+        &self,
+        _key: Cow<[u8]>,
+        delta: i64,
+    ) -> Result<Result<i64, PinkExtError>, Self::Error> {
+        Ok(Ok(delta))
+    }
+
+    fn cache_stats(&self) -> Result<Result<CacheStats, PinkExtError>, Self::Error> {
+        Ok(Ok(Default::default()))
+    }
+
+    fn query_sidevm(
+        &self,
+        _contract: ext::AccountId,
+        _payload: Cow<[u8]>,
+        _timeout_ms: u64,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::SidevmNotFound))
+    }
+
+    fn try_push_message(
+        &self,
+        _payload: Cow<[u8]>,
+        _topic: Cow<[u8]>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        // There is no egress queue to fill up without a worker.
+        Ok(Ok(()))
+    }
+
+    fn try_push_osp_message(
+        &self,
+        _payload: Cow<[u8]>,
+        _topic: Cow<[u8]>,
+        _remote_pubkey: Option<EcdhPublicKey>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        Ok(Ok(()))
+    }
+
+    fn http_request_v1(
+        &self,
+        request: HttpRequest,
+    ) -> Result<Result<HttpResponse, PinkExtError>, Self::Error> {
+        let config = self.env.http_egress_config();
+        let timeout = self.http_timeout(&config)?;
+        Ok(send_http_request(request, timeout, &config).map_err(Into::into))
+    }
+
+    fn sign_v1(
+        &self,
+        sigtype: SigType,
+        key: Cow<[u8]>,
+        message: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        macro_rules! sign_with {
+            ($sigtype:ident) => {{
+                let pair = match sp_core::$sigtype::Pair::from_seed_slice(&key) {
+                    Ok(pair) => pair,
+                    Err(_) => return Ok(Err(PinkExtError::InvalidKey)),
+                };
+                let signature = pair.sign(&message);
+                let signature: &[u8] = signature.as_ref();
+                signature.to_vec()
+            }};
+        }
+
+        Ok(Ok(match sigtype {
+            SigType::Sr25519 => sign_with!(sr25519),
+            SigType::Ed25519 => sign_with!(ed25519),
+            SigType::Ecdsa => sign_with!(ecdsa),
+        }))
+    }
+
+    fn get_public_key_v1(
+        &self,
+        sigtype: SigType,
+        key: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        macro_rules! public_key_with {
+            ($sigtype:ident) => {{
+                let pair = match sp_core::$sigtype::Pair::from_seed_slice(&key) {
+                    Ok(pair) => pair,
+                    Err(_) => return Ok(Err(PinkExtError::InvalidKey)),
+                };
+                pair.public().to_raw_vec()
+            }};
+        }
+        let pubkey = match sigtype {
+            SigType::Ed25519 => public_key_with!(ed25519),
+            SigType::Sr25519 => public_key_with!(sr25519),
+            SigType::Ecdsa => public_key_with!(ecdsa),
+        };
+        Ok(Ok(pubkey))
+    }
+
+    fn cache_set_v1(
+        &self,
+        key: Cow<[u8]>,
+        value: Cow<[u8]>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        Ok(self
+            .cache_set(key, value)?
+            .or(Err(PinkExtError::QuotaExceeded)))
+    }
+
+    fn getrandom_v1(&self, length: u8) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        self.getrandom(length).map(Ok)
+    }
+
+    fn ecdsa_sign_prehashed_v1(
+        &self,
+        key: Cow<[u8]>,
+        message_hash: Hash,
+    ) -> Result<Result<EcdsaSignature, PinkExtError>, Self::Error> {
+        let pair = match sp_core::ecdsa::Pair::from_seed_slice(&key) {
+            Ok(pair) => pair,
+            Err(_) => return Ok(Err(PinkExtError::InvalidKey)),
+        };
+        let signature = pair.sign_prehashed(&message_hash);
+        Ok(Ok(signature.0))
+    }
+
+    fn system_contract_id_v1(&self) -> Result<Result<ext::AccountId, PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::SystemContractMissing))
+    }
+}
+
+/// Failures of sending an HTTP request.
+enum HttpError {
+    Request(HttpRequestError),
+    Denied(String),
+    Unreachable(reqwest::Error),
+    Body(reqwest::Error),
+}
+
+impl From<HttpRequestError> for HttpError {
+    fn from(err: HttpRequestError) -> Self {
+        Self::Request(err)
+    }
+}
+
+impl From<HttpError> for PinkExtError {
+    fn from(err: HttpError) -> Self {
+        match err {
+            HttpError::Request(err) => Self::InvalidHttpRequest(err),
+            HttpError::Denied(_) => Self::NotAllowed,
+            HttpError::Unreachable(_) => Self::NetworkError,
+            HttpError::Body(_) => Self::ResponseBodyError,
+        }
+    }
+}
+
+/// Send an HTTP request, reporting the failures after the request is built by the response
+/// status code.
+fn http_request(
+    request: HttpRequest,
+    timeout: Duration,
+    config: &HttpEgressConfig,
+) -> Result<HttpResponse, HttpRequestError> {
+    let (status_code, reason_phrase, body) = match send_http_request(request, timeout, config) {
+        Ok(response) => return Ok(response),
+        Err(HttpError::Request(err)) => return Err(err),
+        Err(HttpError::Denied(host)) => (525, "Denied", format!("Requests to {} are denied", host)),
+        // If there is somthing wrong with the network, we can not inspect the reason too
+        // much here. Let it return a non-standard 523 here.
+        Err(HttpError::Unreachable(err)) => (523, "Unreachable", format!("{:?}", err)),
+        Err(HttpError::Body(err)) => (524, "IO Error", format!("{:?}", err)),
+    };
+    Ok(HttpResponse {
+        status_code,
+        reason_phrase: reason_phrase.into(),
+        body: body.into_bytes(),
+        headers: vec![],
+    })
+}
+
+fn send_http_request(
+    request: HttpRequest,
+    timeout: Duration,
+    config: &HttpEgressConfig,
+) -> Result<HttpResponse, HttpError> {
+    let url: reqwest::Url = request.url.parse().or(Err(HttpRequestError::InvalidUrl))?;
+    let host = url.host_str().unwrap_or_default();
+
+    if !config.is_allowed(host) {
+        log::info!("HTTP request to {} denied by the cluster", host);
+        return Err(HttpError::Denied(host.into()));
+    }
+
+    let builder = reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .redirect(redirect_policy(config.clone()));
+    let builder = match HTTP_PROXY.read().unwrap().as_deref() {
+        Some(uri) => {
+            let proxy = Proxy::all(uri).or(Err(HttpRequestError::FailedToCreateClient))?;
+            builder.proxy(proxy)
+        }
+        None => builder.env_proxy(host),
+    };
+    let client = builder
+        .build()
+        .or(Err(HttpRequestError::FailedToCreateClient))?;
+
+    let method: Method =
+        FromStr::from_str(request.method.as_str()).or(Err(HttpRequestError::InvalidMethod))?;
+    let mut headers = HeaderMap::new();
+    for (key, value) in &request.headers {
+        let key =
+            HeaderName::from_str(key.as_str()).or(Err(HttpRequestError::InvalidHeaderName))?;
+        let value = HeaderValue::from_str(value).or(Err(HttpRequestError::InvalidHeaderValue))?;
+        headers.insert(key, value);
+    }
+
+    let mut response = client
+        .request(method, url)
+        .headers(headers)
+        .body(request.body)
+        .send()
+        .map_err(|err| {
+            if let Some(DeniedRedirect(host)) = denied_redirect(&err) {
+                log::info!("HTTP redirect to {} denied by the cluster", host);
+                return HttpError::Denied(host.clone());
+            }
+            log::info!("HTTP request error: {}", err);
+            HttpError::Unreachable(err)
+        })?;
+
+    let headers: Vec<_> = response
+        .headers()
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().into()))
+        .collect();
+
+    let mut body = Vec::new();
+    let mut writer = LimitedWriter::new(&mut body, config.max_body_size as usize);
+
+    if let Err(err) = response.copy_to(&mut writer) {
+        log::info!("Failed to read HTTP body: {}", err);
+        return Err(HttpError::Body(err));
+    };
+
+    let response = HttpResponse {
+        status_code: response.status().as_u16(),
+        reason_phrase: response
+            .status()
+            .canonical_reason()
+            .unwrap_or_default()
+            .into(),
+        body,
+        headers,
+    };
+    Ok(response)
+}
+
+/// Max number of redirects followed by a request, the same as reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Debug)]
+struct DeniedRedirect(String);
+
+impl Display for DeniedRedirect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "redirect to {} denied", self.0)
+    }
+}
+
+impl std::error::Error for DeniedRedirect {}
+
+/// Follow the redirects only to the hosts allowed by `config`.
+fn redirect_policy(config: HttpEgressConfig) -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() > MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        let host = attempt.url().host_str().unwrap_or_default().to_string();
+        if config.is_allowed(&host) {
+            attempt.follow()
+        } else {
+            attempt.error(DeniedRedirect(host))
+        }
+    })
+}
+
+fn denied_redirect(err: &reqwest::Error) -> Option<&DeniedRedirect> {
+    if !err.is_redirect() {
+        return None;
+    }
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        if let Some(denied) = err.downcast_ref::<DeniedRedirect>() {
+            return Some(denied);
+        }
+        source = err.source();
+    }
+    None
 }
 
 struct LimitedWriter<W> {
@@ -292,3 +646,48 @@ impl<W: std::io::Write> std::io::Write for LimitedWriter<W> {
         self.writer.flush()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Serve one request with a redirect to `location`.
+    fn redirect_once(location: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).unwrap();
+            let response =
+                format!("HTTP/1.1 302 Found\r\nLocation: {location}\r\nContent-Length: 0\r\n\r\n");
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        port
+    }
+
+    #[test]
+    fn redirect_to_denied_host_is_not_followed() {
+        set_http_proxy(None);
+        let port = redirect_once("http://denied.example/secret");
+        let config = HttpEgressConfig {
+            allowed_domains: vec!["127.0.0.1".into()],
+            denied_domains: vec!["denied.example".into()],
+            ..Default::default()
+        };
+        let request = HttpRequest {
+            url: format!("http://127.0.0.1:{port}/"),
+            method: "GET".into(),
+            headers: vec![],
+            body: vec![],
+        };
+        let response = http_request(request, Duration::from_secs(5), &config).unwrap();
+        assert_eq!(response.status_code, 525);
+        assert_eq!(
+            response.body,
+            b"Requests to denied.example are denied".to_vec()
+        );
+    }
+}
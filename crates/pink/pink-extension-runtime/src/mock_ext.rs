@@ -1,12 +1,19 @@
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
 
 use pink_extension::chain_extension::SigType;
 use pink_extension::chain_extension::mock::mock_all_with;
-use pink_extension::{chain_extension as ext, EcdsaPublicKey, EcdsaSignature, Hash};
+use pink_extension::{chain_extension as ext, EcdhPublicKey, EcdsaPublicKey, EcdsaSignature, Hash};
 use sp_core::crypto::AccountId32;
 
 pub struct MockExtension;
 
+// The local store shared by all the mocked contracts.
+static MOCK_STORE: Mutex<BTreeMap<Vec<u8>, Vec<u8>>> = Mutex::new(BTreeMap::new());
+// The local cache shared by all the mocked contracts. Values never expire.
+static MOCK_CACHE: Mutex<BTreeMap<Vec<u8>, Vec<u8>>> = Mutex::new(BTreeMap::new());
+
 impl super::PinkRuntimeEnv for MockExtension {
     type AccountId = AccountId32;
 
@@ -28,6 +35,14 @@ impl ext::PinkExtBackend for MockExtension {
         super::DefaultPinkExtension::new(self).http_request(request)
     }
 
+    fn batch_http_request(
+        &self,
+        requests: Vec<ext::HttpRequest>,
+        timeout_ms: u64,
+    ) -> Result<ext::BatchHttpResult, Self::Error> {
+        super::DefaultPinkExtension::new(self).batch_http_request(requests, timeout_ms)
+    }
+
     fn sign(
         &self,
         sigtype: SigType,
@@ -57,26 +72,33 @@ impl ext::PinkExtBackend for MockExtension {
 
     fn cache_set(
         &self,
-        _key: std::borrow::Cow<[u8]>,
-        _value: std::borrow::Cow<[u8]>,
+        key: std::borrow::Cow<[u8]>,
+        value: std::borrow::Cow<[u8]>,
     ) -> Result<Result<(), ext::StorageQuotaExceeded>, Self::Error> {
+        MOCK_CACHE
+            .lock()
+            .unwrap()
+            .insert(key.into_owned(), value.into_owned());
         Ok(Ok(()))
     }
 
     fn cache_set_expire(
         &self,
-        _key: std::borrow::Cow<[u8]>,
-        _expire: u64,
+        key: std::borrow::Cow<[u8]>,
+        expire: u64,
     ) -> Result<(), Self::Error> {
+        if expire == 0 {
+            MOCK_CACHE.lock().unwrap().remove(key.as_ref());
+        }
         Ok(())
     }
 
-    fn cache_get(&self, _key: std::borrow::Cow<[u8]>) -> Result<Option<Vec<u8>>, Self::Error> {
-        Ok(None)
+    fn cache_get(&self, key: std::borrow::Cow<[u8]>) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(MOCK_CACHE.lock().unwrap().get(key.as_ref()).cloned())
     }
 
-    fn cache_remove(&self, _args: std::borrow::Cow<[u8]>) -> Result<Option<Vec<u8>>, Self::Error> {
-        Ok(None)
+    fn cache_remove(&self, key: std::borrow::Cow<[u8]>) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(MOCK_CACHE.lock().unwrap().remove(key.as_ref()))
     }
 
     fn log(&self, level: u8, message: std::borrow::Cow<str>) -> Result<(), Self::Error> {
@@ -115,6 +137,183 @@ impl ext::PinkExtBackend for MockExtension {
     fn system_contract_id(&self) -> Result<ext::AccountId, Self::Error> {
         Err("No default system contract id".into())
     }
+
+    fn hash(
+        &self,
+        algorithm: ext::HashAlgorithm,
+        message: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).hash(algorithm, message)
+    }
+
+    fn ecdh_agree(
+        &self,
+        salt: Cow<[u8]>,
+        public_key: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).ecdh_agree(salt, public_key)
+    }
+
+    fn aead_encrypt(
+        &self,
+        key: Cow<[u8]>,
+        iv: Cow<[u8]>,
+        plaintext: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).aead_encrypt(key, iv, plaintext)
+    }
+
+    fn aead_decrypt(
+        &self,
+        key: Cow<[u8]>,
+        iv: Cow<[u8]>,
+        ciphertext: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).aead_decrypt(key, iv, ciphertext)
+    }
+
+    fn recover_prehashed(
+        &self,
+        sigtype: SigType,
+        signature: Cow<[u8]>,
+        message_hash: Hash,
+    ) -> Result<Result<Vec<u8>, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).recover_prehashed(sigtype, signature, message_hash)
+    }
+
+    fn local_store_get(
+        &self,
+        key: Cow<[u8]>,
+    ) -> Result<Result<Option<Vec<u8>>, ext::PinkExtError>, Self::Error> {
+        Ok(Ok(MOCK_STORE.lock().unwrap().get(key.as_ref()).cloned()))
+    }
+
+    fn local_store_set(
+        &self,
+        key: Cow<[u8]>,
+        value: Cow<[u8]>,
+    ) -> Result<Result<(), ext::PinkExtError>, Self::Error> {
+        MOCK_STORE
+            .lock()
+            .unwrap()
+            .insert(key.into_owned(), value.into_owned());
+        Ok(Ok(()))
+    }
+
+    fn local_store_remove(
+        &self,
+        key: Cow<[u8]>,
+    ) -> Result<Result<Option<Vec<u8>>, ext::PinkExtError>, Self::Error> {
+        Ok(Ok(MOCK_STORE.lock().unwrap().remove(key.as_ref())))
+    }
+
+    fn cache_scan(
+        &self,
+        prefix: Cow<[u8]>,
+        start_after: Option<Vec<u8>>,
+        limit: u32,
+    ) -> Result<Result<Vec<(Vec<u8>, Vec<u8>)>, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).cache_scan(prefix, start_after, limit)
+    }
+
+    fn cache_compare_and_swap(
+        &self,
+        key: Cow<[u8]>,
+        expected: Option<Vec<u8>>,
+        value: Option<Vec<u8>>,
+    ) -> Result<Result<bool, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).cache_compare_and_swap(key, expected, value)
+    }
+
+    fn cache_increment(
+        &self,
+        key: Cow<[u8]>,
+        delta: i64,
+    ) -> Result<Result<i64, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).cache_increment(key, delta)
+    }
+
+    fn cache_stats(&self) -> Result<Result<ext::CacheStats, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).cache_stats()
+    }
+
+    fn query_sidevm(
+        &self,
+        contract: ext::AccountId,
+        payload: Cow<[u8]>,
+        timeout_ms: u64,
+    ) -> Result<Result<Vec<u8>, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).query_sidevm(contract, payload, timeout_ms)
+    }
+
+    fn try_push_message(
+        &self,
+        payload: Cow<[u8]>,
+        topic: Cow<[u8]>,
+    ) -> Result<Result<(), ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).try_push_message(payload, topic)
+    }
+
+    fn try_push_osp_message(
+        &self,
+        payload: Cow<[u8]>,
+        topic: Cow<[u8]>,
+        remote_pubkey: Option<EcdhPublicKey>,
+    ) -> Result<Result<(), ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).try_push_osp_message(payload, topic, remote_pubkey)
+    }
+
+    fn http_request_v1(
+        &self,
+        request: ext::HttpRequest,
+    ) -> Result<Result<ext::HttpResponse, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).http_request_v1(request)
+    }
+
+    fn sign_v1(
+        &self,
+        sigtype: SigType,
+        key: Cow<[u8]>,
+        message: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).sign_v1(sigtype, key, message)
+    }
+
+    fn get_public_key_v1(
+        &self,
+        sigtype: SigType,
+        key: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).get_public_key_v1(sigtype, key)
+    }
+
+    fn cache_set_v1(
+        &self,
+        key: Cow<[u8]>,
+        value: Cow<[u8]>,
+    ) -> Result<Result<(), ext::PinkExtError>, Self::Error> {
+        Ok(self
+            .cache_set(key, value)?
+            .or(Err(ext::PinkExtError::QuotaExceeded)))
+    }
+
+    fn getrandom_v1(&self, length: u8) -> Result<Result<Vec<u8>, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).getrandom_v1(length)
+    }
+
+    fn ecdsa_sign_prehashed_v1(
+        &self,
+        key: Cow<[u8]>,
+        message_hash: Hash,
+    ) -> Result<Result<EcdsaSignature, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).ecdsa_sign_prehashed_v1(key, message_hash)
+    }
+
+    fn system_contract_id_v1(
+        &self,
+    ) -> Result<Result<ext::AccountId, ext::PinkExtError>, Self::Error> {
+        super::DefaultPinkExtension::new(self).system_contract_id_v1()
+    }
 }
 
 thread_local! {
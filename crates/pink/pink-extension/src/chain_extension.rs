@@ -3,12 +3,16 @@ use alloc::vec::Vec;
 use ink::ChainExtensionInstance;
 use ink_lang as ink;
 
-pub use http_request::{HttpRequest, HttpResponse};
+pub use crypto::HashAlgorithm;
+pub use http_request::{
+    BatchHttpResult, HttpEgressConfig, HttpRequest, HttpRequestError, HttpResponse,
+};
 pub use ink_env::AccountId;
 pub use signing::SigType;
 
-use crate::{EcdsaPublicKey, EcdsaSignature, Hash};
+use crate::{EcdhPublicKey, EcdsaPublicKey, EcdsaSignature, Hash};
 
+pub mod crypto;
 mod http_request;
 pub mod signing;
 
@@ -19,19 +23,132 @@ pub mod test;
 #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
 pub struct StorageQuotaExceeded;
 
-#[derive(scale::Encode, scale::Decode)]
+/// Usage of the local cache of a contract.
+#[derive(scale::Encode, scale::Decode, Debug, Clone, Copy, Default, PartialEq, Eq)]
 #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
-pub enum ErrorCode {}
+pub struct CacheStats {
+    /// Number of keys, including the expired ones not yet collected.
+    pub keys: u64,
+    /// Sum of the size of all the keys and values in bytes.
+    pub size: u64,
+    /// Max size in bytes the cache of the contract can grow to.
+    pub quota: u64,
+}
+
+/// A nonzero status code returned by the runtime for an extension call.
+#[derive(scale::Encode, scale::Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct ErrorCode(pub u32);
 
 impl ink_env::chain_extension::FromStatusCode for ErrorCode {
     fn from_status_code(status_code: u32) -> Result<(), Self> {
         match status_code {
             0 => Ok(()),
-            _ => panic!("encountered unknown status code"),
+            code => Err(ErrorCode(code)),
         }
     }
 }
 
+/// Errors returned by the versioned (`*_v1`) extension functions.
+#[derive(scale::Encode, scale::Decode, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum PinkExtError {
+    /// The key is not valid for the signature type.
+    InvalidKey,
+    /// The HTTP request could not be built.
+    InvalidHttpRequest(HttpRequestError),
+    /// Failed to get the HTTP response.
+    NetworkError,
+    /// Failed to read the HTTP response body, or the body exceeded the size limit.
+    ResponseBodyError,
+    /// The request is denied by the cluster.
+    NotAllowed,
+    /// The function can only be called in query mode.
+    NotAllowedInCommand,
+    /// The local cache storage quota exceeded.
+    QuotaExceeded,
+    /// No system contract is installed in the cluster.
+    SystemContractMissing,
+    /// The runtime returned a nonzero status code.
+    ExtensionFailed(u32),
+    /// The output of the extension could not be decoded.
+    DecodeFailed,
+    /// The public key is not valid.
+    InvalidPublicKey,
+    /// The signature is not valid.
+    InvalidSignature,
+    /// The AEAD iv is not 12 bytes.
+    InvalidIv,
+    /// Failed to encrypt the message.
+    EncryptionFailed,
+    /// Failed to decrypt the message, or the auth tag does not match.
+    DecryptionFailed,
+    /// The cached value is not an integer.
+    NotAnInteger,
+    /// The integer arithmetic overflowed.
+    IntegerOverflow,
+    /// The target contract has no running sidevm instance.
+    SidevmNotFound,
+    /// The sidevm instance dropped the query without replying.
+    SidevmNoResponse,
+    /// The operation did not complete before the deadline.
+    Timeout,
+    /// The signature type does not support the operation.
+    UnsupportedSigType,
+    /// The function can only be called in command mode.
+    NotAllowedInQuery,
+    /// The egress queue of the contract is full.
+    EgressQuotaExceeded,
+}
+
+impl PinkExtError {
+    pub fn display(&self) -> &'static str {
+        match self {
+            Self::InvalidKey => "Invalid key",
+            Self::InvalidHttpRequest(err) => err.display(),
+            Self::NetworkError => "Network error",
+            Self::ResponseBodyError => "Failed to read the response body",
+            Self::NotAllowed => "Not allowed",
+            Self::NotAllowedInCommand => "Not allowed in command",
+            Self::QuotaExceeded => "Storage quota exceeded",
+            Self::SystemContractMissing => "No system contract installed",
+            Self::ExtensionFailed(_) => "Extension failed",
+            Self::DecodeFailed => "Failed to decode the extension output",
+            Self::InvalidPublicKey => "Invalid public key",
+            Self::InvalidSignature => "Invalid signature",
+            Self::InvalidIv => "Invalid iv",
+            Self::EncryptionFailed => "Encryption failed",
+            Self::DecryptionFailed => "Decryption failed",
+            Self::NotAnInteger => "Not an integer",
+            Self::IntegerOverflow => "Integer overflow",
+            Self::SidevmNotFound => "Sidevm not found",
+            Self::SidevmNoResponse => "No response from the sidevm",
+            Self::Timeout => "Timeout",
+            Self::UnsupportedSigType => "Unsupported signature type",
+            Self::NotAllowedInQuery => "Not allowed in query",
+            Self::EgressQuotaExceeded => "Egress quota exceeded",
+        }
+    }
+}
+
+impl From<ErrorCode> for PinkExtError {
+    fn from(code: ErrorCode) -> Self {
+        Self::ExtensionFailed(code.0)
+    }
+}
+
+impl From<scale::Error> for PinkExtError {
+    fn from(_: scale::Error) -> Self {
+        Self::DecodeFailed
+    }
+}
+
+impl From<HttpRequestError> for PinkExtError {
+    fn from(err: HttpRequestError) -> Self {
+        Self::InvalidHttpRequest(err)
+    }
+}
+
 /// Extensions for the ink runtime defined by fat contract.
 #[pink_extension_macro::chain_extension]
 pub trait PinkExt {
@@ -108,6 +225,170 @@ pub trait PinkExt {
     /// Get the contract id of the preinstalled pink-system
     #[ink(extension = 15, handle_status = false, returns_result = false)]
     fn system_contract_id() -> AccountId;
+
+    /// Send a batch of HTTP requests concurrently, for query only.
+    ///
+    /// The results are returned in the same order as the requests. `timeout_ms` applies to the
+    /// whole batch and is capped by the time left for the query. At most 5 requests can be sent
+    /// in a batch.
+    #[ink(extension = 16, handle_status = false, returns_result = false)]
+    fn batch_http_request(requests: Vec<HttpRequest>, timeout_ms: u64) -> BatchHttpResult;
+
+    /// Hash a message with the given algorithm.
+    #[ink(extension = 17, handle_status = false, returns_result = true)]
+    fn hash(algorithm: HashAlgorithm, message: &[u8]) -> Result<Vec<u8>, PinkExtError>;
+
+    /// Agree on a shared secret between the contract key derived with `salt` and a sr25519
+    /// public key.
+    #[ink(extension = 18, handle_status = false, returns_result = true)]
+    fn ecdh_agree(salt: &[u8], public_key: &[u8]) -> Result<Vec<u8>, PinkExtError>;
+
+    /// Encrypt a message with AES-256-GCM. The auth tag is appended to the returned ciphertext.
+    #[ink(extension = 19, handle_status = false, returns_result = true)]
+    fn aead_encrypt(key: &[u8], iv: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, PinkExtError>;
+
+    /// Decrypt a message encrypted with AES-256-GCM.
+    #[ink(extension = 20, handle_status = false, returns_result = true)]
+    fn aead_decrypt(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, PinkExtError>;
+
+    /// Recover the public key from a signature of a prehashed message.
+    ///
+    /// Only `SigType::Ecdsa` supports recovery, returning the compressed secp256k1 public key.
+    /// Other types return `Err(UnsupportedSigType)`.
+    #[ink(extension = 21, handle_status = false, returns_result = true)]
+    fn recover_prehashed(
+        sigtype: SigType,
+        signature: &[u8],
+        message_hash: Hash,
+    ) -> Result<Vec<u8>, PinkExtError>;
+
+    /// Get a value from the local store.
+    ///
+    /// The local store is the persistent counterpart of the local cache. Values never expire and
+    /// survive restarts of the worker, but they are still different in different workers.
+    /// The stores are saved along with the checkpoints of the worker, so a restart loses the
+    /// writes made since the latest checkpoint.
+    /// Only for query functions. Returns `Err(NotAllowedInCommand)` in a command context.
+    #[ink(extension = 22, handle_status = false, returns_result = true)]
+    fn local_store_get(key: &[u8]) -> Result<Option<Vec<u8>>, PinkExtError>;
+
+    /// Set a value in the local store.
+    ///
+    /// Returns `Err(QuotaExceeded)` if the store of the contract would grow beyond its quota.
+    /// Only for query functions. Returns `Err(NotAllowedInCommand)` in a command context.
+    #[ink(extension = 23, handle_status = false, returns_result = true)]
+    fn local_store_set(key: &[u8], value: &[u8]) -> Result<(), PinkExtError>;
+
+    /// Remove a value from the local store, returning the removed value if it existed.
+    ///
+    /// Only for query functions. Returns `Err(NotAllowedInCommand)` in a command context.
+    #[ink(extension = 24, handle_status = false, returns_result = true)]
+    fn local_store_remove(key: &[u8]) -> Result<Option<Vec<u8>>, PinkExtError>;
+
+    /// Get the entries of the local cache whose keys start with `prefix`, in key order.
+    ///
+    /// At most `limit` entries, capped to 100, are returned. To get the next page, pass the last
+    /// key of the previous page as `start_after`.
+    /// Only for query functions. Returns `Err(NotAllowedInCommand)` in a command context.
+    #[ink(extension = 25, handle_status = false, returns_result = true)]
+    fn cache_scan(
+        prefix: &[u8],
+        start_after: Option<Vec<u8>>,
+        limit: u32,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, PinkExtError>;
+
+    /// Atomically set a value in the local cache if the current value equals `expected`.
+    ///
+    /// `None` as `expected` means the key must be absent, and `None` as `value` removes the key.
+    /// Returns whether the value was swapped.
+    /// Only for query functions. Returns `Err(NotAllowedInCommand)` in a command context.
+    #[ink(extension = 26, handle_status = false, returns_result = true)]
+    fn cache_compare_and_swap(
+        key: &[u8],
+        expected: Option<Vec<u8>>,
+        value: Option<Vec<u8>>,
+    ) -> Result<bool, PinkExtError>;
+
+    /// Atomically add `delta` to an integer in the local cache and return the new value.
+    ///
+    /// The integer is stored as a SCALE encoded `i64`. A missing key counts as 0.
+    /// Only for query functions. Returns `Err(NotAllowedInCommand)` in a command context.
+    #[ink(extension = 27, handle_status = false, returns_result = true)]
+    fn cache_increment(key: &[u8], delta: i64) -> Result<i64, PinkExtError>;
+
+    /// Get the usage of the local cache of the contract.
+    ///
+    /// Only for query functions. Returns `Err(NotAllowedInCommand)` in a command context.
+    #[ink(extension = 28, handle_status = false, returns_result = true)]
+    fn cache_stats() -> Result<CacheStats, PinkExtError>;
+
+    /// Send a query to the sidevm instance of a contract in the same cluster and wait for the reply.
+    ///
+    /// `contract` can be the caller itself. The sidevm receives the query with the caller as the
+    /// origin, the same way as the queries from the RPC. Returns `Err(Timeout)` if no reply
+    /// arrives within `timeout_ms`, which is also capped by the time left for the current call.
+    /// Only for query functions. Returns `Err(NotAllowedInCommand)` in a command context.
+    #[ink(extension = 29, handle_status = false, returns_result = true)]
+    fn query_sidevm(
+        contract: AccountId,
+        payload: &[u8],
+        timeout_ms: u64,
+    ) -> Result<Vec<u8>, PinkExtError>;
+
+    /// Push a raw message to a topic, like `crate::push_message`.
+    ///
+    /// Returns `Err(EgressQuotaExceeded)` instead of dropping the message if the egress queue of
+    /// the contract has no room left for it, counting the messages already pushed in this call.
+    /// Only for command functions. Returns `Err(NotAllowedInQuery)` in a query context.
+    #[ink(extension = 30, handle_status = false, returns_result = true)]
+    fn try_push_message(payload: &[u8], topic: &[u8]) -> Result<(), PinkExtError>;
+
+    /// Push a message to a topic accepting optional secret messages, like
+    /// `crate::push_osp_message`.
+    ///
+    /// Fails the same way as `try_push_message`.
+    #[ink(extension = 31, handle_status = false, returns_result = true)]
+    fn try_push_osp_message(
+        payload: &[u8],
+        topic: &[u8],
+        remote_pubkey: Option<EcdhPublicKey>,
+    ) -> Result<(), PinkExtError>;
+
+    // The v1 extensions below report the host side failures as `PinkExtError` rather than
+    // trapping the contract. The ids are the ids of the original ones plus `0x8000`. They must
+    // stay below `0x10000`, because pallet-contracts takes the upper 16 bits of the id as the
+    // extension id and the runtime only serves extension id 0.
+
+    /// Same as `http_request`, reporting the failures as `PinkExtError`.
+    #[ink(extension = 0x8001, handle_status = false, returns_result = true)]
+    fn http_request_v1(request: HttpRequest) -> Result<HttpResponse, PinkExtError>;
+
+    /// Same as `sign`, reporting the failures as `PinkExtError`.
+    #[ink(extension = 0x8002, handle_status = false, returns_result = true)]
+    fn sign_v1(sigtype: SigType, key: &[u8], message: &[u8]) -> Result<Vec<u8>, PinkExtError>;
+
+    /// Same as `get_public_key`, reporting the failures as `PinkExtError`.
+    #[ink(extension = 0x8005, handle_status = false, returns_result = true)]
+    fn get_public_key_v1(sigtype: SigType, key: &[u8]) -> Result<Vec<u8>, PinkExtError>;
+
+    /// Same as `cache_set`, reporting the failures as `PinkExtError`.
+    #[ink(extension = 0x8006, handle_status = false, returns_result = true)]
+    fn cache_set_v1(key: &[u8], value: &[u8]) -> Result<(), PinkExtError>;
+
+    /// Same as `getrandom`, reporting the failures as `PinkExtError`.
+    #[ink(extension = 0x800b, handle_status = false, returns_result = true)]
+    fn getrandom_v1(length: u8) -> Result<Vec<u8>, PinkExtError>;
+
+    /// Same as `ecdsa_sign_prehashed`, reporting the failures as `PinkExtError`.
+    #[ink(extension = 0x800d, handle_status = false, returns_result = true)]
+    fn ecdsa_sign_prehashed_v1(
+        key: &[u8],
+        message_hash: Hash,
+    ) -> Result<EcdsaSignature, PinkExtError>;
+
+    /// Same as `system_contract_id`, reporting the failures as `PinkExtError`.
+    #[ink(extension = 0x800f, handle_status = false, returns_result = true)]
+    fn system_contract_id_v1() -> Result<AccountId, PinkExtError>;
 }
 
 pub fn pink_extension_instance() -> <PinkExt as ChainExtensionInstance>::Instance {
@@ -0,0 +1,51 @@
+use alloc::vec::Vec;
+
+use super::PinkExtError;
+
+/// Hash algorithms provided by the runtime.
+#[derive(scale::Encode, scale::Decode, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum HashAlgorithm {
+    Keccak256,
+    Blake2b128,
+    Blake2b256,
+    Sha256,
+}
+
+/// Hash a message with the given algorithm.
+///
+/// # Examples
+/// ```ignore
+/// let digest = hash(HashAlgorithm::Sha256, b"hello world")?;
+/// assert_eq!(digest.len(), 32);
+/// ```
+pub fn hash(algorithm: HashAlgorithm, message: &[u8]) -> Result<Vec<u8>, PinkExtError> {
+    crate::ext().hash(algorithm, message)
+}
+
+/// Agree on a shared secret between the contract key derived with `salt` and `public_key`.
+///
+/// The contract key is the one returned by `signing::derive_sr25519_key(salt)`, and the peer key
+/// is an sr25519 public key. The secret is the same on both sides.
+///
+/// # Examples
+/// ```ignore
+/// let secret = ecdh_agree(b"a spoon of salt", &peer_pubkey)?;
+/// let encrypted = aead_encrypt(&secret, &iv, b"hello world")?;
+/// ```
+pub fn ecdh_agree(salt: &[u8], public_key: &[u8]) -> Result<Vec<u8>, PinkExtError> {
+    crate::ext().ecdh_agree(salt, public_key)
+}
+
+/// Encrypt a message with AES-256-GCM, returning the ciphertext with the 128 bits auth tag
+/// appended.
+///
+/// The key must be 32 bytes and the iv 12 bytes. Never reuse an iv with the same key.
+pub fn aead_encrypt(key: &[u8], iv: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, PinkExtError> {
+    crate::ext().aead_encrypt(key, iv, plaintext)
+}
+
+/// Decrypt a message encrypted by `aead_encrypt`.
+pub fn aead_decrypt(key: &[u8], iv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, PinkExtError> {
+    crate::ext().aead_decrypt(key, iv, ciphertext)
+}
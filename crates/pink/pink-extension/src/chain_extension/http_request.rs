@@ -38,6 +38,89 @@ impl HttpResponse {
     }
 }
 
+/// Errors preventing an HTTP request from being sent.
+///
+/// Failures happening after the request is sent are reported by the response status code
+/// instead.
+#[derive(scale::Encode, scale::Decode, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum HttpRequestError {
+    InvalidUrl,
+    InvalidMethod,
+    InvalidHeaderName,
+    InvalidHeaderValue,
+    FailedToCreateClient,
+    TooManyRequests,
+    /// The runtime failed while handling the request, e.g. its worker thread panicked.
+    Internal,
+}
+
+impl HttpRequestError {
+    pub fn display(&self) -> &'static str {
+        match self {
+            Self::InvalidUrl => "Invalid url",
+            Self::InvalidMethod => "Invalid HTTP method",
+            Self::InvalidHeaderName => "Invalid HTTP header key",
+            Self::InvalidHeaderValue => "Invalid HTTP header value",
+            Self::FailedToCreateClient => "Failed to create client",
+            Self::TooManyRequests => "Too many requests",
+            Self::Internal => "Internal error",
+        }
+    }
+}
+
+/// The result of `batch_http_request`, holding the result of each request in order.
+pub type BatchHttpResult = Result<Vec<Result<HttpResponse, HttpRequestError>>, HttpRequestError>;
+
+/// Limits and filters applied to the HTTP requests made by the contracts in a cluster.
+///
+/// Requests to a denied domain are not sent and get a response with the non-standard status
+/// code 525.
+#[derive(scale::Encode, scale::Decode, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct HttpEgressConfig {
+    /// Max time in milliseconds a query can spend, including its HTTP requests.
+    pub timeout_ms: u64,
+    /// Max size in bytes of a response body.
+    pub max_body_size: u32,
+    /// If not empty, only these domains and their subdomains can be requested.
+    pub allowed_domains: Vec<String>,
+    /// These domains and their subdomains can never be requested. Takes precedence over
+    /// `allowed_domains`.
+    pub denied_domains: Vec<String>,
+}
+
+impl Default for HttpEgressConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 10_000,
+            max_body_size: 1024 * 256,
+            allowed_domains: Vec::new(),
+            denied_domains: Vec::new(),
+        }
+    }
+}
+
+impl HttpEgressConfig {
+    /// Whether requests to `host` are allowed by the domain lists.
+    pub fn is_allowed(&self, host: &str) -> bool {
+        fn matches(host: &str, domain: &str) -> bool {
+            let host = host.trim_end_matches('.');
+            let domain = domain.trim_end_matches('.');
+            if host.eq_ignore_ascii_case(domain) {
+                return true;
+            }
+            host.len() > domain.len()
+                && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+                && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+        }
+        if self.denied_domains.iter().any(|d| matches(host, d)) {
+            return false;
+        }
+        self.allowed_domains.is_empty() || self.allowed_domains.iter().any(|d| matches(host, d))
+    }
+}
+
 #[macro_export]
 macro_rules! http_req {
     ($method: expr, $url: expr, $data: expr, $headers: expr) => {{
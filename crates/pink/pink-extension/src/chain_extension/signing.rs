@@ -52,6 +52,24 @@ pub fn ecdsa_verify_prehashed(
     crate::ext().ecdsa_verify_prehashed(signature, message_hash, pubkey)
 }
 
+/// Recover the public key from a signature of a prehashed message.
+///
+/// Only `SigType::Ecdsa` supports recovery, returning the compressed secp256k1 public key.
+///
+/// # Examples
+/// ```ignore
+/// let signature = ecdsa_sign_prehashed(privkey, message_hash);
+/// let pubkey = recover_prehashed(&signature, message_hash, SigType::Ecdsa)?;
+/// assert_eq!(pubkey, get_public_key(privkey, SigType::Ecdsa));
+/// ```
+pub fn recover_prehashed(
+    signature: &[u8],
+    message_hash: Hash,
+    sigtype: SigType,
+) -> Result<Vec<u8>, crate::chain_extension::PinkExtError> {
+    crate::ext().recover_prehashed(sigtype, signature, message_hash)
+}
+
 /// Derive a key pair from the contract key
 ///
 /// # Examples
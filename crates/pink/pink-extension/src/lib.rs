@@ -58,10 +58,23 @@ pub struct OspMessage {
     pub remote_pubkey: Option<EcdhPublicKey>,
 }
 
-#[derive(Encode, Decode, Debug)]
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
 pub enum HookPoint {
+    /// Fired at the end of each block, after all the messages in the block are processed.
     OnBlockEnd,
+    /// Fired at the start of each block, before any message in the block is processed.
+    OnBlockStart,
+    /// Fired at the end of every `interval_blocks` blocks. A contract has at most one timer.
+    OnTimer { interval_blocks: u32 },
+    /// Fired when the key of the cluster the contract belongs to is rotated.
+    OnClusterKeyRotated,
+    /// Fired when the sidevm instance of the contract exits.
+    ///
+    /// Sidevm instances exit at different moments on different workers, so the hook is called
+    /// with `rollback` set, that is in query mode: its state changes are discarded and only the
+    /// messages to the sidevm are kept.
+    OnSidevmExit,
 }
 
 /// System Event used to communicate between the contract and the runtime.
@@ -103,6 +116,21 @@ pub enum PinkEvent {
     SetLogHandler(AccountId),
     /// Set the weight of contract used to schedule queries and sidevm vruntime
     SetContractWeight { contract: AccountId, weight: u32 },
+    /// Set the limits and domain filters of HTTP requests made by contracts in current cluster.
+    SetHttpEgressConfig(chain_extension::HttpEgressConfig),
+    /// Set contract hook, limiting the gas consumed by each call of the hook.
+    ///
+    /// `SetHook` is the same as this with a gas_limit of 0.
+    SetHookWithGasLimit {
+        /// The event to hook
+        hook: HookPoint,
+        /// The target contract address
+        contract: AccountId,
+        /// The selector to invoke on hooked event fired.
+        selector: u32,
+        /// The max gas the hook can consume in each call. 0 means the default limit of commands.
+        gas_limit: u64,
+    },
 }
 
 impl PinkEvent {
@@ -118,6 +146,8 @@ impl PinkEvent {
             PinkEvent::ForceStopSidevm { .. } => true,
             PinkEvent::SetLogHandler(_) => false,
             PinkEvent::SetContractWeight { .. } => false,
+            PinkEvent::SetHttpEgressConfig(_) => false,
+            PinkEvent::SetHookWithGasLimit { .. } => false,
         }
     }
 
@@ -133,6 +163,8 @@ impl PinkEvent {
             PinkEvent::ForceStopSidevm { .. } => "ForceStopSidevm",
             PinkEvent::SetLogHandler(_) => "SetLogHandler",
             PinkEvent::SetContractWeight { .. } => "SetContractWeight",
+            PinkEvent::SetHttpEgressConfig(_) => "SetHttpEgressConfig",
+            PinkEvent::SetHookWithGasLimit { .. } => "SetHookWithGasLimit",
         }
     }
 }
@@ -176,6 +208,9 @@ impl PinkEvent {
 /// Push a raw message to a topic accepting only vanilla messages
 ///
 /// Most phala system topics accept vanilla messages
+///
+/// The message is dropped if the egress queue of the contract is full. Use
+/// `ext().try_push_message` to be told about it.
 pub fn push_message(payload: Vec<u8>, topic: Vec<u8>) {
     emit_event::<PinkEnvironment, _>(PinkEvent::Message(Message { payload, topic }))
 }
@@ -183,6 +218,9 @@ pub fn push_message(payload: Vec<u8>, topic: Vec<u8>) {
 /// Push a message to a topic accepting optional secret messages
 ///
 /// Contract commands topic accept osp messages
+///
+/// The message is dropped if the egress queue of the contract is full. Use
+/// `ext().try_push_osp_message` to be told about it.
 pub fn push_osp_message(payload: Vec<u8>, topic: Vec<u8>, remote_pubkey: Option<EcdhPublicKey>) {
     emit_event::<PinkEnvironment, _>(PinkEvent::OspMessage(OspMessage {
         message: Message { payload, topic },
@@ -190,8 +228,7 @@ pub fn push_osp_message(payload: Vec<u8>, topic: Vec<u8>, remote_pubkey: Option<
     }))
 }
 
-/// Turn on on_block_end feature and set it's selector
-///
+/// Register the method with given selector to be called on the hooked event.
 pub fn set_hook(hook: HookPoint, contract: AccountId, selector: u32) {
     emit_event::<PinkEnvironment, _>(PinkEvent::SetHook {
         hook,
@@ -200,6 +237,24 @@ pub fn set_hook(hook: HookPoint, contract: AccountId, selector: u32) {
     })
 }
 
+/// Same as `set_hook`, but caps the gas consumed by each call of the hook.
+///
+/// `gas_limit` is in turn capped by the gas limit of commands. Pass 0 to use the gas limit of
+/// commands.
+pub fn set_hook_with_gas_limit(
+    hook: HookPoint,
+    contract: AccountId,
+    selector: u32,
+    gas_limit: u64,
+) {
+    emit_event::<PinkEnvironment, _>(PinkEvent::SetHookWithGasLimit {
+        hook,
+        contract,
+        selector,
+        gas_limit,
+    })
+}
+
 /// Start a SideVM instance
 pub fn start_sidevm(code_hash: Hash) -> Result<(), system::Error> {
     let driver =
@@ -245,6 +300,11 @@ pub fn set_contract_weight(contract: AccountId, weight: u32) {
     emit_event::<PinkEnvironment, _>(PinkEvent::SetContractWeight { contract, weight });
 }
 
+/// Set the limits and domain filters of HTTP requests made by contracts in current cluster
+pub fn set_http_egress_config(config: chain_extension::HttpEgressConfig) {
+    emit_event::<PinkEnvironment, _>(PinkEvent::SetHttpEgressConfig(config));
+}
+
 /// Pink defined environment. Used this environment to access the fat contract runtime features.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
@@ -275,4 +335,22 @@ mod tests {
     fn test_event_topics() {
         insta::assert_debug_snapshot!(super::PinkEvent::event_topic());
     }
+
+    #[test]
+    fn test_http_egress_domain_filter() {
+        use super::chain_extension::HttpEgressConfig;
+
+        let config = HttpEgressConfig {
+            allowed_domains: vec!["example.com".into()],
+            denied_domains: vec!["secret.example.com".into()],
+            ..Default::default()
+        };
+        assert!(config.is_allowed("example.com"));
+        assert!(config.is_allowed("API.Example.com"));
+        assert!(!config.is_allowed("secret.example.com"));
+        assert!(!config.is_allowed("a.secret.example.com"));
+        assert!(!config.is_allowed("badexample.com"));
+        assert!(!config.is_allowed("example.org"));
+        assert!(HttpEgressConfig::default().is_allowed("example.org"));
+    }
 }
@@ -69,11 +69,33 @@ pub trait System {
         selector: u32,
     ) -> Result<()>;
 
+    /// Same as `set_hook`, but each call of the hook can consume at most `gas_limit` gas.
+    /// 0 means the default limit.
+    ///
+    /// The caller must be an administrator.
+    #[ink(message)]
+    fn set_hook_with_gas_limit(
+        &mut self,
+        hook: crate::HookPoint,
+        contract_id: AccountId,
+        selector: u32,
+        gas_limit: u64,
+    ) -> Result<()>;
+
     /// Set weight of the contract for query requests and sidevm scheduling.
     ///
     /// Higher weight would let the contract to get more resource.
     #[ink(message)]
     fn set_contract_weight(&self, contract_id: AccountId, weight: u32) -> Result<()>;
+
+    /// Set the limits and domain filters of HTTP requests made by contracts in the cluster.
+    ///
+    /// The caller must be the owner of the cluster.
+    #[ink(message)]
+    fn set_http_egress_config(
+        &mut self,
+        config: crate::chain_extension::HttpEgressConfig,
+    ) -> Result<()>;
 }
 
 /// Driver to manage sidevm deployments.
@@ -7,10 +7,11 @@ use sp_core::hashing;
 use sp_runtime::DispatchError;
 
 use crate::{
-    runtime::{BoxedEventCallbacks, Contracts, ExecSideEffects, System, Timestamp},
+    runtime::{BoxedEventCallbacks, Contracts, ExecSideEffects, RuntimeOrigin, System, Timestamp},
     storage,
     types::{
-        AccountId, BlockNumber, Hash, COMMAND_GAS_LIMIT, INSTANTIATE_GAS_LIMIT, QUERY_GAS_LIMIT,
+        AccountId, Balance, BlockNumber, Hash, COMMAND_GAS_LIMIT, INSTANTIATE_GAS_LIMIT,
+        QUERY_GAS_LIMIT,
     },
 };
 
@@ -36,6 +37,23 @@ pub struct ExecError {
     pub message: String,
 }
 
+/// The leading fields of the ContractInfo in pallet-contracts.
+#[derive(Encode, Decode)]
+struct ContractInfo {
+    trie_id: Vec<u8>,
+    code_hash: Hash,
+}
+
+/// The OwnerInfo of an uploaded code in pallet-contracts.
+#[derive(Encode, Decode)]
+struct OwnerInfo {
+    owner: AccountId,
+    #[codec(compact)]
+    deposit: Balance,
+    #[codec(compact)]
+    refcount: u64,
+}
+
 #[derive(Debug, Default, Encode, Decode, Clone)]
 struct HookSelectors {
     on_block_end: Option<u32>,
@@ -183,10 +201,16 @@ impl Contract {
                 ExecSideEffects::default(),
             );
         }
+        let gas_limit = if rollback {
+            QUERY_GAS_LIMIT
+        } else {
+            COMMAND_GAS_LIMIT
+        };
         self.unchecked_bare_call(
             storage,
             origin,
             input_data,
+            gas_limit,
             rollback,
             block_number,
             now,
@@ -200,6 +224,7 @@ impl Contract {
         storage: &mut Storage,
         origin: AccountId,
         input_data: Vec<u8>,
+        gas_limit: Weight,
         rollback: bool,
         block_number: BlockNumber,
         now: u64,
@@ -209,11 +234,6 @@ impl Contract {
         storage.execute_with(rollback, callbacks, move || {
             System::set_block_number(block_number);
             Timestamp::set_timestamp(now);
-            let gas_limit = if rollback {
-                QUERY_GAS_LIMIT
-            } else {
-                COMMAND_GAS_LIMIT
-            };
             Contracts::bare_call(origin, addr, 0, gas_limit, None, input_data, false)
         })
     }
@@ -261,41 +281,104 @@ impl Contract {
         callbacks: Option<BoxedEventCallbacks>,
     ) -> Result<ExecSideEffects, ExecError> {
         if let Some(selector) = self.hooks.on_block_end {
-            let mut input_data = vec![];
-            selector.to_be_bytes().encode_to(&mut input_data);
-
-            let (result, effects) = self.unchecked_bare_call(
-                storage,
-                AccountId::new(ACCOUNT_RUNTIME),
-                input_data,
-                false,
-                block_number,
-                now,
-                callbacks,
-            );
-            let _ = transpose_contract_result(&result)?;
-            Ok(effects)
+            self.call_hook(storage, selector, 0, false, block_number, now, callbacks)
         } else {
             Ok(Default::default())
         }
     }
 
+    /// Call a hooked method on behalf of the runtime
+    ///
+    /// The gas consumed is capped by `gas_limit`, which in turn is capped by the gas limit of
+    /// commands. 0 means the gas limit of commands.
+    #[allow(clippy::too_many_arguments)]
+    pub fn call_hook(
+        &self,
+        storage: &mut Storage,
+        selector: u32,
+        gas_limit: u64,
+        rollback: bool,
+        block_number: BlockNumber,
+        now: u64,
+        callbacks: Option<BoxedEventCallbacks>,
+    ) -> Result<ExecSideEffects, ExecError> {
+        let mut input_data = vec![];
+        selector.to_be_bytes().encode_to(&mut input_data);
+
+        let (result, effects) = self.unchecked_bare_call(
+            storage,
+            AccountId::new(ACCOUNT_RUNTIME),
+            input_data,
+            hook_gas_limit(gas_limit),
+            rollback,
+            block_number,
+            now,
+            callbacks,
+        );
+        let _ = transpose_contract_result(&result)?;
+        Ok(effects)
+    }
+
     pub fn set_on_block_end_selector(&mut self, selector: u32) {
         self.hooks.on_block_end = Some(selector)
     }
 
     pub fn code_hash(&self, storage: &Storage) -> Option<Hash> {
-        #[derive(Encode, Decode)]
-        struct ContractInfo {
-            trie_id: Vec<u8>,
-            code_hash: Hash,
-        }
         // The pallet-contracts doesn't export an API the get the code hash. So we dig it out from the storage.
         let key = storage_map_prefix_twox_64_concat(b"Contracts", b"ContractInfoOf", &self.address);
         let value = storage.get(&key)?;
         let info = ContractInfo::decode(&mut &value[..]).ok()?;
         Some(info.code_hash)
     }
+
+    /// Replace the code of the contract, keeping its address and storage.
+    pub fn set_code(&self, storage: &mut Storage, code_hash: Hash) -> Result<(), DispatchError> {
+        let dest = self.address.clone();
+        storage
+            .execute_with(false, None, move || {
+                Contracts::set_code(RuntimeOrigin::root(), dest, code_hash)
+            })
+            .0
+    }
+
+    /// Remove the contract and all of its storage.
+    ///
+    /// Mirrors the termination in pallet-contracts: the contract info and the child trie are
+    /// removed and the reference count of the contract code is decremented, so the code can be
+    /// removed by its owner once no contract uses it. There is no storage deposit to refund
+    /// since the cluster runtime charges none.
+    pub fn terminate(&self, storage: &mut Storage) -> Result<(), DispatchError> {
+        use frame_support::storage::{child, unhashed};
+
+        // The pallet-contracts only supports contracts terminating themselves. So we remove it
+        // from the storage directly.
+        let key = storage_map_prefix_twox_64_concat(b"Contracts", b"ContractInfoOf", &self.address);
+        storage
+            .execute_with(false, None, move || {
+                let info: ContractInfo =
+                    unhashed::get(&key).ok_or(DispatchError::Other("Contract not found"))?;
+                let child_info = child::ChildInfo::new_default(&info.trie_id);
+                let _ = child::clear_storage(&child_info, None, None);
+                unhashed::kill(&key);
+
+                let owner_key =
+                    storage_map_prefix_identity(b"Contracts", b"OwnerInfoOf", &info.code_hash);
+                if let Some(mut owner_info) = unhashed::get::<OwnerInfo>(&owner_key) {
+                    owner_info.refcount = owner_info.refcount.saturating_sub(1);
+                    unhashed::put(&owner_key, &owner_info);
+                }
+                Ok(())
+            })
+            .0
+    }
+}
+
+/// Calculates the Substrate storage key of an item in a StorageMap with the Identity hasher
+fn storage_map_prefix_identity(module: &[u8], storage_item: &[u8], key: &impl Encode) -> Vec<u8> {
+    let mut bytes = sp_core::twox_128(module).to_vec();
+    bytes.extend(&sp_core::twox_128(storage_item)[..]);
+    bytes.extend(key.encode());
+    bytes
 }
 
 /// Calculates the Substrate storage key prefix for a StorageMap
@@ -323,6 +406,14 @@ pub fn transpose_contract_result(result: &ContractExecResult) -> Result<&[u8], E
         })
 }
 
+/// The gas limit of a hook call. Only the ref time is limited by the hook owner.
+fn hook_gas_limit(gas_limit: u64) -> Weight {
+    if gas_limit == 0 {
+        return COMMAND_GAS_LIMIT;
+    }
+    COMMAND_GAS_LIMIT.set_ref_time(gas_limit.min(COMMAND_GAS_LIMIT.ref_time()))
+}
+
 pub use contract_file::ContractFile;
 
 mod contract_file {
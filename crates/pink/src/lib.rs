@@ -3,6 +3,7 @@ extern crate alloc;
 mod contract;
 mod export_fixtures;
 pub mod local_cache;
+pub mod local_store;
 
 pub mod runtime;
 pub mod storage;
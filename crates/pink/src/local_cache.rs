@@ -4,21 +4,29 @@
 //! by some kind of cache expiring machanism.
 
 use alloc::borrow::Cow;
+use core::ops::Bound;
 use once_cell::sync::Lazy;
 use pink_extension::CacheOp;
-use std::{collections::HashMap, sync::RwLock, time::Instant};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+    time::Instant,
+};
 
-pub use pink_extension::chain_extension::StorageQuotaExceeded;
+pub use pink_extension::chain_extension::{CacheStats, PinkExtError, StorageQuotaExceeded};
 
 use crate::types::AccountId;
 
 pub static GLOBAL_CACHE: Lazy<RwLock<LocalCache>> = Lazy::new(Default::default);
 
+/// Max number of entries returned by a single scan.
+pub const MAX_SCAN_LIMIT: usize = 100;
+
 #[derive(Default, Debug)]
 struct Storage {
     // Sum of the size of all the keys and values.
     size: usize,
-    kvs: HashMap<Vec<u8>, StorageValue>,
+    kvs: BTreeMap<Vec<u8>, StorageValue>,
 }
 
 #[derive(Debug)]
@@ -144,6 +152,95 @@ impl LocalCache {
         v
     }
 
+    /// Get the unexpired entries whose keys start with `prefix`, in key order.
+    ///
+    /// The entries start after `start_after` if given, which is the last key of the previous
+    /// page. At most `limit` entries, capped to `MAX_SCAN_LIMIT`, are returned.
+    pub fn scan(
+        &self,
+        id: &[u8],
+        prefix: &[u8],
+        start_after: Option<&[u8]>,
+        limit: usize,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let storage = match self.storages.get(id) {
+            Some(storage) => storage,
+            None => return vec![],
+        };
+        let lower = match start_after {
+            Some(key) if key >= prefix => Bound::Excluded(key),
+            _ => Bound::Included(prefix),
+        };
+        let now = now();
+        storage
+            .kvs
+            .range::<[u8], _>((lower, Bound::Unbounded))
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .filter(|(_, v)| v.expire_at > now)
+            .take(limit.min(MAX_SCAN_LIMIT))
+            .map(|(k, v)| (k.clone(), v.value.clone()))
+            .collect()
+    }
+
+    /// Set the value of `key` only if its current value equals `expected`.
+    ///
+    /// `None` as `expected` means the key is absent, and `None` as `value` removes the key.
+    /// Returns whether the value was swapped.
+    pub fn compare_and_swap(
+        &mut self,
+        id: Cow<[u8]>,
+        key: Cow<[u8]>,
+        expected: Option<&[u8]>,
+        value: Option<Cow<[u8]>>,
+    ) -> Result<bool, StorageQuotaExceeded> {
+        if self.get(&id, &key).as_deref() != expected {
+            return Ok(false);
+        }
+        match value {
+            Some(value) => self.set(id, key, value)?,
+            None => {
+                let _ = self.remove(&id, &key);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Add `delta` to the integer stored in `key` and return the new value.
+    ///
+    /// The integer is stored as a SCALE encoded `i64`. A missing key counts as 0.
+    pub fn increment(
+        &mut self,
+        id: Cow<[u8]>,
+        key: Cow<[u8]>,
+        delta: i64,
+    ) -> Result<i64, PinkExtError> {
+        let current = match self.get(&id, &key) {
+            Some(value) => {
+                i64::from_le_bytes(value.try_into().or(Err(PinkExtError::NotAnInteger))?)
+            }
+            None => 0,
+        };
+        let value = current
+            .checked_add(delta)
+            .ok_or(PinkExtError::IntegerOverflow)?;
+        self.set(id, key, value.to_le_bytes()[..].into())
+            .or(Err(PinkExtError::QuotaExceeded))?;
+        Ok(value)
+    }
+
+    pub fn stats(&self, id: &[u8]) -> CacheStats {
+        let (keys, size) = self
+            .storages
+            .get(id)
+            .map(|storage| (storage.kvs.len(), storage.size))
+            .unwrap_or_default();
+        CacheStats {
+            keys: keys as _,
+            size: size as _,
+            quota: self.max_cache_size_per_contract as _,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn remove_storage(&mut self, id: &[u8]) {
         let _ = self.storages.remove(id);
@@ -197,6 +294,43 @@ pub fn local_cache_remove(contract: &[u8], key: &[u8]) -> Option<Vec<u8>> {
     GLOBAL_CACHE.write().unwrap().remove(contract, key)
 }
 
+pub fn local_cache_scan(
+    contract: &[u8],
+    prefix: &[u8],
+    start_after: Option<&[u8]>,
+    limit: usize,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    GLOBAL_CACHE
+        .read()
+        .unwrap()
+        .scan(contract, prefix, start_after, limit)
+}
+
+pub fn local_cache_compare_and_swap(
+    contract: &[u8],
+    key: &[u8],
+    expected: Option<&[u8]>,
+    value: Option<&[u8]>,
+) -> Result<bool, StorageQuotaExceeded> {
+    GLOBAL_CACHE.write().unwrap().compare_and_swap(
+        contract.into(),
+        key.into(),
+        expected,
+        value.map(Into::into),
+    )
+}
+
+pub fn local_cache_increment(contract: &[u8], key: &[u8], delta: i64) -> Result<i64, PinkExtError> {
+    GLOBAL_CACHE
+        .write()
+        .unwrap()
+        .increment(contract.into(), key.into(), delta)
+}
+
+pub fn local_cache_stats(contract: &[u8]) -> CacheStats {
+    GLOBAL_CACHE.read().unwrap().stats(contract)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -268,6 +402,71 @@ mod test {
         assert!(cache.set(cow(b"id"), cow(b"bar"), cow(b"value")).is_err());
     }
 
+    #[test]
+    fn scan_should_work() {
+        let mut cache = test_cache();
+        for key in [&b"a"[..], b"b/1", b"b/2", b"b/3", b"c"] {
+            let _ = cache.set(cow(b"id"), cow(&key), cow(b"v"));
+        }
+        let keys = |entries: Vec<(Vec<u8>, Vec<u8>)>| -> Vec<Vec<u8>> {
+            entries.into_iter().map(|(k, _)| k).collect()
+        };
+        assert_eq!(
+            keys(cache.scan(b"id", b"b/", None, 2)),
+            vec![b"b/1".to_vec(), b"b/2".to_vec()]
+        );
+        assert_eq!(
+            keys(cache.scan(b"id", b"b/", Some(b"b/2"), 2)),
+            vec![b"b/3".to_vec()]
+        );
+        assert_eq!(
+            keys(cache.scan(b"id", b"b/", Some(b"a"), 1)),
+            vec![b"b/1".to_vec()]
+        );
+        assert!(cache.scan(b"id", b"d", None, 10).is_empty());
+    }
+
+    #[test]
+    fn compare_and_swap_should_work() {
+        let mut cache = test_cache();
+        let mut cas = |expected: Option<&[u8]>, value: Option<&[u8]>| {
+            cache
+                .compare_and_swap(cow(b"id"), cow(b"foo"), expected, value.map(Cow::Borrowed))
+                .unwrap()
+        };
+        assert!(!cas(Some(&b"bar"[..]), Some(&b"baz"[..])));
+        assert!(cas(None, Some(&b"bar"[..])));
+        assert!(!cas(None, Some(&b"baz"[..])));
+        assert!(cas(Some(&b"bar"[..]), None));
+        assert_eq!(cache.get(b"id", b"foo"), None);
+        assert_eq!(get_size(&cache, b"id"), 0);
+    }
+
+    #[test]
+    fn increment_should_work() {
+        let mut cache = test_cache();
+        assert_eq!(cache.increment(cow(b"id"), cow(b"n"), 2), Ok(2));
+        assert_eq!(cache.increment(cow(b"id"), cow(b"n"), -5), Ok(-3));
+        assert_eq!(cache.get(b"id", b"n"), Some((-3i64).to_le_bytes().to_vec()));
+        assert_eq!(
+            cache.increment(cow(b"id"), cow(b"n"), i64::MIN),
+            Err(PinkExtError::IntegerOverflow)
+        );
+        let _ = cache.set(cow(b"id"), cow(b"s"), cow(b"str"));
+        assert_eq!(
+            cache.increment(cow(b"id"), cow(b"s"), 1),
+            Err(PinkExtError::NotAnInteger)
+        );
+        assert_eq!(
+            cache.stats(b"id"),
+            CacheStats {
+                keys: 2,
+                size: 13,
+                quota: 1024
+            }
+        );
+    }
+
     #[test]
     fn size_calc() {
         let mut cache = test_cache();
@@ -0,0 +1,205 @@
+//! The LocalStore is the persistent counterpart of the LocalCache. Like the cache, the data is
+//! different in different machines of the same contract. Unlike the cache, the values never
+//! expire and the stores are sealed to disk by pruntime, so they survive restarts.
+//!
+//! The stores are sealed whenever pruntime takes a checkpoint, so they are only as durable as
+//! the latest checkpoint. The writes made after it are lost if the worker stops.
+//!
+//! This module only keeps the stores in memory. It tracks which stores have changed since they
+//! were last dumped, so that the host can persist them incrementally.
+
+use alloc::borrow::Cow;
+use once_cell::sync::Lazy;
+use scale::{Decode, Encode};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::RwLock,
+};
+
+pub use pink_extension::chain_extension::StorageQuotaExceeded;
+
+pub static GLOBAL_STORE: Lazy<RwLock<LocalStore>> = Lazy::new(Default::default);
+
+#[derive(Default, Debug)]
+struct Storage {
+    // Sum of the size of all the keys and values.
+    size: usize,
+    kvs: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct LocalStore {
+    max_store_size_per_contract: usize,
+    storages: HashMap<Vec<u8>, Storage>,
+    // Ids of the stores changed since the last call to `take_changes`.
+    changed: BTreeSet<Vec<u8>>,
+}
+
+impl Default for LocalStore {
+    fn default() -> Self {
+        Self {
+            max_store_size_per_contract: 16 * 1024 * 1024, // 16MB
+            storages: Default::default(),
+            changed: Default::default(),
+        }
+    }
+}
+
+impl LocalStore {
+    pub fn get(&self, id: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+        self.storages.get(id)?.kvs.get(key).cloned()
+    }
+
+    pub fn set(
+        &mut self,
+        id: Cow<[u8]>,
+        key: Cow<[u8]>,
+        value: Cow<[u8]>,
+    ) -> Result<(), StorageQuotaExceeded> {
+        let store = self.storages.get(id.as_ref());
+        let size = store.map_or(0, |store| store.size);
+        let new_size = match store.and_then(|store| store.kvs.get(key.as_ref())) {
+            Some(v) => size + value.len() - v.len(),
+            None => size + key.len() + value.len(),
+        };
+        if new_size > self.max_store_size_per_contract {
+            return Err(StorageQuotaExceeded);
+        }
+        let store = self.storages.entry(id.to_vec()).or_default();
+        store.size = new_size;
+        store.kvs.insert(key.into_owned(), value.into_owned());
+        self.changed.insert(id.into_owned());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+        let store = self.storages.get_mut(id)?;
+        let v = store.kvs.remove(key)?;
+        store.size -= v.len() + key.len();
+        self.changed.insert(id.to_vec());
+        Some(v)
+    }
+
+    pub fn remove_storage(&mut self, id: &[u8]) {
+        if self.storages.remove(id).is_some() {
+            self.changed.insert(id.to_vec());
+        }
+    }
+
+    /// Load a store dumped by `take_changes`, replacing the one in memory.
+    pub fn load(&mut self, id: Vec<u8>, encoded: &[u8]) -> Result<(), scale::Error> {
+        let kvs = BTreeMap::<Vec<u8>, Vec<u8>>::decode(&mut &encoded[..])?;
+        let size = kvs.iter().map(|(k, v)| k.len() + v.len()).sum();
+        self.changed.remove(&id);
+        self.storages.insert(id, Storage { size, kvs });
+        Ok(())
+    }
+
+    /// Mark stores as changed, so that the next `take_changes` dumps them again.
+    ///
+    /// Used by the host to retry the stores it failed to persist.
+    pub fn mark_changed(&mut self, ids: impl IntoIterator<Item = Vec<u8>>) {
+        self.changed.extend(ids);
+    }
+
+    /// Dump the stores changed since the last call.
+    ///
+    /// Returns the encoded stores by their ids. `None` means the store has been removed.
+    pub fn take_changes(&mut self) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        core::mem::take(&mut self.changed)
+            .into_iter()
+            .map(|id| {
+                let encoded = self.storages.get(&id).map(|store| store.kvs.encode());
+                (id, encoded)
+            })
+            .collect()
+    }
+}
+
+pub fn local_store_set(
+    contract: &[u8],
+    key: &[u8],
+    value: &[u8],
+) -> Result<(), StorageQuotaExceeded> {
+    GLOBAL_STORE
+        .write()
+        .unwrap()
+        .set(contract.into(), key.into(), value.into())
+}
+
+pub fn local_store_get(contract: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+    GLOBAL_STORE.read().unwrap().get(contract, key)
+}
+
+pub fn local_store_remove(contract: &[u8], key: &[u8]) -> Option<Vec<u8>> {
+    GLOBAL_STORE.write().unwrap().remove(contract, key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_store() -> LocalStore {
+        LocalStore {
+            max_store_size_per_contract: 10,
+            ..Default::default()
+        }
+    }
+
+    fn cow(s: &impl AsRef<[u8]>) -> Cow<[u8]> {
+        Cow::Borrowed(s.as_ref())
+    }
+
+    #[test]
+    fn size_limit_should_work() {
+        let mut store = test_store();
+        assert!(store.set(cow(b"id"), cow(b"foo"), cow(b"value")).is_ok());
+        assert!(store.set(cow(b"id"), cow(b"bar"), cow(b"value")).is_err());
+        assert!(store.set(cow(b"id"), cow(b"foo"), cow(b"1234567")).is_ok());
+        assert!(store.remove(b"id", b"foo").is_some());
+        assert!(store.set(cow(b"id"), cow(b"bar"), cow(b"value")).is_ok());
+    }
+
+    #[test]
+    fn rejected_set_leaves_no_store() {
+        let mut store = test_store();
+        assert!(store
+            .set(cow(b"id"), cow(b"foo"), cow(b"a value too long"))
+            .is_err());
+        assert!(store.storages.is_empty());
+        assert!(store.take_changes().is_empty());
+    }
+
+    #[test]
+    fn changes_can_be_loaded_back() {
+        let mut store = test_store();
+        let _ = store.set(cow(b"id0"), cow(b"foo"), cow(b"bar"));
+        let _ = store.set(cow(b"id1"), cow(b"foo"), cow(b"bar"));
+        store.remove_storage(b"id1");
+        let changes = store.take_changes();
+        assert!(store.take_changes().is_empty());
+
+        let mut restored = test_store();
+        for (id, encoded) in changes {
+            match encoded {
+                Some(encoded) => restored.load(id, &encoded).unwrap(),
+                None => assert_eq!(id, b"id1"),
+            }
+        }
+        assert_eq!(restored.get(b"id0", b"foo"), Some(b"bar".to_vec()));
+        assert_eq!(restored.storages.get(&b"id0"[..]).unwrap().size, 6);
+        assert_eq!(restored.get(b"id1", b"foo"), None);
+    }
+
+    #[test]
+    fn marked_stores_are_dumped_again() {
+        let mut store = test_store();
+        let _ = store.set(cow(b"id0"), cow(b"foo"), cow(b"bar"));
+        let changes = store.take_changes();
+        store.mark_changed(changes.into_iter().map(|(id, _)| id));
+        let changes = store.take_changes();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].0, b"id0");
+        assert!(changes[0].1.is_some());
+    }
+}
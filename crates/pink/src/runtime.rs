@@ -21,7 +21,11 @@ use sp_runtime::{
 };
 
 pub use extension::{get_side_effects, ExecSideEffects};
-pub use pink_extension::{HookPoint, Message, OspMessage, PinkEvent};
+pub use pink_extension::{
+    chain_extension::{HttpEgressConfig, PinkExtError},
+    HookPoint, Message, OspMessage, PinkEvent,
+};
+pub use pink_extension_runtime::set_http_proxy;
 
 type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<PinkRuntime>;
 type Block = frame_system::mocking::MockBlock<PinkRuntime>;
@@ -166,6 +170,24 @@ pub enum CallMode {
 
 pub trait EventCallbacks {
     fn emit_log(&self, contract: &AccountId, in_query: bool, level: u8, message: String);
+
+    /// Send a query from `origin` to the sidevm instance of `contract` and wait for the reply.
+    fn query_sidevm(
+        &self,
+        _origin: &AccountId,
+        _contract: &AccountId,
+        _payload: Vec<u8>,
+        _timeout: Duration,
+    ) -> Result<Vec<u8>, PinkExtError> {
+        Err(PinkExtError::SidevmNotFound)
+    }
+
+    /// Number of messages `contract` can still push into its egress queue, `None` if unlimited.
+    ///
+    /// Used to answer the contracts in commands, so it must be the same on all workers.
+    fn egress_room(&self, _contract: &AccountId) -> Option<usize> {
+        None
+    }
 }
 
 pub type BoxedEventCallbacks = Box<dyn EventCallbacks>;
@@ -207,6 +229,28 @@ pub fn emit_log(id: &AccountId, level: u8, msg: String) {
     });
 }
 
+pub fn query_sidevm(
+    origin: &AccountId,
+    contract: &AccountId,
+    payload: Vec<u8>,
+    timeout: Duration,
+) -> Result<Vec<u8>, PinkExtError> {
+    call_info::with(|info| match &info.callbacks {
+        Some(callbacks) => callbacks.query_sidevm(origin, contract, payload, timeout),
+        None => Err(PinkExtError::SidevmNotFound),
+    })
+    .unwrap_or(Err(PinkExtError::SidevmNotFound))
+}
+
+pub fn egress_room(contract: &AccountId) -> Option<usize> {
+    call_info::with(|info| {
+        info.callbacks
+            .as_ref()
+            .and_then(|callbacks| callbacks.egress_room(contract))
+    })
+    .flatten()
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::type_complexity)]
@@ -320,6 +364,69 @@ mod tests {
         })
     }
 
+    #[test]
+    pub fn chain_extension_test() {
+        use pink_extension::chain_extension::{func_ids, AccountId, PinkExtError};
+        use scale::{Decode, Encode};
+        pub const ALICE: AccountId32 = AccountId32::new([1u8; 32]);
+
+        let (wasm, code_hash) =
+            compile_wat::<PinkRuntime>(include_bytes!("../tests/fixtures/chain_extension.wat"))
+                .unwrap();
+
+        exec::execute_with(|| {
+            Contracts::instantiate_with_code(
+                Origin::signed(ALICE),
+                ENOUGH,
+                QUERY_GAS_LIMIT,
+                None,
+                wasm,
+                vec![],
+                vec![],
+            )
+            .unwrap();
+            let addr = Contracts::contract_address(&ALICE, &code_hash, &[]);
+            // Call the extension function through the contract, so it goes through
+            // `PinkExtension::call` the same way as in a real contract.
+            let call_ext = |func_id: u32, input: Vec<u8>| {
+                let mut params = func_id.encode();
+                params.extend(input);
+                let result = Contracts::bare_call(
+                    ALICE,
+                    addr.clone(),
+                    0,
+                    QUERY_GAS_LIMIT,
+                    None,
+                    params,
+                    false,
+                )
+                .result
+                .unwrap();
+                assert!(!result.did_revert());
+                result.data
+            };
+
+            let output = call_ext(
+                func_ids::CACHE_SET_V1,
+                (b"key".to_vec(), b"value".to_vec()).encode(),
+            );
+            assert_eq!(
+                Result::<(), PinkExtError>::decode(&mut &output[..]).unwrap(),
+                Ok(())
+            );
+            let output = call_ext(func_ids::CACHE_GET, b"key".to_vec().encode());
+            assert_eq!(
+                Option::<Vec<u8>>::decode(&mut &output[..]).unwrap(),
+                Some(b"value".to_vec())
+            );
+            let output = call_ext(func_ids::SYSTEM_CONTRACT_ID_V1, vec![]);
+            assert_eq!(
+                Result::<AccountId, PinkExtError>::decode(&mut &output[..]).unwrap(),
+                Err(PinkExtError::SystemContractMissing)
+            );
+        })
+    }
+
     pub mod exec {
         use sp_runtime::traits::BlakeTwo256;
         use sp_state_machine::{
@@ -9,9 +9,11 @@ use pallet_contracts::chain_extension::{
 use phala_crypto::sr25519::{Persistence, KDF};
 use pink_extension::{
     chain_extension::{
-        self as ext, HttpRequest, HttpResponse, PinkExtBackend, SigType, StorageQuotaExceeded,
+        self as ext, BatchHttpResult, CacheStats, HashAlgorithm, HttpEgressConfig, HttpRequest,
+        HttpResponse, PinkExtBackend, PinkExtError, SigType, StorageQuotaExceeded,
     },
-    dispatch_ext_call, CacheOp, EcdsaPublicKey, EcdsaSignature, Hash, PinkEvent,
+    dispatch_ext_call, CacheOp, EcdhPublicKey, EcdsaPublicKey, EcdsaSignature, Hash, Message,
+    OspMessage, PinkEvent,
 };
 use pink_extension_runtime::{DefaultPinkExtension, PinkRuntimeEnv};
 use scale::{Decode, Encode};
@@ -24,6 +26,7 @@ use crate::{
 };
 
 use crate::local_cache::GLOBAL_CACHE;
+use crate::local_store::GLOBAL_STORE;
 
 #[derive(Default, Debug)]
 pub struct ExecSideEffects {
@@ -143,6 +146,9 @@ impl ChainExtension<super::PinkRuntime> for PinkExtension {
     }
 }
 
+/// The max time a contract can wait for the replies of sidevm queries in a call.
+const MAX_SIDEVM_QUERY_TIME: Duration = Duration::from_secs(10);
+
 struct CallInQuery {
     address: AccountId,
 }
@@ -157,6 +163,10 @@ impl PinkRuntimeEnv for CallInQuery {
     fn call_elapsed(&self) -> Option<Duration> {
         get_call_elapsed()
     }
+
+    fn http_egress_config(&self) -> HttpEgressConfig {
+        crate::runtime::Pink::http_egress_config()
+    }
 }
 
 impl PinkExtBackend for CallInQuery {
@@ -165,6 +175,14 @@ impl PinkExtBackend for CallInQuery {
         DefaultPinkExtension::new(self).http_request(request)
     }
 
+    fn batch_http_request(
+        &self,
+        requests: Vec<HttpRequest>,
+        timeout_ms: u64,
+    ) -> Result<BatchHttpResult, Self::Error> {
+        DefaultPinkExtension::new(self).batch_http_request(requests, timeout_ms)
+    }
+
     fn sign(
         &self,
         sigtype: SigType,
@@ -273,12 +291,259 @@ impl PinkExtBackend for CallInQuery {
             })
             .ok_or(DispatchError::Other("No system contract installed"))
     }
+
+    fn hash(
+        &self,
+        algorithm: HashAlgorithm,
+        message: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        DefaultPinkExtension::new(self).hash(algorithm, message)
+    }
+
+    fn ecdh_agree(
+        &self,
+        salt: Cow<[u8]>,
+        public_key: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        let secret_key = self.derive_sr25519_key(salt)?;
+        Ok(pink_extension_runtime::crypto::ecdh_agree(
+            &secret_key,
+            &public_key,
+        ))
+    }
+
+    fn aead_encrypt(
+        &self,
+        key: Cow<[u8]>,
+        iv: Cow<[u8]>,
+        plaintext: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        DefaultPinkExtension::new(self).aead_encrypt(key, iv, plaintext)
+    }
+
+    fn aead_decrypt(
+        &self,
+        key: Cow<[u8]>,
+        iv: Cow<[u8]>,
+        ciphertext: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        DefaultPinkExtension::new(self).aead_decrypt(key, iv, ciphertext)
+    }
+
+    fn recover_prehashed(
+        &self,
+        sigtype: SigType,
+        signature: Cow<[u8]>,
+        message_hash: Hash,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        DefaultPinkExtension::new(self).recover_prehashed(sigtype, signature, message_hash)
+    }
+
+    fn local_store_get(
+        &self,
+        key: Cow<[u8]>,
+    ) -> Result<Result<Option<Vec<u8>>, PinkExtError>, Self::Error> {
+        let contract: &[u8] = self.address.as_ref();
+        let value = GLOBAL_STORE.read().unwrap().get(contract, key.as_ref());
+        Ok(Ok(value))
+    }
+
+    fn local_store_set(
+        &self,
+        key: Cow<[u8]>,
+        value: Cow<[u8]>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        let contract: &[u8] = self.address.as_ref();
+        let result = GLOBAL_STORE
+            .write()
+            .unwrap()
+            .set(contract.into(), key, value)
+            .or(Err(PinkExtError::QuotaExceeded));
+        Ok(result)
+    }
+
+    fn local_store_remove(
+        &self,
+        key: Cow<[u8]>,
+    ) -> Result<Result<Option<Vec<u8>>, PinkExtError>, Self::Error> {
+        let contract: &[u8] = self.address.as_ref();
+        let value = GLOBAL_STORE.write().unwrap().remove(contract, key.as_ref());
+        Ok(Ok(value))
+    }
+
+    fn cache_scan(
+        &self,
+        prefix: Cow<[u8]>,
+        start_after: Option<Vec<u8>>,
+        limit: u32,
+    ) -> Result<Result<Vec<(Vec<u8>, Vec<u8>)>, PinkExtError>, Self::Error> {
+        let contract: &[u8] = self.address.as_ref();
+        let entries = GLOBAL_CACHE.read().unwrap().scan(
+            contract,
+            &prefix,
+            start_after.as_deref(),
+            limit as _,
+        );
+        Ok(Ok(entries))
+    }
+
+    fn cache_compare_and_swap(
+        &self,
+        key: Cow<[u8]>,
+        expected: Option<Vec<u8>>,
+        value: Option<Vec<u8>>,
+    ) -> Result<Result<bool, PinkExtError>, Self::Error> {
+        let contract: &[u8] = self.address.as_ref();
+        let result = GLOBAL_CACHE
+            .write()
+            .unwrap()
+            .compare_and_swap(
+                contract.into(),
+                key,
+                expected.as_deref(),
+                value.map(Into::into),
+            )
+            .or(Err(PinkExtError::QuotaExceeded));
+        Ok(result)
+    }
+
+    fn cache_increment(
+        &self,
+        key: Cow<[u8]>,
+        delta: i64,
+    ) -> Result<Result<i64, PinkExtError>, Self::Error> {
+        let contract: &[u8] = self.address.as_ref();
+        let result = GLOBAL_CACHE
+            .write()
+            .unwrap()
+            .increment(contract.into(), key, delta);
+        Ok(result)
+    }
+
+    fn cache_stats(&self) -> Result<Result<CacheStats, PinkExtError>, Self::Error> {
+        let contract: &[u8] = self.address.as_ref();
+        Ok(Ok(GLOBAL_CACHE.read().unwrap().stats(contract)))
+    }
+
+    fn query_sidevm(
+        &self,
+        contract: ext::AccountId,
+        payload: Cow<[u8]>,
+        timeout_ms: u64,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        let elapsed = get_call_elapsed().ok_or(DispatchError::Other("Invalid exec env"))?;
+        let timeout = MAX_SIDEVM_QUERY_TIME
+            .saturating_sub(elapsed)
+            .min(Duration::from_millis(timeout_ms));
+        let contract: &[u8; 32] = contract.as_ref();
+        let contract = AccountId::new(*contract);
+        Ok(crate::runtime::query_sidevm(
+            &self.address,
+            &contract,
+            payload.into_owned(),
+            timeout,
+        ))
+    }
+
+    fn try_push_message(
+        &self,
+        _payload: Cow<[u8]>,
+        _topic: Cow<[u8]>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInQuery))
+    }
+
+    fn try_push_osp_message(
+        &self,
+        _payload: Cow<[u8]>,
+        _topic: Cow<[u8]>,
+        _remote_pubkey: Option<EcdhPublicKey>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInQuery))
+    }
+
+    fn http_request_v1(
+        &self,
+        request: HttpRequest,
+    ) -> Result<Result<HttpResponse, PinkExtError>, Self::Error> {
+        DefaultPinkExtension::new(self).http_request_v1(request)
+    }
+
+    fn sign_v1(
+        &self,
+        sigtype: SigType,
+        key: Cow<[u8]>,
+        message: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        DefaultPinkExtension::new(self).sign_v1(sigtype, key, message)
+    }
+
+    fn get_public_key_v1(
+        &self,
+        sigtype: SigType,
+        key: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        DefaultPinkExtension::new(self).get_public_key_v1(sigtype, key)
+    }
+
+    fn cache_set_v1(
+        &self,
+        key: Cow<[u8]>,
+        value: Cow<[u8]>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        Ok(self
+            .cache_set(key, value)?
+            .or(Err(PinkExtError::QuotaExceeded)))
+    }
+
+    fn getrandom_v1(&self, length: u8) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        DefaultPinkExtension::new(self).getrandom_v1(length)
+    }
+
+    fn ecdsa_sign_prehashed_v1(
+        &self,
+        key: Cow<[u8]>,
+        message_hash: Hash,
+    ) -> Result<Result<EcdsaSignature, PinkExtError>, Self::Error> {
+        DefaultPinkExtension::new(self).ecdsa_sign_prehashed_v1(key, message_hash)
+    }
+
+    fn system_contract_id_v1(&self) -> Result<Result<ext::AccountId, PinkExtError>, Self::Error> {
+        Ok(self
+            .system_contract_id()
+            .or(Err(PinkExtError::SystemContractMissing)))
+    }
 }
 
 struct CallInCommand {
     as_in_query: CallInQuery,
 }
 
+impl CallInCommand {
+    /// Emit a message event unless the egress queue of the contract has no room left for it.
+    ///
+    /// The events are only applied to the queue after the call, so the messages emitted earlier
+    /// in this call take up room as well.
+    fn try_emit_message(&self, event: PinkEvent) -> Result<(), PinkExtError> {
+        let address = &self.as_in_query.address;
+        if let Some(room) = crate::runtime::egress_room(address) {
+            let emitted = get_side_effects()
+                .pink_events
+                .iter()
+                .filter(|(origin, event)| {
+                    origin == address
+                        && matches!(event, PinkEvent::Message(_) | PinkEvent::OspMessage(_))
+                })
+                .count();
+            if emitted >= room {
+                return Err(PinkExtError::EgressQuotaExceeded);
+            }
+        }
+        deposit_pink_event(address.clone(), event);
+        Ok(())
+    }
+}
+
 /// This implementation is used when calling the extension in a command.
 /// # NOTE FOR IMPLEMENTORS
 /// Make sure the return values are deterministic.
@@ -290,6 +555,17 @@ impl PinkExtBackend for CallInCommand {
             "http_request can only be called in query mode",
         ))
     }
+
+    fn batch_http_request(
+        &self,
+        _requests: Vec<HttpRequest>,
+        _timeout_ms: u64,
+    ) -> Result<BatchHttpResult, Self::Error> {
+        Err(DispatchError::Other(
+            "batch_http_request can only be called in query mode",
+        ))
+    }
+
     fn sign(
         &self,
         sigtype: SigType,
@@ -393,4 +669,262 @@ impl PinkExtBackend for CallInCommand {
     fn system_contract_id(&self) -> Result<ext::AccountId, Self::Error> {
         self.as_in_query.system_contract_id()
     }
+
+    fn hash(
+        &self,
+        algorithm: HashAlgorithm,
+        message: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        self.as_in_query.hash(algorithm, message)
+    }
+
+    fn ecdh_agree(
+        &self,
+        salt: Cow<[u8]>,
+        public_key: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        self.as_in_query.ecdh_agree(salt, public_key)
+    }
+
+    fn aead_encrypt(
+        &self,
+        key: Cow<[u8]>,
+        iv: Cow<[u8]>,
+        plaintext: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        self.as_in_query.aead_encrypt(key, iv, plaintext)
+    }
+
+    fn aead_decrypt(
+        &self,
+        key: Cow<[u8]>,
+        iv: Cow<[u8]>,
+        ciphertext: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        self.as_in_query.aead_decrypt(key, iv, ciphertext)
+    }
+
+    fn recover_prehashed(
+        &self,
+        sigtype: SigType,
+        signature: Cow<[u8]>,
+        message_hash: Hash,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        self.as_in_query
+            .recover_prehashed(sigtype, signature, message_hash)
+    }
+
+    fn local_store_get(
+        &self,
+        _key: Cow<[u8]>,
+    ) -> Result<Result<Option<Vec<u8>>, PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInCommand))
+    }
+
+    fn local_store_set(
+        &self,
+        _key: Cow<[u8]>,
+        _value: Cow<[u8]>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInCommand))
+    }
+
+    fn local_store_remove(
+        &self,
+        _key: Cow<[u8]>,
+    ) -> Result<Result<Option<Vec<u8>>, PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInCommand))
+    }
+
+    fn cache_scan(
+        &self,
+        _prefix: Cow<[u8]>,
+        _start_after: Option<Vec<u8>>,
+        _limit: u32,
+    ) -> Result<Result<Vec<(Vec<u8>, Vec<u8>)>, PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInCommand))
+    }
+
+    fn cache_compare_and_swap(
+        &self,
+        _key: Cow<[u8]>,
+        _expected: Option<Vec<u8>>,
+        _value: Option<Vec<u8>>,
+    ) -> Result<Result<bool, PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInCommand))
+    }
+
+    fn cache_increment(
+        &self,
+        _key: Cow<[u8]>,
+        _delta: i64,
+    ) -> Result<Result<i64, PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInCommand))
+    }
+
+    fn cache_stats(&self) -> Result<Result<CacheStats, PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInCommand))
+    }
+
+    fn query_sidevm(
+        &self,
+        _contract: ext::AccountId,
+        _payload: Cow<[u8]>,
+        _timeout_ms: u64,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInCommand))
+    }
+
+    fn try_push_message(
+        &self,
+        payload: Cow<[u8]>,
+        topic: Cow<[u8]>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        Ok(self.try_emit_message(PinkEvent::Message(Message {
+            payload: payload.into_owned(),
+            topic: topic.into_owned(),
+        })))
+    }
+
+    fn try_push_osp_message(
+        &self,
+        payload: Cow<[u8]>,
+        topic: Cow<[u8]>,
+        remote_pubkey: Option<EcdhPublicKey>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        Ok(self.try_emit_message(PinkEvent::OspMessage(OspMessage {
+            message: Message {
+                payload: payload.into_owned(),
+                topic: topic.into_owned(),
+            },
+            remote_pubkey,
+        })))
+    }
+
+    fn http_request_v1(
+        &self,
+        _request: HttpRequest,
+    ) -> Result<Result<HttpResponse, PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInCommand))
+    }
+
+    fn sign_v1(
+        &self,
+        sigtype: SigType,
+        key: Cow<[u8]>,
+        message: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        if matches!(sigtype, SigType::Sr25519) {
+            return Ok(Err(PinkExtError::NotAllowedInCommand));
+        }
+        self.as_in_query.sign_v1(sigtype, key, message)
+    }
+
+    fn get_public_key_v1(
+        &self,
+        sigtype: SigType,
+        key: Cow<[u8]>,
+    ) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        self.as_in_query.get_public_key_v1(sigtype, key)
+    }
+
+    fn cache_set_v1(
+        &self,
+        key: Cow<[u8]>,
+        value: Cow<[u8]>,
+    ) -> Result<Result<(), PinkExtError>, Self::Error> {
+        Ok(self
+            .cache_set(key, value)?
+            .or(Err(PinkExtError::QuotaExceeded)))
+    }
+
+    fn getrandom_v1(&self, _length: u8) -> Result<Result<Vec<u8>, PinkExtError>, Self::Error> {
+        Ok(Err(PinkExtError::NotAllowedInCommand))
+    }
+
+    fn ecdsa_sign_prehashed_v1(
+        &self,
+        key: Cow<[u8]>,
+        message_hash: Hash,
+    ) -> Result<Result<EcdsaSignature, PinkExtError>, Self::Error> {
+        self.as_in_query.ecdsa_sign_prehashed_v1(key, message_hash)
+    }
+
+    fn system_contract_id_v1(&self) -> Result<Result<ext::AccountId, PinkExtError>, Self::Error> {
+        self.as_in_query.system_contract_id_v1()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{tests::exec::execute_with, using_mode, EventCallbacks, System};
+
+    struct EgressRoom(usize);
+
+    impl EventCallbacks for EgressRoom {
+        fn emit_log(&self, _contract: &AccountId, _in_query: bool, _level: u8, _message: String) {}
+
+        fn egress_room(&self, _contract: &AccountId) -> Option<usize> {
+            Some(self.0)
+        }
+    }
+
+    #[test]
+    fn try_push_message_reports_egress_quota() {
+        let address = AccountId::new([1u8; 32]);
+        let ext = CallInCommand {
+            as_in_query: CallInQuery {
+                address: address.clone(),
+            },
+        };
+        let payload = || Cow::Borrowed(&b"payload"[..]);
+        let topic = || Cow::Borrowed(&b"topic"[..]);
+        execute_with(|| {
+            System::set_block_number(1);
+            using_mode(CallMode::Command, Some(Box::new(EgressRoom(3))), || {
+                assert_eq!(ext.try_push_message(payload(), topic()), Ok(Ok(())));
+                // Only the messages of the contract itself count, including the ones emitted as events.
+                deposit_pink_event(
+                    AccountId::new([2u8; 32]),
+                    PinkEvent::Message(Message {
+                        payload: payload().into_owned(),
+                        topic: topic().into_owned(),
+                    }),
+                );
+                deposit_pink_event(
+                    address.clone(),
+                    PinkEvent::Message(Message {
+                        payload: payload().into_owned(),
+                        topic: topic().into_owned(),
+                    }),
+                );
+                assert_eq!(
+                    ext.try_push_osp_message(payload(), topic(), None),
+                    Ok(Ok(()))
+                );
+                assert_eq!(
+                    ext.try_push_message(payload(), topic()),
+                    Ok(Err(PinkExtError::EgressQuotaExceeded))
+                );
+                assert_eq!(
+                    ext.try_push_osp_message(payload(), topic(), None),
+                    Ok(Err(PinkExtError::EgressQuotaExceeded))
+                );
+            });
+            let pushed = get_side_effects()
+                .pink_events
+                .into_iter()
+                .filter(|(origin, _)| origin == &address)
+                .count();
+            assert_eq!(pushed, 3);
+
+            using_mode(CallMode::Query, None, || {
+                assert_eq!(
+                    ext.as_in_query.try_push_message(payload(), topic()),
+                    Ok(Err(PinkExtError::NotAllowedInQuery))
+                );
+            });
+        });
+    }
 }
@@ -43,6 +43,12 @@ pub mod pallet {
     #[pallet::getter(fn system_contract)]
     pub(crate) type SystemContract<T: Config> = StorageValue<_, T::AccountId, OptionQuery>;
 
+    /// Limits and domain filters of HTTP requests made by the contracts in the cluster
+    #[pallet::storage]
+    #[pallet::getter(fn http_egress_config)]
+    pub(crate) type HttpEgressConfig<T: Config> =
+        StorageValue<_, pink_extension::chain_extension::HttpEgressConfig, ValueQuery>;
+
     #[pallet::pallet]
     #[pallet::without_storage_info]
     pub struct Pallet<T>(PhantomData<T>);
@@ -85,5 +91,9 @@ pub mod pallet {
         pub fn set_system_contract(address: T::AccountId) {
             <SystemContract<T>>::put(address);
         }
+
+        pub fn set_http_egress_config(config: pink_extension::chain_extension::HttpEgressConfig) {
+            <HttpEgressConfig<T>>::put(config);
+        }
     }
 }
@@ -4,6 +4,7 @@ use crate::{
 };
 use phala_crypto::sr25519::Sr25519SecretKey;
 use phala_trie_storage::{deserialize_trie_backend, serialize_trie_backend, MemoryDB};
+use pink_extension::chain_extension::HttpEgressConfig;
 use serde::{Deserialize, Serialize};
 use sp_runtime::DispatchError;
 use sp_state_machine::backend::AsTrieBackend;
@@ -169,6 +170,17 @@ where
             .0
     }
 
+    pub fn set_http_egress_config(&mut self, config: HttpEgressConfig) {
+        self.execute_with(false, None, move || {
+            crate::runtime::Pink::set_http_egress_config(config);
+        });
+    }
+
+    pub fn http_egress_config(&mut self) -> HttpEgressConfig {
+        self.execute_with(true, None, crate::runtime::Pink::http_egress_config)
+            .0
+    }
+
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
         self.backend.storage(key).ok().flatten()
     }
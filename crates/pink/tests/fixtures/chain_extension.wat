@@ -0,0 +1,41 @@
+;; Forwards the input to the pink chain extension and returns its output.
+;;
+;; The first 4 bytes of the input are the little endian id of the extension function to call.
+;; The rest of the input is passed to the function as is.
+(module
+	(import "seal0" "seal_input" (func $seal_input (param i32 i32)))
+	(import "seal0" "seal_return" (func $seal_return (param i32 i32 i32)))
+	(import "seal0" "seal_call_chain_extension"
+		(func $seal_call_chain_extension (param i32 i32 i32 i32 i32) (result i32))
+	)
+	(import "env" "memory" (memory 1 1))
+
+	;; [0, 4) input length
+	;; [4, 8) output length
+	;; [16, 1040) input buffer
+	;; [2048, 3072) output buffer
+
+	(func (export "deploy"))
+
+	(func (export "call")
+		(i32.store (i32.const 0) (i32.const 1024))
+		(call $seal_input (i32.const 16) (i32.const 0))
+		(if (i32.lt_u (i32.load (i32.const 0)) (i32.const 4))
+			(unreachable)
+		)
+		(i32.store (i32.const 4) (i32.const 1024))
+		(drop (call $seal_call_chain_extension
+			(i32.load (i32.const 16))
+			(i32.const 20)
+			(i32.sub (i32.load (i32.const 0)) (i32.const 4))
+			(i32.const 2048)
+			(i32.const 4)
+		))
+		(call $seal_return
+			(i32.const 0)
+			(i32.const 2048)
+			(i32.load (i32.const 4))
+		)
+		(unreachable)
+	)
+)
@@ -2,7 +2,10 @@
 
 use frame_support::assert_ok;
 use hex_literal::hex;
-use pink::{runtime::HookPoint, Contract, Storage};
+use pink::{
+    runtime::{Contracts, HookPoint, RuntimeOrigin},
+    Contract, Storage,
+};
 use pink_extension::PinkEvent;
 use sp_runtime::AccountId32;
 
@@ -89,6 +92,63 @@ fn test_ink_flip() {
     assert_eq!(result, (42, 24));
 }
 
+#[test]
+fn test_snapshot_isolation() {
+    let mut storage = Storage::default();
+    let code_hash = storage
+        .upload_code(
+            ALICE.clone(),
+            include_bytes!("./fixtures/flip/flip.wasm").to_vec(),
+        )
+        .unwrap();
+    let contract = Contract::new_with_selector(
+        &mut storage,
+        ALICE.clone(),
+        code_hash,
+        hex!("9bae9d5e"), // init_value
+        true,
+        vec![],
+        vec![],
+        0,
+        0,
+    )
+    .unwrap()
+    .0;
+
+    let mut snapshot = storage.snapshot();
+
+    let _: () = contract
+        .call_with_selector(
+            &mut storage,
+            ALICE.clone(),
+            hex!("633aa551"), // flip
+            (),
+            false,
+            0,
+            0,
+        )
+        .unwrap()
+        .0;
+
+    let get = |storage: &mut Storage| -> bool {
+        contract
+            .call_with_selector(
+                storage,
+                ALICE.clone(),
+                hex!("2f865bd9"), // get
+                (),
+                true,
+                0,
+                0,
+            )
+            .unwrap()
+            .0
+    };
+    assert!(!get(&mut storage));
+    // The snapshot taken before the flip is not affected
+    assert!(get(&mut snapshot));
+}
+
 #[test]
 fn test_load_contract_file() {
     assert_ok!(pink::ContractFile::load(include_bytes!(
@@ -205,6 +265,55 @@ fn test_mq_egress() {
     insta::assert_debug_snapshot!(effects);
 }
 
+#[test]
+fn test_set_code_and_terminate() {
+    let mut storage = Storage::default();
+    let code_hash = storage
+        .upload_code(
+            ALICE.clone(),
+            include_bytes!("./fixtures/flip/flip.wasm").to_vec(),
+        )
+        .unwrap();
+    let new_code_hash = storage
+        .upload_code(
+            ALICE.clone(),
+            include_bytes!("./fixtures/hooks_test/hooks_test.wasm").to_vec(),
+        )
+        .unwrap();
+    let contract = Contract::new_with_selector(
+        &mut storage,
+        ALICE.clone(),
+        code_hash,
+        hex!("9bae9d5e"), // init_value
+        true,
+        vec![],
+        vec![],
+        0,
+        0,
+    )
+    .unwrap()
+    .0;
+
+    // The owner can only remove the code that no contract uses.
+    let remove_code = |storage: &mut Storage, code_hash| {
+        storage
+            .execute_with(false, None, move || {
+                Contracts::remove_code(RuntimeOrigin::signed(ALICE), code_hash)
+            })
+            .0
+    };
+
+    assert_ok!(contract.set_code(&mut storage, new_code_hash));
+    assert_eq!(contract.code_hash(&storage), Some(new_code_hash));
+    assert!(remove_code(&mut storage, new_code_hash).is_err());
+    assert_ok!(remove_code(&mut storage, code_hash));
+
+    assert_ok!(contract.terminate(&mut storage));
+    assert_eq!(contract.code_hash(&storage), None);
+    assert!(contract.terminate(&mut storage).is_err());
+    assert_ok!(remove_code(&mut storage, new_code_hash));
+}
+
 #[test]
 fn test_on_block_end() {
     let mut storage = Storage::default();
@@ -231,6 +340,7 @@ fn test_on_block_end() {
 
     insta::assert_debug_snapshot!(effects);
 
+    let mut hook_selector = None;
     for (_account, event) in effects.pink_events {
         if let PinkEvent::SetHook {
             hook,
@@ -239,12 +349,9 @@ fn test_on_block_end() {
         } = event
         {
             use phala_types::contract::ConvertTo;
-            match hook {
-                HookPoint::OnBlockEnd => {
-                    if target_contract == contract.address.convert_to() {
-                        contract.set_on_block_end_selector(selector);
-                    }
-                }
+            if hook == HookPoint::OnBlockEnd && target_contract == contract.address.convert_to() {
+                contract.set_on_block_end_selector(selector);
+                hook_selector = Some(selector);
             }
         }
     }
@@ -252,6 +359,12 @@ fn test_on_block_end() {
     let effects = contract.on_block_end(&mut storage, 1, 1, None).unwrap();
 
     insta::assert_debug_snapshot!(effects);
+
+    // The hook fails if it runs out of the given gas limit
+    let selector = hook_selector.expect("The hook should be set");
+    assert!(contract
+        .call_hook(&mut storage, selector, 1, false, 2, 2, None)
+        .is_err());
 }
 
 fn test_with_wasm(wasm: &[u8], constructor: [u8; 4], message: [u8; 4]) {
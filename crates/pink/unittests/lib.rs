@@ -50,4 +50,138 @@ mod tests {
         let pass = sig::ecdsa_verify_prehashed(signature, fake_message, pubkey);
         assert!(!pass);
     }
+
+    #[test]
+    fn test_batch_http_request() {
+        use pink::chain_extension::{HttpRequest, HttpRequestError};
+
+        pink_extension_runtime::mock_ext::mock_all_ext();
+
+        let request = |method: &str, url: &str| HttpRequest {
+            url: url.into(),
+            method: method.into(),
+            headers: vec![],
+            body: vec![],
+        };
+        let results = pink::ext()
+            .batch_http_request(
+                vec![
+                    request("GET", "not a url"),
+                    request("GET\n", "http://localhost/"),
+                ],
+                1000,
+            )
+            .unwrap();
+        assert_eq!(
+            results
+                .into_iter()
+                .map(|result| result.err())
+                .collect::<Vec<_>>(),
+            vec![
+                Some(HttpRequestError::InvalidUrl),
+                Some(HttpRequestError::InvalidMethod)
+            ]
+        );
+
+        let requests = (0..6)
+            .map(|_| request("GET", "http://localhost/"))
+            .collect();
+        assert_eq!(
+            pink::ext().batch_http_request(requests, 1000).err(),
+            Some(HttpRequestError::TooManyRequests)
+        );
+    }
+
+    #[test]
+    fn test_ext_errors() {
+        use pink::chain_extension::{PinkExtError, SigType};
+
+        pink_extension_runtime::mock_ext::mock_all_ext();
+
+        assert_eq!(
+            pink::ext().sign_v1(SigType::Ed25519, b"bad key", b"message"),
+            Err(PinkExtError::InvalidKey)
+        );
+        assert_eq!(
+            pink::ext().get_public_key_v1(SigType::Sr25519, b"bad key"),
+            Err(PinkExtError::InvalidKey)
+        );
+        assert_eq!(
+            pink::ext().system_contract_id_v1(),
+            Err(PinkExtError::SystemContractMissing)
+        );
+        assert_eq!(
+            pink::ext().query_sidevm([0u8; 32].into(), b"ping", 1000),
+            Err(PinkExtError::SidevmNotFound)
+        );
+        assert_eq!(pink::ext().try_push_message(b"payload", b"topic"), Ok(()));
+        assert_eq!(pink::ext().cache_set_v1(b"v1 key", b"value"), Ok(()));
+        assert_eq!(pink::ext().cache_get(b"v1 key"), Some(b"value".to_vec()));
+        let key = pink::ext().derive_sr25519_key(b"salt".as_ref().into());
+        assert!(pink::ext()
+            .sign_v1(SigType::Sr25519, &key, b"message")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_crypto_primitives() {
+        use pink::chain_extension::{crypto, signing, HashAlgorithm, PinkExtError, SigType};
+
+        pink_extension_runtime::mock_ext::mock_all_ext();
+
+        let sha256_abc = [
+            0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+            0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61,
+            0xf2, 0x00, 0x15, 0xad,
+        ];
+        assert_eq!(
+            crypto::hash(HashAlgorithm::Sha256, b"abc").unwrap(),
+            sha256_abc
+        );
+        assert_eq!(
+            crypto::hash(HashAlgorithm::Blake2b128, b"abc")
+                .unwrap()
+                .len(),
+            16
+        );
+
+        let alice = signing::derive_sr25519_key(b"alice");
+        let bob = signing::derive_sr25519_key(b"bob");
+        let alice_pubkey = signing::get_public_key(&alice, SigType::Sr25519);
+        let bob_pubkey = signing::get_public_key(&bob, SigType::Sr25519);
+        let secret = crypto::ecdh_agree(b"alice", &bob_pubkey).unwrap();
+        assert_eq!(secret, crypto::ecdh_agree(b"bob", &alice_pubkey).unwrap());
+
+        let iv = [1u8; 12];
+        let encrypted = crypto::aead_encrypt(&secret, &iv, b"hello").unwrap();
+        assert_eq!(
+            crypto::aead_decrypt(&secret, &iv, &encrypted).unwrap(),
+            b"hello"
+        );
+        assert_eq!(
+            crypto::aead_decrypt(&secret, &[2u8; 12], &encrypted),
+            Err(PinkExtError::DecryptionFailed)
+        );
+        assert_eq!(
+            crypto::aead_encrypt(&secret, b"short iv", b"hello"),
+            Err(PinkExtError::InvalidIv)
+        );
+
+        let key = signing::derive_sr25519_key(b"ecdsa");
+        let message_hash = [7u8; 32];
+        let pubkey = signing::get_public_key(&key, SigType::Ecdsa);
+        let signature = signing::ecdsa_sign_prehashed(&key, message_hash);
+        assert_eq!(
+            signing::recover_prehashed(&signature, message_hash, SigType::Ecdsa).unwrap(),
+            pubkey
+        );
+        assert_eq!(
+            signing::recover_prehashed(&signature[1..], message_hash, SigType::Ecdsa),
+            Err(PinkExtError::InvalidSignature)
+        );
+        assert_eq!(
+            signing::recover_prehashed(&signature, message_hash, SigType::Sr25519),
+            Err(PinkExtError::UnsupportedSigType)
+        );
+    }
 }
@@ -85,6 +85,28 @@ pub trait OcallFuncs {
     #[ocall(id = 214, encode_input)]
     fn tcp_connect_tls(host: String, port: u16, config: TlsClientConfig) -> Result<i32>;
 
+    /// Create a UDP socket bound to given address.
+    #[ocall(id = 215)]
+    fn udp_bind(addr: &str) -> Result<i32>;
+
+    /// Poll given UDP socket to send a datagram to `addr`.
+    #[ocall(id = 216, encode_input)]
+    fn udp_send_to(waker_id: i32, resource_id: i32, data: Cow<[u8]>, addr: Cow<str>)
+        -> Result<u32>;
+
+    /// Poll given UDP socket to receive a datagram.
+    ///
+    /// Returns the size of the datagram and the address it came from.
+    #[ocall(id = 217, encode_output)]
+    fn udp_recv_from(waker_id: i32, resource_id: i32, data: &mut [u8]) -> Result<(u32, String)>;
+
+    /// Resolve the IP addresses of given host name.
+    ///
+    /// Invoke poll on the returned resource_id to get the SCALE encoded addresses as
+    /// `Vec<String>`.
+    #[ocall(id = 218)]
+    fn resolve(host: &str) -> Result<i32>;
+
     /// Print log message.
     #[ocall(id = 220)]
     fn log(level: log::Level, message: &str) -> Result<()>;
@@ -107,9 +129,103 @@ pub trait OcallFuncs {
     #[ocall(id = 233, encode_output)]
     fn local_cache_remove(key: &[u8]) -> Result<Option<Vec<u8>>>;
 
+    /// Get the entries of the local cache whose keys start with `prefix`, in key order.
+    ///
+    /// At most `limit` entries, capped to 100, are returned. To get the next page, pass the
+    /// last key of the previous page as `start_after`.
+    #[ocall(id = 234, encode_input, encode_output)]
+    fn local_cache_scan(
+        prefix: Vec<u8>,
+        start_after: Option<Vec<u8>>,
+        limit: u32,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Set a value in the local cache if the current value equals `expected`.
+    ///
+    /// `None` as `expected` means the key must be absent, and `None` as `value` removes the key.
+    /// Returns whether the value was swapped.
+    #[ocall(id = 235, encode_input)]
+    fn local_cache_compare_and_swap(
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        value: Option<Vec<u8>>,
+    ) -> Result<bool>;
+
+    /// Add `delta` to an integer in the local cache and return the new value.
+    ///
+    /// The integer is stored as a SCALE encoded `i64`. A missing key counts as 0.
+    #[ocall(id = 236, encode_output)]
+    fn local_cache_increment(key: &[u8], delta: i64) -> Result<i64>;
+
+    /// Get the usage of the local cache.
+    #[ocall(id = 237, encode_output)]
+    fn local_cache_stats() -> Result<LocalCacheStats>;
+
     /// Create input channel
     #[ocall(id = 240, encode_output)]
     fn create_input_channel(ch: InputChannel) -> Result<i32>;
+
+    /// Get value from the local store.
+    ///
+    /// Unlike the local cache, values in the local store never expire and survive restarts
+    /// of the worker.
+    #[ocall(id = 250, encode_output)]
+    fn local_store_get(key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Set value to the local store.
+    #[ocall(id = 251)]
+    fn local_store_set(key: &[u8], value: &[u8]) -> Result<()>;
+
+    /// Remove a value from the local store.
+    ///
+    /// Returns the previous value if it existed.
+    #[ocall(id = 252, encode_output)]
+    fn local_store_remove(key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Save a snapshot of the program state, replacing the previous one.
+    ///
+    /// The snapshot is stored by the host with its checkpoint, and handed back through the
+    /// `Snapshot` input channel when the instance is restarted. A snapshot saved after the last
+    /// checkpoint of the worker is lost if the worker restarts. Costs gas in proportion to the
+    /// size of the snapshot.
+    #[ocall(id = 260)]
+    fn save_snapshot(data: &[u8]) -> Result<()>;
+
+    /// Query the pink contract of this instance with an ink message, the contract itself being
+    /// the origin.
+    ///
+    /// Poll the returned resource to get the reply, encoded the same way as the replies of the
+    /// contract query RPC. Changes made by the query are discarded.
+    #[ocall(id = 270)]
+    fn query_contract(payload: &[u8]) -> Result<i32>;
+
+    /// Send an ink message to the pink contract of this instance as a command, the contract
+    /// itself being the origin.
+    ///
+    /// The command is relayed through the chain so that it is executed by all the workers in
+    /// the cluster. Commands with the same `dedup_key` sent by the instances on different
+    /// workers are executed only once, so programs should derive the key from the event they
+    /// react to.
+    #[ocall(id = 271)]
+    fn send_contract_command(dedup_key: &[u8], message: &[u8]) -> Result<()>;
+
+    /// Emit an mq message under the origin of the pink contract of this instance.
+    ///
+    /// Like `send_contract_command`, the message is relayed through the chain and emitted only
+    /// once for each `dedup_key` in the cluster.
+    #[ocall(id = 272, encode_input)]
+    fn emit_message(dedup_key: Cow<[u8]>, topic: Cow<[u8]>, payload: Cow<[u8]>) -> Result<()>;
+}
+
+/// Usage of the local cache of a sidevm instance.
+#[derive(Encode, Decode, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LocalCacheStats {
+    /// Number of keys, including the expired ones not yet collected.
+    pub keys: u64,
+    /// Sum of the size of all the keys and values in bytes.
+    pub size: u64,
+    /// Max size in bytes the cache can grow to.
+    pub quota: u64,
 }
 
 #[repr(u8)]
@@ -121,6 +237,8 @@ pub enum InputChannel {
     GeneralMessage = 2,
     /// Input channel for queries from external RPC requests.
     Query = 3,
+    /// Input channel yielding the snapshot saved by the previous run of the instance, if any.
+    Snapshot = 4,
 }
 
 impl I32Convertible for InputChannel {
@@ -132,6 +250,7 @@ impl I32Convertible for InputChannel {
             1 => Ok(InputChannel::SystemMessage),
             2 => Ok(InputChannel::GeneralMessage),
             3 => Ok(InputChannel::Query),
+            4 => Ok(InputChannel::Snapshot),
             _ => Err(OcallError::InvalidParameter),
         }
     }
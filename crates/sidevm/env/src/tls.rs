@@ -16,4 +16,25 @@ pub enum TlsServerConfig {
 pub enum TlsClientConfig {
     /// Nothing to be configured in this version.
     V0,
+    V1 {
+        /// Root CA certificates in PEM format to trust. The builtin webpki roots are used if
+        /// this is `None`.
+        root_certs: Option<String>,
+        /// Client certificate for mutual TLS.
+        client_cert: Option<TlsClientCert>,
+        /// Protocols to offer via ALPN, in order of preference.
+        alpn_protocols: Vec<Vec<u8>>,
+        /// SHA-256 hashes of the DER encoded SubjectPublicKeyInfo to pin. If not empty, the
+        /// public key of the server certificate must match one of them.
+        spki_pins: Vec<[u8; 32]>,
+    },
+}
+
+/// Client certificate for mutual TLS.
+#[derive(Encode, Decode, Clone)]
+pub struct TlsClientCert {
+    /// Certificate chain in PEM format.
+    pub cert: String,
+    /// The private key of the certificate, in PEM format.
+    pub key: String,
 }
@@ -4,6 +4,7 @@ use std::{
     collections::VecDeque,
     fmt,
     future::Future,
+    net::IpAddr,
     ops::{Deref, DerefMut},
     sync::{Arc, Mutex},
     task::Poll::{Pending, Ready},
@@ -12,7 +13,7 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 use tokio::{
-    net::TcpListener,
+    net::{TcpListener, UdpSocket},
     sync::mpsc::{error::SendError, Sender},
     sync::oneshot::Sender as OneshotSender,
 };
@@ -24,7 +25,7 @@ use wasmer::{
 use env::{
     messages::{AccountId, QueryRequest, SystemMessage},
     tls::{TlsClientConfig, TlsServerConfig},
-    IntPtr, IntRet, OcallError, Result, RetEncode,
+    IntPtr, IntRet, LocalCacheStats, OcallError, Result, RetEncode,
 };
 use scale::Encode;
 use sidevm_env as env;
@@ -34,7 +35,9 @@ use wasmer_middlewares::metering;
 use crate::{
     async_context::{get_task_cx, set_task_env, GuestWaker},
     resource::{Resource, ResourceKeeper},
-    tls::{load_tls_config, TlsStream},
+    service::{OutgoingRequest, OutgoingRequestSender},
+    tls::{load_client_config, load_tls_config, TlsStream},
+    vfs::{FsConfig, Vfs},
     VmId,
 };
 
@@ -82,8 +85,16 @@ fn _sizeof_i32_must_eq_to_intptr() {
     let _ = core::mem::transmute::<i32, IntPtr>;
 }
 
-pub fn create_env(id: VmId, store: &mut Store, cache_ops: DynCacheOps) -> (Env, Imports) {
-    let raw_env = Env::new(id, cache_ops);
+pub fn create_env(
+    id: VmId,
+    store: &mut Store,
+    cache_ops: DynCacheOps,
+    store_ops: DynStoreOps,
+    fs_config: FsConfig,
+    snapshot: SnapshotSlot,
+    outgoing_tx: Option<OutgoingRequestSender>,
+) -> (Env, Imports) {
+    let raw_env = Env::new(id, cache_ops, store_ops, fs_config, snapshot, outgoing_tx);
     let env = FunctionEnv::new(store, raw_env.clone());
     let wasi_imports = wasi_env::wasi_imports(store, &env);
     (
@@ -147,10 +158,44 @@ pub trait CacheOps {
     fn set(&self, contract: &[u8], key: &[u8], value: &[u8]) -> Result<()>;
     fn set_expiration(&self, contract: &[u8], key: &[u8], expire_after_secs: u64) -> Result<()>;
     fn remove(&self, contract: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>>;
+    fn scan(
+        &self,
+        contract: &[u8],
+        prefix: &[u8],
+        start_after: Option<&[u8]>,
+        limit: u32,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+    fn compare_and_swap(
+        &self,
+        contract: &[u8],
+        key: &[u8],
+        expected: Option<&[u8]>,
+        value: Option<&[u8]>,
+    ) -> Result<bool>;
+    fn increment(&self, contract: &[u8], key: &[u8], delta: i64) -> Result<i64>;
+    fn stats(&self, contract: &[u8]) -> Result<LocalCacheStats>;
 }
 
 pub type DynCacheOps = &'static (dyn CacheOps + Send + Sync);
 
+pub trait StoreOps {
+    fn get(&self, contract: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>>;
+    fn set(&self, contract: &[u8], key: &[u8], value: &[u8]) -> Result<()>;
+    fn remove(&self, contract: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>>;
+}
+
+pub type DynStoreOps = &'static (dyn StoreOps + Send + Sync);
+
+/// Holds the snapshot saved by a guest program.
+///
+/// The host keeps it across restarts of the instance and the new run reads it back. It is
+/// persisted with the checkpoints of the worker, so a snapshot saved after the last checkpoint
+/// is lost when the worker restarts.
+pub type SnapshotSlot = Arc<Mutex<Option<Vec<u8>>>>;
+
+/// Max size of a snapshot saved by a guest program.
+const MAX_SNAPSHOT_SIZE: usize = 16 * 1024 * 1024;
+
 struct VmMemory(Option<Memory>);
 
 pub(crate) struct EnvInner {
@@ -166,6 +211,10 @@ pub(crate) struct EnvInner {
     awake_tasks: Arc<TaskSet>,
     current_task: i32,
     cache_ops: DynCacheOps,
+    store_ops: DynStoreOps,
+    vfs: Vfs,
+    snapshot: SnapshotSlot,
+    outgoing_tx: Option<OutgoingRequestSender>,
     weight: u32,
     instance: Option<Instance>,
 }
@@ -225,7 +274,14 @@ pub struct Env {
 }
 
 impl Env {
-    fn new(id: VmId, cache_ops: DynCacheOps) -> Self {
+    fn new(
+        id: VmId,
+        cache_ops: DynCacheOps,
+        store_ops: DynStoreOps,
+        fs_config: FsConfig,
+        snapshot: SnapshotSlot,
+        outgoing_tx: Option<OutgoingRequestSender>,
+    ) -> Self {
         Self {
             inner: Arc::new(Mutex::new(EnvInner {
                 memory: VmMemory(None),
@@ -240,6 +296,10 @@ impl Env {
                 awake_tasks: Arc::new(TaskSet::with_task0()),
                 current_task: 0,
                 cache_ops,
+                store_ops,
+                vfs: Vfs::new(id, fs_config),
+                snapshot,
+                outgoing_tx,
                 weight: 1,
                 instance: None,
             })),
@@ -251,8 +311,10 @@ impl Env {
     }
 
     pub fn cleanup(&self) {
+        let mut inner = self.inner.lock().unwrap();
         // Cut up the reference cycle to avoid leaks.
-        self.inner.lock().unwrap().memory.0 = None;
+        inner.memory.0 = None;
+        inner.vfs.clear();
     }
 
     /// Push a pink message into the Sidevm instance.
@@ -462,7 +524,7 @@ impl<'a, 'b> env::OcallFuncs for FnEnvMut<'a, &'b mut EnvInner> {
         if host.len() > 253 {
             return Err(OcallError::InvalidParameter);
         }
-        let TlsClientConfig::V0 = config;
+        let client_config = load_client_config(config)?;
         let domain = host
             .as_str()
             .try_into()
@@ -470,11 +532,55 @@ impl<'a, 'b> env::OcallFuncs for FnEnvMut<'a, &'b mut EnvInner> {
         let fut = async move {
             tcp_connect(&host, port)
                 .await
-                .map(move |stream| TlsStream::connect(domain, stream))
+                .map(move |stream| TlsStream::connect(domain, stream, client_config))
         };
         self.resources.push(Resource::TlsConnect(Box::pin(fut)))
     }
 
+    fn udp_bind(&mut self, addr: &str) -> Result<i32> {
+        let std_socket = std::net::UdpSocket::bind(addr).or(Err(OcallError::IoError))?;
+        std_socket
+            .set_nonblocking(true)
+            .or(Err(OcallError::IoError))?;
+        let socket = UdpSocket::from_std(std_socket).or(Err(OcallError::IoError))?;
+        self.resources.push(Resource::UdpSocket(socket))
+    }
+
+    fn udp_send_to(
+        &mut self,
+        waker_id: i32,
+        resource_id: i32,
+        data: Cow<[u8]>,
+        addr: Cow<str>,
+    ) -> Result<u32> {
+        let addr = addr.parse().or(Err(OcallError::InvalidParameter))?;
+        self.resources
+            .get_mut(resource_id)?
+            .poll_send_to(waker_id, &data, addr)
+    }
+
+    fn udp_recv_from(
+        &mut self,
+        waker_id: i32,
+        resource_id: i32,
+        data: &mut [u8],
+    ) -> Result<(u32, String)> {
+        let (len, addr) = self
+            .resources
+            .get_mut(resource_id)?
+            .poll_recv_from(waker_id, data)?;
+        Ok((len, addr.to_string()))
+    }
+
+    fn resolve(&mut self, host: &str) -> Result<i32> {
+        if host.len() > 253 {
+            return Err(OcallError::InvalidParameter);
+        }
+        let host = host.to_owned();
+        let fut = async move { resolve(&host).await };
+        self.resources.push(Resource::Resolve(Box::pin(fut)))
+    }
+
     fn log(&mut self, level: log::Level, message: &str) -> Result<()> {
         let task = self.current_task;
         let vm_id = ShortId(&self.id);
@@ -499,6 +605,46 @@ impl<'a, 'b> env::OcallFuncs for FnEnvMut<'a, &'b mut EnvInner> {
         self.cache_ops.remove(&self.id[..], key)
     }
 
+    fn local_cache_scan(
+        &mut self,
+        prefix: Vec<u8>,
+        start_after: Option<Vec<u8>>,
+        limit: u32,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.cache_ops
+            .scan(&self.id[..], &prefix, start_after.as_deref(), limit)
+    }
+
+    fn local_cache_compare_and_swap(
+        &mut self,
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        value: Option<Vec<u8>>,
+    ) -> Result<bool> {
+        self.cache_ops
+            .compare_and_swap(&self.id[..], &key, expected.as_deref(), value.as_deref())
+    }
+
+    fn local_cache_increment(&mut self, key: &[u8], delta: i64) -> Result<i64> {
+        self.cache_ops.increment(&self.id[..], key, delta)
+    }
+
+    fn local_cache_stats(&mut self) -> Result<LocalCacheStats> {
+        self.cache_ops.stats(&self.id[..])
+    }
+
+    fn local_store_get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store_ops.get(&self.id[..], key)
+    }
+
+    fn local_store_set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.store_ops.set(&self.id[..], key, value)
+    }
+
+    fn local_store_remove(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store_ops.remove(&self.id[..], key)
+    }
+
     fn awake_wakers(&mut self) -> Result<Vec<i32>> {
         Ok(self
             .awake_tasks
@@ -548,9 +694,60 @@ impl<'a, 'b> env::OcallFuncs for FnEnvMut<'a, &'b mut EnvInner> {
             GeneralMessage => create_channel!(self.message_tx),
             SystemMessage => create_channel!(self.sys_message_tx),
             Query => create_channel!(self.query_tx),
+            Snapshot => {
+                // The sender is dropped right away, so the guest sees the end of the channel
+                // after the snapshot.
+                let (tx, rx) = tokio::sync::mpsc::channel(1);
+                if let Some(snapshot) = self.snapshot.lock().unwrap().clone() {
+                    let _ = tx.try_send(snapshot);
+                }
+                self.resources.push(Resource::ChannelRx(rx))
+            }
         }
     }
 
+    fn save_snapshot(&mut self, data: &[u8]) -> Result<()> {
+        // Charged by size, the snapshot is copied here and then stored with every checkpoint.
+        const SNAPSHOT_BYTE_WEIGHT: u64 = 1_000;
+
+        if data.len() > MAX_SNAPSHOT_SIZE {
+            return Err(OcallError::ResourceLimited);
+        }
+        self.inner
+            .pay(&mut self.store, SNAPSHOT_BYTE_WEIGHT * data.len() as u64)?;
+        *self.snapshot.lock().unwrap() = Some(data.to_vec());
+        Ok(())
+    }
+
+    fn query_contract(&mut self, payload: &[u8]) -> Result<i32> {
+        let (reply_tx, rx) = tokio::sync::oneshot::channel();
+        self.send_outgoing(OutgoingRequest::Query {
+            payload: payload.to_vec(),
+            reply_tx,
+        })?;
+        self.resources.push(Resource::OneshotRx(rx))
+    }
+
+    fn send_contract_command(&mut self, dedup_key: &[u8], message: &[u8]) -> Result<()> {
+        self.send_outgoing(OutgoingRequest::Command {
+            dedup_key: dedup_key.to_vec(),
+            message: message.to_vec(),
+        })
+    }
+
+    fn emit_message(
+        &mut self,
+        dedup_key: Cow<[u8]>,
+        topic: Cow<[u8]>,
+        payload: Cow<[u8]>,
+    ) -> Result<()> {
+        self.send_outgoing(OutgoingRequest::Message {
+            dedup_key: dedup_key.into_owned(),
+            topic: topic.into_owned(),
+            payload: payload.into_owned(),
+        })
+    }
+
     fn gas_remaining(&mut self) -> Result<u8> {
         self.inner.pay(&mut self.store, 1_000_000)?;
         Ok(if self.gas_per_breath == 0 {
@@ -605,6 +802,18 @@ impl EnvInner {
         self.set_gas_to_breath(store, gas - cost);
         Ok(())
     }
+
+    fn send_outgoing(&self, request: OutgoingRequest) -> Result<()> {
+        use tokio::sync::mpsc::error::TrySendError;
+        let tx = self
+            .outgoing_tx
+            .as_ref()
+            .ok_or(OcallError::UnsupportedOperation)?;
+        tx.try_send(request).map_err(|err| match err {
+            TrySendError::Full(_) => OcallError::ResourceLimited,
+            TrySendError::Closed(_) => OcallError::IoError,
+        })
+    }
 }
 
 async fn tcp_connect(host: &str, port: u16) -> std::io::Result<tokio::net::TcpStream> {
@@ -631,6 +840,11 @@ async fn tcp_connect(host: &str, port: u16) -> std::io::Result<tokio::net::TcpSt
     }
 }
 
+async fn resolve(host: &str) -> std::io::Result<Vec<IpAddr>> {
+    let addrs = tokio::net::lookup_host((host, 0)).await?;
+    Ok(addrs.map(|addr| addr.ip()).collect())
+}
+
 fn sidevm_ocall_fast_return(
     func_env: FunctionEnvMut<Env>,
     task_id: i32,
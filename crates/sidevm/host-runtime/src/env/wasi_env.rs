@@ -1,15 +1,19 @@
 use super::{Env as WasiEnv, Result};
+use crate::vfs::Vfs;
 use libc::{clock_getres, clock_gettime, timespec, CLOCK_MONOTONIC, CLOCK_REALTIME};
 use sidevm_env::{OcallError, OcallFuncs};
 use thiserror::Error;
 use wasmer::{
-    namespace, AsStoreMut, Exports, Function, FunctionEnv, FunctionEnvMut, Memory32, WasmPtr,
+    namespace, AsStoreMut, Exports, Function, FunctionEnv, FunctionEnvMut, Memory32,
+    MemoryAccessError, MemoryView, WasmPtr,
 };
 use wasmer_wasi_types::{
     types::*,
     wasi::{self, Errno},
 };
 
+const MAX_PATH_LEN: u32 = 4096;
+
 /// This is returned in `RuntimeError`.
 /// Use `downcast` or `downcast_ref` to retrieve the `ExitCode`.
 #[derive(Error, Debug)]
@@ -196,36 +200,49 @@ pub fn fd_advise(
 }
 
 pub fn fd_allocate(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _offset: wasi::Filesize,
-    _len: wasi::Filesize,
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    offset: wasi::Filesize,
+    len: wasi::Filesize,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |_, vfs| errno(vfs.allocate(fd, offset, len)))
 }
 
-pub fn fd_close(_env: FunctionEnvMut<WasiEnv>, _fd: wasi::Fd) -> Errno {
-    Errno::Nosys
+pub fn fd_close(env: FunctionEnvMut<WasiEnv>, fd: wasi::Fd) -> Errno {
+    with_vfs(&env, |_, vfs| errno(vfs.close(fd)))
 }
 
-pub fn fd_datasync(_env: FunctionEnvMut<WasiEnv>, _fd: wasi::Fd) -> Errno {
-    Errno::Nosys
+pub fn fd_datasync(env: FunctionEnvMut<WasiEnv>, fd: wasi::Fd) -> Errno {
+    with_vfs(&env, |_, vfs| errno(vfs.sync(fd)))
 }
 
 pub fn fd_fdstat_get(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _buf_ptr: WasmPtr<wasi::Fdstat>,
-) -> Errno {
-    Errno::Nosys
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    buf_ptr: WasmPtr<wasi::Fdstat>,
+) -> Errno {
+    with_vfs(&env, |memory, vfs| {
+        let stat = if is_stdio(fd) {
+            wasi::Fdstat {
+                fs_filetype: wasi::Filetype::CharacterDevice,
+                fs_flags: wasi::Fdflags::empty(),
+                fs_rights_base: wasi::Rights::FD_READ | wasi::Rights::FD_WRITE,
+                fs_rights_inheriting: wasi::Rights::empty(),
+            }
+        } else {
+            wasi_try!(vfs.fdstat(fd))
+        };
+        wasi_try!(buf_ptr.deref(memory).write(stat).ok(), Errno::Fault);
+        Errno::Success
+    })
 }
 
 pub fn fd_fdstat_set_flags(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _flags: wasi::Fdflags,
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    flags: wasi::Fdflags,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |_, vfs| errno(vfs.set_fdflags(fd, flags)))
 }
 
 pub fn fd_fdstat_set_rights(
@@ -238,159 +255,289 @@ pub fn fd_fdstat_set_rights(
 }
 
 pub fn fd_filestat_get(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _buf: WasmPtr<wasi::Filestat>,
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    buf: WasmPtr<wasi::Filestat>,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |memory, vfs| {
+        let stat = wasi_try!(vfs.fd_filestat(fd));
+        wasi_try!(buf.deref(memory).write(stat).ok(), Errno::Fault);
+        Errno::Success
+    })
 }
 
 pub fn fd_filestat_set_size(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _st_size: wasi::Filesize,
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    st_size: wasi::Filesize,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |_, vfs| errno(vfs.set_size(fd, st_size)))
 }
 
 pub fn fd_filestat_set_times(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _st_atim: wasi::Timestamp,
-    _st_mtim: wasi::Timestamp,
-    _fst_flags: wasi::Fstflags,
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    st_atim: wasi::Timestamp,
+    st_mtim: wasi::Timestamp,
+    fst_flags: wasi::Fstflags,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |_, vfs| {
+        errno(vfs.fd_set_times(fd, st_atim, st_mtim, fst_flags))
+    })
 }
 
 pub fn fd_pread(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _iovs: WasmPtr<__wasi_iovec_t<Memory32>>,
-    _iovs_len: u32,
-    _offset: wasi::Filesize,
-    _nread: WasmPtr<u32>,
-) -> Errno {
-    Errno::Nosys
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    iovs: WasmPtr<__wasi_iovec_t<Memory32>>,
+    iovs_len: u32,
+    offset: wasi::Filesize,
+    nread: WasmPtr<u32>,
+) -> Errno {
+    with_vfs(&env, |memory, vfs| {
+        if is_stdio(fd) {
+            return Errno::Spipe;
+        }
+        let iovs = wasi_try!(read_iovecs(memory, iovs, iovs_len));
+        let data = wasi_try!(vfs.read(fd, total_len(&iovs), Some(offset)));
+        wasi_try!(scatter(memory, &iovs, &data));
+        wasi_try!(
+            nread.deref(memory).write(data.len() as u32).ok(),
+            Errno::Fault
+        );
+        Errno::Success
+    })
 }
 
 pub fn fd_prestat_get(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _buf: WasmPtr<wasi::Prestat>,
-) -> Errno {
-    Errno::Badf
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    buf: WasmPtr<wasi::Prestat>,
+) -> Errno {
+    with_vfs(&env, |memory, vfs| {
+        let name = wasi_try!(vfs.prestat_name(fd));
+        let prestat = wasi::Prestat {
+            pr_type: wasi::Preopentype::Dir,
+            u: wasi::PrestatEnum::Dir {
+                pr_name_len: name.len() as u32,
+            }
+            .untagged(),
+        };
+        wasi_try!(buf.deref(memory).write(prestat).ok(), Errno::Fault);
+        Errno::Success
+    })
 }
 
 pub fn fd_prestat_dir_name(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _path: WasmPtr<u8>,
-    _path_len: u32,
-) -> Errno {
-    Errno::Nosys
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    path: WasmPtr<u8>,
+    path_len: u32,
+) -> Errno {
+    with_vfs(&env, |memory, vfs| {
+        let name = wasi_try!(vfs.prestat_name(fd));
+        if (path_len as usize) < name.len() {
+            return Errno::Nametoolong;
+        }
+        wasi_try!(
+            path.slice(memory, name.len() as u32)
+                .and_then(|buf| buf.write_slice(name.as_bytes()))
+                .ok(),
+            Errno::Fault
+        );
+        Errno::Success
+    })
 }
 
 pub fn fd_pwrite(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _iovs: WasmPtr<__wasi_ciovec_t<Memory32>>,
-    _iovs_len: u32,
-    _offset: wasi::Filesize,
-    _nwritten: WasmPtr<u32>,
-) -> Errno {
-    Errno::Nosys
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    iovs: WasmPtr<__wasi_ciovec_t<Memory32>>,
+    iovs_len: u32,
+    offset: wasi::Filesize,
+    nwritten: WasmPtr<u32>,
+) -> Errno {
+    with_vfs(&env, |memory, vfs| {
+        if is_stdio(fd) {
+            return Errno::Spipe;
+        }
+        let data = wasi_try!(gather(memory, iovs, iovs_len));
+        let written = wasi_try!(vfs.write(fd, &data, Some(offset)));
+        wasi_try!(
+            nwritten.deref(memory).write(written as u32).ok(),
+            Errno::Fault
+        );
+        Errno::Success
+    })
 }
 
 pub fn fd_read(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _iovs: WasmPtr<__wasi_iovec_t<Memory32>>,
-    _iovs_len: u32,
-    _nread: WasmPtr<u32>,
-) -> Errno {
-    Errno::Nosys
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    iovs: WasmPtr<__wasi_iovec_t<Memory32>>,
+    iovs_len: u32,
+    nread: WasmPtr<u32>,
+) -> Errno {
+    with_vfs(&env, |memory, vfs| {
+        let iovs = wasi_try!(read_iovecs(memory, iovs, iovs_len));
+        let data = if is_stdio(fd) {
+            // Nothing is connected to the stdin.
+            vec![]
+        } else {
+            wasi_try!(vfs.read(fd, total_len(&iovs), None))
+        };
+        wasi_try!(scatter(memory, &iovs, &data));
+        wasi_try!(
+            nread.deref(memory).write(data.len() as u32).ok(),
+            Errno::Fault
+        );
+        Errno::Success
+    })
 }
 
 pub fn fd_readdir(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _buf: WasmPtr<u8>,
-    _buf_len: u32,
-    _cookie: wasi::Dircookie,
-    _bufused: WasmPtr<u32>,
-) -> Errno {
-    Errno::Nosys
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    buf: WasmPtr<u8>,
+    buf_len: u32,
+    cookie: wasi::Dircookie,
+    bufused: WasmPtr<u32>,
+) -> Errno {
+    with_vfs(&env, |memory, vfs| {
+        let buf_len = buf_len as usize;
+        let mut output = Vec::with_capacity(buf_len);
+        for (next, ino, filetype, name) in wasi_try!(vfs.readdir(fd, cookie)) {
+            // The layout of the wasi dirent, followed by the name.
+            let mut dirent = Vec::with_capacity(24 + name.len());
+            dirent.extend_from_slice(&next.to_le_bytes());
+            dirent.extend_from_slice(&ino.to_le_bytes());
+            dirent.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            dirent.extend_from_slice(&[filetype as u8, 0, 0, 0]);
+            dirent.extend_from_slice(name.as_bytes());
+            // A truncated last entry tells the guest to call again with a bigger buffer.
+            let room = buf_len - output.len();
+            output.extend_from_slice(&dirent[..dirent.len().min(room)]);
+            if output.len() == buf_len {
+                break;
+            }
+        }
+        wasi_try!(
+            buf.slice(memory, output.len() as u32)
+                .and_then(|buf| buf.write_slice(&output))
+                .ok(),
+            Errno::Fault
+        );
+        wasi_try!(
+            bufused.deref(memory).write(output.len() as u32).ok(),
+            Errno::Fault
+        );
+        Errno::Success
+    })
 }
 
-pub fn fd_renumber(_env: FunctionEnvMut<WasiEnv>, _from: wasi::Fd, _to: wasi::Fd) -> Errno {
-    Errno::Nosys
+pub fn fd_renumber(env: FunctionEnvMut<WasiEnv>, from: wasi::Fd, to: wasi::Fd) -> Errno {
+    with_vfs(&env, |_, vfs| errno(vfs.renumber(from, to)))
 }
 
 pub fn fd_seek(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _offset: wasi::FileDelta,
-    _whence: wasi::Whence,
-    _newoffset: WasmPtr<wasi::Filesize>,
-) -> Errno {
-    Errno::Nosys
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    offset: wasi::FileDelta,
+    whence: wasi::Whence,
+    newoffset: WasmPtr<wasi::Filesize>,
+) -> Errno {
+    with_vfs(&env, |memory, vfs| {
+        if is_stdio(fd) {
+            return Errno::Spipe;
+        }
+        let pos = wasi_try!(vfs.seek(fd, offset, whence));
+        wasi_try!(newoffset.deref(memory).write(pos).ok(), Errno::Fault);
+        Errno::Success
+    })
 }
 
-pub fn fd_sync(_env: FunctionEnvMut<WasiEnv>, _fd: wasi::Fd) -> Errno {
-    Errno::Nosys
+pub fn fd_sync(env: FunctionEnvMut<WasiEnv>, fd: wasi::Fd) -> Errno {
+    with_vfs(&env, |_, vfs| errno(vfs.sync(fd)))
 }
 
 pub fn fd_tell(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _offset: WasmPtr<wasi::Filesize>,
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    offset: WasmPtr<wasi::Filesize>,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |memory, vfs| {
+        let pos = wasi_try!(vfs.tell(fd));
+        wasi_try!(offset.deref(memory).write(pos).ok(), Errno::Fault);
+        Errno::Success
+    })
 }
 
 pub fn fd_write(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _iovs: WasmPtr<__wasi_ciovec_t<Memory32>>,
-    _iovs_len: u32,
-    _nwritten: WasmPtr<u32>,
-) -> Errno {
-    Errno::Success
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    iovs: WasmPtr<__wasi_ciovec_t<Memory32>>,
+    iovs_len: u32,
+    nwritten: WasmPtr<u32>,
+) -> Errno {
+    with_vfs(&env, |memory, vfs| {
+        let data = wasi_try!(gather(memory, iovs, iovs_len));
+        let written = if is_stdio(fd) {
+            // The output to the stdio is dropped. Programs should use the log ocall instead.
+            data.len()
+        } else {
+            wasi_try!(vfs.write(fd, &data, None))
+        };
+        wasi_try!(
+            nwritten.deref(memory).write(written as u32).ok(),
+            Errno::Fault
+        );
+        Errno::Success
+    })
 }
 
 pub fn path_create_directory(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _path: WasmPtr<u8>,
-    _path_len: u32,
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    path: WasmPtr<u8>,
+    path_len: u32,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |memory, vfs| {
+        let path = wasi_try!(read_path(memory, path, path_len));
+        errno(vfs.create_dir(fd, &path))
+    })
 }
 
 pub fn path_filestat_get(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
     _flags: wasi::LookupFlags,
-    _path: WasmPtr<u8>,
-    _path_len: u32,
-    _buf: WasmPtr<wasi::Filestat>,
+    path: WasmPtr<u8>,
+    path_len: u32,
+    buf: WasmPtr<wasi::Filestat>,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |memory, vfs| {
+        let path = wasi_try!(read_path(memory, path, path_len));
+        let stat = wasi_try!(vfs.path_filestat(fd, &path));
+        wasi_try!(buf.deref(memory).write(stat).ok(), Errno::Fault);
+        Errno::Success
+    })
 }
 
 #[allow(clippy::too_many_arguments)]
 pub fn path_filestat_set_times(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
     _flags: wasi::LookupFlags,
-    _path: WasmPtr<u8>,
-    _path_len: u32,
-    _st_atim: wasi::Timestamp,
-    _st_mtim: wasi::Timestamp,
-    _fst_flags: wasi::Fstflags,
+    path: WasmPtr<u8>,
+    path_len: u32,
+    st_atim: wasi::Timestamp,
+    st_mtim: wasi::Timestamp,
+    fst_flags: wasi::Fstflags,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |memory, vfs| {
+        let path = wasi_try!(read_path(memory, path, path_len));
+        errno(vfs.path_set_times(fd, &path, st_atim, st_mtim, fst_flags))
+    })
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -409,18 +556,33 @@ pub fn path_link(
 
 #[allow(clippy::too_many_arguments)]
 pub fn path_open(
-    _env: FunctionEnvMut<WasiEnv>,
-    _dirfd: wasi::Fd,
+    env: FunctionEnvMut<WasiEnv>,
+    dirfd: wasi::Fd,
     _dirflags: wasi::LookupFlags,
-    _path: WasmPtr<u8>,
-    _path_len: u32,
-    _o_flags: wasi::Oflags,
-    _fs_rights_base: wasi::Rights,
-    _fs_rights_inheriting: wasi::Rights,
-    _fs_flags: wasi::Fdflags,
-    _fd: WasmPtr<wasi::Fd>,
-) -> Errno {
-    Errno::Nosys
+    path: WasmPtr<u8>,
+    path_len: u32,
+    o_flags: wasi::Oflags,
+    fs_rights_base: wasi::Rights,
+    fs_rights_inheriting: wasi::Rights,
+    fs_flags: wasi::Fdflags,
+    fd: WasmPtr<wasi::Fd>,
+) -> Errno {
+    with_vfs(&env, |memory, vfs| {
+        let path = wasi_try!(read_path(memory, path, path_len));
+        let opened = wasi_try!(vfs.open(
+            dirfd,
+            &path,
+            o_flags,
+            fs_rights_base,
+            fs_rights_inheriting,
+            fs_flags
+        ));
+        if fd.deref(memory).write(opened).is_err() {
+            let _ = vfs.close(opened);
+            return Errno::Fault;
+        }
+        Errno::Success
+    })
 }
 
 pub fn path_readlink(
@@ -436,24 +598,31 @@ pub fn path_readlink(
 }
 
 pub fn path_remove_directory(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _path: WasmPtr<u8>,
-    _path_len: u32,
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    path: WasmPtr<u8>,
+    path_len: u32,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |memory, vfs| {
+        let path = wasi_try!(read_path(memory, path, path_len));
+        errno(vfs.remove_dir(fd, &path))
+    })
 }
 
 pub fn path_rename(
-    _env: FunctionEnvMut<WasiEnv>,
-    _old_fd: wasi::Fd,
-    _old_path: WasmPtr<u8>,
-    _old_path_len: u32,
-    _new_fd: wasi::Fd,
-    _new_path: WasmPtr<u8>,
-    _new_path_len: u32,
+    env: FunctionEnvMut<WasiEnv>,
+    old_fd: wasi::Fd,
+    old_path: WasmPtr<u8>,
+    old_path_len: u32,
+    new_fd: wasi::Fd,
+    new_path: WasmPtr<u8>,
+    new_path_len: u32,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |memory, vfs| {
+        let old_path = wasi_try!(read_path(memory, old_path, old_path_len));
+        let new_path = wasi_try!(read_path(memory, new_path, new_path_len));
+        errno(vfs.rename(old_fd, &old_path, new_fd, &new_path))
+    })
 }
 
 pub fn path_symlink(
@@ -468,12 +637,15 @@ pub fn path_symlink(
 }
 
 pub fn path_unlink_file(
-    _env: FunctionEnvMut<WasiEnv>,
-    _fd: wasi::Fd,
-    _path: WasmPtr<u8>,
-    _path_len: u32,
+    env: FunctionEnvMut<WasiEnv>,
+    fd: wasi::Fd,
+    path: WasmPtr<u8>,
+    path_len: u32,
 ) -> Errno {
-    Errno::Nosys
+    with_vfs(&env, |memory, vfs| {
+        let path = wasi_try!(read_path(memory, path, path_len));
+        errno(vfs.unlink_file(fd, &path))
+    })
 }
 
 pub fn poll_oneoff(
@@ -540,3 +712,89 @@ pub fn sock_send(
 pub fn sock_shutdown(_env: FunctionEnvMut<WasiEnv>, _sock: wasi::Fd, _how: SdFlags) -> Errno {
     Errno::Nosys
 }
+
+/// Run `f` with the memory and the virtual filesystem of the VM.
+fn with_vfs<R>(env: &FunctionEnvMut<WasiEnv>, f: impl FnOnce(&MemoryView, &mut Vfs) -> R) -> R {
+    let inner = env.data().inner.clone();
+    let mut guard = inner.lock().unwrap();
+    let memory = guard.memory.unwrap_ref().clone();
+    let view = memory.view(env);
+    f(&view, &mut guard.vfs)
+}
+
+fn errno(result: Result<(), Errno>) -> Errno {
+    match result {
+        Ok(()) => Errno::Success,
+        Err(err) => err,
+    }
+}
+
+fn is_stdio(fd: wasi::Fd) -> bool {
+    fd <= 2
+}
+
+fn read_path(memory: &MemoryView, path: WasmPtr<u8>, path_len: u32) -> Result<String, Errno> {
+    if path_len > MAX_PATH_LEN {
+        return Err(Errno::Nametoolong);
+    }
+    path.read_utf8_string(memory, path_len)
+        .map_err(|err| match err {
+            MemoryAccessError::NonUtf8String => Errno::Ilseq,
+            _ => Errno::Fault,
+        })
+}
+
+fn read_iovecs(
+    memory: &MemoryView,
+    iovs: WasmPtr<__wasi_iovec_t<Memory32>>,
+    iovs_len: u32,
+) -> Result<Vec<__wasi_iovec_t<Memory32>>, Errno> {
+    iovs.slice(memory, iovs_len)
+        .and_then(|iovs| iovs.read_to_vec())
+        .or(Err(Errno::Fault))
+}
+
+fn total_len(iovs: &[__wasi_iovec_t<Memory32>]) -> usize {
+    iovs.iter().map(|iov| iov.buf_len as usize).sum()
+}
+
+/// Copy `data` into the guest buffers described by `iovs`.
+fn scatter(
+    memory: &MemoryView,
+    iovs: &[__wasi_iovec_t<Memory32>],
+    mut data: &[u8],
+) -> Result<(), Errno> {
+    for iov in iovs {
+        if data.is_empty() {
+            break;
+        }
+        let n = data.len().min(iov.buf_len as usize);
+        WasmPtr::<u8>::new(iov.buf)
+            .slice(memory, n as u32)
+            .and_then(|buf| buf.write_slice(&data[..n]))
+            .or(Err(Errno::Fault))?;
+        data = &data[n..];
+    }
+    Ok(())
+}
+
+/// Concatenate the guest buffers described by `iovs`.
+fn gather(
+    memory: &MemoryView,
+    iovs: WasmPtr<__wasi_ciovec_t<Memory32>>,
+    iovs_len: u32,
+) -> Result<Vec<u8>, Errno> {
+    let iovs = iovs
+        .slice(memory, iovs_len)
+        .and_then(|iovs| iovs.read_to_vec())
+        .or(Err(Errno::Fault))?;
+    let mut data = vec![];
+    for iov in iovs {
+        let buf = WasmPtr::<u8>::new(iov.buf)
+            .slice(memory, iov.buf_len)
+            .and_then(|buf| buf.read_to_vec())
+            .or(Err(Errno::Fault))?;
+        data.extend_from_slice(&buf);
+    }
+    Ok(data)
+}
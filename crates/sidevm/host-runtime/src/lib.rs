@@ -6,10 +6,12 @@ mod resource;
 mod run;
 pub mod service;
 mod tls;
+mod vfs;
 
-pub use env::{CacheOps, DynCacheOps, OcallAborted, ShortId};
+pub use env::{CacheOps, DynCacheOps, DynStoreOps, OcallAborted, ShortId, SnapshotSlot, StoreOps};
 
 pub type VmId = [u8; 32];
 pub use run::WasmRun;
+pub use vfs::{DynSealOps, FsConfig, SealOps};
 
-pub use sidevm_env::OcallError;
+pub use sidevm_env::{LocalCacheStats, OcallError};
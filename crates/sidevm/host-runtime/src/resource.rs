@@ -1,14 +1,16 @@
 use futures::pin_mut;
+use scale::Encode;
 use sidevm_env::{OcallError, Result};
 use std::future::Future;
 use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::Poll::*;
 use tokio::io::{AsyncRead, AsyncWrite as _};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::sync::mpsc::Receiver;
-use tokio::sync::oneshot::Sender;
+use tokio::sync::oneshot::{Receiver as OneshotReceiver, Sender};
 use tokio::time::Sleep;
 use tokio_rustls::rustls::ServerConfig;
 use Resource::*;
@@ -20,6 +22,7 @@ pub enum Resource {
     Sleep(Pin<Box<Sleep>>),
     ChannelRx(Receiver<Vec<u8>>),
     OneshotTx(Option<Sender<Vec<u8>>>),
+    OneshotRx(OneshotReceiver<Vec<u8>>),
     TcpListener {
         listener: TcpListener,
         tls_config: Option<Arc<ServerConfig>>,
@@ -28,6 +31,8 @@ pub enum Resource {
     TlsStream(Box<TlsStream>),
     TcpConnect(Pin<Box<dyn Future<Output = std::io::Result<TcpStream>> + Send>>),
     TlsConnect(Pin<Box<dyn Future<Output = std::io::Result<TlsStream>> + Send>>),
+    UdpSocket(UdpSocket),
+    Resolve(Pin<Box<dyn Future<Output = std::io::Result<Vec<IpAddr>>> + Send>>),
 }
 
 impl Resource {
@@ -45,6 +50,23 @@ impl Resource {
                     Pending => Err(OcallError::Pending),
                 }
             }
+            OneshotRx(rx) => match poll_in_task_cx(waker, Pin::new(rx)) {
+                Ready(Ok(data)) => Ok(data),
+                // The sender has been dropped without replying
+                Ready(Err(_)) => Err(OcallError::IoError),
+                Pending => Err(OcallError::Pending),
+            },
+            Resolve(fut) => match poll_in_task_cx(waker, fut.as_mut()) {
+                Pending => Err(OcallError::Pending),
+                Ready(Ok(addrs)) => {
+                    let addrs: Vec<String> = addrs.iter().map(ToString::to_string).collect();
+                    Ok(addrs.encode())
+                }
+                Ready(Err(err)) => {
+                    log::error!("Resolve error: {}", err);
+                    Err(OcallError::IoError)
+                }
+            },
             _ => Err(OcallError::UnsupportedOperation),
         }
     }
@@ -169,6 +191,44 @@ impl Resource {
             _ => Err(OcallError::UnsupportedOperation),
         }
     }
+
+    pub(crate) fn poll_send_to(
+        &mut self,
+        waker_id: i32,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Result<u32> {
+        let waker = GuestWaker::from_id(waker_id);
+        match self {
+            UdpSocket(socket) => {
+                match get_task_cx(waker, |cx| socket.poll_send_to(cx, buf, target)) {
+                    Pending => Err(OcallError::Pending),
+                    Ready(Err(_err)) => Err(OcallError::IoError),
+                    Ready(Ok(sz)) => Ok(sz as _),
+                }
+            }
+            _ => Err(OcallError::UnsupportedOperation),
+        }
+    }
+
+    pub(crate) fn poll_recv_from(
+        &mut self,
+        waker_id: i32,
+        buf: &mut [u8],
+    ) -> Result<(u32, SocketAddr)> {
+        let waker = GuestWaker::from_id(waker_id);
+        match self {
+            UdpSocket(socket) => {
+                let mut buf = tokio::io::ReadBuf::new(buf);
+                match get_task_cx(waker, |cx| socket.poll_recv_from(cx, &mut buf)) {
+                    Pending => Err(OcallError::Pending),
+                    Ready(Err(_err)) => Err(OcallError::IoError),
+                    Ready(Ok(addr)) => Ok((buf.filled().len() as _, addr)),
+                }
+            }
+            _ => Err(OcallError::UnsupportedOperation),
+        }
+    }
 }
 
 #[derive(Default)]
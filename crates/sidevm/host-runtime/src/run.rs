@@ -11,7 +11,9 @@ use wasmer_compiler_llvm::LLVM;
 use wasmer_compiler_singlepass::Singlepass;
 use wasmer_tunables::LimitingTunables;
 
-use crate::env::DynCacheOps;
+use crate::env::{DynCacheOps, DynStoreOps, SnapshotSlot};
+use crate::service::OutgoingRequestSender;
+use crate::vfs::FsConfig;
 use crate::{async_context, env, metering::metering, VmId};
 
 pub struct WasmRun {
@@ -29,12 +31,17 @@ impl Drop for WasmRun {
 }
 
 impl WasmRun {
+    #[allow(clippy::too_many_arguments)]
     pub fn run(
         code: &[u8],
         max_pages: u32,
         id: crate::VmId,
         gas_per_breath: u64,
         cache_ops: DynCacheOps,
+        store_ops: DynStoreOps,
+        fs_config: FsConfig,
+        snapshot: SnapshotSlot,
+        outgoing_tx: Option<OutgoingRequestSender>,
         scheduler: TaskScheduler<VmId>,
         weight: u32,
     ) -> Result<(WasmRun, env::Env)> {
@@ -61,7 +68,15 @@ impl WasmRun {
         let tunables = LimitingTunables::new(base, Pages(max_pages));
         let mut store = Store::new_with_tunables(&engine, tunables);
         let module = Module::new(&store, code)?;
-        let (env, import_object) = env::create_env(id, &mut store, cache_ops);
+        let (env, import_object) = env::create_env(
+            id,
+            &mut store,
+            cache_ops,
+            store_ops,
+            fs_config,
+            snapshot,
+            outgoing_tx,
+        );
         let instance = Instance::new(&mut store, &module, &import_object)?;
         let memory = instance
             .exports
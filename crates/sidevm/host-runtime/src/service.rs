@@ -1,4 +1,5 @@
-use crate::env::DynCacheOps;
+use crate::env::{DynCacheOps, DynStoreOps, SnapshotSlot};
+use crate::vfs::FsConfig;
 use crate::{env::OcallAborted, run::WasmRun};
 use crate::{ShortId, VmId};
 use anyhow::{Context as _, Result};
@@ -15,6 +16,7 @@ use tokio::{
 
 pub use sidevm_env::messages::SystemMessage;
 pub type CommandSender = Sender<Command>;
+pub type OutgoingRequestSender = Sender<OutgoingRequest>;
 
 #[derive(Debug)]
 pub enum Report {
@@ -58,6 +60,26 @@ pub enum Command {
     UpdateWeight(u32),
 }
 
+/// A request from the instance to its pink contract.
+pub enum OutgoingRequest {
+    // Query the contract with an ink message.
+    Query {
+        payload: Vec<u8>,
+        reply_tx: OneshotSender<Vec<u8>>,
+    },
+    // Send an ink message to the contract as a command.
+    Command {
+        dedup_key: Vec<u8>,
+        message: Vec<u8>,
+    },
+    // Emit an mq message under the origin of the contract.
+    Message {
+        dedup_key: Vec<u8>,
+        topic: Vec<u8>,
+        payload: Vec<u8>,
+    },
+}
+
 pub struct ServiceRun {
     runtime: tokio::runtime::Runtime,
     report_rx: Receiver<Report>,
@@ -117,6 +139,7 @@ impl ServiceRun {
 }
 
 impl Spawner {
+    #[allow(clippy::too_many_arguments)]
     pub fn start(
         &self,
         wasm_bytes: &[u8],
@@ -124,6 +147,10 @@ impl Spawner {
         id: VmId,
         gas_per_breath: u64,
         cache_ops: DynCacheOps,
+        store_ops: DynStoreOps,
+        fs_config: FsConfig,
+        snapshot: SnapshotSlot,
+        outgoing_tx: Option<OutgoingRequestSender>,
         weight: u32,
     ) -> Result<(CommandSender, JoinHandle<ExitReason>)> {
         let (cmd_tx, mut cmd_rx) = channel(128);
@@ -133,6 +160,10 @@ impl Spawner {
             id,
             gas_per_breath,
             cache_ops,
+            store_ops,
+            fs_config,
+            snapshot,
+            outgoing_tx,
             self.scheduler.clone(),
             weight,
         )
@@ -3,17 +3,23 @@ use std::io;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::SystemTime;
 
 use futures::ready;
 use once_cell::sync::Lazy;
-use sidevm_env::tls::TlsServerConfig;
+use sha2::{Digest, Sha256};
+use sidevm_env::tls::{TlsClientCert, TlsClientConfig, TlsServerConfig};
 use sidevm_env::OcallError;
 use rustls_pemfile::Item;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::TcpStream;
 use tokio_rustls::{
     client::TlsStream as ClientTlsStream,
-    rustls::{self, ClientConfig, ServerConfig, ServerName},
+    rustls::{
+        self,
+        client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+        Certificate, ClientConfig, ServerConfig, ServerName,
+    },
     server::TlsStream as ServerTlsStream,
     Accept, Connect, TlsAcceptor, TlsConnector,
 };
@@ -38,34 +44,166 @@ impl From<ServerTlsStream<TcpStream>> for TlsStream {
     }
 }
 
+fn webpki_root_store() -> rustls::RootCertStore {
+    let mut root_store = rustls::RootCertStore::empty();
+    root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    root_store
+}
+
 fn default_client_config() -> Arc<ClientConfig> {
     static CLIENT_CONFIG: Lazy<Arc<ClientConfig>> = Lazy::new(|| {
-        let mut root_store = rustls::RootCertStore::empty();
-        root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
-            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
-                ta.subject,
-                ta.spki,
-                ta.name_constraints,
-            )
-        }));
-
         let config = ClientConfig::builder()
             .with_safe_defaults()
-            .with_root_certificates(root_store)
+            .with_root_certificates(webpki_root_store())
             .with_no_client_auth();
         Arc::new(config)
     });
     CLIENT_CONFIG.clone()
 }
 
+/// Verifies the server certificate as usual, and then requires its public key to match one of
+/// the pinned SPKI hashes.
+struct PinnedVerifier {
+    inner: WebPkiVerifier,
+    spki_pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+        let spki = spki_of(&end_entity.0).ok_or_else(|| {
+            rustls::Error::InvalidCertificateData("Malformed server certificate".into())
+        })?;
+        let hash: [u8; 32] = Sha256::digest(spki).into();
+        if !self.spki_pins.contains(&hash) {
+            return Err(rustls::Error::InvalidCertificateData(
+                "Server public key does not match any pinned SPKI hash".into(),
+            ));
+        }
+        Ok(verified)
+    }
+}
+
+/// Split the first DER TLV off the input. Returns the tag, the whole TLV, its content and the
+/// rest of the input.
+fn der_next(input: &[u8]) -> Option<(u8, &[u8], &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&len, rest) = rest.split_first()?;
+    let (len, rest) = if len < 0x80 {
+        (len as usize, rest)
+    } else {
+        let n = (len & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | *b as usize);
+        (len, &rest[n..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let tlv_len = input.len() - rest.len() + len;
+    Some((tag, &input[..tlv_len], &rest[..len], &rest[len..]))
+}
+
+/// Extract the DER encoded SubjectPublicKeyInfo from a DER encoded X.509 certificate.
+fn spki_of(cert: &[u8]) -> Option<&[u8]> {
+    const TAG_SEQUENCE: u8 = 0x30;
+    const TAG_VERSION: u8 = 0xa0;
+
+    let (_, _, cert, _) = der_next(cert)?;
+    let (_, _, tbs, _) = der_next(cert)?;
+    let (tag, _, _, rest) = der_next(tbs)?;
+    let mut fields = if tag == TAG_VERSION { rest } else { tbs };
+    // Skip serialNumber, signature, issuer, validity and subject
+    for _ in 0..5 {
+        fields = der_next(fields)?.3;
+    }
+    let (tag, spki, _, _) = der_next(fields)?;
+    (tag == TAG_SEQUENCE).then_some(spki)
+}
+
+pub(crate) fn load_client_config(config: TlsClientConfig) -> Result<Arc<ClientConfig>, OcallError> {
+    let (root_certs, client_cert, alpn_protocols, spki_pins) = match config {
+        TlsClientConfig::V0 => return Ok(default_client_config()),
+        TlsClientConfig::V1 {
+            root_certs,
+            client_cert,
+            alpn_protocols,
+            spki_pins,
+        } => (root_certs, client_cert, alpn_protocols, spki_pins),
+    };
+
+    let root_store = match root_certs {
+        Some(pem) => {
+            let certs = load_certs(&pem)?;
+            if certs.is_empty() {
+                return Err(OcallError::InvalidParameter);
+            }
+            let mut root_store = rustls::RootCertStore::empty();
+            for cert in certs {
+                root_store
+                    .add(&cert)
+                    .or(Err(OcallError::InvalidParameter))?;
+            }
+            root_store
+        }
+        None => webpki_root_store(),
+    };
+
+    let builder = ClientConfig::builder().with_safe_defaults();
+    let builder = if spki_pins.is_empty() {
+        builder.with_root_certificates(root_store)
+    } else {
+        builder.with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+            inner: WebPkiVerifier::new(root_store, None),
+            spki_pins,
+        }))
+    };
+    let mut config = match client_cert {
+        Some(TlsClientCert { cert, key }) => builder
+            .with_single_cert(load_certs(&cert)?, load_private_key(&key)?)
+            .or(Err(OcallError::InvalidParameter))?,
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = alpn_protocols;
+    Ok(Arc::new(config))
+}
+
 impl TlsStream {
     pub(crate) fn accept(stream: TcpStream, config: Arc<ServerConfig>) -> TlsStream {
         let accept = TlsAcceptor::from(config).accept(stream);
         TlsStream::ServerHandshaking(accept)
     }
 
-    pub(crate) fn connect(domain: ServerName, stream: TcpStream) -> TlsStream {
-        let client_config = default_client_config();
+    pub(crate) fn connect(
+        domain: ServerName,
+        stream: TcpStream,
+        client_config: Arc<ClientConfig>,
+    ) -> TlsStream {
         let connector = TlsConnector::from(client_config);
         TlsStream::ClientHandshaking(connector.connect(domain, stream))
     }
@@ -241,3 +379,100 @@ fn load_private_key(pem_str: &str) -> Result<rustls::PrivateKey, OcallError> {
     };
     Ok(rustls::PrivateKey(key.clone()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CA: &str = include_str!("../tests/fixtures/tls/ca.pem");
+    /// A v3 certificate for example.com issued by CA, with an EC key.
+    const LEAF: &str = include_str!("../tests/fixtures/tls/leaf.pem");
+    const LEAF_SPKI: &[u8] = include_bytes!("../tests/fixtures/tls/leaf.spki.der");
+    /// A self-signed v1 certificate, with an RSA key.
+    const V1: &str = include_str!("../tests/fixtures/tls/v1.pem");
+    const V1_SPKI: &[u8] = include_bytes!("../tests/fixtures/tls/v1.spki.der");
+
+    fn cert(pem: &str) -> Certificate {
+        load_certs(pem).unwrap().remove(0)
+    }
+
+    #[test]
+    fn der_next_reads_short_and_long_form_lengths() {
+        assert_eq!(
+            der_next(&[0x04, 0x02, 1, 2, 3]),
+            Some((0x04, &[0x04, 0x02, 1, 2][..], &[1, 2][..], &[3][..]))
+        );
+
+        let content = [7u8; 0x100];
+        for header in [&[0x04, 0x81, 0xff][..], &[0x04, 0x82, 0x00, 0xff][..]] {
+            let tlv = [header, &content[..0xff]].concat();
+            let input = [&tlv[..], &[9]].concat();
+            assert_eq!(
+                der_next(&input),
+                Some((0x04, &tlv[..], &content[..0xff], &[9][..]))
+            );
+        }
+        let tlv = [&[0x04, 0x82, 0x01, 0x00][..], &content[..]].concat();
+        assert_eq!(
+            der_next(&tlv),
+            Some((0x04, &tlv[..], &content[..], &[][..]))
+        );
+    }
+
+    #[test]
+    fn der_next_rejects_bad_input() {
+        // Indefinite length and lengths longer than 4 bytes
+        assert_eq!(der_next(&[0x30, 0x80, 0, 0]), None);
+        assert_eq!(der_next(&[0x04, 0x85, 0, 0, 0, 0, 1, 0]), None);
+        // Truncated header, length or content
+        assert_eq!(der_next(&[]), None);
+        assert_eq!(der_next(&[0x04]), None);
+        assert_eq!(der_next(&[0x04, 0x82, 0x01]), None);
+        assert_eq!(der_next(&[0x04, 0x03, 1, 2]), None);
+        assert_eq!(der_next(&[0x04, 0x81, 0x80, 1]), None);
+    }
+
+    #[test]
+    fn spki_of_v1_and_v3_certificates() {
+        assert_eq!(spki_of(&cert(V1).0), Some(V1_SPKI));
+        assert_eq!(spki_of(&cert(LEAF).0), Some(LEAF_SPKI));
+    }
+
+    #[test]
+    fn spki_of_truncated_certificates() {
+        for pem in [V1, LEAF] {
+            let der = cert(pem).0;
+            for len in 0..der.len() {
+                assert_eq!(spki_of(&der[..len]), None, "truncated to {len} bytes");
+            }
+        }
+        assert_eq!(spki_of(&[0x30, 0x00]), None);
+        assert_eq!(spki_of(LEAF_SPKI), None);
+    }
+
+    fn verify(spki_pins: Vec<[u8; 32]>, name: &str) -> Result<ServerCertVerified, rustls::Error> {
+        let mut root_store = rustls::RootCertStore::empty();
+        root_store.add(&cert(CA)).unwrap();
+        let verifier = PinnedVerifier {
+            inner: WebPkiVerifier::new(root_store, None),
+            spki_pins,
+        };
+        verifier.verify_server_cert(
+            &cert(LEAF),
+            &[],
+            &ServerName::try_from(name).unwrap(),
+            &mut std::iter::empty(),
+            &[],
+            SystemTime::now(),
+        )
+    }
+
+    #[test]
+    fn pinned_verifier_checks_the_spki_hash() {
+        let pin: [u8; 32] = Sha256::digest(LEAF_SPKI).into();
+        assert!(verify(vec![[0; 32], pin], "example.com").is_ok());
+        assert!(verify(vec![[0; 32]], "example.com").is_err());
+        // A matching pin does not skip the usual verification.
+        assert!(verify(vec![pin], "example.org").is_err());
+    }
+}
@@ -0,0 +1,1061 @@
+//! The per-VM virtual filesystem behind the WASI file functions.
+//!
+//! Each sidevm instance gets its own tree of inodes, exposed to the guest as the preopened
+//! directory `/`. The file contents are either kept in memory, or sealed to a private directory
+//! on disk and only loaded while the file is open. The total size of the filesystem is limited
+//! by a quota, and everything is removed when the VM is destroyed.
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hex_fmt::HexFmt;
+use log::warn;
+use wasmer_wasi_types::wasi::{
+    Dircookie, Errno, Fd, Fdflags, Fdstat, Filesize, Filestat, Filetype, Fstflags, Inode, Oflags,
+    Rights, Timestamp, Whence,
+};
+
+use crate::VmId;
+
+type Result<T, E = Errno> = std::result::Result<T, E>;
+
+/// The fd of the preopened root directory. 0, 1 and 2 are taken by the stdio.
+pub(crate) const ROOT_FD: Fd = 3;
+const ROOT_INO: Inode = 1;
+/// Quota charged for each inode besides its content, so that the guest can not exhaust the host
+/// memory by creating lots of empty files.
+const INODE_COST: u64 = 256;
+const MAX_NAME_LEN: usize = 255;
+
+/// Seals the file contents before they are written to disk.
+pub trait SealOps {
+    fn seal(&self, data: Vec<u8>) -> Option<Vec<u8>>;
+    fn unseal(&self, data: Vec<u8>) -> Option<Vec<u8>>;
+}
+
+pub type DynSealOps = &'static (dyn SealOps + Send + Sync);
+
+/// The virtual filesystem settings of a sidevm instance.
+#[derive(Clone)]
+pub struct FsConfig {
+    /// Max number of bytes taken by the files and directories.
+    pub quota: u64,
+    /// Seal the file contents to a directory under the given path rather than keep them in
+    /// memory.
+    pub sealed: Option<(PathBuf, DynSealOps)>,
+}
+
+impl FsConfig {
+    pub fn in_memory(quota: u64) -> Self {
+        Self {
+            quota,
+            sealed: None,
+        }
+    }
+}
+
+fn now() -> Timestamp {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as _)
+        .unwrap_or_default()
+}
+
+struct FileNode {
+    /// The content of the file. None if it is sealed on disk while the file is not open.
+    data: Option<Vec<u8>>,
+    size: u64,
+    n_opened: u32,
+    /// Whether the content has been changed since it was last sealed.
+    dirty: bool,
+    /// Whether the file is still in a directory. Unlinked files are removed on the last close.
+    linked: bool,
+}
+
+struct DirNode {
+    parent: Inode,
+    entries: BTreeMap<String, Inode>,
+}
+
+enum Node {
+    File(FileNode),
+    Dir(DirNode),
+}
+
+struct InodeEntry {
+    node: Node,
+    atim: Timestamp,
+    mtim: Timestamp,
+    ctim: Timestamp,
+}
+
+impl InodeEntry {
+    fn new(node: Node) -> Self {
+        let now = now();
+        Self {
+            node,
+            atim: now,
+            mtim: now,
+            ctim: now,
+        }
+    }
+}
+
+struct OpenFd {
+    ino: Inode,
+    offset: u64,
+    flags: Fdflags,
+    rights: Rights,
+    rights_inheriting: Rights,
+}
+
+struct SealedDir {
+    path: PathBuf,
+    ops: DynSealOps,
+}
+
+pub(crate) struct Vfs {
+    quota: u64,
+    used: u64,
+    inodes: BTreeMap<Inode, InodeEntry>,
+    next_ino: Inode,
+    fds: BTreeMap<Fd, OpenFd>,
+    sealed: Option<SealedDir>,
+}
+
+impl Vfs {
+    pub(crate) fn new(id: VmId, config: FsConfig) -> Self {
+        // A restarted instance may reuse the VM id before the old directory is removed, so add
+        // a random suffix to keep them apart.
+        let sealed = config.sealed.map(|(base, ops)| SealedDir {
+            path: base.join(format!("{}-{:08x}", HexFmt(&id), rand::random::<u32>())),
+            ops,
+        });
+        let mut vfs = Self {
+            quota: config.quota,
+            used: 0,
+            inodes: Default::default(),
+            next_ino: ROOT_INO + 1,
+            fds: Default::default(),
+            sealed,
+        };
+        vfs.init_root();
+        vfs
+    }
+
+    fn init_root(&mut self) {
+        let root = DirNode {
+            parent: ROOT_INO,
+            entries: Default::default(),
+        };
+        self.inodes
+            .insert(ROOT_INO, InodeEntry::new(Node::Dir(root)));
+        self.fds.insert(
+            ROOT_FD,
+            OpenFd {
+                ino: ROOT_INO,
+                offset: 0,
+                flags: Fdflags::empty(),
+                rights: Rights::all(),
+                rights_inheriting: Rights::all(),
+            },
+        );
+    }
+
+    /// Drop all the files, including the sealed ones on disk.
+    pub(crate) fn clear(&mut self) {
+        self.fds.clear();
+        self.inodes.clear();
+        self.used = 0;
+        if let Some(sealed) = &self.sealed {
+            match std::fs::remove_dir_all(&sealed.path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => {
+                    warn!(target: "sidevm", "Failed to remove {:?}: {err}", sealed.path);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// The name of the directory preopened as `fd`.
+    pub(crate) fn prestat_name(&self, fd: Fd) -> Result<&'static str> {
+        if fd == ROOT_FD && self.fds.contains_key(&fd) {
+            Ok("/")
+        } else {
+            Err(Errno::Badf)
+        }
+    }
+
+    pub(crate) fn open(
+        &mut self,
+        dirfd: Fd,
+        path: &str,
+        oflags: Oflags,
+        rights: Rights,
+        rights_inheriting: Rights,
+        flags: Fdflags,
+    ) -> Result<Fd> {
+        let (parent, name) = self.resolve_parent(dirfd, path)?;
+        let existing = match name {
+            None => Some(parent),
+            Some(name) => self.dir(parent)?.entries.get(name).copied(),
+        };
+        let ino = match (existing, name) {
+            (Some(ino), _) => {
+                if oflags.contains(Oflags::CREATE | Oflags::EXCL) {
+                    return Err(Errno::Exist);
+                }
+                ino
+            }
+            (None, Some(name)) => {
+                if !oflags.contains(Oflags::CREATE) {
+                    return Err(Errno::Noent);
+                }
+                if oflags.contains(Oflags::DIRECTORY) {
+                    return Err(Errno::Inval);
+                }
+                self.link_new(parent, name, Node::File(FileNode::new()))?
+            }
+            (None, None) => return Err(Errno::Noent),
+        };
+        let is_dir = matches!(self.node(ino)?, Node::Dir(_));
+        if is_dir {
+            if rights.contains(Rights::FD_WRITE) || oflags.contains(Oflags::TRUNC) {
+                return Err(Errno::Isdir);
+            }
+        } else {
+            if oflags.contains(Oflags::DIRECTORY) {
+                return Err(Errno::Notdir);
+            }
+            self.acquire(ino)?;
+            if oflags.contains(Oflags::TRUNC) {
+                if let Err(err) = self.resize(ino, 0) {
+                    self.release(ino);
+                    return Err(err);
+                }
+            }
+        }
+        let fd = self.next_fd();
+        self.fds.insert(
+            fd,
+            OpenFd {
+                ino,
+                offset: 0,
+                flags,
+                rights,
+                rights_inheriting,
+            },
+        );
+        Ok(fd)
+    }
+
+    pub(crate) fn close(&mut self, fd: Fd) -> Result<()> {
+        let open = self.fds.remove(&fd).ok_or(Errno::Badf)?;
+        self.release(open.ino);
+        Ok(())
+    }
+
+    pub(crate) fn renumber(&mut self, from: Fd, to: Fd) -> Result<()> {
+        if !self.fds.contains_key(&from) {
+            return Err(Errno::Badf);
+        }
+        if from == to {
+            return Ok(());
+        }
+        if self.fds.contains_key(&to) {
+            self.close(to)?;
+        }
+        let open = self.fds.remove(&from).ok_or(Errno::Badf)?;
+        self.fds.insert(to, open);
+        Ok(())
+    }
+
+    /// Read at most `len` bytes from `offset`, or from the current position if `offset` is None.
+    pub(crate) fn read(&mut self, fd: Fd, len: usize, offset: Option<u64>) -> Result<Vec<u8>> {
+        let open = self.fds.get_mut(&fd).ok_or(Errno::Badf)?;
+        if !open.rights.contains(Rights::FD_READ) {
+            return Err(Errno::Badf);
+        }
+        let inode = self.inodes.get_mut(&open.ino).ok_or(Errno::Badf)?;
+        let data = match &inode.node {
+            Node::File(file) => file.data.as_ref().ok_or(Errno::Io)?,
+            Node::Dir(_) => return Err(Errno::Isdir),
+        };
+        let pos = offset.unwrap_or(open.offset);
+        let start = pos.min(data.len() as u64) as usize;
+        let end = start.saturating_add(len).min(data.len());
+        let buf = data[start..end].to_vec();
+        if offset.is_none() {
+            open.offset = pos + buf.len() as u64;
+        }
+        inode.atim = now();
+        Ok(buf)
+    }
+
+    /// Write `buf` at `offset`, or at the current position if `offset` is None.
+    pub(crate) fn write(&mut self, fd: Fd, buf: &[u8], offset: Option<u64>) -> Result<usize> {
+        let open = self.fds.get_mut(&fd).ok_or(Errno::Badf)?;
+        if !open.rights.contains(Rights::FD_WRITE) {
+            return Err(Errno::Badf);
+        }
+        let inode = self.inodes.get_mut(&open.ino).ok_or(Errno::Badf)?;
+        let file = match &mut inode.node {
+            Node::File(file) => file,
+            Node::Dir(_) => return Err(Errno::Isdir),
+        };
+        let pos = match offset {
+            Some(offset) => offset,
+            None if open.flags.contains(Fdflags::APPEND) => file.size,
+            None => open.offset,
+        };
+        let end = pos.checked_add(buf.len() as u64).ok_or(Errno::Fbig)?;
+        let new_size = end.max(file.size);
+        let used = self.used - file.size + new_size;
+        if used > self.quota {
+            return Err(Errno::Dquot);
+        }
+        let data = file.data.as_mut().ok_or(Errno::Io)?;
+        data.resize(new_size as usize, 0);
+        data[pos as usize..end as usize].copy_from_slice(buf);
+        file.size = new_size;
+        file.dirty = true;
+        self.used = used;
+        if offset.is_none() {
+            open.offset = end;
+        }
+        inode.mtim = now();
+        Ok(buf.len())
+    }
+
+    pub(crate) fn seek(&mut self, fd: Fd, delta: i64, whence: Whence) -> Result<u64> {
+        let open = self.fds.get_mut(&fd).ok_or(Errno::Badf)?;
+        let size = match &self.inodes.get(&open.ino).ok_or(Errno::Badf)?.node {
+            Node::File(file) => file.size,
+            Node::Dir(_) => return Err(Errno::Isdir),
+        };
+        let base = match whence {
+            Whence::Set => 0,
+            Whence::Cur => open.offset,
+            Whence::End => size,
+        };
+        let pos = (base as i64)
+            .checked_add(delta)
+            .filter(|pos| *pos >= 0)
+            .ok_or(Errno::Inval)?;
+        open.offset = pos as u64;
+        Ok(open.offset)
+    }
+
+    pub(crate) fn tell(&self, fd: Fd) -> Result<u64> {
+        Ok(self.fds.get(&fd).ok_or(Errno::Badf)?.offset)
+    }
+
+    pub(crate) fn fdstat(&self, fd: Fd) -> Result<Fdstat> {
+        let open = self.fds.get(&fd).ok_or(Errno::Badf)?;
+        Ok(Fdstat {
+            fs_filetype: self.filetype(open.ino)?,
+            fs_flags: open.flags,
+            fs_rights_base: open.rights,
+            fs_rights_inheriting: open.rights_inheriting,
+        })
+    }
+
+    pub(crate) fn set_fdflags(&mut self, fd: Fd, flags: Fdflags) -> Result<()> {
+        self.fds.get_mut(&fd).ok_or(Errno::Badf)?.flags = flags;
+        Ok(())
+    }
+
+    pub(crate) fn fd_filestat(&self, fd: Fd) -> Result<Filestat> {
+        self.filestat(self.fd_ino(fd)?)
+    }
+
+    pub(crate) fn path_filestat(&self, dirfd: Fd, path: &str) -> Result<Filestat> {
+        self.filestat(self.resolve(dirfd, path)?)
+    }
+
+    pub(crate) fn set_size(&mut self, fd: Fd, size: Filesize) -> Result<()> {
+        let open = self.fds.get(&fd).ok_or(Errno::Badf)?;
+        if !open.rights.contains(Rights::FD_WRITE) {
+            return Err(Errno::Badf);
+        }
+        let ino = open.ino;
+        self.resize(ino, size)
+    }
+
+    pub(crate) fn allocate(&mut self, fd: Fd, offset: Filesize, len: Filesize) -> Result<()> {
+        let ino = self.fd_ino(fd)?;
+        let end = offset.checked_add(len).ok_or(Errno::Fbig)?;
+        let size = match self.node(ino)? {
+            Node::File(file) => file.size,
+            Node::Dir(_) => return Err(Errno::Isdir),
+        };
+        if end > size {
+            self.resize(ino, end)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn sync(&mut self, fd: Fd) -> Result<()> {
+        let ino = self.fd_ino(fd)?;
+        self.flush(ino)
+    }
+
+    pub(crate) fn fd_set_times(
+        &mut self,
+        fd: Fd,
+        atim: Timestamp,
+        mtim: Timestamp,
+        flags: Fstflags,
+    ) -> Result<()> {
+        let ino = self.fd_ino(fd)?;
+        self.set_times(ino, atim, mtim, flags)
+    }
+
+    pub(crate) fn path_set_times(
+        &mut self,
+        dirfd: Fd,
+        path: &str,
+        atim: Timestamp,
+        mtim: Timestamp,
+        flags: Fstflags,
+    ) -> Result<()> {
+        let ino = self.resolve(dirfd, path)?;
+        self.set_times(ino, atim, mtim, flags)
+    }
+
+    /// List the directory from the entry at `cookie`.
+    ///
+    /// Returns the cookie of the next entry, the inode, the type and the name of each entry.
+    pub(crate) fn readdir(
+        &self,
+        fd: Fd,
+        cookie: Dircookie,
+    ) -> Result<Vec<(Dircookie, Inode, Filetype, String)>> {
+        let ino = self.fd_ino(fd)?;
+        let dir = self.dir(ino)?;
+        let dots = [(".", ino), ("..", dir.parent)];
+        let entries = dots
+            .into_iter()
+            .chain(dir.entries.iter().map(|(name, ino)| (name.as_str(), *ino)));
+        entries
+            .enumerate()
+            .skip(cookie as usize)
+            .map(|(i, (name, ino))| Ok((i as u64 + 1, ino, self.filetype(ino)?, name.into())))
+            .collect()
+    }
+
+    pub(crate) fn create_dir(&mut self, dirfd: Fd, path: &str) -> Result<()> {
+        let (parent, name) = self.resolve_parent(dirfd, path)?;
+        let name = name.ok_or(Errno::Exist)?;
+        if self.dir(parent)?.entries.contains_key(name) {
+            return Err(Errno::Exist);
+        }
+        let dir = DirNode {
+            parent,
+            entries: Default::default(),
+        };
+        self.link_new(parent, name, Node::Dir(dir))?;
+        Ok(())
+    }
+
+    pub(crate) fn remove_dir(&mut self, dirfd: Fd, path: &str) -> Result<()> {
+        let (parent, name) = self.resolve_parent(dirfd, path)?;
+        let name = name.ok_or(Errno::Inval)?;
+        let ino = self.lookup(parent, name)?;
+        match self.node(ino)? {
+            Node::Dir(dir) if !dir.entries.is_empty() => return Err(Errno::Notempty),
+            Node::Dir(_) => (),
+            Node::File(_) => return Err(Errno::Notdir),
+        }
+        self.unlink(parent, name);
+        self.free_inode(ino);
+        Ok(())
+    }
+
+    pub(crate) fn unlink_file(&mut self, dirfd: Fd, path: &str) -> Result<()> {
+        let (parent, name) = self.resolve_parent(dirfd, path)?;
+        let name = name.ok_or(Errno::Isdir)?;
+        let ino = self.lookup(parent, name)?;
+        if let Node::Dir(_) = self.node(ino)? {
+            return Err(Errno::Isdir);
+        }
+        self.unlink(parent, name);
+        self.drop_link(ino);
+        Ok(())
+    }
+
+    pub(crate) fn rename(
+        &mut self,
+        old_dirfd: Fd,
+        old_path: &str,
+        new_dirfd: Fd,
+        new_path: &str,
+    ) -> Result<()> {
+        let (old_parent, old_name) = self.resolve_parent(old_dirfd, old_path)?;
+        let (new_parent, new_name) = self.resolve_parent(new_dirfd, new_path)?;
+        let old_name = old_name.ok_or(Errno::Inval)?;
+        let new_name = new_name.ok_or(Errno::Inval)?;
+        let ino = self.lookup(old_parent, old_name)?;
+        let src_is_dir = matches!(self.node(ino)?, Node::Dir(_));
+        let replaced = self.dir(new_parent)?.entries.get(new_name).copied();
+        if let Some(dst) = replaced {
+            if dst == ino {
+                return Ok(());
+            }
+            match (src_is_dir, self.node(dst)?) {
+                (true, Node::Dir(dir)) if !dir.entries.is_empty() => return Err(Errno::Notempty),
+                (true, Node::File(_)) => return Err(Errno::Notdir),
+                (false, Node::Dir(_)) => return Err(Errno::Isdir),
+                _ => (),
+            }
+        }
+        if src_is_dir {
+            // Moving a directory into its own subtree would detach it from the root.
+            let mut cur = new_parent;
+            while cur != ROOT_INO {
+                if cur == ino {
+                    return Err(Errno::Inval);
+                }
+                cur = self.dir(cur)?.parent;
+            }
+        }
+        self.unlink(old_parent, old_name);
+        if let Some(dst) = replaced {
+            self.unlink(new_parent, new_name);
+            self.drop_link(dst);
+        }
+        self.dir_mut(new_parent)?
+            .entries
+            .insert(new_name.into(), ino);
+        if let Some(Node::Dir(dir)) = self.inodes.get_mut(&ino).map(|inode| &mut inode.node) {
+            dir.parent = new_parent;
+        }
+        Ok(())
+    }
+
+    fn next_fd(&self) -> Fd {
+        let mut fd = ROOT_FD + 1;
+        for used in self.fds.keys().filter(|used| **used >= fd) {
+            if *used != fd {
+                break;
+            }
+            fd += 1;
+        }
+        fd
+    }
+
+    fn fd_ino(&self, fd: Fd) -> Result<Inode> {
+        Ok(self.fds.get(&fd).ok_or(Errno::Badf)?.ino)
+    }
+
+    fn node(&self, ino: Inode) -> Result<&Node> {
+        Ok(&self.inodes.get(&ino).ok_or(Errno::Noent)?.node)
+    }
+
+    fn dir(&self, ino: Inode) -> Result<&DirNode> {
+        match self.node(ino)? {
+            Node::Dir(dir) => Ok(dir),
+            Node::File(_) => Err(Errno::Notdir),
+        }
+    }
+
+    fn dir_mut(&mut self, ino: Inode) -> Result<&mut DirNode> {
+        match self.inodes.get_mut(&ino).map(|inode| &mut inode.node) {
+            Some(Node::Dir(dir)) => Ok(dir),
+            Some(Node::File(_)) => Err(Errno::Notdir),
+            None => Err(Errno::Noent),
+        }
+    }
+
+    fn lookup(&self, dir: Inode, name: &str) -> Result<Inode> {
+        self.dir(dir)?
+            .entries
+            .get(name)
+            .copied()
+            .ok_or(Errno::Noent)
+    }
+
+    fn filetype(&self, ino: Inode) -> Result<Filetype> {
+        Ok(match self.node(ino)? {
+            Node::File(_) => Filetype::RegularFile,
+            Node::Dir(_) => Filetype::Directory,
+        })
+    }
+
+    fn filestat(&self, ino: Inode) -> Result<Filestat> {
+        let inode = self.inodes.get(&ino).ok_or(Errno::Noent)?;
+        let (st_filetype, st_nlink, st_size) = match &inode.node {
+            Node::File(file) => (Filetype::RegularFile, file.linked as u64, file.size),
+            Node::Dir(_) => (Filetype::Directory, 1, 0),
+        };
+        Ok(Filestat {
+            st_dev: 0,
+            st_ino: ino,
+            st_filetype,
+            st_nlink,
+            st_size,
+            st_atim: inode.atim,
+            st_mtim: inode.mtim,
+            st_ctim: inode.ctim,
+        })
+    }
+
+    /// Resolve `path` relative to the directory opened as `dirfd`.
+    ///
+    /// Returns the directory containing the target and the name of the target in it. The name is
+    /// None if the path refers to the directory itself, like `.` or `a/..`.
+    fn resolve_parent<'p>(&self, dirfd: Fd, path: &'p str) -> Result<(Inode, Option<&'p str>)> {
+        if path.is_empty() {
+            return Err(Errno::Noent);
+        }
+        let mut cur = if path.starts_with('/') {
+            ROOT_INO
+        } else {
+            self.fd_ino(dirfd)?
+        };
+        self.dir(cur)?;
+        let mut components = path
+            .split('/')
+            .filter(|c| !c.is_empty() && *c != ".")
+            .peekable();
+        while let Some(name) = components.next() {
+            if name == ".." {
+                // There is nothing above the root, so `..` can never escape the sandbox.
+                cur = self.dir(cur)?.parent;
+                continue;
+            }
+            if components.peek().is_none() {
+                if name.len() > MAX_NAME_LEN {
+                    return Err(Errno::Nametoolong);
+                }
+                return Ok((cur, Some(name)));
+            }
+            cur = self.lookup(cur, name)?;
+            self.dir(cur)?;
+        }
+        Ok((cur, None))
+    }
+
+    fn resolve(&self, dirfd: Fd, path: &str) -> Result<Inode> {
+        match self.resolve_parent(dirfd, path)? {
+            (dir, None) => Ok(dir),
+            (dir, Some(name)) => self.lookup(dir, name),
+        }
+    }
+
+    fn link_new(&mut self, parent: Inode, name: &str, node: Node) -> Result<Inode> {
+        self.dir(parent)?;
+        if self.used + INODE_COST > self.quota {
+            return Err(Errno::Dquot);
+        }
+        self.used += INODE_COST;
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.inodes.insert(ino, InodeEntry::new(node));
+        let parent = self.inodes.get_mut(&parent).ok_or(Errno::Noent)?;
+        if let Node::Dir(dir) = &mut parent.node {
+            dir.entries.insert(name.into(), ino);
+        }
+        parent.mtim = now();
+        Ok(ino)
+    }
+
+    fn unlink(&mut self, parent: Inode, name: &str) {
+        if let Some(parent) = self.inodes.get_mut(&parent) {
+            if let Node::Dir(dir) = &mut parent.node {
+                dir.entries.remove(name);
+            }
+            parent.mtim = now();
+        }
+    }
+
+    /// Drop a file removed from its directory, or defer it to the last close if it is open.
+    fn drop_link(&mut self, ino: Inode) {
+        match self.inodes.get_mut(&ino).map(|inode| &mut inode.node) {
+            Some(Node::File(file)) if file.n_opened > 0 => file.linked = false,
+            _ => self.free_inode(ino),
+        }
+    }
+
+    fn free_inode(&mut self, ino: Inode) {
+        let inode = match self.inodes.remove(&ino) {
+            Some(inode) => inode,
+            None => return,
+        };
+        let size = match inode.node {
+            Node::File(file) => file.size,
+            Node::Dir(_) => 0,
+        };
+        self.used -= size + INODE_COST;
+        if let Some(sealed) = &self.sealed {
+            let _ = std::fs::remove_file(sealed.path.join(ino.to_string()));
+        }
+    }
+
+    fn resize(&mut self, ino: Inode, size: Filesize) -> Result<()> {
+        let inode = self.inodes.get_mut(&ino).ok_or(Errno::Badf)?;
+        let file = match &mut inode.node {
+            Node::File(file) => file,
+            Node::Dir(_) => return Err(Errno::Isdir),
+        };
+        let used = self.used - file.size + size;
+        if size > file.size && used > self.quota {
+            return Err(Errno::Dquot);
+        }
+        file.data
+            .as_mut()
+            .ok_or(Errno::Io)?
+            .resize(size as usize, 0);
+        file.size = size;
+        file.dirty = true;
+        self.used = used;
+        inode.mtim = now();
+        Ok(())
+    }
+
+    fn set_times(
+        &mut self,
+        ino: Inode,
+        atim: Timestamp,
+        mtim: Timestamp,
+        flags: Fstflags,
+    ) -> Result<()> {
+        let inode = self.inodes.get_mut(&ino).ok_or(Errno::Badf)?;
+        if flags.contains(Fstflags::SET_ATIM_NOW) {
+            inode.atim = now();
+        } else if flags.contains(Fstflags::SET_ATIM) {
+            inode.atim = atim;
+        }
+        if flags.contains(Fstflags::SET_MTIM_NOW) {
+            inode.mtim = now();
+        } else if flags.contains(Fstflags::SET_MTIM) {
+            inode.mtim = mtim;
+        }
+        Ok(())
+    }
+
+    /// Make sure the content of the file is loaded, and count the new opener.
+    fn acquire(&mut self, ino: Inode) -> Result<()> {
+        let file = match self.inodes.get_mut(&ino).map(|inode| &mut inode.node) {
+            Some(Node::File(file)) => file,
+            _ => return Ok(()),
+        };
+        if file.data.is_none() {
+            let sealed = self.sealed.as_ref().ok_or(Errno::Io)?;
+            let data = std::fs::read(sealed.path.join(ino.to_string())).or(Err(Errno::Io))?;
+            file.data = Some(sealed.ops.unseal(data).ok_or(Errno::Io)?);
+        }
+        file.n_opened += 1;
+        Ok(())
+    }
+
+    /// Undo an `acquire`, sealing the content of the file to disk on the last close.
+    fn release(&mut self, ino: Inode) {
+        let file = match self.inodes.get_mut(&ino).map(|inode| &mut inode.node) {
+            Some(Node::File(file)) => file,
+            _ => return,
+        };
+        file.n_opened = file.n_opened.saturating_sub(1);
+        if file.n_opened > 0 {
+            return;
+        }
+        if !file.linked {
+            self.free_inode(ino);
+            return;
+        }
+        if self.sealed.is_none() {
+            return;
+        }
+        match self.flush(ino) {
+            Ok(()) => {
+                if let Some(Node::File(file)) =
+                    self.inodes.get_mut(&ino).map(|inode| &mut inode.node)
+                {
+                    file.data = None;
+                }
+            }
+            // Keep the content in memory so that it is not lost.
+            Err(_) => warn!(target: "sidevm", "Failed to seal file {ino} to disk"),
+        }
+    }
+
+    /// Seal the content of the file to disk if it has been changed.
+    fn flush(&mut self, ino: Inode) -> Result<()> {
+        let sealed = match &self.sealed {
+            Some(sealed) => sealed,
+            None => return Ok(()),
+        };
+        let file = match self.inodes.get_mut(&ino).map(|inode| &mut inode.node) {
+            Some(Node::File(file)) => file,
+            _ => return Ok(()),
+        };
+        if !file.dirty {
+            return Ok(());
+        }
+        let data = file.data.clone().ok_or(Errno::Io)?;
+        let sealed_data = sealed.ops.seal(data).ok_or(Errno::Io)?;
+        std::fs::create_dir_all(&sealed.path).or(Err(Errno::Io))?;
+        std::fs::write(sealed.path.join(ino.to_string()), sealed_data).or(Err(Errno::Io))?;
+        file.dirty = false;
+        Ok(())
+    }
+}
+
+impl FileNode {
+    fn new() -> Self {
+        Self {
+            data: Some(vec![]),
+            size: 0,
+            n_opened: 0,
+            dirty: true,
+            linked: true,
+        }
+    }
+}
+
+impl Drop for Vfs {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_vfs(quota: u64) -> Vfs {
+        Vfs::new([0; 32], FsConfig::in_memory(quota))
+    }
+
+    fn open(vfs: &mut Vfs, path: &str, oflags: Oflags) -> Result<Fd> {
+        let rights = Rights::FD_READ | Rights::FD_WRITE;
+        vfs.open(
+            ROOT_FD,
+            path,
+            oflags,
+            rights,
+            Rights::empty(),
+            Fdflags::empty(),
+        )
+    }
+
+    fn create(vfs: &mut Vfs, path: &str) -> Fd {
+        open(vfs, path, Oflags::CREATE).unwrap()
+    }
+
+    fn ino_of(vfs: &Vfs, path: &str) -> Result<Inode> {
+        vfs.path_filestat(ROOT_FD, path).map(|stat| stat.st_ino)
+    }
+
+    #[test]
+    fn quota_covers_inodes_and_contents() {
+        let mut vfs = new_vfs(INODE_COST + 10);
+        let fd = create(&mut vfs, "a");
+        assert_eq!(vfs.used, INODE_COST);
+
+        assert_eq!(vfs.write(fd, &[1; 10], None), Ok(10));
+        assert_eq!(vfs.used, INODE_COST + 10);
+        assert_eq!(vfs.write(fd, &[1], None), Err(Errno::Dquot));
+        assert_eq!(vfs.set_size(fd, 11), Err(Errno::Dquot));
+        assert_eq!(vfs.create_dir(ROOT_FD, "d"), Err(Errno::Dquot));
+        assert_eq!(open(&mut vfs, "b", Oflags::CREATE), Err(Errno::Dquot));
+
+        // Overwriting does not take more space.
+        assert_eq!(vfs.write(fd, &[2; 5], Some(5)), Ok(5));
+        assert_eq!(vfs.used, INODE_COST + 10);
+
+        assert_eq!(vfs.set_size(fd, 4), Ok(()));
+        assert_eq!(vfs.used, INODE_COST + 4);
+
+        vfs.close(fd).unwrap();
+        vfs.unlink_file(ROOT_FD, "a").unwrap();
+        assert_eq!(vfs.used, 0);
+        assert_eq!(vfs.create_dir(ROOT_FD, "d"), Ok(()));
+        assert_eq!(vfs.used, INODE_COST);
+        assert_eq!(vfs.remove_dir(ROOT_FD, "d"), Ok(()));
+        assert_eq!(vfs.used, 0);
+    }
+
+    #[test]
+    fn dotdot_stops_at_the_root() {
+        let mut vfs = new_vfs(1 << 20);
+        assert_eq!(ino_of(&vfs, ".."), Ok(ROOT_INO));
+        assert_eq!(ino_of(&vfs, "/../.."), Ok(ROOT_INO));
+
+        let fd = create(&mut vfs, "../../a");
+        vfs.close(fd).unwrap();
+        let a = ino_of(&vfs, "a").unwrap();
+        assert_eq!(ino_of(&vfs, "/a"), Ok(a));
+
+        vfs.create_dir(ROOT_FD, "d").unwrap();
+        assert_eq!(ino_of(&vfs, "d/../../../a"), Ok(a));
+
+        let entries = vfs.readdir(ROOT_FD, 0).unwrap();
+        assert_eq!(entries[1].1, ROOT_INO);
+        assert_eq!(entries[1].3, "..");
+    }
+
+    #[test]
+    fn unlinking_an_open_file_is_deferred_to_the_last_close() {
+        let mut vfs = new_vfs(1 << 20);
+        let fd = create(&mut vfs, "a");
+        let fd2 = open(&mut vfs, "a", Oflags::empty()).unwrap();
+        vfs.write(fd, b"hello", None).unwrap();
+        let ino = vfs.fds[&fd].ino;
+
+        vfs.unlink_file(ROOT_FD, "a").unwrap();
+        assert_eq!(ino_of(&vfs, "a"), Err(Errno::Noent));
+        assert_eq!(vfs.read(fd2, 100, Some(0)), Ok(b"hello".to_vec()));
+        assert_eq!(vfs.fd_filestat(fd).map(|stat| stat.st_nlink), Ok(0));
+        assert_eq!(vfs.used, INODE_COST + 5);
+
+        // A new file can take the name while the old one is still open.
+        let fd3 = create(&mut vfs, "a");
+        assert_ne!(vfs.fds[&fd3].ino, ino);
+        vfs.close(fd3).unwrap();
+
+        vfs.close(fd).unwrap();
+        assert!(vfs.inodes.contains_key(&ino));
+        vfs.close(fd2).unwrap();
+        assert!(!vfs.inodes.contains_key(&ino));
+        assert_eq!(vfs.used, INODE_COST);
+    }
+
+    #[test]
+    fn rename_can_not_move_a_dir_into_its_own_subtree() {
+        let mut vfs = new_vfs(1 << 20);
+        vfs.create_dir(ROOT_FD, "a").unwrap();
+        vfs.create_dir(ROOT_FD, "a/b").unwrap();
+        let a = ino_of(&vfs, "a").unwrap();
+
+        assert_eq!(vfs.rename(ROOT_FD, "a", ROOT_FD, "a/c"), Err(Errno::Inval));
+        assert_eq!(
+            vfs.rename(ROOT_FD, "a", ROOT_FD, "a/b/c"),
+            Err(Errno::Inval)
+        );
+        assert_eq!(ino_of(&vfs, "a"), Ok(a));
+
+        assert_eq!(vfs.rename(ROOT_FD, "a/b", ROOT_FD, "c"), Ok(()));
+        assert_eq!(vfs.rename(ROOT_FD, "a", ROOT_FD, "c/a"), Ok(()));
+        assert_eq!(ino_of(&vfs, "c/a"), Ok(a));
+        assert_eq!(ino_of(&vfs, "c/a/.."), ino_of(&vfs, "c"));
+    }
+
+    #[test]
+    fn dir_errors() {
+        let mut vfs = new_vfs(1 << 20);
+        vfs.create_dir(ROOT_FD, "a").unwrap();
+        vfs.create_dir(ROOT_FD, "a/b").unwrap();
+        vfs.create_dir(ROOT_FD, "e").unwrap();
+        let fd = create(&mut vfs, "f");
+        vfs.close(fd).unwrap();
+
+        // Notempty
+        assert_eq!(vfs.remove_dir(ROOT_FD, "a"), Err(Errno::Notempty));
+        assert_eq!(vfs.rename(ROOT_FD, "e", ROOT_FD, "a"), Err(Errno::Notempty));
+
+        // Isdir
+        assert_eq!(vfs.unlink_file(ROOT_FD, "a"), Err(Errno::Isdir));
+        assert_eq!(vfs.rename(ROOT_FD, "f", ROOT_FD, "e"), Err(Errno::Isdir));
+        assert_eq!(open(&mut vfs, "a", Oflags::empty()), Err(Errno::Isdir));
+        assert_eq!(
+            vfs.open(
+                ROOT_FD,
+                "a",
+                Oflags::TRUNC,
+                Rights::FD_READ,
+                Rights::empty(),
+                Fdflags::empty()
+            ),
+            Err(Errno::Isdir)
+        );
+        let dirfd = vfs
+            .open(
+                ROOT_FD,
+                "a",
+                Oflags::DIRECTORY,
+                Rights::FD_READ,
+                Rights::empty(),
+                Fdflags::empty(),
+            )
+            .unwrap();
+        assert_eq!(vfs.read(dirfd, 1, None), Err(Errno::Isdir));
+
+        // Notdir
+        assert_eq!(vfs.remove_dir(ROOT_FD, "f"), Err(Errno::Notdir));
+        assert_eq!(vfs.rename(ROOT_FD, "e", ROOT_FD, "f"), Err(Errno::Notdir));
+        assert_eq!(open(&mut vfs, "f", Oflags::DIRECTORY), Err(Errno::Notdir));
+        assert_eq!(vfs.create_dir(ROOT_FD, "f/g"), Err(Errno::Notdir));
+
+        // The failed operations changed nothing.
+        assert!(ino_of(&vfs, "a/b").is_ok());
+        assert!(ino_of(&vfs, "e").is_ok());
+        assert!(ino_of(&vfs, "f").is_ok());
+    }
+
+    struct XorSeal;
+
+    impl SealOps for XorSeal {
+        fn seal(&self, data: Vec<u8>) -> Option<Vec<u8>> {
+            Some(data.into_iter().map(|b| b ^ 0x5a).collect())
+        }
+
+        fn unseal(&self, data: Vec<u8>) -> Option<Vec<u8>> {
+            self.seal(data)
+        }
+    }
+
+    #[test]
+    fn files_are_sealed_on_the_last_close() {
+        static SEAL: XorSeal = XorSeal;
+        let base = std::env::temp_dir().join(format!("sidevm-vfs-{:08x}", rand::random::<u32>()));
+        let config = FsConfig {
+            quota: 1 << 20,
+            sealed: Some((base.clone(), &SEAL)),
+        };
+        let mut vfs = Vfs::new([0; 32], config);
+        let dir = vfs.sealed.as_ref().unwrap().path.clone();
+
+        let fd = create(&mut vfs, "a");
+        let fd2 = open(&mut vfs, "a", Oflags::empty()).unwrap();
+        let ino = vfs.fds[&fd].ino;
+        vfs.write(fd, b"secret", None).unwrap();
+        vfs.close(fd).unwrap();
+        assert!(!dir.join(ino.to_string()).exists(), "sealed while open");
+
+        vfs.close(fd2).unwrap();
+        let on_disk = std::fs::read(dir.join(ino.to_string())).unwrap();
+        assert_eq!(on_disk, SEAL.seal(b"secret".to_vec()).unwrap());
+        assert!(matches!(
+            &vfs.inodes[&ino].node,
+            Node::File(FileNode { data: None, .. })
+        ));
+
+        let fd = open(&mut vfs, "a", Oflags::empty()).unwrap();
+        assert_eq!(vfs.read(fd, 100, None), Ok(b"secret".to_vec()));
+        vfs.close(fd).unwrap();
+
+        // A file failing to unseal can not be opened.
+        struct FailingSeal;
+        impl SealOps for FailingSeal {
+            fn seal(&self, _data: Vec<u8>) -> Option<Vec<u8>> {
+                None
+            }
+
+            fn unseal(&self, _data: Vec<u8>) -> Option<Vec<u8>> {
+                None
+            }
+        }
+        static FAILING: FailingSeal = FailingSeal;
+        vfs.sealed.as_mut().unwrap().ops = &FAILING;
+        assert_eq!(open(&mut vfs, "a", Oflags::empty()), Err(Errno::Io));
+
+        vfs.unlink_file(ROOT_FD, "a").unwrap();
+        assert!(!dir.join(ino.to_string()).exists());
+        drop(vfs);
+        assert!(!dir.exists());
+        let _ = std::fs::remove_dir_all(base);
+    }
+}
@@ -1,8 +1,10 @@
-use sidevm_host_runtime::{CacheOps, DynCacheOps, OcallError};
+use sidevm_host_runtime::{
+    CacheOps, DynCacheOps, DynStoreOps, LocalCacheStats, OcallError, StoreOps,
+};
 
 use clap::Parser;
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::RwLock;
 
 mod web_api;
@@ -15,12 +17,15 @@ pub struct Args {
     gas_per_breath: u64,
     #[arg(long, default_value_t = 1)]
     workers: usize,
+    /// Max number of bytes in the virtual filesystem of each instance.
+    #[arg(long, default_value_t = 16 * 1024 * 1024)]
+    fs_quota: u64,
     /// The WASM program to run
     program: Option<String>,
 }
 
 fn simple_cache() -> DynCacheOps {
-    static CACHE: Lazy<RwLock<HashMap<Vec<u8>, Vec<u8>>>> = Lazy::new(Default::default);
+    static CACHE: Lazy<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>> = Lazy::new(Default::default);
     struct Ops;
     type OpResult<T> = Result<T, OcallError>;
     impl CacheOps for Ops {
@@ -50,6 +55,95 @@ fn simple_cache() -> DynCacheOps {
             let value = cache.remove(key);
             Ok(value)
         }
+
+        fn scan(
+            &self,
+            _contract: &[u8],
+            prefix: &[u8],
+            start_after: Option<&[u8]>,
+            limit: u32,
+        ) -> OpResult<Vec<(Vec<u8>, Vec<u8>)>> {
+            let cache = CACHE.read().unwrap();
+            let entries = cache
+                .range(prefix.to_vec()..)
+                .take_while(|(k, _)| k.starts_with(prefix))
+                .filter(|(k, _)| Some(k.as_slice()) > start_after)
+                .take(limit.min(100) as _)
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            Ok(entries)
+        }
+
+        fn compare_and_swap(
+            &self,
+            _contract: &[u8],
+            key: &[u8],
+            expected: Option<&[u8]>,
+            value: Option<&[u8]>,
+        ) -> OpResult<bool> {
+            let mut cache = CACHE.write().unwrap();
+            if cache.get(key).map(Vec::as_slice) != expected {
+                return Ok(false);
+            }
+            match value {
+                Some(value) => cache.insert(key.to_vec(), value.to_vec()),
+                None => cache.remove(key),
+            };
+            Ok(true)
+        }
+
+        fn increment(&self, _contract: &[u8], key: &[u8], delta: i64) -> OpResult<i64> {
+            let mut cache = CACHE.write().unwrap();
+            let current = match cache.get(key) {
+                Some(value) => i64::from_le_bytes(
+                    value
+                        .as_slice()
+                        .try_into()
+                        .or(Err(OcallError::InvalidParameter))?,
+                ),
+                None => 0,
+            };
+            let value = current
+                .checked_add(delta)
+                .ok_or(OcallError::InvalidParameter)?;
+            cache.insert(key.to_vec(), value.to_le_bytes().to_vec());
+            Ok(value)
+        }
+
+        fn stats(&self, _contract: &[u8]) -> OpResult<LocalCacheStats> {
+            let cache = CACHE.read().unwrap();
+            Ok(LocalCacheStats {
+                keys: cache.len() as _,
+                size: cache.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>() as _,
+                quota: u64::MAX,
+            })
+        }
+    }
+    &Ops
+}
+
+fn simple_store() -> DynStoreOps {
+    static STORE: Lazy<RwLock<HashMap<Vec<u8>, Vec<u8>>>> = Lazy::new(Default::default);
+    struct Ops;
+    type OpResult<T> = Result<T, OcallError>;
+    impl StoreOps for Ops {
+        fn get(&self, _contract: &[u8], key: &[u8]) -> OpResult<Option<Vec<u8>>> {
+            let store = STORE.read().unwrap();
+            let value = store.get(key).cloned();
+            Ok(value)
+        }
+
+        fn set(&self, _contract: &[u8], key: &[u8], value: &[u8]) -> OpResult<()> {
+            let mut store = STORE.write().unwrap();
+            store.insert(key.to_vec(), value.to_vec());
+            Ok(())
+        }
+
+        fn remove(&self, _contract: &[u8], key: &[u8]) -> OpResult<Option<Vec<u8>>> {
+            let mut store = STORE.write().unwrap();
+            let value = store.remove(key);
+            Ok(value)
+        }
     }
     &Ops
 }
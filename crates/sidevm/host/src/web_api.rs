@@ -67,6 +67,10 @@ impl App {
                 vmid,
                 inner.args.gas_per_breath,
                 crate::simple_cache(),
+                crate::simple_store(),
+                sidevm_host_runtime::FsConfig::in_memory(inner.args.fs_quota),
+                Default::default(),
+                None,
                 weight,
             )
             .unwrap();
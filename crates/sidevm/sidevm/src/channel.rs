@@ -27,6 +27,9 @@ pub struct Query {
 /// A message from ink! to the side VM.
 pub type GeneralMessage = Vec<u8>;
 
+/// A snapshot saved via `ocall::save_snapshot`.
+pub type Snapshot = Vec<u8>;
+
 /// Sender end of a oneshot channel connected to host-side.
 pub struct OneshotSender {
     res_id: ResourceId,
@@ -151,3 +154,13 @@ pub fn incoming_system_messages() -> &'static Receiver<SystemMessage> {
 pub fn incoming_queries() -> &'static Receiver<Query> {
     singleton_channel!(Query)
 }
+
+/// The snapshot saved via `ocall::save_snapshot` by the previous run of this instance.
+///
+/// Yields the snapshot, if any, and then None. Programs that want to keep their state across
+/// restarts of the worker should save a snapshot from time to time and restore from it here
+/// on startup. The worker only keeps the snapshots taken into its checkpoints, so the state
+/// saved since the last checkpoint is lost when the worker restarts.
+pub fn restored_snapshot() -> &'static Receiver<Snapshot> {
+    singleton_channel!(Snapshot)
+}
@@ -0,0 +1,54 @@
+//! Interact with the pink contract this instance is attached to.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::env::{self, OcallError, Result};
+use crate::{ocall, ResourceId};
+
+/// Future returned by `query`.
+#[derive(Debug)]
+pub struct Query {
+    res: Result<ResourceId>,
+}
+
+/// Query the contract with an ink message, the contract itself being the origin.
+///
+/// Resolves to the reply, encoded the same way as the replies of the contract query RPC.
+pub fn query(payload: &[u8]) -> Query {
+    let res = ocall::query_contract(payload).map(ResourceId);
+    Query { res }
+}
+
+impl Future for Query {
+    type Output = Result<Vec<u8>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let res_id = match &self.get_mut().res {
+            Ok(res_id) => res_id,
+            Err(err) => return Poll::Ready(Err(*err)),
+        };
+        match ocall::poll(env::tasks::intern_waker(cx.waker().clone()), res_id.0) {
+            Ok(reply) => Poll::Ready(Ok(reply)),
+            Err(OcallError::Pending) => Poll::Pending,
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+}
+
+/// Send an ink message to the contract as a command, the contract itself being the origin.
+///
+/// The command is executed once in the cluster for each `dedup_key`, no matter how many workers
+/// run this program. See `ocall::send_contract_command` for details.
+pub fn send_command(dedup_key: &[u8], message: &[u8]) -> Result<()> {
+    ocall::send_contract_command(dedup_key, message)
+}
+
+/// Emit an mq message under the origin of the contract.
+///
+/// The message is emitted once in the cluster for each `dedup_key`, no matter how many workers
+/// run this program. See `ocall::emit_message` for details.
+pub fn emit_message(dedup_key: &[u8], topic: &[u8], payload: &[u8]) -> Result<()> {
+    ocall::emit_message(dedup_key.into(), topic.into(), payload.into())
+}
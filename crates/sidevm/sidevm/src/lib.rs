@@ -13,6 +13,7 @@ pub use env::spawn;
 pub use env::tasks as task;
 
 pub mod channel;
+pub mod contract;
 pub mod net;
 pub mod time;
 pub mod exec;
@@ -2,11 +2,12 @@
 
 use std::future::Future;
 use std::io::Error;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use env::tls::TlsServerConfig;
+use env::tls::{TlsClientConfig, TlsServerConfig};
+use scale::Decode;
 
 use crate::env::{self, tasks, Result};
 use crate::{ocall, ResourceId};
@@ -28,6 +29,18 @@ pub struct TcpStream {
     res_id: ResourceId,
 }
 
+/// A UDP socket.
+#[derive(Debug)]
+pub struct UdpSocket {
+    res_id: ResourceId,
+}
+
+/// Future returned by `resolve`.
+#[derive(Debug)]
+pub struct Resolver {
+    res: Result<ResourceId, env::OcallError>,
+}
+
 /// Future returned by `TcpListener::accept`.
 pub struct Acceptor<'a> {
     listener: &'a TcpListener,
@@ -101,13 +114,113 @@ impl TcpStream {
     /// Initiate a TCP connection to a remote host.
     pub fn connect(host: &str, port: u16, enable_tls: bool) -> TcpConnector {
         let res = if enable_tls {
-            ocall::tcp_connect_tls(host.into(), port, env::tls::TlsClientConfig::V0)
+            ocall::tcp_connect_tls(host.into(), port, TlsClientConfig::V0)
         } else {
             ocall::tcp_connect(host, port)
         };
         let res = res.map(ResourceId);
         TcpConnector { res }
     }
+
+    /// Initiate a TLS/TCP connection to a remote host with the given TLS client options.
+    ///
+    /// With `TlsClientConfig::V1`, custom root CAs, a client certificate for mutual TLS, ALPN
+    /// protocols and SPKI pins can be configured.
+    pub fn connect_tls(host: &str, port: u16, config: TlsClientConfig) -> TcpConnector {
+        let res = ocall::tcp_connect_tls(host.into(), port, config).map(ResourceId);
+        TcpConnector { res }
+    }
+}
+
+impl UdpSocket {
+    /// Create a UDP socket bound to the specified address.
+    pub async fn bind(addr: &str) -> Result<Self> {
+        let res_id = ResourceId(ocall::udp_bind(addr)?);
+        Ok(Self { res_id })
+    }
+
+    /// Send a datagram to the given address.
+    ///
+    /// Returns the number of bytes sent.
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddr) -> Result<usize> {
+        futures::future::poll_fn(|cx| self.poll_send_to(cx, buf, target)).await
+    }
+
+    /// Receive a datagram into `buf`.
+    ///
+    /// Returns the number of bytes received and the address of the sender. The part of the
+    /// datagram that does not fit into `buf` is discarded.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        futures::future::poll_fn(|cx| self.poll_recv_from(cx, buf)).await
+    }
+
+    /// Poll to send a datagram to the given address.
+    pub fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<Result<usize>> {
+        use env::OcallError;
+        let waker_id = tasks::intern_waker(cx.waker().clone());
+        let target = target.to_string();
+        match ocall::udp_send_to(waker_id, self.res_id.0, buf.into(), target.into()) {
+            Ok(len) => Poll::Ready(Ok(len as usize)),
+            Err(OcallError::Pending) => Poll::Pending,
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+
+    /// Poll to receive a datagram into `buf`.
+    pub fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<(usize, SocketAddr)>> {
+        use env::OcallError;
+        let waker_id = tasks::intern_waker(cx.waker().clone());
+        match ocall::udp_recv_from(waker_id, self.res_id.0, buf) {
+            Ok((len, remote_addr)) => Poll::Ready(Ok((
+                len as usize,
+                remote_addr
+                    .parse()
+                    .expect("ocall::udp_recv_from returned an invalid remote address"),
+            ))),
+            Err(OcallError::Pending) => Poll::Pending,
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+}
+
+/// Resolve the IP addresses of a host name.
+pub fn resolve(host: &str) -> Resolver {
+    let res = ocall::resolve(host).map(ResourceId);
+    Resolver { res }
+}
+
+impl Future for Resolver {
+    type Output = Result<Vec<IpAddr>>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        use env::OcallError;
+
+        let res_id = match &self.get_mut().res {
+            Ok(res_id) => res_id,
+            Err(err) => return Poll::Ready(Err(*err)),
+        };
+
+        match ocall::poll(env::tasks::intern_waker(ctx.waker().clone()), res_id.0) {
+            Ok(encoded) => {
+                let addrs = Vec::<String>::decode(&mut &encoded[..])
+                    .ok()
+                    .and_then(|addrs| addrs.iter().map(|addr| addr.parse().ok()).collect())
+                    .ok_or(OcallError::InvalidEncoding);
+                Poll::Ready(addrs)
+            }
+            Err(OcallError::Pending) => Poll::Pending,
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
 }
 
 #[cfg(feature = "hyper")]
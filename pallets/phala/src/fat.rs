@@ -8,7 +8,7 @@ pub mod pallet {
 	use frame_support::{dispatch::DispatchResult, pallet_prelude::*, traits::StorageVersion};
 	use frame_system::pallet_prelude::*;
 	use sp_core::H256;
-	use sp_runtime::AccountId32;
+	use sp_runtime::{traits::Hash as _, AccountId32};
 	use sp_std::prelude::*;
 
 	use crate::{mq::MessageOriginInfo, registry};
@@ -74,6 +74,18 @@ pub mod pallet {
 	pub type ClusterContracts<T: Config> =
 		StorageMap<_, Twox64Concat, ContractClusterId, Vec<ContractId>, ValueQuery>;
 
+	/// The hashes of the ink codes uploaded to each cluster
+	#[pallet::storage]
+	pub type ClusterCodes<T: Config> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		ContractClusterId,
+		Identity,
+		CodeHash<T>,
+		(),
+		OptionQuery,
+	>;
+
 	#[pallet::storage]
 	pub type ClusterWorkers<T> =
 		StorageMap<_, Twox64Concat, ContractClusterId, Vec<WorkerPublicKey>, ValueQuery>;
@@ -127,6 +139,14 @@ pub mod pallet {
 		ClusterDestroyed {
 			cluster: ContractClusterId,
 		},
+		ContractUpgrading {
+			contract: ContractId,
+			code_hash: CodeHash<T>,
+		},
+		ContractDestroyed {
+			contract: ContractId,
+			cluster: ContractClusterId,
+		},
 	}
 
 	#[pallet::error]
@@ -142,6 +162,8 @@ pub mod pallet {
 		WorkerNotFound,
 		PayloadTooLarge,
 		NoPinkSystemCode,
+		ContractNotFound,
+		ContractPermissionDenied,
 	}
 
 	type CodeHash<T> = <T as frame_system::Config>::Hash;
@@ -247,6 +269,10 @@ pub mod pallet {
 				Error::<T>::PayloadTooLarge
 			);
 
+			if resource_type == ResourceType::InkCode {
+				let code_hash = T::Hashing::hash(&resource_data);
+				ClusterCodes::<T>::insert(cluster_id, code_hash, ());
+			}
 			Self::push_message(ClusterOperation::<_, T::BlockNumber>::UploadResource {
 				origin,
 				cluster_id,
@@ -295,11 +321,78 @@ pub mod pallet {
 			Ok(())
 		}
 
+		/// Replace the code of a contract while keeping its address and storage
+		///
+		/// Can only be called by the deployer of the contract, with a code uploaded to the cluster
+		/// of the contract.
+		#[pallet::weight(0)]
+		pub fn upgrade_contract(
+			origin: OriginFor<T>,
+			contract_id: ContractId,
+			code_hash: CodeHash<T>,
+		) -> DispatchResult {
+			let origin = ensure_signed(origin)?;
+			let contract_info =
+				Contracts::<T>::get(contract_id).ok_or(Error::<T>::ContractNotFound)?;
+			ensure!(
+				contract_info.deployer == origin,
+				Error::<T>::ContractPermissionDenied
+			);
+			ensure!(
+				ClusterCodes::<T>::contains_key(contract_info.cluster_id, code_hash),
+				Error::<T>::CodeNotFound
+			);
+
+			Self::push_message(ContractOperation::<_, T::AccountId>::upgrade_code(
+				contract_id,
+				code_hash,
+			));
+			Self::deposit_event(Event::ContractUpgrading {
+				contract: contract_id,
+				code_hash,
+			});
+			Ok(())
+		}
+
+		/// Remove a contract from its cluster, freeing its storage
+		///
+		/// Can only be called by the deployer of the contract.
+		#[pallet::weight(0)]
+		pub fn destroy_contract(origin: OriginFor<T>, contract_id: ContractId) -> DispatchResult {
+			let origin = ensure_signed(origin)?;
+			let contract_info =
+				Contracts::<T>::get(contract_id).ok_or(Error::<T>::ContractNotFound)?;
+			ensure!(
+				contract_info.deployer == origin,
+				Error::<T>::ContractPermissionDenied
+			);
+			ensure!(
+				Self::get_system_contract(&contract_id) != Some(contract_id),
+				Error::<T>::ContractPermissionDenied
+			);
+
+			let cluster = contract_info.cluster_id;
+			Contracts::<T>::remove(contract_id);
+			ClusterContracts::<T>::mutate(cluster, |contracts| {
+				contracts.retain(|id| *id != contract_id)
+			});
+			registry::ContractKeys::<T>::remove(contract_id);
+			Self::push_message(ContractOperation::<CodeHash<T>, T::AccountId>::destroy(
+				contract_id,
+			));
+			Self::deposit_event(Event::ContractDestroyed {
+				contract: contract_id,
+				cluster,
+			});
+			Ok(())
+		}
+
 		#[pallet::weight(0)]
 		pub fn cluster_destroy(origin: OriginFor<T>, cluster: ContractClusterId) -> DispatchResult {
 			ensure_root(origin)?;
 
 			Clusters::<T>::take(cluster).ok_or(Error::<T>::ClusterNotFound)?;
+			let _ = ClusterCodes::<T>::clear_prefix(cluster, u32::MAX, None);
 			Self::push_message(
 				ClusterOperation::<T::AccountId, T::BlockNumber>::DestroyCluster(cluster),
 			);
@@ -62,6 +62,12 @@ struct Args {
     #[arg(default_value_t = 5)]
     max_checkpoint_files: u32,
 
+    /// Number of delta checkpoints, which only contain the chain state changed since the
+    /// last full checkpoint, to take between two full checkpoints.
+    #[arg(long)]
+    #[arg(default_value_t = 0)]
+    delta_checkpoints: u32,
+
     /// Measuring the time it takes to process each RPC call.
     #[arg(long)]
     measure_rpc_time: bool,
@@ -70,6 +76,23 @@ struct Args {
     #[arg(long)]
     #[arg(default_value_t = 100)]
     gc_interval: BlockNumber,
+
+    /// Route all HTTP requests made by contracts through the given proxy
+    /// (e.g. socks5://127.0.0.1:9050)
+    #[arg(long)]
+    contract_http_proxy: Option<String>,
+
+    /// Seal the files written by sidevm programs to the storage path instead of keeping them
+    /// in memory
+    #[arg(long)]
+    seal_sidevm_fs: bool,
+
+    /// Keep the chain storage in a log under the storage path instead of in memory, so its
+    /// size is not bounded by the enclave memory. Checkpoints then only refer to the log.
+    /// Takes effect when the runtime is initialized, a restored checkpoint keeps the backend
+    /// it was taken with.
+    #[arg(long)]
+    disk_chain_storage: bool,
 }
 
 #[rocket::main]
@@ -130,15 +153,20 @@ async fn main() -> Result<(), rocket::Error> {
             checkpoint_interval: args.checkpoint_interval,
             remove_corrupted_checkpoint: args.remove_corrupted_checkpoint,
             max_checkpoint_files: args.max_checkpoint_files,
+            delta_checkpoints: args.delta_checkpoints,
             gc_interval: args.gc_interval,
             cores,
             public_port: args.public_port,
+            contract_http_proxy: args.contract_http_proxy,
+            seal_sidevm_fs: args.seal_sidevm_fs,
+            disk_chain_storage: args.disk_chain_storage,
         }
     };
     info!("init_args: {:#?}", init_args);
     if let Err(err) = runtime::ecall_init(init_args) {
         panic!("Initialize Failed: {err:?}");
     }
+    rocket::tokio::spawn(runtime::ecall_serve_sidevm_queries());
 
     for i in 0..cores {
         thread::Builder::new()